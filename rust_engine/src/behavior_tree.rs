@@ -0,0 +1,531 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::coordinates::Coordinates;
+use crate::faction::Attitude;
+use crate::condition::EntityRef;
+use crate::functions::FunctionCall;
+use crate::game_state::{EntityId, GameState};
+use crate::property::Condition;
+use crate::registry::EntityHandle;
+use crate::script::ScriptValue;
+use crate::state_machine::StateAction;
+use crate::stats::StatValue;
+
+/// Result of ticking a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeStatus {
+    Success,
+    Failure,
+    /// Not done yet; ticked again next update
+    Running,
+}
+
+impl fmt::Display for NodeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeStatus::Success => write!(f, "success"),
+            NodeStatus::Failure => write!(f, "failure"),
+            NodeStatus::Running => write!(f, "running"),
+        }
+    }
+}
+
+/// Who a leaf acts on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BtTarget {
+    Player,
+    /// The NPC's `target`
+    Target,
+    /// The nearest entity in sight the NPC's faction is hostile to
+    NearestHostile,
+    /// An entity named by a blackboard value ("player" or an NPC ID)
+    Blackboard(String),
+}
+
+impl BtTarget {
+    /// "player", "target", "nearest_hostile", or else a blackboard key
+    pub fn parse(value: &str) -> BtTarget {
+        match value {
+            "player" => BtTarget::Player,
+            "target" => BtTarget::Target,
+            "nearest_hostile" => BtTarget::NearestHostile,
+            key => BtTarget::Blackboard(key.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for BtTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BtTarget::Player => write!(f, "player"),
+            BtTarget::Target => write!(f, "target"),
+            BtTarget::NearestHostile => write!(f, "nearest_hostile"),
+            BtTarget::Blackboard(key) => write!(f, "{}", key),
+        }
+    }
+}
+
+/// A behavior tree node.
+///
+/// Composites are re-evaluated from their first child every tick, so a higher priority
+/// branch takes over as soon as it can run.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum BtNode {
+    /// Ticks children in order until one does not succeed
+    Sequence(Vec<BtNode>),
+    /// Ticks children in order until one does not fail
+    Selector(Vec<BtNode>),
+    /// Ticks every child. Succeeds once `success_threshold` children succeed and fails once
+    /// that is no longer possible.
+    Parallel { success_threshold: usize, children: Vec<BtNode> },
+    /// Swaps success and failure
+    Inverter(Box<BtNode>),
+    /// Fails without ticking the child until `seconds` of game time have passed since the
+    /// child last finished
+    Cooldown { seconds: f32, child: Box<BtNode> },
+    /// Runs the child until it has succeeded `times` times (forever if `None`), failing
+    /// as soon as it fails
+    Repeat { times: Option<u32>, child: Box<BtNode> },
+    /// Runs for this many seconds, then succeeds
+    Wait(f32),
+    /// Succeeds if the condition holds for the NPC
+    Condition(Condition),
+    /// Moves toward the target at the NPC's "speed"; succeeds once within `range`
+    MoveToward { target: BtTarget, range: f32 },
+    /// Makes a real-time `combat::attack` on the target if it is within `range`; fails if
+    /// it is out of range or the attack cannot be made (e.g. on cooldown)
+    Attack { target: BtTarget, range: f32 },
+    /// Moves away from the target at the NPC's "speed"; succeeds once `distance` away
+    Flee { target: BtTarget, distance: f32 },
+    /// Stores a value on the blackboard
+    SetBlackboard { key: String, value: StatValue },
+    /// Calls a function or runs a script; fails if it errors (or a script returns false)
+    Action(StateAction),
+}
+
+impl BtNode {
+    /// Number of nodes in this subtree, itself included
+    pub fn size(&self) -> usize {
+        1 + self.children().iter().map(|child| child.size()).sum::<usize>()
+    }
+
+    pub fn children(&self) -> Vec<&BtNode> {
+        match self {
+            BtNode::Sequence(children) | BtNode::Selector(children) | BtNode::Parallel { children, .. } => children.iter().collect(),
+            BtNode::Inverter(child) | BtNode::Cooldown { child, .. } | BtNode::Repeat { child, .. } => vec![child],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Short description for debug dumps
+    pub fn label(&self) -> String {
+        match self {
+            BtNode::Sequence(_) => "sequence".to_string(),
+            BtNode::Selector(_) => "selector".to_string(),
+            BtNode::Parallel { success_threshold, .. } => format!("parallel (succeed at {})", success_threshold),
+            BtNode::Inverter(_) => "inverter".to_string(),
+            BtNode::Cooldown { seconds, .. } => format!("cooldown {}s", seconds),
+            BtNode::Repeat { times: Some(times), .. } => format!("repeat {}x", times),
+            BtNode::Repeat { times: None, .. } => "repeat forever".to_string(),
+            BtNode::Wait(seconds) => format!("wait {}s", seconds),
+            BtNode::Condition(_) => "condition".to_string(),
+            BtNode::MoveToward { target, range } => format!("move_toward {} (range {})", target, range),
+            BtNode::Attack { target, range } => format!("attack {} (range {})", target, range),
+            BtNode::Flee { target, distance } => format!("flee {} (distance {})", target, distance),
+            BtNode::SetBlackboard { key, value } => format!("set {} = {:?}", key, value),
+            BtNode::Action(StateAction::Function(function_id)) => format!("function {}", function_id),
+            BtNode::Action(StateAction::Script(_)) => "script".to_string(),
+        }
+    }
+}
+
+/// A named tree that entity types refer to with `behavior_tree`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BehaviorTree {
+    pub id: String,
+    pub root: BtNode,
+}
+
+impl BehaviorTree {
+    pub fn new(id: &str, root: BtNode) -> Self {
+        BehaviorTree { id: id.to_string(), root }
+    }
+}
+
+/// Per-NPC memory for its behavior tree: values leaves read and write, plus the state of
+/// timers and counters. Nodes are identified by their position in the tree, depth first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Blackboard {
+    pub values: BTreeMap<String, StatValue>,
+    /// Game time at which each cooldown node is ready again
+    #[serde(default)]
+    cooldowns: BTreeMap<usize, f32>,
+    /// Elapsed time of wait nodes and success counts of repeat nodes; forgotten when the
+    /// node is not ticked
+    #[serde(default)]
+    progress: BTreeMap<usize, f32>,
+    /// Status of every node ticked in the last tick
+    #[serde(skip)]
+    last_statuses: HashMap<usize, NodeStatus>,
+}
+
+impl Blackboard {
+    pub fn get(&self, key: &str) -> Option<&StatValue> {
+        self.values.get(key)
+    }
+
+    pub fn set(&mut self, key: &str, value: StatValue) {
+        self.values.insert(key.to_string(), value);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<StatValue> {
+        self.values.remove(key)
+    }
+
+    /// Status a node returned in the last tick, `None` if it was not ticked
+    pub fn last_status(&self, node_index: usize) -> Option<NodeStatus> {
+        self.last_statuses.get(&node_index).copied()
+    }
+}
+
+/// The tree with each node's status from the last tick, one node per line
+pub fn dump(tree: &BehaviorTree, blackboard: &Blackboard) -> String {
+    fn write_node(node: &BtNode, index: usize, depth: usize, blackboard: &Blackboard, out: &mut String) {
+        let status = blackboard.last_status(index).map(|s| s.to_string()).unwrap_or_else(|| "-".to_string());
+        out.push_str(&format!("{}{} [{}]\n", "  ".repeat(depth), node.label(), status));
+        let mut child_index = index + 1;
+        for child in node.children() {
+            write_node(child, child_index, depth + 1, blackboard, out);
+            child_index += child.size();
+        }
+    }
+    let mut out = format!("{}\n", tree.id);
+    write_node(&tree.root, 0, 1, blackboard, &mut out);
+    out
+}
+
+/// Tick the behavior tree of every NPC whose type has one
+pub fn update_behavior_trees(game_state: &mut GameState, delta_time: f32) {
+    // Trees are taken out so leaves can borrow the whole game state
+    let trees = std::mem::take(&mut game_state.behavior_trees);
+    for handle in game_state.npcs.handles() {
+        if game_state.npcs.is_despawning(handle) {
+            continue;
+        }
+        let Some(npc) = game_state.npcs.get_mut(handle) else { continue };
+        let Some(tree) = npc.npc_type.behavior_tree.as_ref().and_then(|id| trees.get(id)) else { continue };
+        let mut ticker = Ticker {
+            handle,
+            id: EntityId::Npc(npc.id.clone()),
+            delta_time,
+            blackboard: std::mem::take(&mut npc.blackboard),
+            ticked: HashMap::new(),
+        };
+        ticker.tick(game_state, &tree.root, 0);
+
+        let Ticker { mut blackboard, ticked, .. } = ticker;
+        blackboard.progress.retain(|index, _| ticked.contains_key(index));
+        blackboard.last_statuses = ticked;
+        if let Some(npc) = game_state.npcs.get_mut(handle) {
+            npc.blackboard = blackboard;
+        }
+    }
+    let added = std::mem::replace(&mut game_state.behavior_trees, trees);
+    game_state.behavior_trees.extend(added);
+}
+
+/// The live entity a target names, from the point of view of the NPC behind `handle`
+pub(crate) fn resolve_target(game_state: &GameState, handle: EntityHandle, blackboard: &Blackboard, target: &BtTarget) -> Option<EntityId> {
+    let id = match target {
+        BtTarget::Player => EntityId::Player,
+        BtTarget::Target => {
+            let target_handle = game_state.npcs.get(handle)?.target?;
+            EntityId::Npc(game_state.npcs.get(target_handle)?.id.clone())
+        },
+        BtTarget::NearestHostile => {
+            let npc = game_state.npcs.get(handle)?;
+            let self_id = EntityId::Npc(npc.id.clone());
+            npc.perception.visible.iter()
+                .filter(|id| game_state.attitude(&self_id, id) == Attitude::Hostile)
+                .filter_map(|id| Some((game_state.get_entity(id)?.position().distance(&npc.position), id)))
+                .min_by(|a, b| a.0.total_cmp(&b.0))?
+                .1.clone()
+        },
+        BtTarget::Blackboard(key) => match blackboard.get(key)? {
+            StatValue::String(name) => EntityId::parse(name),
+            _ => return None,
+        },
+    };
+    game_state.get_entity(&id).is_some().then_some(id)
+}
+
+struct Ticker {
+    handle: EntityHandle,
+    id: EntityId,
+    delta_time: f32,
+    blackboard: Blackboard,
+    ticked: HashMap<usize, NodeStatus>,
+}
+
+impl Ticker {
+    fn tick(&mut self, game_state: &mut GameState, node: &BtNode, index: usize) -> NodeStatus {
+        let status = self.evaluate(game_state, node, index);
+        self.ticked.insert(index, status);
+        status
+    }
+
+    fn evaluate(&mut self, game_state: &mut GameState, node: &BtNode, index: usize) -> NodeStatus {
+        if game_state.npcs.get(self.handle).is_none() {
+            return NodeStatus::Failure;
+        }
+        match node {
+            BtNode::Sequence(children) => self.composite(game_state, children, index, NodeStatus::Success),
+            BtNode::Selector(children) => self.composite(game_state, children, index, NodeStatus::Failure),
+            BtNode::Parallel { success_threshold, children } => {
+                let mut child_index = index + 1;
+                let (mut succeeded, mut failed) = (0, 0);
+                for child in children {
+                    match self.tick(game_state, child, child_index) {
+                        NodeStatus::Success => succeeded += 1,
+                        NodeStatus::Failure => failed += 1,
+                        NodeStatus::Running => {},
+                    }
+                    child_index += child.size();
+                }
+                if succeeded >= *success_threshold {
+                    NodeStatus::Success
+                } else if children.len() - failed < *success_threshold {
+                    NodeStatus::Failure
+                } else {
+                    NodeStatus::Running
+                }
+            },
+            BtNode::Inverter(child) => match self.tick(game_state, child, index + 1) {
+                NodeStatus::Success => NodeStatus::Failure,
+                NodeStatus::Failure => NodeStatus::Success,
+                NodeStatus::Running => NodeStatus::Running,
+            },
+            BtNode::Cooldown { seconds, child } => {
+                if self.blackboard.cooldowns.get(&index).is_some_and(|ready| game_state.game_time < *ready) {
+                    return NodeStatus::Failure;
+                }
+                let status = self.tick(game_state, child, index + 1);
+                if status != NodeStatus::Running {
+                    self.blackboard.cooldowns.insert(index, game_state.game_time + seconds);
+                }
+                status
+            },
+            BtNode::Repeat { times, child } => match self.tick(game_state, child, index + 1) {
+                NodeStatus::Failure => {
+                    self.blackboard.progress.remove(&index);
+                    NodeStatus::Failure
+                },
+                NodeStatus::Running => NodeStatus::Running,
+                NodeStatus::Success => {
+                    let count = self.blackboard.progress.get(&index).copied().unwrap_or(0.0) + 1.0;
+                    match times {
+                        Some(times) if count >= *times as f32 => {
+                            self.blackboard.progress.remove(&index);
+                            NodeStatus::Success
+                        },
+                        _ => {
+                            self.blackboard.progress.insert(index, count);
+                            NodeStatus::Running
+                        },
+                    }
+                },
+            },
+            BtNode::Wait(seconds) => {
+                let elapsed = self.blackboard.progress.get(&index).copied().unwrap_or(0.0) + self.delta_time;
+                if elapsed >= *seconds {
+                    self.blackboard.progress.remove(&index);
+                    NodeStatus::Success
+                } else {
+                    self.blackboard.progress.insert(index, elapsed);
+                    NodeStatus::Running
+                }
+            },
+            BtNode::Condition(condition) => {
+                let npc = game_state.npcs.get(self.handle).expect("checked above");
+                if game_state.conditions.evaluate(EntityRef::Npc(npc), game_state, condition) {
+                    NodeStatus::Success
+                } else {
+                    NodeStatus::Failure
+                }
+            },
+            BtNode::MoveToward { target, range } => {
+                let Some(goal) = self.target_position(game_state, target) else { return NodeStatus::Failure };
+                let npc = game_state.npcs.get_mut(self.handle).expect("checked above");
+                let distance = npc.position.distance(&goal);
+                if distance.is_nan() {
+                    return NodeStatus::Failure;
+                }
+                if distance <= *range {
+                    return NodeStatus::Success;
+                }
+                let step = npc.get_float_stat("speed").unwrap_or(1.0) * self.delta_time;
+                npc.position.move_toward(&goal, step.min(distance - range));
+                if distance - step <= *range { NodeStatus::Success } else { NodeStatus::Running }
+            },
+            BtNode::Flee { target, distance: safe_distance } => {
+                let Some(threat) = self.target_position(game_state, target) else { return NodeStatus::Failure };
+                let npc = game_state.npcs.get_mut(self.handle).expect("checked above");
+                let distance = npc.position.distance(&threat);
+                if distance.is_nan() {
+                    return NodeStatus::Failure;
+                }
+                if distance >= *safe_distance {
+                    return NodeStatus::Success;
+                }
+                let step = npc.get_float_stat("speed").unwrap_or(1.0) * self.delta_time;
+                let Some(away) = threat.direction_to(&npc.position) else { return NodeStatus::Failure };
+                for (value, direction) in npc.position.values.iter_mut().zip(&away.values) {
+                    *value += direction * step;
+                }
+                if distance + step >= *safe_distance { NodeStatus::Success } else { NodeStatus::Running }
+            },
+            BtNode::Attack { target, range } => {
+                let Some(target_id) = self.resolve(game_state, target) else { return NodeStatus::Failure };
+                // Never turn on allies
+                if game_state.attitude(&self.id, &target_id) == Attitude::Ally {
+                    return NodeStatus::Failure;
+                }
+                let Some(defender) = game_state.get_entity(&target_id) else { return NodeStatus::Failure };
+                let attacker = game_state.npcs.get(self.handle).expect("checked above");
+                let distance = attacker.position.distance(defender.position());
+                if distance.is_nan() || distance > *range {
+                    return NodeStatus::Failure;
+                }
+                match crate::combat::attack(game_state, &self.id, &target_id) {
+                    Ok(_) => NodeStatus::Success,
+                    Err(_) => NodeStatus::Failure,
+                }
+            },
+            BtNode::SetBlackboard { key, value } => {
+                self.blackboard.set(key, value.clone());
+                NodeStatus::Success
+            },
+            BtNode::Action(action) => {
+                let target = self.resolve(game_state, &BtTarget::Target);
+                let succeeded = match action {
+                    StateAction::Function(function_id) => {
+                        let call = FunctionCall::new(function_id, self.id.clone(), target.into_iter().collect());
+                        game_state.invoke_function(call).is_ok()
+                    },
+                    StateAction::Script(source) => crate::script::run_script(game_state, source, self.id.clone(), target)
+                        .is_ok_and(|output| output.value != ScriptValue::Bool(false)),
+                };
+                if succeeded { NodeStatus::Success } else { NodeStatus::Failure }
+            },
+        }
+    }
+
+    // Sequence (stops at the first non-success) or selector (stops at the first non-failure)
+    fn composite(&mut self, game_state: &mut GameState, children: &[BtNode], index: usize, keep_going: NodeStatus) -> NodeStatus {
+        let mut child_index = index + 1;
+        for child in children {
+            let status = self.tick(game_state, child, child_index);
+            if status != keep_going {
+                return status;
+            }
+            child_index += child.size();
+        }
+        keep_going
+    }
+
+    fn resolve(&self, game_state: &GameState, target: &BtTarget) -> Option<EntityId> {
+        resolve_target(game_state, self.handle, &self.blackboard, target)
+    }
+
+    fn target_position(&self, game_state: &GameState, target: &BtTarget) -> Option<Coordinates> {
+        let id = self.resolve(game_state, target)?;
+        Some(game_state.get_entity(&id)?.position().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+    use crate::property::Property;
+
+    fn game(root: BtNode) -> GameState {
+        let mut game_state = GameState::new();
+        game_state.add_behavior_tree(BehaviorTree::new("boss", root));
+        game_state.add_entity_type(EntityType::new("ogre", "Ogre").with_behavior_tree("boss"));
+        let mut ogre = NPC::new("ogre1".to_string(), game_state.entity_types["ogre"].clone());
+        ogre.set_base_stat("hp", StatValue::Integer(50));
+        ogre.set_base_stat("speed", StatValue::Float(2.0));
+        ogre.set_base_stat("attack", StatValue::Integer(7));
+        ogre.set_base_stat("attack_cooldown", StatValue::Float(2.0));
+        game_state.add_npc(ogre).unwrap();
+        game_state.player.position = Coordinates::new_2d(5.0, 0.0);
+        game_state.player.set_base_stat("hp", StatValue::Integer(30));
+        game_state
+    }
+
+    #[test]
+    fn test_boss_chases_attacks_and_flees() {
+        let low_hp = Property::create_stat_threshold_condition("hp", StatValue::Integer(10), false);
+        let mut game_state = game(BtNode::Selector(vec![
+            BtNode::Sequence(vec![
+                BtNode::Condition(low_hp),
+                BtNode::Flee { target: BtTarget::Player, distance: 10.0 },
+            ]),
+            BtNode::Sequence(vec![
+                BtNode::MoveToward { target: BtTarget::Player, range: 1.0 },
+                BtNode::Attack { target: BtTarget::Player, range: 1.0 },
+            ]),
+        ]));
+        let ogre = |game_state: &GameState| game_state.get_npc("ogre1").unwrap().position.clone();
+
+        game_state.update(1.0);
+        assert_eq!(ogre(&game_state), Coordinates::new_2d(2.0, 0.0));
+        // Arrives and attacks in the same tick
+        game_state.update(1.0);
+        assert_eq!(ogre(&game_state), Coordinates::new_2d(4.0, 0.0));
+        assert_eq!(game_state.player.get_int_stat("hp"), Some(23));
+        let dump = game_state.dump_behavior_tree("ogre1").unwrap();
+        assert!(dump.contains("    attack player (range 1) [success]"), "{}", dump);
+        assert!(dump.contains("    flee player (distance 10) [-]"), "{}", dump);
+
+        // On cooldown, so the attack fails
+        game_state.update(1.0);
+        assert_eq!(game_state.player.get_int_stat("hp"), Some(23));
+
+        game_state.get_npc_mut("ogre1").unwrap().set_base_stat("hp", StatValue::Integer(5));
+        game_state.update(1.0);
+        assert_eq!(ogre(&game_state), Coordinates::new_2d(2.0, 0.0));
+    }
+
+    #[test]
+    fn test_decorators_and_blackboard() {
+        let roar = BtNode::Action(StateAction::Script("add_stat(self, \"roars\", 1);".to_string()));
+        let mut game_state = game(BtNode::Sequence(vec![
+            BtNode::Selector(vec![
+                BtNode::Cooldown { seconds: 3.0, child: Box::new(roar) },
+                BtNode::SetBlackboard { key: "quiet".to_string(), value: StatValue::Boolean(true) },
+            ]),
+            BtNode::Parallel { success_threshold: 1, children: vec![
+                BtNode::Repeat { times: Some(2), child: Box::new(BtNode::Wait(1.5)) },
+                BtNode::Inverter(Box::new(BtNode::SetBlackboard { key: "prey".to_string(), value: StatValue::String("player".to_string()) })),
+            ] },
+        ]));
+        game_state.get_npc_mut("ogre1").unwrap().set_base_stat("roars", StatValue::Integer(0));
+
+        let mut statuses = Vec::new();
+        for _ in 0..4 {
+            game_state.update(1.0);
+            statuses.push(game_state.get_npc("ogre1").unwrap().blackboard.last_status(0).unwrap());
+        }
+        // The waits finish on the 2nd and 4th ticks; the inverter always fails
+        assert_eq!(statuses, [NodeStatus::Running, NodeStatus::Running, NodeStatus::Running, NodeStatus::Success]);
+        let ogre = game_state.get_npc("ogre1").unwrap();
+        // Roared at 1s and again once the cooldown ran out at 4s
+        assert_eq!(ogre.get_int_stat("roars"), Some(2));
+        assert_eq!(ogre.blackboard.get("prey"), Some(&StatValue::String("player".to_string())));
+        assert_eq!(ogre.blackboard.get("quiet"), Some(&StatValue::Boolean(true)));
+    }
+}
@@ -12,7 +12,7 @@ pub struct StatModifier {
     pub priority: i32,       // For determining order of application
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ModifierType {
     Additive,        // Simple addition/subtraction
     Multiplicative,  // Percentage-based multiplier
@@ -46,7 +46,7 @@ impl CalculatedStats {
     
     // Add a modifier to a stat
     pub fn add_modifier(&mut self, stat: &str, modifier: StatModifier) {
-        let stat_modifiers = self.modifiers.entry(stat.to_string()).or_insert_with(Vec::new);
+        let stat_modifiers = self.modifiers.entry(stat.to_string()).or_default();
         stat_modifiers.push(modifier);
         
         // Sort modifiers by priority to ensure consistent application
@@ -68,13 +68,28 @@ impl CalculatedStats {
         self.cache_valid = false;
     }
     
+    // Remove modifiers whose source starts with a prefix (e.g. every "tag:" modifier)
+    pub fn remove_modifiers_by_source_prefix(&mut self, prefix: &str) {
+        for (_, modifiers) in self.modifiers.iter_mut() {
+            modifiers.retain(|m| !m.source.starts_with(prefix));
+        }
+        
+        self.cached_results.clear();
+        self.cache_valid = false;
+    }
+    
+    // Get the modifiers currently applied to a stat, in application order
+    pub fn get_modifiers(&self, stat: &str) -> &[StatModifier] {
+        self.modifiers.get(stat).map(|m| m.as_slice()).unwrap_or(&[])
+    }
+    
     // Calculate a stat value by applying all modifiers
     pub fn calculate_stat(&self, stat: &str) -> Option<StatValue> {
         // First check if it's in the cache
-        if self.cache_valid {
-            if let Some(cached) = self.cached_results.get(stat) {
-                return Some(cached.clone());
-            }
+        if self.cache_valid
+            && let Some(cached) = self.cached_results.get(stat)
+        {
+            return Some(cached.clone());
         }
         
         // Start with base stat
@@ -169,10 +184,10 @@ impl CalculatedStats {
         
         // Add new modifiers from equipment
         for item_id in inventory.get_all_item_ids() {
-            if let Some(item) = inventory.get_item(&item_id) {
-                if item.get_bool("equipped").unwrap_or(false) {
-                    self.apply_item_modifiers(item);
-                }
+            if let Some(item) = inventory.get_item(&item_id)
+                && item.get_bool("equipped").unwrap_or(false)
+            {
+                self.apply_item_modifiers(item);
             }
         }
     }
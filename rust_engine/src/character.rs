@@ -1,22 +1,50 @@
 use crate::stats::{Stats, StatValue};
 use crate::inventory::{Inventory, Item};
-use crate::calculated_stats::CalculatedStats;
+use crate::calculated_stats::{CalculatedStats, StatModifier, ModifierType};
 use crate::coordinates::Coordinates;
+use crate::entity_type::EntityType;
+use crate::tag::InstanceTags;
+use crate::context::ContextStack;
+use crate::ecs::{Components, ComponentsMut};
 use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
 
 #[derive(Serialize, Deserialize)]
 pub struct Character {
     pub position: Coordinates,
     pub inventory: Inventory,
-    #[serde(skip)]
+    // Optional type (class, race, ...) whose tags and properties apply to the character
+    #[serde(default)]
+    pub character_type: Option<EntityType>,
+    // Contexts the character is currently in (e.g. "combat"), used to select properties
+    #[serde(default)]
+    pub active_contexts: ContextStack,
+    // Tags given to this character at runtime, on top of its type's tags
+    #[serde(default)]
+    pub instance_tags: InstanceTags,
+    // Named conditions such as "poisoned", same as on NPCs
+    #[serde(default)]
+    pub status_effects: Vec<String>,
+    // Base stats and modifiers, saved so status effects and equipment survive a load
+    #[serde(default)]
     cached_stats: CalculatedStats,
 }
 
+impl Default for Character {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Character {
     pub fn new() -> Character {
         Character {
             position: Coordinates::new_2d(0.0, 0.0),
             inventory: Inventory::new(),
+            character_type: None,
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            status_effects: Vec::new(),
             cached_stats: CalculatedStats::new(),
         }
     }
@@ -26,6 +54,10 @@ impl Character {
         Character {
             position: Coordinates::new(dimensions),
             inventory: Inventory::new(),
+            character_type: None,
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            status_effects: Vec::new(),
             cached_stats: CalculatedStats::new(),
         }
     }
@@ -35,6 +67,10 @@ impl Character {
         Character {
             position: Coordinates::new_1d(x),
             inventory: Inventory::new(),
+            character_type: None,
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            status_effects: Vec::new(),
             cached_stats: CalculatedStats::new(),
         }
     }
@@ -44,6 +80,10 @@ impl Character {
         Character {
             position: Coordinates::new_3d(x, y, z),
             inventory: Inventory::new(),
+            character_type: None,
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            status_effects: Vec::new(),
             cached_stats: CalculatedStats::new(),
         }
     }
@@ -53,6 +93,10 @@ impl Character {
         Character {
             position: Coordinates::new_4d(x, y, z, t),
             inventory: Inventory::new(),
+            character_type: None,
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            status_effects: Vec::new(),
             cached_stats: CalculatedStats::new(),
         }
     }
@@ -111,6 +155,10 @@ impl Character {
         Character {
             position: Coordinates::new_2d(0.0, 0.0),
             inventory: Inventory::new(),
+            character_type: None,
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            status_effects: Vec::new(),
             cached_stats: CalculatedStats::with_base_stats(base_stats),
         }
     }
@@ -120,6 +168,10 @@ impl Character {
         let mut character = Character {
             position: Coordinates::new_2d(0.0, 0.0),
             inventory: custom_inventory,
+            character_type: None,
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            status_effects: Vec::new(),
             cached_stats: CalculatedStats::new(),
         };
         // Update stats based on inventory
@@ -137,19 +189,19 @@ impl Character {
     }
     
     // Get a stat from the calculated stats
-    pub fn get_stat(&mut self, key: &str) -> Option<StatValue> {
+    pub fn get_stat(&self, key: &str) -> Option<StatValue> {
         self.cached_stats.get(key)
     }
     
-    pub fn get_int_stat(&mut self, key: &str) -> Option<i32> {
+    pub fn get_int_stat(&self, key: &str) -> Option<i32> {
         self.cached_stats.get_int(key)
     }
     
-    pub fn get_float_stat(&mut self, key: &str) -> Option<f32> {
+    pub fn get_float_stat(&self, key: &str) -> Option<f32> {
         self.cached_stats.get_float(key)
     }
     
-    pub fn get_string_stat(&mut self, key: &str) -> Option<String> {
+    pub fn get_string_stat(&self, key: &str) -> Option<String> {
         self.cached_stats.get_string(key)
     }
     
@@ -211,8 +263,120 @@ impl Character {
         self.cached_stats.remove_buff(stat);
     }
     
+    // Give the character a type whose tags and properties apply to it
+    pub fn with_character_type(mut self, character_type: EntityType) -> Character {
+        self.character_type = Some(character_type);
+        self
+    }
+    
+    // Add a modifier to a stat
+    pub fn add_stat_modifier(&mut self, stat: &str, source: &str, mod_type: ModifierType, value: StatValue, priority: i32) {
+        self.cached_stats.add_modifier(stat, StatModifier {
+            source: source.to_string(),
+            modifier_type: mod_type,
+            value,
+            priority,
+        });
+    }
+    
+    // Remove every modifier whose source starts with a prefix
+    pub fn remove_stat_modifiers_by_source_prefix(&mut self, prefix: &str) {
+        self.cached_stats.remove_modifiers_by_source_prefix(prefix);
+    }
+    
+    // Modifiers currently applied to a stat
+    pub fn get_stat_modifiers(&self, stat: &str) -> &[StatModifier] {
+        self.cached_stats.get_modifiers(stat)
+    }
+    
+    // Context management
+    pub fn enter_context(&mut self, context: &str) {
+        self.active_contexts.push(context);
+    }
+    
+    // Undo the most recent enter_context for this context
+    pub fn leave_context(&mut self, context: &str) {
+        self.active_contexts.remove(context);
+    }
+    
+    // Leave the most recently entered context
+    pub fn pop_context(&mut self) -> Option<String> {
+        self.active_contexts.pop()
+    }
+    
+    pub fn is_in_context(&self, context: &str) -> bool {
+        self.active_contexts.contains(context)
+    }
+    
+    // Instance tag management
+    pub fn add_instance_tag(&mut self, tag_id: i32) -> bool {
+        self.instance_tags.add(tag_id)
+    }
+    
+    pub fn add_timed_instance_tag(&mut self, tag_id: i32, duration: f32) -> bool {
+        self.instance_tags.add_timed(tag_id, duration)
+    }
+    
+    pub fn remove_instance_tag(&mut self, tag_id: i32) -> bool {
+        self.instance_tags.remove(tag_id)
+    }
+    
+    // Check for a tag from either the character type or the instance tags
+    pub fn has_tag_id(&self, tag_id: i32) -> bool {
+        self.character_type.as_ref().is_some_and(|t| t.has_tag_id(tag_id)) || self.instance_tags.contains(tag_id)
+    }
+    
+    // Character type tags and instance tags combined
+    pub fn tag_ids(&self) -> HashSet<i32> {
+        self.character_type.iter()
+            .flat_map(|t| t.tag_ids.iter().copied())
+            .chain(self.instance_tags.ids())
+            .collect()
+    }
+    
     // Force recalculation of stats if needed
     pub fn invalidate_stat_cache(&mut self) {
         self.cached_stats.invalidate_cache();
     }
+    
+    // Status effect management
+    pub fn add_status_effect(&mut self, effect: &str) {
+        if !self.has_status_effect(effect) {
+            self.status_effects.push(effect.to_string());
+        }
+    }
+    
+    pub fn remove_status_effect(&mut self, effect: &str) {
+        self.status_effects.retain(|e| e != effect);
+    }
+    
+    pub fn has_status_effect(&self, effect: &str) -> bool {
+        self.status_effects.iter().any(|e| e == effect)
+    }
+    
+    // The character's data as ECS components (characters have no Behavior)
+    pub fn components(&self) -> Components<'_> {
+        Components {
+            position: &self.position,
+            stats: &self.cached_stats,
+            inventory: &self.inventory,
+            type_tags: self.character_type.as_ref().map(|t| &t.tag_ids),
+            instance_tags: &self.instance_tags,
+            status_effects: &self.status_effects,
+            behavior: None,
+            perception: None,
+        }
+    }
+    
+    pub fn components_mut(&mut self) -> ComponentsMut<'_> {
+        ComponentsMut {
+            position: Some(&mut self.position),
+            stats: Some(&mut self.cached_stats),
+            inventory: Some(&mut self.inventory),
+            tags: Some((self.character_type.as_mut().map(|t| &mut t.tag_ids), &mut self.instance_tags)),
+            status_effects: Some(&mut self.status_effects),
+            behavior: None,
+            perception: None,
+        }
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,595 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::calculated_stats::{ModifierType, StatModifier};
+use crate::condition::EntityRef;
+use crate::damage::{lineage, resolve_damage, DamagePacket, DamageReport, DAMAGE_SUFFIX, PHYSICAL};
+use crate::entity::Entity;
+use crate::game_state::{EntityId, GameState};
+use crate::inventory::Item;
+use crate::property_modifiers::{applies_in_any_context, stat_modifier_from_property};
+use crate::rng::SeededRng;
+use crate::stats::StatValue;
+
+/// Context whose tag and type properties count toward attacks, and which encounter
+/// participants are put in
+pub const COMBAT_CONTEXT: &str = "combat";
+
+/// Stat added to every attack's damage
+pub const ATTACK_STAT: &str = "attack";
+/// Stat taken off every hit's damage
+pub const DEFENSE_STAT: &str = "defense";
+/// Stat with the chance (0 to 1) an attack hits before evasion
+pub const ACCURACY_STAT: &str = "accuracy";
+/// Stat taken off an attacker's accuracy
+pub const EVASION_STAT: &str = "evasion";
+/// Stat with the chance (0 to 1) a hit is critical
+pub const CRIT_CHANCE_STAT: &str = "crit_chance";
+/// Stat a critical hit's damage is multiplied by
+pub const CRIT_MULTIPLIER_STAT: &str = "crit_multiplier";
+/// String stat with the type of an attack's main damage when no weapon sets it
+pub const DAMAGE_TYPE_STAT: &str = "damage_type";
+/// Stat with seconds between real-time attacks
+pub const ATTACK_COOLDOWN_STAT: &str = "attack_cooldown";
+/// Stat that orders encounter turns, highest first
+pub const INITIATIVE_STAT: &str = "initiative";
+
+pub const DEFAULT_ACCURACY: f32 = 1.0;
+pub const DEFAULT_CRIT_MULTIPLIER: f32 = 2.0;
+/// Entries kept in the combat log; older ones are dropped
+pub const COMBAT_LOG_LIMIT: usize = 200;
+
+/// Problems resolving attacks and encounters
+#[derive(Debug, Clone, PartialEq)]
+pub enum CombatError {
+    /// The attacker or defender does not exist
+    UnknownEntity(EntityId),
+    /// The defender has no integer "hp" to take damage from
+    NoHitPoints(EntityId),
+    /// The attacker or defender is already at 0 hp
+    Defeated(EntityId),
+    /// A real-time attack before the attacker's cooldown ran out
+    OnCooldown { entity: EntityId, remaining: f32 },
+    /// A turn-based action with no encounter running
+    NoEncounter,
+    /// An encounter was started while another is running
+    EncounterInProgress,
+    /// A real-time attack by an encounter participant, who acts on its turns instead
+    InEncounter(EntityId),
+    /// A turn taken by someone other than the participant whose turn it is
+    NotYourTurn { expected: EntityId, actual: EntityId },
+    /// Damage of a type the game does not define
+    UnknownDamageType(String),
+}
+
+impl fmt::Display for CombatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CombatError::UnknownEntity(entity) => write!(f, "Unknown entity: {}", entity),
+            CombatError::NoHitPoints(entity) => write!(f, "{} has no hit points", entity),
+            CombatError::Defeated(entity) => write!(f, "{} is defeated", entity),
+            CombatError::OnCooldown { entity, remaining } => write!(f, "{} can attack again in {:.1}s", entity, remaining),
+            CombatError::NoEncounter => write!(f, "No encounter is running"),
+            CombatError::EncounterInProgress => write!(f, "An encounter is already running"),
+            CombatError::InEncounter(entity) => write!(f, "{} is in a turn-based encounter", entity),
+            CombatError::NotYourTurn { expected, actual } => write!(f, "It is {}'s turn, not {}'s", expected, actual),
+            CombatError::UnknownDamageType(damage_type) => write!(f, "Unknown damage type: {}", damage_type),
+        }
+    }
+}
+
+pub type CombatResult<T> = Result<T, CombatError>;
+
+/// An attack as the attacker would make it, before anything is rolled against a defender
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attack {
+    pub attacker: EntityId,
+    /// Equipped weapon the attack is made with
+    pub weapon: Option<String>,
+    /// Damage before the defender's defense and defenses; the first component is the
+    /// main one
+    pub damage: DamagePacket,
+    pub accuracy: f32,
+    pub crit_chance: f32,
+    pub crit_multiplier: f32,
+}
+
+/// How an attack landed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttackOutcome {
+    Miss,
+    Hit,
+    Critical,
+}
+
+/// One resolved attack
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CombatLogEntry {
+    /// Game time of the attack
+    pub time: f32,
+    /// Encounter round, or `None` for a real-time attack
+    pub round: Option<u32>,
+    pub attacker: EntityId,
+    pub defender: EntityId,
+    pub weapon: Option<String>,
+    /// Chance the attack had to hit, after evasion
+    pub hit_chance: f32,
+    pub outcome: AttackOutcome,
+    /// What each type of damage did; empty on a miss
+    pub damage: DamageReport,
+    /// Whether the attack brought the defender to 0 hp
+    pub defeated: bool,
+}
+
+impl fmt::Display for CombatLogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(round) = self.round {
+            write!(f, "[round {}] ", round)?;
+        }
+        let verb = match self.outcome {
+            AttackOutcome::Miss => "misses",
+            AttackOutcome::Hit => "hits",
+            AttackOutcome::Critical => "critically hits",
+        };
+        write!(f, "{} {} {}", self.attacker, verb, self.defender)?;
+        if let Some(weapon) = &self.weapon {
+            write!(f, " with {}", weapon)?;
+        }
+        if self.outcome != AttackOutcome::Miss {
+            write!(f, " for {}", self.damage)?;
+        }
+        if self.defeated {
+            write!(f, ", defeating them")?;
+        }
+        Ok(())
+    }
+}
+
+/// A turn-based fight: participants act one at a time in initiative order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Encounter {
+    pub participants: Vec<EntityId>,
+    /// Index of the participant whose turn it is
+    pub turn: usize,
+    /// Starts at 1 and goes up each time the turn order wraps around
+    pub round: u32,
+}
+
+impl Encounter {
+    /// The participant whose turn it is
+    pub fn current(&self) -> Option<&EntityId> {
+        self.participants.get(self.turn)
+    }
+}
+
+/// Combat log, the running encounter and real-time attack cooldowns
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CombatState {
+    /// Resolved attacks, oldest first, at most `COMBAT_LOG_LIMIT`
+    pub log: Vec<CombatLogEntry>,
+    pub encounter: Option<Encounter>,
+    /// Game time each entity can next attack in real time
+    #[serde(default)]
+    ready_at: BTreeMap<String, f32>,
+}
+
+impl CombatState {
+    pub fn last_entry(&self) -> Option<&CombatLogEntry> {
+        self.log.last()
+    }
+
+    /// Add to the log, dropping the oldest entries past `COMBAT_LOG_LIMIT`
+    pub fn record(&mut self, entry: CombatLogEntry) {
+        self.log.push(entry);
+        if self.log.len() > COMBAT_LOG_LIMIT {
+            let excess = self.log.len() - COMBAT_LOG_LIMIT;
+            self.log.drain(..excess);
+        }
+    }
+
+    /// Drop what is kept about an entity that left the game
+    pub fn forget(&mut self, entity: &EntityId) {
+        self.ready_at.remove(&entity.to_string());
+    }
+}
+
+// Tag and type stat modifiers that apply in combat but not in the entity's active
+// contexts, so they are not part of its calculated stats yet
+fn combat_only_modifiers(entity: EntityRef, game_state: &GameState) -> Vec<(String, StatModifier)> {
+    let combat = [COMBAT_CONTEXT.to_string()];
+    let active = entity.active_contexts();
+    let applies = |property: &crate::property::Property| {
+        applies_in_any_context(property, &combat)
+            && !applies_in_any_context(property, active)
+            && game_state.is_property_active(entity, property)
+    };
+    let tag_properties = game_state.tag_collection.resolved_properties_for_tags(&entity.tag_ids())
+        .into_iter()
+        .map(|(_, property)| property);
+    let own_properties = entity.entity_type().into_iter().flat_map(|t| t.properties.iter());
+    let mut modifiers: Vec<_> = tag_properties.chain(own_properties)
+        .filter(|property| applies(property))
+        .filter_map(|property| stat_modifier_from_property(property, COMBAT_CONTEXT))
+        .collect();
+    modifiers.sort_by_key(|(_, modifier)| modifier.priority);
+    modifiers
+}
+
+/// A stat as it is in combat: the calculated stat with the modifiers of tag and type
+/// properties limited to the "combat" context applied on top
+pub fn combat_stat(game_state: &GameState, entity: &EntityId, key: &str) -> Option<f32> {
+    let entity = game_state.get_entity(entity)?;
+    let mut value = entity.get_stat(key).and_then(|value| value.as_float());
+    for (stat, modifier) in combat_only_modifiers(entity, game_state) {
+        if stat != key {
+            continue;
+        }
+        let Some(amount) = modifier.value.as_float() else { continue };
+        value = Some(match modifier.modifier_type {
+            ModifierType::Additive => value.unwrap_or(0.0) + amount,
+            ModifierType::Multiplicative => value.unwrap_or(0.0) * amount,
+            ModifierType::Override => amount,
+        });
+    }
+    value
+}
+
+/// The equipped weapon an entity attacks with: the first equipped item of type "weapon",
+/// by item ID
+pub fn equipped_weapon<E: Entity + ?Sized>(entity: &E) -> Option<&Item> {
+    let mut weapons: Vec<&Item> = entity.inventory().get_all_items().into_iter()
+        .filter(|item| item.get_bool("equipped").unwrap_or(false))
+        .filter(|item| item.get_string("type").is_some_and(|t| t == "weapon"))
+        .collect();
+    weapons.sort_by(|a, b| a.id().cmp(b.id()));
+    weapons.into_iter().next()
+}
+
+fn item_float(item: &Item, key: &str) -> Option<f32> {
+    item.get_float(key).or_else(|| item.get_int(key).map(|value| value as f32))
+}
+
+/// Build the attack an entity would make from its combat stats and equipped weapon.
+///
+/// The main damage is the "attack" stat, of the weapon's "damage_type", else the
+/// attacker's, else physical; for NPCs the weapon's "damage" is added to it (the player's
+/// equipped items already add theirs to "attack"). Every defined damage type also gets the
+/// attacker's "<type>_damage" stat and the weapon's "<type>_damage" value. The weapon's
+/// "accuracy" and "crit_chance" add to the stats, and its "crit_multiplier" replaces them.
+pub fn build_attack(game_state: &GameState, attacker: &EntityId) -> CombatResult<Attack> {
+    let entity = game_state.get_entity(attacker).ok_or_else(|| CombatError::UnknownEntity(attacker.clone()))?;
+    let stat = |key: &str| combat_stat(game_state, attacker, key);
+    let weapon = equipped_weapon(&entity);
+    let weapon_stat = |key: &str| weapon.and_then(|weapon| item_float(weapon, key));
+
+    let main_type = weapon.and_then(|weapon| weapon.get_string(DAMAGE_TYPE_STAT).cloned())
+        .or_else(|| match entity.get_stat(DAMAGE_TYPE_STAT) {
+            Some(StatValue::String(damage_type)) => Some(damage_type),
+            _ => None,
+        })
+        .unwrap_or_else(|| PHYSICAL.to_string());
+    let mut main_damage = stat(ATTACK_STAT).unwrap_or(0.0);
+    if matches!(attacker, EntityId::Npc(_)) {
+        main_damage += weapon_stat("damage").unwrap_or(0.0);
+    }
+    let mut damage = DamagePacket::of(&main_type, main_damage);
+    let mut damage_types: Vec<&String> = game_state.damage_types.keys().collect();
+    damage_types.sort();
+    for damage_type in damage_types {
+        let key = format!("{}{}", damage_type, DAMAGE_SUFFIX);
+        let extra = stat(&key).unwrap_or(0.0) + weapon_stat(&key).unwrap_or(0.0);
+        if extra > 0.0 {
+            damage.add(damage_type, extra);
+        }
+    }
+
+    Ok(Attack {
+        attacker: attacker.clone(),
+        weapon: weapon.map(|weapon| weapon.id().to_string()),
+        damage,
+        accuracy: stat(ACCURACY_STAT).unwrap_or(DEFAULT_ACCURACY) + weapon_stat(ACCURACY_STAT).unwrap_or(0.0),
+        crit_chance: stat(CRIT_CHANCE_STAT).unwrap_or(0.0) + weapon_stat(CRIT_CHANCE_STAT).unwrap_or(0.0),
+        crit_multiplier: weapon_stat(CRIT_MULTIPLIER_STAT)
+            .or_else(|| stat(CRIT_MULTIPLIER_STAT))
+            .unwrap_or(DEFAULT_CRIT_MULTIPLIER),
+    })
+}
+
+// Certain outcomes do not use up a roll, so adding a sure hit leaves other rolls unchanged
+fn roll(rng: &mut SeededRng, chance: f32) -> bool {
+    if chance >= 1.0 {
+        true
+    } else if chance <= 0.0 {
+        false
+    } else {
+        rng.chance(chance)
+    }
+}
+
+fn hit_points(game_state: &GameState, entity: &EntityId) -> CombatResult<i32> {
+    let entity_ref = game_state.get_entity(entity).ok_or_else(|| CombatError::UnknownEntity(entity.clone()))?;
+    match entity_ref.get_stat("hp") {
+        Some(StatValue::Integer(hp)) => Ok(hp),
+        _ => Err(CombatError::NoHitPoints(entity.clone())),
+    }
+}
+
+/// Whether an entity is still in the fight: it exists and is not at 0 hp
+pub fn is_standing(game_state: &GameState, entity: &EntityId) -> bool {
+    match game_state.get_entity(entity) {
+        Some(entity) => !matches!(entity.get_stat("hp"), Some(StatValue::Integer(hp)) if hp <= 0),
+        None => false,
+    }
+}
+
+/// Roll an attack against a defender and apply it, without checking turns or cooldowns.
+///
+/// The hit chance is the attack's accuracy minus the defender's evasion. A hit's damage
+/// packet loses the defender's defense (`damage::resolve_damage`), is multiplied by the
+/// crit multiplier on a critical hit and then by the defender's defenses against each type.
+/// Damage is applied with `GameState::damage_entity`, and the result is added to the
+/// combat log.
+pub fn resolve_attack(game_state: &mut GameState, attacker: &EntityId, defender: &EntityId) -> CombatResult<CombatLogEntry> {
+    if hit_points(game_state, attacker).is_ok_and(|hp| hp <= 0) {
+        return Err(CombatError::Defeated(attacker.clone()));
+    }
+    let attack = build_attack(game_state, attacker)?;
+    if hit_points(game_state, defender)? <= 0 {
+        return Err(CombatError::Defeated(defender.clone()));
+    }
+    for component in &attack.damage.components {
+        lineage(game_state, &component.damage_type)?;
+    }
+    let defender_stat = |key: &str| combat_stat(game_state, defender, key);
+    let hit_chance = (attack.accuracy - defender_stat(EVASION_STAT).unwrap_or(0.0)).clamp(0.0, 1.0);
+    let defense = defender_stat(DEFENSE_STAT).unwrap_or(0.0);
+
+    let outcome = if !roll(&mut game_state.rng, hit_chance) {
+        AttackOutcome::Miss
+    } else if roll(&mut game_state.rng, attack.crit_chance) {
+        AttackOutcome::Critical
+    } else {
+        AttackOutcome::Hit
+    };
+    let damage = match outcome {
+        AttackOutcome::Miss => DamageReport::default(),
+        AttackOutcome::Hit => resolve_damage(game_state, defender, &attack.damage, defense)?,
+        AttackOutcome::Critical => {
+            let mut report = resolve_damage(game_state, defender, &attack.damage, defense)?;
+            report.scale(attack.crit_multiplier);
+            report
+        },
+    };
+    let defeated = damage.total > 0 && game_state.damage_entity(defender, damage.total, Some(attacker.clone()));
+
+    let entry = CombatLogEntry {
+        time: game_state.game_time,
+        round: game_state.combat.encounter.as_ref().map(|encounter| encounter.round),
+        attacker: attacker.clone(),
+        defender: defender.clone(),
+        weapon: attack.weapon,
+        hit_chance,
+        outcome,
+        damage,
+        defeated,
+    };
+    game_state.combat.record(entry.clone());
+    Ok(entry)
+}
+
+/// Real-time attack: resolves it if the attacker's "attack_cooldown" has run out since its
+/// last real-time attack, then starts the cooldown again. Encounter participants act on
+/// their turns instead.
+pub fn attack(game_state: &mut GameState, attacker: &EntityId, defender: &EntityId) -> CombatResult<CombatLogEntry> {
+    if game_state.combat.encounter.as_ref().is_some_and(|encounter| encounter.participants.contains(attacker)) {
+        return Err(CombatError::InEncounter(attacker.clone()));
+    }
+    let key = attacker.to_string();
+    let game_time = game_state.game_time;
+    if let Some(ready_at) = game_state.combat.ready_at.get(&key)
+        && game_time < *ready_at
+    {
+        return Err(CombatError::OnCooldown { entity: attacker.clone(), remaining: ready_at - game_time });
+    }
+    let entry = resolve_attack(game_state, attacker, defender)?;
+    let cooldown = combat_stat(game_state, attacker, ATTACK_COOLDOWN_STAT).unwrap_or(0.0);
+    game_state.combat.ready_at.insert(key, game_time + cooldown);
+    Ok(entry)
+}
+
+fn set_in_combat(game_state: &mut GameState, participants: &[EntityId], in_combat: bool) {
+    for participant in participants {
+        match participant {
+            EntityId::Player if in_combat => game_state.player.enter_context(COMBAT_CONTEXT),
+            EntityId::Player => game_state.player.leave_context(COMBAT_CONTEXT),
+            EntityId::Npc(id) => if let Some(npc) = game_state.get_npc_mut(id) {
+                if in_combat { npc.enter_context(COMBAT_CONTEXT) } else { npc.leave_context(COMBAT_CONTEXT) }
+            },
+        }
+    }
+    game_state.refresh_property_modifiers();
+}
+
+/// Start a turn-based encounter. Participants are ordered by "initiative", highest first
+/// (ties keep the given order), and put in the "combat" context until it ends.
+pub fn start_encounter(game_state: &mut GameState, participants: Vec<EntityId>) -> CombatResult<()> {
+    if game_state.combat.encounter.is_some() {
+        return Err(CombatError::EncounterInProgress);
+    }
+    let mut ordered = Vec::new();
+    for participant in participants {
+        let initiative = game_state.get_entity(&participant)
+            .ok_or_else(|| CombatError::UnknownEntity(participant.clone()))?
+            .get_stat(INITIATIVE_STAT)
+            .and_then(|value| value.as_float())
+            .unwrap_or(0.0);
+        if !ordered.iter().any(|(id, _)| *id == participant) {
+            ordered.push((participant, initiative));
+        }
+    }
+    ordered.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let participants: Vec<EntityId> = ordered.into_iter().map(|(id, _)| id).collect();
+    set_in_combat(game_state, &participants, true);
+    game_state.combat.encounter = Some(Encounter { participants, turn: 0, round: 1 });
+    skip_fallen(game_state);
+    Ok(())
+}
+
+/// End the running encounter and take its participants out of the "combat" context
+pub fn end_encounter(game_state: &mut GameState) -> Option<Encounter> {
+    let encounter = game_state.combat.encounter.take()?;
+    set_in_combat(game_state, &encounter.participants, false);
+    Some(encounter)
+}
+
+// Move past participants that are gone or at 0 hp, ending the encounter once fewer than
+// two are standing
+fn skip_fallen(game_state: &mut GameState) {
+    let Some(encounter) = &game_state.combat.encounter else { return };
+    let standing: Vec<bool> = encounter.participants.iter().map(|id| is_standing(game_state, id)).collect();
+    if standing.iter().filter(|standing| **standing).count() < 2 {
+        end_encounter(game_state);
+        return;
+    }
+    let encounter = game_state.combat.encounter.as_mut().expect("checked above");
+    while !standing[encounter.turn] {
+        advance(encounter);
+    }
+}
+
+fn advance(encounter: &mut Encounter) {
+    encounter.turn += 1;
+    if encounter.turn >= encounter.participants.len() {
+        encounter.turn = 0;
+        encounter.round += 1;
+    }
+}
+
+fn check_turn(game_state: &GameState, actor: &EntityId) -> CombatResult<()> {
+    let encounter = game_state.combat.encounter.as_ref().ok_or(CombatError::NoEncounter)?;
+    let expected = encounter.current().expect("encounters are never empty");
+    if expected != actor {
+        return Err(CombatError::NotYourTurn { expected: expected.clone(), actual: actor.clone() });
+    }
+    Ok(())
+}
+
+/// Attack on the attacker's turn in the running encounter, then pass the turn on.
+/// Cooldowns do not apply.
+pub fn take_turn(game_state: &mut GameState, attacker: &EntityId, defender: &EntityId) -> CombatResult<CombatLogEntry> {
+    check_turn(game_state, attacker)?;
+    let entry = resolve_attack(game_state, attacker, defender)?;
+    end_turn(game_state, attacker)?;
+    Ok(entry)
+}
+
+/// Finish an actor's turn without attacking
+pub fn end_turn(game_state: &mut GameState, actor: &EntityId) -> CombatResult<()> {
+    check_turn(game_state, actor)?;
+    if let Some(encounter) = game_state.combat.encounter.as_mut() {
+        advance(encounter);
+    }
+    skip_fallen(game_state);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+    use crate::property::Property;
+
+    fn arena() -> GameState {
+        let mut game_state = GameState::new();
+        game_state.rng = SeededRng::new(7);
+        game_state.add_damage_type(crate::damage::DamageType::new("fire", "Fire"));
+        game_state.player.set_base_stat("hp", StatValue::Integer(40));
+        game_state.player.set_base_stat(ATTACK_STAT, StatValue::Integer(6));
+        game_state.player.set_base_stat(INITIATIVE_STAT, StatValue::Integer(5));
+        let mut goblin = NPC::new("goblin".to_string(), EntityType::new("goblin", "Goblin"));
+        goblin.set_base_stat("hp", StatValue::Integer(20));
+        goblin.set_base_stat(ATTACK_STAT, StatValue::Integer(2));
+        goblin.set_base_stat(DEFENSE_STAT, StatValue::Integer(1));
+        goblin.set_base_stat("fire_resistance", StatValue::Float(0.5));
+        goblin.set_base_stat(ATTACK_COOLDOWN_STAT, StatValue::Float(1.5));
+        game_state.add_npc(goblin).unwrap();
+        game_state
+    }
+
+    #[test]
+    fn test_weapon_tags_crits_and_resistance() {
+        let mut game_state = arena();
+        let goblin = EntityId::Npc("goblin".to_string());
+        let mut torch = Item::new("torch", "Torch");
+        torch.set_string("type", "weapon".to_string());
+        torch.set_string(DAMAGE_TYPE_STAT, "fire".to_string());
+        torch.set_int("damage", 4);
+        torch.set_bool("equipped", true);
+        game_state.get_npc_mut("goblin").unwrap().inventory.add_item(torch);
+        // Only counts in combat
+        let tag = game_state.tag_collection.add_tag("brute");
+        game_state.tag_collection.get_tag_mut(tag).unwrap().properties.push(
+            Property::stat_modifier(ATTACK_STAT, StatValue::Integer(3)).exclusive_to(COMBAT_CONTEXT));
+        game_state.get_npc_mut("goblin").unwrap().add_instance_tag(tag);
+
+        let attack = build_attack(&game_state, &goblin).unwrap();
+        assert_eq!((attack.weapon.as_deref(), attack.damage), (Some("torch"), DamagePacket::of("fire", 9.0)));
+
+        // Player's fire hits are halved by the goblin: (6 - 1) * 0.5 rounds to 3
+        game_state.player.set_base_stat(DAMAGE_TYPE_STAT, StatValue::String("fire".to_string()));
+        let entry = resolve_attack(&mut game_state, &EntityId::Player, &goblin).unwrap();
+        assert_eq!((entry.outcome, entry.damage.raw(), entry.damage.total), (AttackOutcome::Hit, 5.0, 3));
+        assert_eq!(game_state.get_npc("goblin").unwrap().get_int_stat("hp"), Some(17));
+
+        // Certain crits double the damage; certain misses deal none
+        game_state.player.set_base_stat(CRIT_CHANCE_STAT, StatValue::Float(1.0));
+        assert_eq!(resolve_attack(&mut game_state, &EntityId::Player, &goblin).unwrap().damage.total, 5);
+        game_state.get_npc_mut("goblin").unwrap().set_base_stat(EVASION_STAT, StatValue::Float(1.0));
+        let miss = resolve_attack(&mut game_state, &EntityId::Player, &goblin).unwrap();
+        assert_eq!((miss.outcome, miss.damage.total), (AttackOutcome::Miss, 0));
+        assert_eq!(game_state.combat.log.len(), 3);
+        assert_eq!(game_state.combat.log[1].to_string(), "player critically hits goblin for 5 fire damage");
+
+        // Seeded rolls repeat
+        let rolls = |seed| {
+            let mut game_state = arena();
+            game_state.rng = SeededRng::new(seed);
+            game_state.player.set_base_stat(ACCURACY_STAT, StatValue::Float(0.5));
+            (0..5).map(|_| resolve_attack(&mut game_state, &EntityId::Player, &goblin).unwrap().outcome).collect::<Vec<_>>()
+        };
+        assert_eq!(rolls(3), rolls(3));
+    }
+
+    #[test]
+    fn test_real_time_cooldowns_and_turn_based_encounters() {
+        let mut game_state = arena();
+        let goblin = EntityId::Npc("goblin".to_string());
+
+        // Real time: the goblin waits out its cooldown
+        attack(&mut game_state, &goblin, &EntityId::Player).unwrap();
+        assert!(matches!(attack(&mut game_state, &goblin, &EntityId::Player), Err(CombatError::OnCooldown { .. })));
+        game_state.game_time += 1.5;
+        assert_eq!(attack(&mut game_state, &goblin, &EntityId::Player).unwrap().damage.total, 2);
+        assert_eq!(game_state.player.get_int_stat("hp"), Some(36));
+
+        // Turn-based: the player has the higher initiative, and no cooldowns apply
+        game_state.start_encounter(vec![goblin.clone(), EntityId::Player]).unwrap();
+        assert!(game_state.player.is_in_context(COMBAT_CONTEXT));
+        assert_eq!(game_state.combat.encounter.as_ref().unwrap().current(), Some(&EntityId::Player));
+        assert_eq!(game_state.take_turn(&goblin, &EntityId::Player),
+            Err(CombatError::NotYourTurn { expected: EntityId::Player, actual: goblin.clone() }));
+        assert!(matches!(game_state.attack(&goblin, &EntityId::Player), Err(CombatError::InEncounter(_))));
+        game_state.take_turn(&EntityId::Player, &goblin).unwrap();
+        game_state.take_turn(&goblin, &EntityId::Player).unwrap();
+        game_state.take_turn(&goblin, &EntityId::Player).unwrap_err();
+        assert_eq!(game_state.combat.encounter.as_ref().unwrap().round, 2);
+
+        // Fighting until the goblin falls ends the encounter
+        game_state.player.set_base_stat(ATTACK_STAT, StatValue::Integer(20));
+        let last = game_state.take_turn(&EntityId::Player, &goblin).unwrap();
+        assert_eq!((last.round, last.defeated), (Some(2), true));
+        assert!(game_state.combat.encounter.is_none());
+        assert!(!game_state.player.is_in_context(COMBAT_CONTEXT));
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use crate::character::Character;
+use crate::npc::NPC;
+use crate::coordinates::Coordinates;
+use crate::entity_type::EntityType;
+use crate::inventory::Inventory;
+use crate::ecs::Components;
+use crate::entity::Entity;
+use crate::game_state::{EntityId, GameState};
+use crate::property::{Condition, ConditionType, Property};
+use crate::stats::StatValue;
+use crate::tag::TagCollection;
+
+/// Length of a game day in game-time seconds, used when a TimeOfDay condition has no "day_length"
+pub const DEFAULT_DAY_LENGTH: f32 = 1440.0;
+
+/// A borrowed view of an entity that conditions can be evaluated against
+#[derive(Clone, Copy)]
+pub enum EntityRef<'a> {
+    Character(&'a Character),
+    Npc(&'a NPC),
+}
+
+impl<'a> EntityRef<'a> {
+    /// Position of the entity in the world
+    pub fn position(&self) -> &'a Coordinates {
+        match self {
+            EntityRef::Character(character) => &character.position,
+            EntityRef::Npc(npc) => &npc.position,
+        }
+    }
+
+    /// Calculated stat value (base stats with modifiers applied)
+    pub fn get_stat(&self, key: &str) -> Option<StatValue> {
+        match self {
+            EntityRef::Character(character) => character.get_stat(key),
+            EntityRef::Npc(npc) => npc.get_stat(key),
+        }
+    }
+
+    /// The entity's type (the player's optional character type)
+    pub fn entity_type(&self) -> Option<&'a EntityType> {
+        match self {
+            EntityRef::Character(character) => character.character_type.as_ref(),
+            EntityRef::Npc(npc) => Some(&npc.npc_type),
+        }
+    }
+
+    /// Tags of the entity's type together with its instance tags
+    pub fn tag_ids(&self) -> HashSet<i32> {
+        match self {
+            EntityRef::Character(character) => character.tag_ids(),
+            EntityRef::Npc(npc) => npc.tag_ids(),
+        }
+    }
+
+    /// Check if the entity carries a tag, from its type or as an instance tag
+    pub fn has_tag_id(&self, tag_id: i32) -> bool {
+        match self {
+            EntityRef::Character(character) => character.has_tag_id(tag_id),
+            EntityRef::Npc(npc) => npc.has_tag_id(tag_id),
+        }
+    }
+
+    /// Check if the entity carries a tag or one of its descendants
+    pub fn has_tag_id_in_hierarchy(&self, tag_id: i32, tag_collection: &TagCollection) -> bool {
+        self.tag_ids().into_iter().any(|id| tag_collection.is_a(id, tag_id))
+    }
+
+    /// Contexts the entity is in, oldest first
+    pub fn active_contexts(&self) -> &'a [String] {
+        match self {
+            EntityRef::Character(character) => character.active_contexts.as_slice(),
+            EntityRef::Npc(npc) => npc.active_contexts.as_slice(),
+        }
+    }
+
+    /// Current behavior state, if the entity has one
+    pub fn behavior_state(&self) -> Option<&'a str> {
+        match self {
+            EntityRef::Character(_) => None,
+            EntityRef::Npc(npc) => Some(&npc.behavior_state),
+        }
+    }
+
+    /// Check if the entity is under a status effect
+    pub fn has_status_effect(&self, effect: &str) -> bool {
+        match self {
+            EntityRef::Character(character) => character.has_status_effect(effect),
+            EntityRef::Npc(npc) => npc.has_status_effect(effect),
+        }
+    }
+
+    /// The entity's data as ECS components
+    pub fn components(&self) -> Components<'a> {
+        match self {
+            EntityRef::Character(character) => character.components(),
+            EntityRef::Npc(npc) => npc.components(),
+        }
+    }
+
+    /// The entity's inventory
+    pub fn inventory(&self) -> &'a Inventory {
+        match self {
+            EntityRef::Character(character) => &character.inventory,
+            EntityRef::Npc(npc) => &npc.inventory,
+        }
+    }
+
+    /// Check if the entity carries an item
+    pub fn has_item(&self, item_id: &str) -> bool {
+        self.inventory().has_item(item_id)
+    }
+
+    /// Check if the entity has an item equipped
+    pub fn has_item_equipped(&self, item_id: &str) -> bool {
+        self.inventory().get_item(item_id)
+            .and_then(|item| item.get_bool("equipped"))
+            .unwrap_or(false)
+    }
+}
+
+impl<'a> From<&'a Character> for EntityRef<'a> {
+    fn from(character: &'a Character) -> Self {
+        EntityRef::Character(character)
+    }
+}
+
+impl<'a> From<&'a NPC> for EntityRef<'a> {
+    fn from(npc: &'a NPC) -> Self {
+        EntityRef::Npc(npc)
+    }
+}
+
+/// Handler for `ConditionType::Custom` conditions
+pub type CustomConditionFn = Arc<dyn Fn(EntityRef, &GameState, &Condition) -> bool + Send + Sync>;
+
+/// Decides whether conditions (and the properties that carry them) hold for an entity
+#[derive(Default, Clone)]
+pub struct ConditionEvaluator {
+    custom_conditions: HashMap<String, CustomConditionFn>,
+}
+
+impl ConditionEvaluator {
+    pub fn new() -> Self {
+        ConditionEvaluator {
+            custom_conditions: HashMap::new(),
+        }
+    }
+
+    /// Register a handler for `ConditionType::Custom(name)`, replacing any previous one
+    pub fn register_custom<F>(&mut self, name: &str, handler: F)
+    where F: Fn(EntityRef, &GameState, &Condition) -> bool + Send + Sync + 'static {
+        self.custom_conditions.insert(name.to_string(), Arc::new(handler));
+    }
+
+    /// Remove a custom condition handler
+    pub fn unregister_custom(&mut self, name: &str) -> bool {
+        self.custom_conditions.remove(name).is_some()
+    }
+
+    /// Check if a custom condition handler is registered
+    pub fn has_custom(&self, name: &str) -> bool {
+        self.custom_conditions.contains_key(name)
+    }
+
+    /// Check if a property is active for an entity right now (all of its conditions hold)
+    pub fn is_property_active(&self, entity: EntityRef, game_state: &GameState, property: &Property) -> bool {
+        self.evaluate_all(entity, game_state, &property.conditions)
+    }
+
+    /// Check that every condition holds (an empty list always holds)
+    pub fn evaluate_all(&self, entity: EntityRef, game_state: &GameState, conditions: &[Condition]) -> bool {
+        conditions.iter().all(|condition| self.evaluate(entity, game_state, condition))
+    }
+
+    /// Evaluate a single condition. Missing or mistyped parameters make the condition fail.
+    pub fn evaluate(&self, entity: EntityRef, game_state: &GameState, condition: &Condition) -> bool {
+        match &condition.condition_type {
+            ConditionType::StatThreshold => Self::evaluate_stat_threshold(&entity, condition),
+            ConditionType::HasTag => Self::evaluate_has_tag(&entity, game_state, condition),
+            ConditionType::InState => match condition.get_string("state") {
+                Some(state) => entity.behavior_state() == Some(state) || entity.has_status_effect(state),
+                None => false,
+            },
+            ConditionType::TimeOfDay => Self::evaluate_time_of_day(game_state, condition),
+            ConditionType::Proximity => Self::evaluate_proximity(&entity, game_state, condition),
+            ConditionType::InventoryContains => match condition.get_string("item") {
+                Some(item_id) if condition.get_bool("equipped").unwrap_or(false) => entity.has_item_equipped(item_id),
+                Some(item_id) => entity.has_item(item_id),
+                None => false,
+            },
+            ConditionType::TimeInState => match (condition.get_float("seconds"), entity) {
+                // Entities without a running state machine have no state time
+                (Some(seconds), EntityRef::Npc(npc)) => npc.state_time.is_some_and(|time| time >= seconds),
+                _ => false,
+            },
+            ConditionType::CanSee => match (condition.get_string("target"), entity) {
+                (Some(target), EntityRef::Npc(npc)) => npc.perception.can_see(&EntityId::parse(target)),
+                _ => false,
+            },
+            ConditionType::Remembers => match (condition.get_string("target"), entity) {
+                (Some(target), EntityRef::Npc(npc)) => npc.perception.memory_of(&EntityId::parse(target))
+                    .is_some_and(|memory| condition.get_float("within")
+                        .is_none_or(|seconds| game_state.game_time - memory.time <= seconds)),
+                _ => false,
+            },
+            ConditionType::HostileNearby => condition.get_float("distance").is_some_and(|distance| {
+                !game_state.hostile_entities_within(&entity.entity_id(), distance).is_empty()
+            }),
+            ConditionType::Custom(name) => match self.custom_conditions.get(name) {
+                Some(handler) => handler(entity, game_state, condition),
+                None => false,
+            },
+            ConditionType::All(conditions) => self.evaluate_all(entity, game_state, conditions),
+            ConditionType::Any(conditions) => conditions.iter()
+                .any(|nested| self.evaluate(entity, game_state, nested)),
+            ConditionType::Not(nested) => !self.evaluate(entity, game_state, nested),
+        }
+    }
+
+    /// Compare a stat against the condition's "threshold"
+    pub fn evaluate_stat_threshold<E: Entity + ?Sized>(entity: &E, condition: &Condition) -> bool {
+        let (Some(stat), Some(threshold)) = (condition.get_string("stat"), condition.parameters.get("threshold")) else {
+            return false;
+        };
+        let Some(value) = entity.get_stat(stat) else {
+            return false;
+        };
+        let is_greater_than = condition.get_bool("is_greater_than").unwrap_or(true);
+
+        match (value.as_float(), threshold.as_float()) {
+            (Some(value), Some(threshold)) if is_greater_than => value > threshold,
+            (Some(value), Some(threshold)) => value < threshold,
+            // Non-numeric stats can only be matched exactly
+            _ => match (&value, threshold) {
+                (StatValue::Boolean(a), StatValue::Boolean(b)) => a == b,
+                (StatValue::String(a), StatValue::String(b)) => a == b,
+                _ => false,
+            },
+        }
+    }
+
+    /// Check for the condition's "tag" (by name) or "tag_id"
+    pub fn evaluate_has_tag<E: Entity + ?Sized>(entity: &E, game_state: &GameState, condition: &Condition) -> bool {
+        let tag_id = match condition.get_string("tag") {
+            Some(name) => game_state.tag_collection.get_tag_by_name(name).map(|tag| tag.id),
+            None => match condition.parameters.get("tag_id") {
+                Some(StatValue::Integer(id)) => Some(*id),
+                _ => None,
+            },
+        };
+        // Tags inherited through the hierarchy count unless "exact" is set
+        let Some(tag_id) = tag_id else {
+            return false;
+        };
+        if condition.get_bool("exact").unwrap_or(false) {
+            entity.has_tag_id(tag_id)
+        } else {
+            entity.has_tag_id_in_hierarchy(tag_id, &game_state.tag_collection)
+        }
+    }
+
+    fn evaluate_time_of_day(game_state: &GameState, condition: &Condition) -> bool {
+        let (Some(start), Some(end)) = (condition.get_float("start_hour"), condition.get_float("end_hour")) else {
+            return false;
+        };
+        let day_length = condition.get_float("day_length").unwrap_or(DEFAULT_DAY_LENGTH);
+        let hour = time_of_day(game_state.game_time, day_length);
+
+        if start <= end {
+            hour >= start && hour < end
+        } else {
+            hour >= start || hour < end
+        }
+    }
+
+    /// Check the distance to the condition's "target" ("player" or an NPC ID)
+    pub fn evaluate_proximity<E: Entity + ?Sized>(entity: &E, game_state: &GameState, condition: &Condition) -> bool {
+        let (Some(target), Some(max_distance)) = (condition.get_string("target"), condition.get_float("distance")) else {
+            return false;
+        };
+        let target_position = if target == "player" {
+            Some(&game_state.player.position)
+        } else {
+            game_state.npcs.iter()
+                .find(|npc| npc.id == target)
+                .map(|npc| &npc.position)
+        };
+        let Some(target_position) = target_position else {
+            return false;
+        };
+
+        let distance = entity.position().distance(target_position);
+        if distance.is_nan() {
+            return false; // Positions in different dimensionalities are not comparable
+        }
+
+        if condition.get_bool("within").unwrap_or(true) {
+            distance <= max_distance
+        } else {
+            distance > max_distance
+        }
+    }
+}
+
+/// Convert game time into an hour of the day in [0, 24)
+pub fn time_of_day(game_time: f32, day_length: f32) -> f32 {
+    if day_length <= 0.0 {
+        return 0.0;
+    }
+    game_time.rem_euclid(day_length) / day_length * 24.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::inventory::Item;
+
+    fn goblin(state: &mut GameState) -> NPC {
+        let angry_id = state.tag_collection.add_tag("angry");
+        let goblin_type = EntityType::new("goblin", "Goblin").with_tag_id(angry_id);
+        let mut npc = NPC::new("goblin1".to_string(), goblin_type);
+        npc.set_base_stat("hp", StatValue::Integer(20));
+        npc
+    }
+
+    #[test]
+    fn test_stat_threshold_and_tag_conditions() {
+        let mut state = GameState::new();
+        let npc = goblin(&mut state);
+        let evaluator = ConditionEvaluator::new();
+
+        let low_hp = Property::create_stat_threshold_condition("hp", StatValue::Integer(25), false);
+        let high_hp = Property::create_stat_threshold_condition("hp", StatValue::Integer(25), true);
+        assert!(evaluator.evaluate((&npc).into(), &state, &low_hp));
+        assert!(!evaluator.evaluate((&npc).into(), &state, &high_hp));
+
+        assert!(evaluator.evaluate((&npc).into(), &state, &Property::create_has_tag_condition("angry")));
+        assert!(!evaluator.evaluate((&npc).into(), &state, &Property::create_has_tag_condition("calm")));
+    }
+
+    #[test]
+    fn test_composite_conditions() {
+        let mut state = GameState::new();
+        let npc = goblin(&mut state);
+        let evaluator = ConditionEvaluator::new();
+
+        let angry = Property::create_has_tag_condition("angry");
+        let fleeing = Property::create_in_state_condition("fleeing");
+
+        assert!(!evaluator.evaluate((&npc).into(), &state, &Condition::all(vec![angry.clone(), fleeing.clone()])));
+        assert!(evaluator.evaluate((&npc).into(), &state, &Condition::any(vec![angry.clone(), fleeing.clone()])));
+        assert!(evaluator.evaluate((&npc).into(), &state, &Condition::all(vec![angry, Condition::negate(fleeing)])));
+        assert!(evaluator.evaluate((&npc).into(), &state, &Condition::all(Vec::new())));
+        assert!(!evaluator.evaluate((&npc).into(), &state, &Condition::any(Vec::new())));
+    }
+
+    #[test]
+    fn test_property_activity() {
+        let mut state = GameState::new();
+        let mut npc = goblin(&mut state);
+        npc.set_position(3.0, 4.0);
+
+        let rage = Property::stat_modifier("attack", StatValue::Integer(5))
+            .with_condition(Property::create_proximity_condition("player", 5.0, true))
+            .with_condition(Property::create_stat_threshold_condition("hp", StatValue::Integer(10), true));
+        assert!(state.is_property_active(EntityRef::Npc(&npc), &rage));
+
+        npc.set_position(30.0, 40.0);
+        assert!(!state.is_property_active(EntityRef::Npc(&npc), &rage));
+    }
+
+    #[test]
+    fn test_custom_conditions_and_inventory() {
+        let mut state = GameState::new();
+        state.conditions.register_custom("is_player", |entity, _, _| matches!(entity, EntityRef::Character(_)));
+        state.player.add_item(Item::new("torch", "Torch"));
+
+        let is_player = Condition::custom("is_player");
+        let has_torch = Property::create_inventory_contains_condition("torch");
+        let torch_equipped = has_torch.clone().with_parameter("equipped", StatValue::Boolean(true));
+
+        assert!(state.conditions.evaluate((&state.player).into(), &state, &is_player));
+        assert!(state.conditions.evaluate((&state.player).into(), &state, &has_torch));
+        assert!(!state.conditions.evaluate((&state.player).into(), &state, &torch_equipped));
+
+        // Unknown custom conditions fail closed
+        assert!(!state.conditions.evaluate((&state.player).into(), &state, &Condition::custom("unknown")));
+    }
+
+    #[test]
+    fn test_time_of_day_wraps_midnight() {
+        let mut state = GameState::new();
+        let night = Property::create_time_of_day_condition(22.0, 6.0)
+            .with_parameter("day_length", StatValue::Float(24.0));
+        let evaluator = ConditionEvaluator::new();
+
+        state.game_time = 23.0;
+        assert!(evaluator.evaluate((&state.player).into(), &state, &night));
+        state.game_time = 24.0 + 3.0;
+        assert!(evaluator.evaluate((&state.player).into(), &state, &night));
+        state.game_time = 12.0;
+        assert!(!evaluator.evaluate((&state.player).into(), &state, &night));
+    }
+}
@@ -0,0 +1,1699 @@
+//! Loads game content from a directory of JSON files, so designers can add tags, entity
+//! types, templates and NPCs without touching Rust.
+//!
+//! Every file is an object with any of these sections; references work across files and to
+//! content already in the `GameState`:
+//!
+//! ```json
+//! {
+//!   "tags": [
+//!     { "name": "fire", "parents": ["elemental"], "metadata": { "color": "red" },
+//!       "properties": [
+//!         { "stat": "damage", "value": 5, "contexts": ["combat"] },
+//!         { "reaction": "damaged", "script": "add_stat(self, \"rage\", 1);" }
+//!       ] }
+//!   ],
+//!   "entity_types": [
+//!     { "id": "fire_goblin", "name": "Fire Goblin", "category": "hostile", "tags": ["fire"] },
+//!     { "id": "fire_goblin_chief", "name": "Fire Goblin Chief", "extends": "fire_goblin",
+//!       "stats": { "hp": [20, 30], "speed": 1.2 }, "items": ["torch"], "behavior": "guard",
+//!       "dimensions": 2 }
+//!   ],
+//!   "npc_templates": [
+//!     { "id": "goblin_scout", "type": "fire_goblin", "stats": { "hp": 12, "speed": 1.5 },
+//!       "behavior": "patrol", "tags": ["sneaky"] }
+//!   ],
+//!   "item_templates": [
+//!     { "id": "torch", "name": "Torch", "values": { "type": "weapon", "damage": 2 } }
+//!   ],
+//!   "npcs": [
+//!     { "id": "scout1", "template": "goblin_scout", "position": [4, 2] }
+//!   ],
+//!   "state_machines": [
+//!     { "id": "guard", "initial": "patrol", "states": {
+//!       "patrol": { "update": [{ "script": "add_stat(self, \"steps\", 1);" }],
+//!                   "transitions": [{ "to": "chase", "conditions": [
+//!                     { "type": "proximity", "target": "player", "distance": 5 }] }] },
+//!       "chase": { "enter": [{ "function": "roar" }], "transitions": [{ "to": "patrol",
+//!                  "conditions": [{ "type": "time_in_state", "seconds": 10 }] }] } } }
+//!   ],
+//!   "behavior_trees": [
+//!     { "id": "boss", "root": { "selector": [
+//!       { "sequence": [{ "condition": { "type": "stat_threshold", "stat": "hp", "threshold": 10,
+//!                                       "is_greater_than": false } },
+//!                      { "flee": "player", "distance": 12 }] },
+//!       { "cooldown": 8, "child": { "function": "summon_minions" } },
+//!       { "sequence": [{ "move_toward": "player", "range": 1.5 }, { "attack": "player", "range": 1.5 }] }
+//!     ] } }
+//!   ],
+//!   "factions": [
+//!     { "id": "watch", "name": "City Watch", "relations": { "bandits": "hostile", "merchants": 60 },
+//!       "reputation": [{ "event": "damaged", "amount": -25 },
+//!                      { "event": "bounty_paid", "amount": 10, "filters": { "tier": "gold" } }] }
+//!   ],
+//!   "damage_types": [
+//!     { "id": "elemental", "name": "Elemental" },
+//!     { "id": "fire", "name": "Fire", "parent": "elemental" },
+//!     { "id": "poison", "name": "Poison", "ignores_defense": true }
+//!   ],
+//!   "status_effects": [
+//!     { "id": "poisoned", "name": "Poisoned", "duration": 6, "stacking": "stack", "max_stacks": 3,
+//!       "tick_interval": 1, "tick_damage": { "poison": 2 },
+//!       "modifiers": [{ "stat": "speed", "multiply": 0.8 }],
+//!       "on_expire": [{ "script": "add_stat(self, \"cured\", 1);" }] }
+//!   ]
+//! }
+//! ```
+//!
+//! A property is one of `stat` (with `value`), `ability`, `trigger` or `reaction` (with a
+//! `function` or `script`), or a `type` with one of `function`, `script`, `text`, `asset`,
+//! `flag` or `data`. It may add `contexts` (which scope it to those contexts; list
+//! "default" too to keep it applying everywhere), `exclusive`, `conditions`, `filters` and
+//! `metadata`. A condition is `{ "all": [...] }`, `{ "any": [...] }`, `{ "not": {...} }`,
+//! `{ "custom": "name", ... }` or `{ "type": "stat_threshold", ... }` where the other keys
+//! are its parameters.
+//!
+//! Entity type `stats` are what spawned NPCs start with: a number, boolean or string, or a
+//! `[min, max]` pair rolled for each NPC (integer bounds roll integers). `items` name item
+//! templates. An entity type's `state_machine` names a state machine, whose states have
+//! `enter`, `update` and `exit` actions (each a `function` or a `script`) and `transitions`
+//! taken when their `conditions` hold.
+//!
+//! An entity type's `behavior_tree` names a behavior tree. A node is one key naming its kind:
+//! `sequence`, `selector` or `parallel` (a list of nodes; `parallel` takes a `succeed` count,
+//! all children by default), `inverter` (a node), `cooldown` (seconds, with a `child`),
+//! `repeat` (a count or `null` for forever, with a `child`), `wait` (seconds), `condition`,
+//! `move_toward` and `attack` (a target with a `range`), `flee` (a target with a `distance`),
+//! `set` (a blackboard key with a `value`), `function` or `script`. Targets are `"player"`,
+//! `"target"`, `"nearest_hostile"` or a blackboard key holding an entity ID.
+//!
+//! An entity type's `faction` names a faction. A faction's `relations` give its standing
+//! toward other factions, `"ally"`, `"neutral"`, `"hostile"` or a number from -100 to 100,
+//! and its `reputation` rules change reputations with it when events happen.
+//!
+//! A damage type's `parent` names a broader damage type whose defenses also apply to it.
+//! An item template's `damage_type` value must name a damage type.
+//!
+//! A status effect without a `duration` lasts until it is removed. Its `stacking` is
+//! `"refresh"` (the default), `"extend"`, `"stack"` (up to `max_stacks`) or `"ignore"`. It
+//! deals `tick_damage` every `tick_interval` seconds, and its `modifiers` each `add`,
+//! `multiply` or `set` a stat. `on_apply`, `on_tick` and `on_expire` are actions like a
+//! state's, and `tick_damage` may only use known damage types.
+//!
+//! Loading is all or nothing: every problem is reported with its file and line, and the
+//! game state is only changed when there are none.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::coordinates::Coordinates;
+use crate::entity_type::EntityType;
+use crate::game_state::GameState;
+use crate::inventory::{Item, ItemValue};
+use crate::property::{Condition, ConditionType, Property, PropertyType, PropertyValue, DEFAULT_CONTEXT};
+use crate::stats::StatValue;
+use crate::tag::{TagCollection, TagHierarchyError};
+use crate::tag_merge::{TagMergePolicy, TagMergeReport};
+use crate::template::NpcTemplate;
+use crate::prefab::{resolve_entity_types, EntityTypeError};
+use crate::spawner::{SpawnProfile, StatTemplate};
+use crate::state_machine::{State, StateAction, StateMachine, StateMachineError, Transition};
+use crate::behavior_tree::{BehaviorTree, BtNode, BtTarget};
+use crate::status_effect::{Stacking, StatusEffectDef};
+use crate::calculated_stats::ModifierType;
+use crate::faction::{Attitude, Faction, ReputationRule};
+use crate::damage::DamageType;
+
+/// A problem found while loading content
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentError {
+    pub file: PathBuf,
+    /// 1-based line, or 0 when the problem is not tied to a line
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ContentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}: {}", self.file.display(), self.line, self.message)
+        } else {
+            write!(f, "{}: {}", self.file.display(), self.message)
+        }
+    }
+}
+
+/// Result of loading content: what was added, or every error found
+pub type ContentResult<T> = Result<T, Vec<ContentError>>;
+
+/// What a successful load added to the game state
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContentSummary {
+    pub files: usize,
+    pub tags: usize,
+    pub entity_types: usize,
+    pub npc_templates: usize,
+    pub item_templates: usize,
+    pub npcs: usize,
+    pub state_machines: usize,
+    pub behavior_trees: usize,
+    pub factions: usize,
+    pub damage_types: usize,
+    pub status_effects: usize,
+    /// How the loaded tags were merged into the existing ones
+    pub tag_report: TagMergeReport,
+}
+
+/// Reads content files into a `GameState`
+#[derive(Debug, Clone)]
+pub struct ContentLoader {
+    /// How loaded tags that share a name with existing tags are merged
+    pub merge_policy: TagMergePolicy,
+}
+
+impl Default for ContentLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ContentFile {
+    #[serde(default)]
+    tags: Vec<TagDef>,
+    #[serde(default)]
+    entity_types: Vec<EntityTypeDef>,
+    #[serde(default)]
+    npc_templates: Vec<NpcTemplateDef>,
+    #[serde(default)]
+    item_templates: Vec<ItemTemplateDef>,
+    #[serde(default)]
+    npcs: Vec<NpcDef>,
+    #[serde(default)]
+    state_machines: Vec<StateMachineDef>,
+    #[serde(default)]
+    behavior_trees: Vec<BehaviorTreeDef>,
+    #[serde(default)]
+    factions: Vec<FactionDef>,
+    #[serde(default)]
+    damage_types: Vec<DamageTypeDef>,
+    #[serde(default)]
+    status_effects: Vec<StatusDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TagDef {
+    name: String,
+    #[serde(default)]
+    parents: Vec<String>,
+    #[serde(default)]
+    properties: Vec<PropertyDef>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EntityTypeDef {
+    id: String,
+    name: String,
+    description: Option<String>,
+    category: Option<String>,
+    extends: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    properties: Vec<PropertyDef>,
+    #[serde(default)]
+    stats: HashMap<String, Value>,
+    dimensions: Option<usize>,
+    #[serde(default)]
+    items: Vec<String>,
+    behavior: Option<String>,
+    state_machine: Option<String>,
+    behavior_tree: Option<String>,
+    faction: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NpcTemplateDef {
+    id: String,
+    #[serde(rename = "type")]
+    entity_type: String,
+    #[serde(default)]
+    stats: HashMap<String, Value>,
+    behavior: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    status_effects: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ItemTemplateDef {
+    id: String,
+    name: String,
+    #[serde(default)]
+    values: HashMap<String, Value>,
+    #[serde(default)]
+    properties: Vec<PropertyDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NpcDef {
+    id: String,
+    template: Option<String>,
+    #[serde(rename = "type")]
+    entity_type: Option<String>,
+    #[serde(default)]
+    position: Vec<f32>,
+    #[serde(default)]
+    stats: HashMap<String, Value>,
+    behavior: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PropertyDef {
+    #[serde(rename = "type")]
+    property_type: Option<String>,
+    stat: Option<String>,
+    value: Option<Value>,
+    ability: Option<String>,
+    trigger: Option<String>,
+    reaction: Option<String>,
+    function: Option<String>,
+    script: Option<String>,
+    text: Option<String>,
+    asset: Option<String>,
+    flag: Option<bool>,
+    data: Option<HashMap<String, Value>>,
+    #[serde(default)]
+    contexts: Vec<String>,
+    #[serde(default)]
+    exclusive: bool,
+    #[serde(default)]
+    conditions: Vec<Value>,
+    #[serde(default)]
+    filters: HashMap<String, String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateMachineDef {
+    id: String,
+    initial: String,
+    states: BTreeMap<String, StateDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateDef {
+    #[serde(default)]
+    enter: Vec<ActionDef>,
+    #[serde(default)]
+    update: Vec<ActionDef>,
+    #[serde(default)]
+    exit: Vec<ActionDef>,
+    #[serde(default)]
+    transitions: Vec<TransitionDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ActionDef {
+    function: Option<String>,
+    script: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransitionDef {
+    to: String,
+    #[serde(default)]
+    conditions: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FactionDef {
+    id: String,
+    name: String,
+    #[serde(default)]
+    relations: BTreeMap<String, Value>,
+    #[serde(default)]
+    reputation: Vec<ReputationRuleDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReputationRuleDef {
+    event: String,
+    amount: f32,
+    #[serde(default)]
+    filters: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DamageTypeDef {
+    id: String,
+    name: String,
+    parent: Option<String>,
+    #[serde(default)]
+    ignores_defense: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatusDef {
+    id: String,
+    name: String,
+    duration: Option<f32>,
+    stacking: Option<String>,
+    max_stacks: Option<u32>,
+    #[serde(default)]
+    tick_interval: f32,
+    #[serde(default)]
+    tick_damage: BTreeMap<String, f32>,
+    #[serde(default)]
+    modifiers: Vec<StatusModifierDef>,
+    #[serde(default)]
+    on_apply: Vec<ActionDef>,
+    #[serde(default)]
+    on_tick: Vec<ActionDef>,
+    #[serde(default)]
+    on_expire: Vec<ActionDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatusModifierDef {
+    stat: String,
+    add: Option<Value>,
+    multiply: Option<f32>,
+    set: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BehaviorTreeDef {
+    id: String,
+    root: Value,
+}
+
+// A definition with the file and byte range it came from
+struct Loc<T> {
+    file: usize,
+    span: Option<(usize, usize)>,
+    def: T,
+}
+
+// All definitions of a load, before anything is resolved
+struct Pack {
+    files: Vec<(PathBuf, String)>,
+    tags: Vec<Loc<TagDef>>,
+    entity_types: Vec<Loc<EntityTypeDef>>,
+    npc_templates: Vec<Loc<NpcTemplateDef>>,
+    item_templates: Vec<Loc<ItemTemplateDef>>,
+    npcs: Vec<Loc<NpcDef>>,
+    state_machines: Vec<Loc<StateMachineDef>>,
+    behavior_trees: Vec<Loc<BehaviorTreeDef>>,
+    factions: Vec<Loc<FactionDef>>,
+    damage_types: Vec<Loc<DamageTypeDef>>,
+    status_effects: Vec<Loc<StatusDef>>,
+}
+
+impl Pack {
+    // Error on a definition, pointing at `needle` (a quoted string inside it) when given
+    fn error<T>(&self, loc: &Loc<T>, needle: Option<&str>, message: String) -> ContentError {
+        let (path, source) = &self.files[loc.file];
+        let line = match loc.span {
+            Some((start, end)) => {
+                let quoted = needle.map(|n| format!("\"{}\"", n));
+                let offset = quoted
+                    .and_then(|q| source[start..end].find(&q))
+                    .map(|found| start + found)
+                    .unwrap_or(start);
+                line_at(source, offset)
+            },
+            None => 0,
+        };
+        ContentError { file: path.clone(), line, message }
+    }
+}
+
+impl ContentLoader {
+    /// Loader that overwrites existing tags with loaded ones of the same name
+    pub fn new() -> Self {
+        ContentLoader { merge_policy: TagMergePolicy::Overwrite }
+    }
+
+    pub fn with_merge_policy(mut self, policy: TagMergePolicy) -> Self {
+        self.merge_policy = policy;
+        self
+    }
+
+    /// Load every `.json` file under a directory (recursively, in path order)
+    pub fn load_dir<P: AsRef<Path>>(&self, dir: P, game_state: &mut GameState) -> ContentResult<ContentSummary> {
+        let mut paths = Vec::new();
+        collect_json_files(dir.as_ref(), &mut paths).map_err(|error| vec![ContentError {
+            file: dir.as_ref().to_path_buf(),
+            line: 0,
+            message: error.to_string(),
+        }])?;
+        paths.sort();
+
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        for path in paths {
+            match std::fs::read_to_string(&path) {
+                Ok(source) => files.push((path, source)),
+                Err(error) => errors.push(ContentError { file: path, line: 0, message: error.to_string() }),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.load_sources(files, game_state)
+    }
+
+    /// Load content from in-memory sources, each named by the path used in errors
+    pub fn load_sources(&self, files: Vec<(PathBuf, String)>, game_state: &mut GameState) -> ContentResult<ContentSummary> {
+        let pack = parse_files(files)?;
+        let mut errors = Vec::new();
+        check_duplicates(&pack, game_state, &mut errors);
+
+        // Tags go into their own collection first, then get merged into a copy of the game's
+        // tags so nothing changes until everything checks out
+        let mut incoming = TagCollection::new();
+        let mut incoming_ids = HashMap::new();
+        for loc in &pack.tags {
+            let id = incoming.add_tag(&loc.def.name);
+            incoming_ids.insert(loc.def.name.clone(), id);
+            let properties = convert_properties(&pack, loc, &loc.def.properties, &mut errors);
+            let tag = incoming.get_tag_mut(id).expect("tag was just added");
+            tag.properties = properties;
+            tag.metadata = loc.def.metadata.clone();
+        }
+        let mut external_parents = Vec::new();
+        for loc in &pack.tags {
+            for parent in &loc.def.parents {
+                if let Some(&parent_id) = incoming_ids.get(parent) {
+                    let child = incoming.get_tag_mut(incoming_ids[&loc.def.name]).expect("tag was added");
+                    if !child.parent_ids.contains(&parent_id) {
+                        child.parent_ids.push(parent_id);
+                    }
+                } else if let Some(existing) = game_state.tag_collection.get_tag_by_name(parent) {
+                    external_parents.push((loc, existing.id));
+                } else {
+                    errors.push(pack.error(loc, Some(parent), format!("tag '{}' has unknown parent '{}'", loc.def.name, parent)));
+                }
+            }
+        }
+
+        let mut tags = game_state.tag_collection.clone();
+        let tag_report = match tags.merge(&incoming, self.merge_policy) {
+            Ok(report) => {
+                for (loc, parent_id) in external_parents {
+                    let child_id = report.remap(incoming_ids[&loc.def.name]).expect("every loaded tag is mapped");
+                    if let Err(error) = tags.add_parent(child_id, parent_id) {
+                        errors.push(pack.error(loc, None, error.to_string()));
+                    }
+                }
+                report
+            },
+            Err(error) => {
+                errors.push(hierarchy_error(&pack, &error));
+                TagMergeReport::default()
+            },
+        };
+
+        // Tag names as loaded content sees them: its own tags first, then existing ones
+        let resolve_tag = |name: &str| -> Option<i32> {
+            match incoming_ids.get(name) {
+                Some(&id) => tag_report.remap(id),
+                None => tags.get_tag_by_name(name).map(|tag| tag.id),
+            }
+        };
+
+        let mut state_machines = Vec::new();
+        for loc in &pack.state_machines {
+            if let Some(machine) = convert_state_machine(&pack, loc, &mut errors) {
+                state_machines.push(machine);
+            }
+        }
+        let machine_exists = |id: &str| pack.state_machines.iter().any(|l| l.def.id == id) || game_state.state_machines.contains_key(id);
+
+        let mut behavior_trees = Vec::new();
+        for loc in &pack.behavior_trees {
+            match parse_bt_node(&loc.def.root) {
+                Ok(root) => behavior_trees.push(BehaviorTree::new(&loc.def.id, root)),
+                Err(message) => errors.push(pack.error(loc, None, format!("behavior tree '{}': {}", loc.def.id, message))),
+            }
+        }
+        let tree_exists = |id: &str| pack.behavior_trees.iter().any(|l| l.def.id == id) || game_state.behavior_trees.contains_key(id);
+
+        let faction_exists = |id: &str| pack.factions.iter().any(|l| l.def.id == id) || game_state.factions.get(id).is_some();
+        let mut factions = Vec::new();
+        for loc in &pack.factions {
+            let def = &loc.def;
+            let mut faction = Faction::new(&def.id, &def.name);
+            for (other, relation) in &def.relations {
+                if !faction_exists(other) {
+                    errors.push(pack.error(loc, Some(other), format!("faction '{}' has a relation to unknown faction '{}'", def.id, other)));
+                }
+                let standing = match relation {
+                    Value::String(attitude) => Attitude::parse(attitude).map(Attitude::standing),
+                    value => value.as_f64().map(|standing| standing as f32),
+                };
+                match standing {
+                    Some(standing) => faction = faction.with_relation(other, standing),
+                    None => errors.push(pack.error(loc, Some(other), format!(
+                        "faction '{}' relation to '{}' must be \"ally\", \"neutral\", \"hostile\" or a number", def.id, other))),
+                }
+            }
+            for rule in &def.reputation {
+                faction.reputation_rules.push(ReputationRule { event: rule.event.clone(), amount: rule.amount, filters: rule.filters.clone() });
+            }
+            factions.push(faction);
+        }
+
+        let damage_type_exists = |id: &str| pack.damage_types.iter().any(|l| l.def.id == id) || game_state.damage_types.contains_key(id);
+        let mut damage_types = Vec::new();
+        for loc in &pack.damage_types {
+            let def = &loc.def;
+            let mut damage_type = DamageType::new(&def.id, &def.name).with_ignores_defense(def.ignores_defense);
+            if let Some(parent) = &def.parent {
+                if !damage_type_exists(parent) {
+                    errors.push(pack.error(loc, Some(parent), format!("damage type '{}' has unknown parent '{}'", def.id, parent)));
+                }
+                damage_type = damage_type.with_parent(parent);
+            }
+            damage_types.push(damage_type);
+        }
+
+        let mut status_effects = Vec::new();
+        for loc in &pack.status_effects {
+            for damage_type in loc.def.tick_damage.keys() {
+                if !damage_type_exists(damage_type) {
+                    errors.push(pack.error(loc, Some(damage_type), format!("status effect '{}' has unknown damage type '{}'", loc.def.id, damage_type)));
+                }
+            }
+            status_effects.push(convert_status_effect(&pack, loc, &mut errors));
+        }
+
+        let mut entity_types = Vec::new();
+        for loc in &pack.entity_types {
+            let def = &loc.def;
+            let mut entity_type = EntityType::new(&def.id, &def.name);
+            entity_type.description = def.description.clone();
+            entity_type.category = def.category.clone();
+            entity_type.extends = def.extends.clone();
+            for name in &def.tags {
+                match resolve_tag(name) {
+                    Some(id) => { entity_type.tag_ids.insert(id); },
+                    None => errors.push(pack.error(loc, Some(name), format!("entity type '{}' has unknown tag '{}'", def.id, name))),
+                }
+            }
+            entity_type.properties = convert_properties(&pack, loc, &def.properties, &mut errors);
+            entity_type.spawn = SpawnProfile {
+                stats: convert_stat_templates(&pack, loc, &def.stats, &mut errors),
+                dimensions: def.dimensions,
+                items: def.items.clone(),
+                behavior: def.behavior.clone(),
+            };
+            if let Some(machine_id) = &def.state_machine
+                && !machine_exists(machine_id)
+            {
+                errors.push(pack.error(loc, Some(machine_id), format!("entity type '{}' has unknown state machine '{}'", def.id, machine_id)));
+            }
+            entity_type.state_machine = def.state_machine.clone();
+            if let Some(tree_id) = &def.behavior_tree
+                && !tree_exists(tree_id)
+            {
+                errors.push(pack.error(loc, Some(tree_id), format!("entity type '{}' has unknown behavior tree '{}'", def.id, tree_id)));
+            }
+            entity_type.behavior_tree = def.behavior_tree.clone();
+            if let Some(faction_id) = &def.faction
+                && !faction_exists(faction_id)
+            {
+                errors.push(pack.error(loc, Some(faction_id), format!("entity type '{}' has unknown faction '{}'", def.id, faction_id)));
+            }
+            entity_type.faction = def.faction.clone();
+            entity_types.push(entity_type);
+        }
+        let type_exists = |id: &str| entity_types.iter().any(|t| t.id == id) || game_state.entity_types.contains_key(id);
+        let entity_type_count = entity_types.len();
+
+        // Resolve inheritance over the existing types and the loaded ones together
+        let mut resolved_types = game_state.entity_types.clone();
+        for entity_type in &entity_types {
+            resolved_types.insert(entity_type.id.clone(), entity_type.clone());
+        }
+        if let Err(error) = resolve_entity_types(&mut resolved_types) {
+            errors.push(inheritance_error(&pack, &error));
+        }
+
+        let mut item_templates = Vec::new();
+        for loc in &pack.item_templates {
+            let def = &loc.def;
+            let mut item = Item::new(&def.id, &def.name);
+            let mut keys: Vec<&String> = def.values.keys().collect();
+            keys.sort();
+            for key in keys {
+                match json_to_item_value(&def.values[key]) {
+                    Some(value) => item.set(key, value),
+                    None => errors.push(pack.error(loc, Some(key), format!("item value '{}' must be a number, boolean or string", key))),
+                }
+            }
+            if let Some(ItemValue::String(damage_type)) = item.get(crate::combat::DAMAGE_TYPE_STAT)
+                && !damage_type_exists(damage_type)
+            {
+                errors.push(pack.error(loc, Some(damage_type), format!("item template '{}' has unknown damage type '{}'", def.id, damage_type)));
+            }
+            for property in convert_properties(&pack, loc, &def.properties, &mut errors) {
+                item.attach_property(property);
+            }
+            item_templates.push(item);
+        }
+
+        for loc in &pack.entity_types {
+            for item_id in &loc.def.items {
+                let known = item_templates.iter().any(|item| item.id() == item_id) || game_state.item_templates.contains_key(item_id);
+                if !known {
+                    errors.push(pack.error(loc, Some(item_id), format!("entity type '{}' has unknown item '{}'", loc.def.id, item_id)));
+                }
+            }
+        }
+
+        let mut npc_templates = Vec::new();
+        for loc in &pack.npc_templates {
+            let def = &loc.def;
+            if !type_exists(&def.entity_type) {
+                errors.push(pack.error(loc, Some(&def.entity_type), format!("NPC template '{}' has unknown type '{}'", def.id, def.entity_type)));
+            }
+            let mut template = NpcTemplate::new(&def.id, &def.entity_type);
+            template.stats = convert_stats(&pack, loc, &def.stats, &mut errors);
+            template.behavior_state = def.behavior.clone();
+            template.status_effects = def.status_effects.clone();
+            for name in &def.tags {
+                match resolve_tag(name) {
+                    Some(id) => template.instance_tags.push(id),
+                    None => errors.push(pack.error(loc, Some(name), format!("NPC template '{}' has unknown tag '{}'", def.id, name))),
+                }
+            }
+            npc_templates.push(template);
+        }
+
+        for loc in &pack.npcs {
+            let def = &loc.def;
+            match (&def.template, &def.entity_type) {
+                (Some(template), None) => {
+                    let known = npc_templates.iter().any(|t| &t.id == template) || game_state.npc_templates.contains_key(template);
+                    if !known {
+                        errors.push(pack.error(loc, Some(template), format!("NPC '{}' has unknown template '{}'", def.id, template)));
+                    }
+                },
+                (None, Some(entity_type)) => {
+                    if !type_exists(entity_type) {
+                        errors.push(pack.error(loc, Some(entity_type), format!("NPC '{}' has unknown type '{}'", def.id, entity_type)));
+                    }
+                },
+                _ => errors.push(pack.error(loc, None, format!("NPC '{}' needs either a template or a type", def.id))),
+            }
+        }
+        let npc_stats: Vec<HashMap<String, StatValue>> = pack.npcs.iter()
+            .map(|loc| convert_stats(&pack, loc, &loc.def.stats, &mut errors))
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        // Everything resolved: apply
+        let summary = ContentSummary {
+            files: pack.files.len(),
+            tags: pack.tags.len(),
+            entity_types: entity_type_count,
+            npc_templates: npc_templates.len(),
+            item_templates: item_templates.len(),
+            npcs: pack.npcs.len(),
+            state_machines: state_machines.len(),
+            behavior_trees: behavior_trees.len(),
+            factions: factions.len(),
+            damage_types: damage_types.len(),
+            status_effects: status_effects.len(),
+            tag_report,
+        };
+        game_state.tag_collection = tags;
+        for machine in state_machines {
+            game_state.state_machines.insert(machine.id.clone(), machine);
+        }
+        for tree in behavior_trees {
+            game_state.add_behavior_tree(tree);
+        }
+        for faction in factions {
+            game_state.add_faction(faction);
+        }
+        for damage_type in damage_types {
+            game_state.add_damage_type(damage_type);
+        }
+        for effect in status_effects {
+            game_state.add_status_effect(effect);
+        }
+        let mut type_ids: Vec<String> = resolved_types.keys().cloned().collect();
+        type_ids.sort();
+        for id in type_ids {
+            let entity_type = resolved_types.remove(&id).expect("ID was taken from the map");
+            game_state.add_entity_type(entity_type);
+        }
+        for item in item_templates {
+            game_state.item_templates.insert(item.id().to_string(), item);
+        }
+        for template in npc_templates {
+            game_state.npc_templates.insert(template.id.clone(), template);
+        }
+        for (loc, stats) in pack.npcs.iter().zip(npc_stats) {
+            let def = &loc.def;
+            let position = if def.position.is_empty() {
+                Coordinates::new_2d(0.0, 0.0)
+            } else {
+                Coordinates::from_values(def.position.clone())
+            };
+            let npc = match (&def.template, &def.entity_type) {
+                (Some(template), _) => game_state.npc_from_template(template, &def.id, position),
+                (None, Some(entity_type)) => game_state.entity_types.get(entity_type).cloned().map(|entity_type| {
+                    crate::spawner::build_npc(game_state, &entity_type, &def.id, position)
+                }),
+                (None, None) => None,
+            };
+            let Some(mut npc) = npc else { continue };
+            for (key, value) in stats {
+                npc.set_base_stat(&key, value);
+            }
+            if let Some(behavior) = &def.behavior {
+                npc.behavior_state = behavior.clone();
+            }
+            game_state.add_npc(npc).expect("NPC IDs were checked for duplicates");
+        }
+
+        Ok(summary)
+    }
+}
+
+fn collect_json_files(dir: &Path, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_json_files(&path, paths)?;
+        } else if path.extension().is_some_and(|ext| ext == "json") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn parse_files(files: Vec<(PathBuf, String)>) -> ContentResult<Pack> {
+    let mut pack = Pack {
+        files: Vec::new(),
+        tags: Vec::new(),
+        entity_types: Vec::new(),
+        npc_templates: Vec::new(),
+        item_templates: Vec::new(),
+        npcs: Vec::new(),
+        state_machines: Vec::new(),
+        behavior_trees: Vec::new(),
+        factions: Vec::new(),
+        damage_types: Vec::new(),
+        status_effects: Vec::new(),
+    };
+    let mut errors = Vec::new();
+
+    for (file, (path, source)) in files.into_iter().enumerate() {
+        match serde_json::from_str::<ContentFile>(&source) {
+            Ok(content) => {
+                let spans = element_spans(&source);
+                pack.tags.extend(locate(content.tags, file, spans.get("tags")));
+                pack.entity_types.extend(locate(content.entity_types, file, spans.get("entity_types")));
+                pack.npc_templates.extend(locate(content.npc_templates, file, spans.get("npc_templates")));
+                pack.item_templates.extend(locate(content.item_templates, file, spans.get("item_templates")));
+                pack.npcs.extend(locate(content.npcs, file, spans.get("npcs")));
+                pack.state_machines.extend(locate(content.state_machines, file, spans.get("state_machines")));
+                pack.behavior_trees.extend(locate(content.behavior_trees, file, spans.get("behavior_trees")));
+                pack.factions.extend(locate(content.factions, file, spans.get("factions")));
+                pack.damage_types.extend(locate(content.damage_types, file, spans.get("damage_types")));
+                pack.status_effects.extend(locate(content.status_effects, file, spans.get("status_effects")));
+            },
+            Err(error) => {
+                // serde_json appends the position to its message; we report it separately
+                let message = error.to_string();
+                let suffix = format!(" at line {} column {}", error.line(), error.column());
+                let message = message.strip_suffix(&suffix).unwrap_or(&message).to_string();
+                errors.push(ContentError { file: path.clone(), line: error.line(), message });
+            },
+        }
+        pack.files.push((path, source));
+    }
+
+    if errors.is_empty() { Ok(pack) } else { Err(errors) }
+}
+
+fn locate<T>(defs: Vec<T>, file: usize, spans: Option<&Vec<(usize, usize)>>) -> impl Iterator<Item = Loc<T>> {
+    defs.into_iter().enumerate().map(move |(index, def)| Loc {
+        file,
+        span: spans.and_then(|spans| spans.get(index)).copied(),
+        def,
+    })
+}
+
+// Byte ranges of the objects in each top-level array, e.g. every entry of "tags"
+fn element_spans(source: &str) -> HashMap<String, Vec<(usize, usize)>> {
+    let bytes = source.as_bytes();
+    let mut spans: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+    let mut depth = 0usize;
+    let mut last_string = None;
+    let mut key = None;
+    let mut section: Option<String> = None;
+    let mut element_start = None;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let start = i + 1;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                if depth == 1 {
+                    last_string = Some(source[start..i.min(bytes.len())].to_string());
+                }
+            },
+            b':' if depth == 1 => key = last_string.take(),
+            b',' if depth == 1 => key = None,
+            open @ (b'{' | b'[') => {
+                depth += 1;
+                if depth == 2 {
+                    section = if open == b'[' { key.take() } else { None };
+                } else if depth == 3 && section.is_some() {
+                    element_start = Some(i);
+                }
+            },
+            b'}' | b']' => {
+                if depth == 3
+                    && let (Some(section), Some(start)) = (&section, element_start.take())
+                {
+                    spans.entry(section.clone()).or_default().push((start, i + 1));
+                }
+                if depth == 2 {
+                    section = None;
+                }
+                depth = depth.saturating_sub(1);
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+    spans
+}
+
+fn line_at(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+fn check_duplicates(pack: &Pack, game_state: &GameState, errors: &mut Vec<ContentError>) {
+    fn check<T>(pack: &Pack, locs: &[Loc<T>], what: &str, key: impl Fn(&T) -> &str, errors: &mut Vec<ContentError>) {
+        let mut seen = HashSet::new();
+        for loc in locs {
+            let name = key(&loc.def);
+            if !seen.insert(name) {
+                errors.push(pack.error(loc, Some(name), format!("{} '{}' is defined more than once", what, name)));
+            }
+        }
+    }
+    check(pack, &pack.tags, "tag", |d| &d.name, errors);
+    check(pack, &pack.entity_types, "entity type", |d| &d.id, errors);
+    check(pack, &pack.npc_templates, "NPC template", |d| &d.id, errors);
+    check(pack, &pack.item_templates, "item template", |d| &d.id, errors);
+    check(pack, &pack.npcs, "NPC", |d| &d.id, errors);
+    check(pack, &pack.state_machines, "state machine", |d| &d.id, errors);
+    check(pack, &pack.behavior_trees, "behavior tree", |d| &d.id, errors);
+    check(pack, &pack.factions, "faction", |d| &d.id, errors);
+    check(pack, &pack.damage_types, "damage type", |d| &d.id, errors);
+    check(pack, &pack.status_effects, "status effect", |d| &d.id, errors);
+
+    for loc in &pack.npcs {
+        if game_state.get_npc(&loc.def.id).is_some() {
+            errors.push(pack.error(loc, Some(&loc.def.id), format!("NPC '{}' already exists", loc.def.id)));
+        }
+    }
+}
+
+fn hierarchy_error(pack: &Pack, error: &TagHierarchyError) -> ContentError {
+    // Point at the first loaded tag in the cycle
+    let name = match error {
+        TagHierarchyError::Cycle(names) => names.iter().find(|n| pack.tags.iter().any(|l| &l.def.name == *n)).cloned(),
+        TagHierarchyError::SelfParent(name) => Some(name.clone()),
+        TagHierarchyError::UnknownTag(_) => None,
+    };
+    match name.and_then(|name| pack.tags.iter().find(|l| l.def.name == name)) {
+        Some(loc) => pack.error(loc, None, error.to_string()),
+        None => ContentError { file: pack.files[0].0.clone(), line: 0, message: error.to_string() },
+    }
+}
+
+fn inheritance_error(pack: &Pack, error: &EntityTypeError) -> ContentError {
+    let (type_ids, needle) = match error {
+        EntityTypeError::UnknownBase { type_id, base } => (vec![type_id.clone()], Some(base.as_str())),
+        EntityTypeError::Cycle(ids) => (ids.clone(), None),
+    };
+    // Point at the first loaded type involved
+    let loc = type_ids.iter().find_map(|id| pack.entity_types.iter().find(|l| &l.def.id == id));
+    match loc {
+        Some(loc) => pack.error(loc, needle, error.to_string()),
+        None => ContentError { file: pack.files[0].0.clone(), line: 0, message: error.to_string() },
+    }
+}
+
+fn convert_properties<T>(pack: &Pack, loc: &Loc<T>, defs: &[PropertyDef], errors: &mut Vec<ContentError>) -> Vec<Property> {
+    defs.iter()
+        .filter_map(|def| match convert_property(def) {
+            Ok(property) => Some(property),
+            Err((needle, message)) => {
+                errors.push(pack.error(loc, needle.as_deref(), message));
+                None
+            },
+        })
+        .collect()
+}
+
+fn convert_actions<T>(pack: &Pack, loc: &Loc<T>, owner: &str, defs: &[ActionDef], errors: &mut Vec<ContentError>) -> Vec<StateAction> {
+    defs.iter()
+        .filter_map(|action| match (&action.function, &action.script) {
+            (Some(function), None) => Some(StateAction::Function(function.clone())),
+            (None, Some(script)) => Some(StateAction::Script(script.clone())),
+            _ => {
+                errors.push(pack.error(loc, None, format!("{} has an action without exactly one of function or script", owner)));
+                None
+            },
+        })
+        .collect()
+}
+
+fn convert_status_effect(pack: &Pack, loc: &Loc<StatusDef>, errors: &mut Vec<ContentError>) -> StatusEffectDef {
+    let def = &loc.def;
+    let owner = format!("status effect '{}'", def.id);
+    let mut effect = StatusEffectDef::new(&def.id, &def.name).with_tick_interval(def.tick_interval);
+    effect.duration = def.duration;
+    if let Some(stacking) = &def.stacking {
+        match Stacking::parse(stacking) {
+            Some(stacking) => effect = effect.with_stacking(stacking, def.max_stacks.unwrap_or(1)),
+            None => errors.push(pack.error(loc, Some(stacking), format!(
+                "{} stacking must be \"refresh\", \"extend\", \"stack\" or \"ignore\"", owner))),
+        }
+    }
+    for (damage_type, amount) in &def.tick_damage {
+        effect = effect.with_tick_damage(damage_type, *amount);
+    }
+    for modifier in &def.modifiers {
+        let converted = match (&modifier.add, modifier.multiply, &modifier.set) {
+            (Some(value), None, None) => json_to_stat(value).map(|value| (ModifierType::Additive, value)),
+            (None, Some(factor), None) => Some((ModifierType::Multiplicative, StatValue::Float(factor))),
+            (None, None, Some(value)) => json_to_stat(value).map(|value| (ModifierType::Override, value)),
+            _ => None,
+        };
+        match converted {
+            Some((modifier_type, value)) => effect = effect.with_modifier(&modifier.stat, modifier_type, value),
+            None => errors.push(pack.error(loc, Some(&modifier.stat), format!(
+                "{} modifier for '{}' needs exactly one of add, multiply or set", owner, modifier.stat))),
+        }
+    }
+    effect.on_apply = convert_actions(pack, loc, &owner, &def.on_apply, errors);
+    effect.on_tick = convert_actions(pack, loc, &owner, &def.on_tick, errors);
+    effect.on_expire = convert_actions(pack, loc, &owner, &def.on_expire, errors);
+    effect
+}
+
+fn convert_state_machine(pack: &Pack, loc: &Loc<StateMachineDef>, errors: &mut Vec<ContentError>) -> Option<StateMachine> {
+    let def = &loc.def;
+    let mut machine = StateMachine::new(&def.id, &def.initial);
+    let error_count = errors.len();
+    let owner = format!("state machine '{}'", def.id);
+    let convert_actions = |defs: &[ActionDef], errors: &mut Vec<ContentError>| convert_actions(pack, loc, &owner, defs, errors);
+    for (name, state_def) in &def.states {
+        let mut state = State {
+            on_enter: convert_actions(&state_def.enter, errors),
+            on_update: convert_actions(&state_def.update, errors),
+            on_exit: convert_actions(&state_def.exit, errors),
+            transitions: Vec::new(),
+        };
+        for transition_def in &state_def.transitions {
+            let mut transition = Transition::new(&transition_def.to);
+            for value in &transition_def.conditions {
+                match parse_condition(value) {
+                    Ok(condition) => transition.conditions.push(condition),
+                    Err(message) => errors.push(pack.error(loc, Some(&transition_def.to), message)),
+                }
+            }
+            state.transitions.push(transition);
+        }
+        machine.states.insert(name.clone(), state);
+    }
+    if let Err(error) = machine.validate() {
+        let needle = match &error {
+            StateMachineError::UnknownState { state, .. } => Some(state.as_str()),
+            _ => None,
+        };
+        errors.push(pack.error(loc, needle, error.to_string()));
+    }
+    (errors.len() == error_count).then_some(machine)
+}
+
+fn convert_stats<T>(pack: &Pack, loc: &Loc<T>, stats: &HashMap<String, Value>, errors: &mut Vec<ContentError>) -> HashMap<String, StatValue> {
+    let mut converted = HashMap::new();
+    for (key, value) in stats {
+        match json_to_stat(value) {
+            Some(value) => { converted.insert(key.clone(), value); },
+            None => errors.push(pack.error(loc, Some(key), format!("stat '{}' must be a number, boolean or string", key))),
+        }
+    }
+    converted
+}
+
+fn convert_stat_templates<T>(pack: &Pack, loc: &Loc<T>, stats: &HashMap<String, Value>, errors: &mut Vec<ContentError>) -> BTreeMap<String, StatTemplate> {
+    let mut converted = BTreeMap::new();
+    for (key, value) in stats {
+        let template = match value {
+            Value::Array(bounds) => match bounds.as_slice() {
+                [min, max] => match (json_to_stat(min), json_to_stat(max)) {
+                    (Some(StatValue::Integer(min)), Some(StatValue::Integer(max))) => Some(StatTemplate::IntRange(min, max)),
+                    (Some(min), Some(max)) => min.as_float().zip(max.as_float())
+                        .map(|(min, max)| StatTemplate::FloatRange(min, max)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            value => json_to_stat(value).map(StatTemplate::Fixed),
+        };
+        match template {
+            Some(template) => { converted.insert(key.clone(), template); },
+            None => errors.push(pack.error(loc, Some(key), format!("stat '{}' must be a number, boolean, string or [min, max]", key))),
+        }
+    }
+    converted
+}
+
+// Errors carry a string to point at, if there is a good one
+type PropertyError = (Option<String>, String);
+
+fn convert_property(def: &PropertyDef) -> Result<Property, PropertyError> {
+    let kinds = [def.stat.is_some(), def.ability.is_some(), def.trigger.is_some(), def.reaction.is_some(), def.property_type.is_some()];
+    if kinds.iter().filter(|k| **k).count() != 1 {
+        return Err((None, "a property needs exactly one of stat, ability, trigger, reaction or type".to_string()));
+    }
+
+    let action = || -> Result<Option<PropertyValue>, PropertyError> {
+        let values = [
+            def.function.clone().map(PropertyValue::Function),
+            def.script.clone().map(PropertyValue::Script),
+            def.text.clone().map(PropertyValue::Text),
+            def.asset.clone().map(PropertyValue::Asset),
+            def.flag.map(PropertyValue::Flag),
+        ];
+        let mut values: Vec<PropertyValue> = values.into_iter().flatten().collect();
+        if let Some(data) = &def.data {
+            let mut converted = HashMap::new();
+            for (key, value) in data {
+                let value = json_to_stat(value)
+                    .ok_or_else(|| (Some(key.clone()), format!("data value '{}' must be a number, boolean or string", key)))?;
+                converted.insert(key.clone(), value);
+            }
+            values.push(PropertyValue::Data(converted));
+        }
+        if values.len() > 1 {
+            return Err((None, "a property can have only one of function, script, text, asset, flag or data".to_string()));
+        }
+        Ok(values.pop())
+    };
+
+    let mut property = if let Some(stat) = &def.stat {
+        let value = def.value.as_ref().and_then(json_to_stat)
+            .ok_or_else(|| (Some(stat.clone()), format!("stat modifier '{}' needs a number, boolean or string value", stat)))?;
+        Property::stat_modifier(stat, value)
+    } else if let Some(ability) = &def.ability {
+        Property::ability(ability)
+    } else if let Some(event) = def.trigger.as_ref().or(def.reaction.as_ref()) {
+        let action = match action()? {
+            Some(value @ (PropertyValue::Function(_) | PropertyValue::Script(_))) => value,
+            _ => return Err((Some(event.clone()), format!("'{}' trigger or reaction needs a function or script", event))),
+        };
+        if def.trigger.is_some() { Property::trigger(event, action) } else { Property::reaction(event, action) }
+    } else {
+        let name = def.property_type.as_deref().unwrap_or_default();
+        let value = action()?.ok_or_else(|| (Some(name.to_string()), format!("property of type '{}' needs a value", name)))?;
+        Property {
+            property_type: parse_property_type(name),
+            value,
+            context: vec![DEFAULT_CONTEXT.to_string()],
+            conditions: Vec::new(),
+            metadata: HashMap::new(),
+            exclusive: false,
+        }
+    };
+
+    if def.exclusive {
+        if def.contexts.is_empty() {
+            return Err((None, "an exclusive property needs at least one context".to_string()));
+        }
+        for context in &def.contexts {
+            property = property.exclusive_to(context);
+        }
+    } else {
+        for context in &def.contexts {
+            property = property.with_context(context);
+        }
+    }
+    for condition in &def.conditions {
+        property = property.with_condition(parse_condition(condition).map_err(|message| (None, message))?);
+    }
+    for (field, filter) in &def.filters {
+        property = property.with_filter(field, filter);
+    }
+    for (key, value) in &def.metadata {
+        property = property.with_metadata(key, value);
+    }
+    Ok(property)
+}
+
+fn parse_property_type(name: &str) -> PropertyType {
+    match name {
+        "stat_modifier" | "StatModifier" => PropertyType::StatModifier,
+        "ability" | "Ability" => PropertyType::Ability,
+        "behavior" | "Behavior" => PropertyType::Behavior,
+        "reaction" | "Reaction" => PropertyType::Reaction,
+        "trigger" | "Trigger" => PropertyType::Trigger,
+        "requirement" | "Requirement" => PropertyType::Requirement,
+        "visual" | "Visual" => PropertyType::Visual,
+        "audio" | "Audio" => PropertyType::Audio,
+        other => PropertyType::Custom(other.to_string()),
+    }
+}
+
+fn parse_condition(value: &Value) -> Result<Condition, String> {
+    let Value::Object(map) = value else {
+        return Err("a condition must be an object".to_string());
+    };
+    let nested = |key: &str| -> Result<Option<Vec<Condition>>, String> {
+        match map.get(key) {
+            Some(Value::Array(items)) => items.iter().map(parse_condition).collect::<Result<_, _>>().map(Some),
+            Some(_) => Err(format!("'{}' must be a list of conditions", key)),
+            None => Ok(None),
+        }
+    };
+    if let Some(conditions) = nested("all")? {
+        return Ok(Condition::all(conditions));
+    }
+    if let Some(conditions) = nested("any")? {
+        return Ok(Condition::any(conditions));
+    }
+    if let Some(condition) = map.get("not") {
+        return Ok(Condition::negate(parse_condition(condition)?));
+    }
+
+    let (condition_type, kind_key) = match (map.get("type").and_then(Value::as_str), map.get("custom").and_then(Value::as_str)) {
+        (Some(name), None) => {
+            let condition_type = match name {
+                "stat_threshold" => ConditionType::StatThreshold,
+                "has_tag" => ConditionType::HasTag,
+                "in_state" => ConditionType::InState,
+                "time_of_day" => ConditionType::TimeOfDay,
+                "proximity" => ConditionType::Proximity,
+                "inventory_contains" => ConditionType::InventoryContains,
+                "time_in_state" => ConditionType::TimeInState,
+                "can_see" => ConditionType::CanSee,
+                "remembers" => ConditionType::Remembers,
+                "hostile_nearby" => ConditionType::HostileNearby,
+                other => return Err(format!(
+                    "unknown condition type '{}' (expected stat_threshold, has_tag, in_state, time_of_day, proximity, inventory_contains, time_in_state, can_see, remembers or hostile_nearby)", other)),
+            };
+            (condition_type, "type")
+        },
+        (None, Some(name)) => (ConditionType::Custom(name.to_string()), "custom"),
+        _ => return Err("a condition needs a type or custom name, or all/any/not".to_string()),
+    };
+
+    let mut condition = Condition::new(condition_type);
+    for (key, value) in map {
+        if key == kind_key {
+            continue;
+        }
+        let value = json_to_stat(value).ok_or_else(|| format!("condition parameter '{}' must be a number, boolean or string", key))?;
+        condition = condition.with_parameter(key, value);
+    }
+    Ok(condition)
+}
+
+fn parse_bt_node(value: &Value) -> Result<BtNode, String> {
+    const KINDS: [&str; 14] = ["sequence", "selector", "parallel", "inverter", "cooldown", "repeat", "wait", "condition",
+        "move_toward", "attack", "flee", "set", "function", "script"];
+    let Value::Object(map) = value else {
+        return Err("a behavior tree node must be an object".to_string());
+    };
+    let kinds: Vec<&str> = KINDS.iter().copied().filter(|kind| map.contains_key(*kind)).collect();
+    let [kind] = kinds[..] else {
+        return Err(format!("a behavior tree node needs exactly one of {}", KINDS.join(", ")));
+    };
+    let allowed: &[&str] = match kind {
+        "parallel" => &["succeed"],
+        "cooldown" | "repeat" => &["child"],
+        "move_toward" | "attack" => &["range"],
+        "flee" => &["distance"],
+        "set" => &["value"],
+        _ => &[],
+    };
+    if let Some(key) = map.keys().find(|key| key.as_str() != kind && !allowed.contains(&key.as_str())) {
+        return Err(format!("unexpected '{}' in a {} node", key, kind));
+    }
+
+    let main = &map[kind];
+    let number = |key: &str| -> Result<f32, String> {
+        map.get(key).and_then(Value::as_f64).map(|n| n as f32).ok_or_else(|| format!("{} needs a number '{}'", kind, key))
+    };
+    let text = || main.as_str().ok_or_else(|| format!("'{}' must be a string", kind));
+    let children = || -> Result<Vec<BtNode>, String> {
+        match main {
+            Value::Array(items) => items.iter().map(parse_bt_node).collect(),
+            _ => Err(format!("'{}' must be a list of nodes", kind)),
+        }
+    };
+    let child = || -> Result<Box<BtNode>, String> {
+        map.get("child").ok_or_else(|| format!("{} needs a 'child'", kind)).and_then(parse_bt_node).map(Box::new)
+    };
+    Ok(match kind {
+        "sequence" => BtNode::Sequence(children()?),
+        "selector" => BtNode::Selector(children()?),
+        "parallel" => {
+            let children = children()?;
+            let success_threshold = match map.get("succeed") {
+                Some(value) => value.as_u64().ok_or("'succeed' must be a count")? as usize,
+                None => children.len(),
+            };
+            BtNode::Parallel { success_threshold, children }
+        },
+        "inverter" => BtNode::Inverter(Box::new(parse_bt_node(main)?)),
+        "cooldown" => BtNode::Cooldown { seconds: number(kind)?, child: child()? },
+        "repeat" => {
+            let times = match main {
+                Value::Null => None,
+                value => Some(value.as_u64().ok_or("'repeat' must be a count or null")? as u32),
+            };
+            BtNode::Repeat { times, child: child()? }
+        },
+        "wait" => BtNode::Wait(number(kind)?),
+        "condition" => BtNode::Condition(parse_condition(main)?),
+        "move_toward" => BtNode::MoveToward { target: BtTarget::parse(text()?), range: number("range")? },
+        "attack" => BtNode::Attack { target: BtTarget::parse(text()?), range: number("range")? },
+        "flee" => BtNode::Flee { target: BtTarget::parse(text()?), distance: number("distance")? },
+        "set" => {
+            let value = map.get("value").and_then(json_to_stat).ok_or("'set' needs a number, boolean or string 'value'")?;
+            BtNode::SetBlackboard { key: text()?.to_string(), value }
+        },
+        "function" => BtNode::Action(StateAction::Function(text()?.to_string())),
+        _ => BtNode::Action(StateAction::Script(text()?.to_string())),
+    })
+}
+
+fn json_to_stat(value: &Value) -> Option<StatValue> {
+    match value {
+        Value::Number(number) => match number.as_i64().and_then(|n| i32::try_from(n).ok()) {
+            Some(integer) => Some(StatValue::Integer(integer)),
+            None => number.as_f64().map(|float| StatValue::Float(float as f32)),
+        },
+        Value::Bool(flag) => Some(StatValue::Boolean(*flag)),
+        Value::String(text) => Some(StatValue::String(text.clone())),
+        _ => None,
+    }
+}
+
+fn json_to_item_value(value: &Value) -> Option<ItemValue> {
+    Some(match json_to_stat(value)? {
+        StatValue::Integer(value) => ItemValue::Integer(value),
+        StatValue::Float(value) => ItemValue::Float(value),
+        StatValue::Boolean(value) => ItemValue::Boolean(value),
+        StatValue::String(value) => ItemValue::String(value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_state::EntityId;
+
+    const TAGS: &str = r#"{
+  "tags": [
+    { "name": "elemental" },
+    { "name": "fire", "parents": ["elemental"],
+      "properties": [
+        { "stat": "damage", "value": 5, "contexts": ["combat"], "exclusive": true },
+        { "reaction": "damaged", "function": "flare", "filters": { "amount": ">=3" } }
+      ] }
+  ]
+}"#;
+
+    const CREATURES: &str = r#"{
+  "entity_types": [
+    { "id": "fire_goblin", "name": "Fire Goblin", "category": "hostile", "tags": ["fire", "sneaky"] }
+  ],
+  "npc_templates": [
+    { "id": "goblin_scout", "type": "fire_goblin", "stats": { "hp": 12, "speed": 1.5 }, "behavior": "patrol" }
+  ],
+  "item_templates": [
+    { "id": "torch", "name": "Torch", "values": { "type": "weapon", "damage": 2 },
+      "properties": [ { "type": "visual", "asset": "torch.png",
+                        "conditions": [ { "not": { "type": "in_state", "state": "wet" } } ] } ] }
+  ],
+  "npcs": [
+    { "id": "scout1", "template": "goblin_scout", "position": [4, 2] },
+    { "id": "scout2", "template": "goblin_scout", "stats": { "hp": 20 } }
+  ]
+}"#;
+
+    fn files(sources: &[(&str, &str)]) -> Vec<(PathBuf, String)> {
+        sources.iter().map(|(path, source)| (PathBuf::from(path), source.to_string())).collect()
+    }
+
+    #[test]
+    fn test_load_resolves_references_across_files() {
+        let mut game_state = GameState::new();
+        game_state.tag_collection.add_tag("sneaky");
+        let summary = ContentLoader::new()
+            .load_sources(files(&[("tags.json", TAGS), ("creatures.json", CREATURES)]), &mut game_state)
+            .unwrap();
+        assert_eq!((summary.files, summary.tags, summary.npcs), (2, 2, 2));
+
+        let fire = game_state.tag_collection.get_tag_by_name("fire").unwrap().id;
+        let elemental = game_state.tag_collection.get_tag_by_name("elemental").unwrap().id;
+        let sneaky = game_state.tag_collection.get_tag_by_name("sneaky").unwrap().id;
+        assert!(game_state.tag_collection.is_a(fire, elemental));
+        let goblin = &game_state.entity_types["fire_goblin"];
+        assert_eq!(goblin.tag_ids, HashSet::from([fire, sneaky]));
+        assert_eq!(goblin.category.as_deref(), Some("hostile"));
+
+        let scout1 = game_state.get_npc("scout1").unwrap();
+        assert_eq!(scout1.behavior_state, "patrol");
+        assert_eq!(scout1.position.values, vec![4.0, 2.0]);
+        assert_eq!(scout1.get_float_stat("speed"), Some(1.5));
+        assert_eq!(game_state.get_npc("scout2").unwrap().get_int_stat("hp"), Some(20));
+        assert_eq!(game_state.tag_index.npcs_with_tag(fire), vec!["scout1", "scout2"]);
+
+        let torch = game_state.item_from_template("torch").unwrap();
+        assert_eq!(torch.get_int("damage"), Some(2));
+        assert_eq!(torch.attached_properties().len(), 1);
+
+        // The fire tag's properties came through intact
+        let reaction = &game_state.tag_collection.get_tag(fire).unwrap().properties[1];
+        let hit = crate::events::GameEvent::Damaged { entity: EntityId::Player, amount: 4, source: None };
+        assert!(crate::events::subscribes_to(reaction, &hit, &EntityId::Player));
+        assert!(!game_state.tag_collection.get_tag(fire).unwrap().properties[0].applies_in_context("default"));
+    }
+
+    #[test]
+    fn test_errors_report_file_and_line() {
+        let broken = r#"{
+  "entity_types": [
+    { "id": "imp", "name": "Imp",
+      "tags": ["fire", "tiny"] }
+  ],
+  "npcs": [
+    { "id": "imp1", "type": "impp" }
+  ]
+}"#;
+        let mut game_state = GameState::new();
+        let errors = ContentLoader::new()
+            .load_sources(files(&[("tags.json", TAGS), ("imps.json", broken)]), &mut game_state)
+            .unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec![
+            "imps.json:4: entity type 'imp' has unknown tag 'tiny'".to_string(),
+            "imps.json:7: NPC 'imp1' has unknown type 'impp'".to_string(),
+        ]);
+        // Nothing was loaded
+        assert_eq!(game_state.tag_collection.tag_count(), 0);
+        assert!(game_state.entity_types.is_empty());
+
+        let typo = "{\n  \"tags\": [\n    { \"name\": \"ice\", \"parent\": [] }\n  ]\n}";
+        let errors = ContentLoader::new().load_sources(files(&[("ice.json", typo)]), &mut game_state).unwrap_err();
+        assert_eq!(errors[0].line, 3);
+        assert!(errors[0].message.starts_with("unknown field `parent`"));
+    }
+
+    #[test]
+    fn test_entity_types_extend_loaded_and_existing_types() {
+        let mut game_state = GameState::new();
+        game_state.add_entity_type(EntityType::new("goblin", "Goblin").with_category("hostile"));
+        let archers = r#"{
+  "entity_types": [
+    { "id": "goblin_archer", "name": "Goblin Archer", "extends": "goblin", "tags": ["fire"],
+      "properties": [ { "stat": "range", "value": 8 } ] },
+    { "id": "goblin_archer_chief", "name": "Goblin Archer Chief", "extends": "goblin_archer",
+      "properties": [ { "stat": "range", "value": 12 } ] }
+  ]
+}"#;
+        ContentLoader::new().load_sources(files(&[("tags.json", TAGS), ("archers.json", archers)]), &mut game_state).unwrap();
+        let chief = &game_state.entity_types["goblin_archer_chief"];
+        let fire = game_state.tag_collection.get_tag_by_name("fire").unwrap().id;
+        assert!(chief.has_tag_id(fire));
+        assert_eq!(chief.category.as_deref(), Some("hostile"));
+        assert_eq!(chief.properties.len(), 1);
+        assert_eq!(chief.provenance.as_ref().unwrap().overrides[0].base, "goblin_archer");
+        assert_eq!(game_state.tag_index.entity_types_with_tag(fire), vec!["goblin_archer", "goblin_archer_chief"]);
+
+        let looped = "{\n  \"entity_types\": [\n    { \"id\": \"a\", \"name\": \"A\", \"extends\": \"b\" },\n    { \"id\": \"b\", \"name\": \"B\", \"extends\": \"a\" }\n  ]\n}";
+        let errors = ContentLoader::new().load_sources(files(&[("loop.json", looped)]), &mut game_state).unwrap_err();
+        assert_eq!(errors[0].to_string(), "loop.json:3: entity types extend each other in a cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn test_entity_type_spawn_profiles() {
+        let guards = r#"{
+  "item_templates": [ { "id": "spear", "name": "Spear" } ],
+  "entity_types": [
+    { "id": "guard", "name": "Guard", "stats": { "hp": [10, 14], "speed": [1, 1.5], "rank": "private" },
+      "items": ["spear"], "behavior": "guard", "dimensions": 2 },
+    { "id": "captain", "name": "Captain", "extends": "guard",
+      "stats": { "hp": 30 }, "items": ["shield"] }
+  ]
+}"#;
+        let mut game_state = GameState::new();
+        let errors = ContentLoader::new().load_sources(files(&[("guards.json", guards)]), &mut game_state).unwrap_err();
+        assert_eq!(errors[0].to_string(), "guards.json:7: entity type 'captain' has unknown item 'shield'");
+
+        let guards = guards.replace("\"Spear\" }", "\"Spear\" }, { \"id\": \"shield\", \"name\": \"Shield\" }");
+        ContentLoader::new().load_sources(files(&[("guards.json", &guards)]), &mut game_state).unwrap();
+        let guard = &game_state.entity_types["guard"].spawn;
+        assert_eq!(guard.stats["hp"], StatTemplate::IntRange(10, 14));
+        assert_eq!(guard.stats["speed"], StatTemplate::FloatRange(1.0, 1.5));
+        assert_eq!(guard.stats["rank"], StatTemplate::Fixed(StatValue::String("private".to_string())));
+
+        let captain = &game_state.entity_types["captain"].spawn;
+        assert_eq!(captain.stats["hp"], StatTemplate::Fixed(StatValue::Integer(30)));
+        assert_eq!(captain.items, vec!["spear", "shield"]);
+        assert_eq!(captain.behavior.as_deref(), Some("guard"));
+        let id = game_state.spawn_npc("captain", Coordinates::new_2d(0.0, 0.0)).unwrap();
+        assert_eq!(game_state.get_npc(&id).unwrap().inventory.count(), 2);
+
+        // Templates and NPCs placed in content start out like spawned NPCs of their type
+        game_state.npc_templates.insert("veteran".to_string(), crate::template::NpcTemplate::new("veteran", "captain")
+            .with_stat("rank", StatValue::String("sergeant".to_string())));
+        let veteran = game_state.npc_from_template("veteran", "veteran1", Coordinates::new_2d(0.0, 0.0)).unwrap();
+        assert_eq!((veteran.inventory.count(), veteran.behavior_state.as_str()), (2, "guard"));
+        assert_eq!(veteran.get_stat("rank"), Some(StatValue::String("sergeant".to_string())));
+        assert_eq!(veteran.get_int_stat("hp"), Some(30));
+        let posted = r#"{ "npcs": [ { "id": "gate_guard", "type": "guard", "position": [1, 1] } ] }"#;
+        ContentLoader::new().load_sources(files(&[("posts.json", posted)]), &mut game_state).unwrap();
+        let gate_guard = game_state.get_npc("gate_guard").unwrap();
+        assert!(gate_guard.inventory.has_item("spear"));
+        assert!((10..=14).contains(&gate_guard.get_int_stat("hp").unwrap()));
+    }
+
+    #[test]
+    fn test_state_machines() {
+        let source = r#"{
+  "state_machines": [
+    { "id": "sentry", "initial": "watch", "states": {
+      "watch": { "transitions": [ { "to": "alarm", "conditions": [ { "type": "time_in_state", "seconds": 2 } ] } ] },
+      "alarm": { "enter": [ { "script": "set_stat(self, \"alarmed\", 1);" } ],
+                 "transitions": [ { "to": "sleep" } ] }
+    } }
+  ],
+  "entity_types": [
+    { "id": "sentry", "name": "Sentry", "state_machine": "sentry" },
+    { "id": "elite_sentry", "name": "Elite Sentry", "extends": "sentry" }
+  ],
+  "npcs": [ { "id": "sentry1", "type": "elite_sentry" } ]
+}"#;
+        let mut game_state = GameState::new();
+        let errors = ContentLoader::new().load_sources(files(&[("ai.json", source)]), &mut game_state).unwrap_err();
+        assert_eq!(errors[0].to_string(), "ai.json:6: State machine sentry has no state sleep");
+
+        let source = source.replace("\"sleep\"", "\"watch\"");
+        let summary = ContentLoader::new().load_sources(files(&[("ai.json", &source)]), &mut game_state).unwrap();
+        assert_eq!(summary.state_machines, 1);
+        assert_eq!(game_state.entity_types["elite_sentry"].state_machine.as_deref(), Some("sentry"));
+        for _ in 0..3 {
+            game_state.update(1.0);
+        }
+        let sentry = game_state.get_npc("sentry1").unwrap();
+        assert_eq!(sentry.behavior_state, "alarm");
+        assert_eq!(sentry.get_int_stat("alarmed"), Some(1));
+    }
+
+    #[test]
+    fn test_behavior_trees() {
+        let source = r#"{
+  "behavior_trees": [
+    { "id": "brute", "root": { "selector": [
+      { "sequence": [ { "move_toward": "player", "range": 1 }, { "attack": "player", "range": 1 } ] },
+      { "repeat": null, "child": { "wait": 1 } },
+      { "parallel": [ { "set": "mood", "value": "calm" }, { "inverter": { "flee": "target", "distance": 3 } } ], "succeed": 1 },
+      { "cooldown": 2, "child": { "script": "log(\"grr\");" } }
+    ] } }
+  ],
+  "entity_types": [ { "id": "brute", "name": "Brute", "behavior_tree": "brute" } ]
+}"#;
+        let mut game_state = GameState::new();
+        let summary = ContentLoader::new().load_sources(files(&[("ai.json", source)]), &mut game_state).unwrap();
+        assert_eq!(summary.behavior_trees, 1);
+        assert_eq!(game_state.behavior_trees["brute"].root.size(), 12);
+        assert_eq!(game_state.entity_types["brute"].behavior_tree.as_deref(), Some("brute"));
+
+        let broken = source.replace("\"range\": 1 } ]", "\"speed\": 1 } ]");
+        let errors = ContentLoader::new().load_sources(files(&[("ai.json", &broken)]), &mut GameState::new()).unwrap_err();
+        assert_eq!(errors[0].to_string(), "ai.json:3: behavior tree 'brute': unexpected 'speed' in a attack node");
+    }
+
+    #[test]
+    fn test_factions() {
+        let source = r#"{
+  "factions": [
+    { "id": "watch", "name": "City Watch", "relations": { "bandits": "hostile", "merchants": 60 },
+      "reputation": [ { "event": "damaged", "amount": -25 } ] },
+    { "id": "bandits", "name": "Bandits" },
+    { "id": "merchants", "name": "Merchants" }
+  ],
+  "entity_types": [ { "id": "guard", "name": "Guard", "faction": "watch" } ]
+}"#;
+        let mut game_state = GameState::new();
+        let summary = ContentLoader::new().load_sources(files(&[("factions.json", source)]), &mut game_state).unwrap();
+        assert_eq!(summary.factions, 3);
+        let watch = game_state.factions.get("watch").unwrap();
+        assert_eq!((watch.relation("bandits"), watch.relation("merchants")), (-100.0, 60.0));
+        assert_eq!(watch.reputation_rules, vec![ReputationRule::new("damaged", -25.0)]);
+        assert_eq!(game_state.entity_types["guard"].faction.as_deref(), Some("watch"));
+
+        let broken = source.replace("\"merchants\": 60", "\"merchants\": \"friendly\"").replace("\"faction\": \"watch\"", "\"faction\": \"wach\"");
+        let errors = ContentLoader::new().load_sources(files(&[("factions.json", &broken)]), &mut GameState::new()).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec![
+            "factions.json:3: faction 'watch' relation to 'merchants' must be \"ally\", \"neutral\", \"hostile\" or a number",
+            "factions.json:8: entity type 'guard' has unknown faction 'wach'",
+        ]);
+    }
+
+    #[test]
+    fn test_damage_types() {
+        let source = r#"{
+  "damage_types": [
+    { "id": "elemental", "name": "Elemental" },
+    { "id": "fire", "name": "Fire", "parent": "elemental" },
+    { "id": "poison", "name": "Poison", "ignores_defense": true }
+  ],
+  "item_templates": [ { "id": "torch", "name": "Torch", "values": { "type": "weapon", "damage_type": "fire" } } ]
+}"#;
+        let mut game_state = GameState::new();
+        let summary = ContentLoader::new().load_sources(files(&[("damage.json", source)]), &mut game_state).unwrap();
+        assert_eq!(summary.damage_types, 3);
+        assert_eq!(game_state.damage_types["fire"], DamageType::new("fire", "Fire").with_parent("elemental"));
+        assert!(game_state.damage_types["poison"].ignores_defense);
+        assert!(game_state.damage_types.contains_key(crate::damage::PHYSICAL));
+
+        let broken = source.replace("\"parent\": \"elemental\"", "\"parent\": \"magic\"").replace("\"damage_type\": \"fire\"", "\"damage_type\": \"frost\"");
+        let errors = ContentLoader::new().load_sources(files(&[("damage.json", &broken)]), &mut GameState::new()).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec![
+            "damage.json:4: damage type 'fire' has unknown parent 'magic'",
+            "damage.json:7: item template 'torch' has unknown damage type 'frost'",
+        ]);
+    }
+
+    #[test]
+    fn test_status_effects() {
+        let source = r#"{
+  "status_effects": [
+    { "id": "poisoned", "name": "Poisoned", "duration": 6, "stacking": "stack", "max_stacks": 3,
+      "tick_interval": 1, "tick_damage": { "physical": 2 },
+      "modifiers": [{ "stat": "speed", "multiply": 0.8 }, { "stat": "armor", "add": -1 }],
+      "on_expire": [{ "script": "add_stat(self, \"cured\", 1);" }] }
+  ]
+}"#;
+        let mut game_state = GameState::new();
+        let summary = ContentLoader::new().load_sources(files(&[("effects.json", source)]), &mut game_state).unwrap();
+        assert_eq!(summary.status_effects, 1);
+        assert_eq!(game_state.status_effects.get("poisoned"), Some(&StatusEffectDef::new("poisoned", "Poisoned")
+            .with_duration(6.0)
+            .with_stacking(Stacking::Stack, 3)
+            .with_tick_interval(1.0)
+            .with_tick_damage(crate::damage::PHYSICAL, 2.0)
+            .with_modifier("speed", ModifierType::Multiplicative, StatValue::Float(0.8))
+            .with_modifier("armor", ModifierType::Additive, StatValue::Integer(-1))
+            .with_on_expire(StateAction::Script("add_stat(self, \"cured\", 1);".to_string()))));
+
+        let broken = source.replace("\"stack\"", "\"pile\"").replace("\"physical\"", "\"acid\"").replace("\"multiply\"", "\"divide\"");
+        let errors = ContentLoader::new().load_sources(files(&[("effects.json", &broken)]), &mut GameState::new()).unwrap_err();
+        assert_eq!(errors.len(), 1, "unknown modifier keys are rejected when parsing");
+        let broken = source.replace("\"stack\"", "\"pile\"").replace("\"physical\"", "\"acid\"");
+        let errors = ContentLoader::new().load_sources(files(&[("effects.json", &broken)]), &mut GameState::new()).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec![
+            "effects.json:4: status effect 'poisoned' has unknown damage type 'acid'",
+            "effects.json:3: status effect 'poisoned' stacking must be \"refresh\", \"extend\", \"stack\" or \"ignore\"",
+        ]);
+    }
+
+    #[test]
+    fn test_load_dir_and_merge_policy() {
+        let dir = std::env::temp_dir().join(format!("kean_content_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("creatures")).unwrap();
+        std::fs::write(dir.join("tags.json"), TAGS).unwrap();
+        std::fs::write(dir.join("creatures").join("goblins.json"), CREATURES.replace("\"sneaky\"", "\"fire\"")).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not content").unwrap();
+
+        let mut game_state = GameState::new();
+        let existing_fire = game_state.tag_collection.add_tag("fire");
+        let summary = ContentLoader::new()
+            .with_merge_policy(TagMergePolicy::Rename)
+            .load_dir(&dir, &mut game_state);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let summary = summary.unwrap();
+
+        // The loaded fire tag became fire_2, and the loaded goblins use it
+        assert_eq!(summary.files, 2);
+        assert_eq!(summary.tag_report.renamed, vec![("fire".to_string(), "fire_2".to_string())]);
+        let fire_2 = game_state.tag_collection.get_tag_by_name("fire_2").unwrap().id;
+        assert!(game_state.entity_types["fire_goblin"].has_tag_id(fire_2));
+        assert!(!game_state.entity_types["fire_goblin"].has_tag_id(existing_fire));
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Serialize, Deserialize};
+use crate::property::{Property, DEFAULT_CONTEXT};
+
+/// The contexts an entity is in, most recent last.
+///
+/// Contexts nest: entering "dialogue" during "combat" keeps both active, and a context pushed
+/// twice (two fights at once) stays active until both pushes are undone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ContextStack {
+    contexts: Vec<String>,
+}
+
+impl ContextStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enter a context on top of the current ones
+    pub fn push(&mut self, context: &str) {
+        self.contexts.push(context.to_string());
+    }
+
+    /// Leave the most recently entered context
+    pub fn pop(&mut self) -> Option<String> {
+        self.contexts.pop()
+    }
+
+    /// Undo the most recent push of a context, returns whether it was active
+    pub fn remove(&mut self, context: &str) -> bool {
+        match self.contexts.iter().rposition(|c| c == context) {
+            Some(index) => {
+                self.contexts.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Leave a context entirely, however many times it was entered
+    pub fn clear_context(&mut self, context: &str) {
+        self.contexts.retain(|c| c != context);
+    }
+
+    pub fn clear(&mut self) {
+        self.contexts.clear();
+    }
+
+    pub fn contains(&self, context: &str) -> bool {
+        self.contexts.iter().any(|c| c == context)
+    }
+
+    /// The most recently entered context
+    pub fn top(&self) -> Option<&str> {
+        self.contexts.last().map(|c| c.as_str())
+    }
+
+    /// All entries, oldest first, including repeated pushes
+    pub fn as_slice(&self) -> &[String] {
+        &self.contexts
+    }
+
+    /// Distinct active contexts, oldest first
+    pub fn active(&self) -> Vec<&str> {
+        let mut active: Vec<&str> = Vec::new();
+        for context in &self.contexts {
+            if !active.contains(&context.as_str()) {
+                active.push(context);
+            }
+        }
+        active
+    }
+
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+}
+
+/// A property that applies in the active contexts, and which contexts made it apply
+#[derive(Clone)]
+pub struct ContextMatch<'a> {
+    pub property: &'a Property,
+    /// Active contexts the property is listed for, plus "default" when it applies everywhere
+    pub contexts: Vec<String>,
+}
+
+impl ContextMatch<'_> {
+    /// Whether the property applies only because it is not limited to any context
+    pub fn is_default_only(&self) -> bool {
+        self.contexts.iter().all(|c| c == DEFAULT_CONTEXT)
+    }
+}
+
+/// Keep the properties that apply in any of the active contexts, reporting the matches
+pub fn match_properties<'a>(
+    properties: impl IntoIterator<Item = &'a Property>,
+    active_contexts: &[String],
+) -> Vec<ContextMatch<'a>> {
+    properties.into_iter()
+        .filter_map(|property| {
+            let contexts = property.matched_contexts(active_contexts);
+            (!contexts.is_empty()).then_some(ContextMatch { property, contexts })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::StatValue;
+
+    #[test]
+    fn test_context_stack_nesting() {
+        let mut stack = ContextStack::new();
+        stack.push("combat");
+        stack.push("dialogue");
+        stack.push("combat");
+        assert_eq!(stack.active(), vec!["combat", "dialogue"]);
+        assert_eq!(stack.top(), Some("combat"));
+
+        // One fight ends, the other is still going
+        assert!(stack.remove("combat"));
+        assert!(stack.contains("combat"));
+        assert_eq!(stack.pop(), Some("dialogue".to_string()));
+        stack.clear_context("combat");
+        assert!(stack.is_empty());
+        assert!(!stack.remove("combat"));
+    }
+
+    #[test]
+    fn test_exclusive_properties_report_matches() {
+        let everywhere = Property::stat_modifier("armor", StatValue::Integer(1));
+        let combat_or_default = Property::stat_modifier("damage", StatValue::Integer(5))
+            .with_context("combat")
+            .with_context("default");
+        let combat_only = Property::stat_modifier("rage", StatValue::Integer(3)).exclusive_to("combat");
+        let stealth_only = Property::stat_modifier("dodge", StatValue::Integer(2)).exclusive_to("stealth");
+        let properties = [everywhere, combat_or_default, combat_only, stealth_only];
+
+        let active = vec!["combat".to_string(), "night".to_string()];
+        let matches = match_properties(properties.iter(), &active);
+        let contexts: Vec<Vec<String>> = matches.iter().map(|m| m.contexts.clone()).collect();
+        assert_eq!(contexts, vec![
+            vec!["default".to_string()],
+            vec!["combat".to_string(), "default".to_string()],
+            vec!["combat".to_string()],
+        ]);
+        assert!(matches[0].is_default_only());
+
+        // Outside of any context only non-exclusive properties apply
+        assert_eq!(match_properties(properties.iter(), &[]).len(), 2);
+        assert!(!properties[2].applies_in_context("exploration"));
+        assert!(properties[2].applies_in_context("combat"));
+    }
+}
@@ -128,23 +128,22 @@ impl Coordinates {
     
     /// Get a value for a dimension by label
     pub fn get_by_label(&self, label: &str) -> Option<f32> {
-        if let Some(labels) = &self.labels {
-            if let Some(&index) = labels.get(label) {
-                return self.values.get(index).copied();
-            }
+        if let Some(labels) = &self.labels
+            && let Some(&index) = labels.get(label)
+        {
+            return self.values.get(index).copied();
         }
         None
     }
     
     /// Set a value for a dimension by label
     pub fn set_by_label(&mut self, label: &str, value: f32) -> bool {
-        if let Some(labels) = &self.labels {
-            if let Some(&index) = labels.get(label) {
-                if index < self.values.len() {
-                    self.values[index] = value;
-                    return true;
-                }
-            }
+        if let Some(labels) = &self.labels
+            && let Some(&index) = labels.get(label)
+            && index < self.values.len()
+        {
+            self.values[index] = value;
+            return true;
         }
         false
     }
@@ -194,10 +193,10 @@ impl Coordinates {
             if let Some(label) = label_to_remove {
                 labels.remove(&label);
                 
-                if let Some(order) = &mut self.label_order {
-                    if let Some(pos) = order.iter().position(|l| l == &label) {
-                        order.remove(pos);
-                    }
+                if let Some(order) = &mut self.label_order
+                    && let Some(pos) = order.iter().position(|l| l == &label)
+                {
+                    order.remove(pos);
                 }
             }
             
@@ -214,10 +213,10 @@ impl Coordinates {
     
     /// Remove a dimension by label
     pub fn remove_dimension_by_label(&mut self, label: &str) -> Option<f32> {
-        if let Some(labels) = &self.labels {
-            if let Some(&index) = labels.get(label) {
-                return self.remove_dimension(index);
-            }
+        if let Some(labels) = &self.labels
+            && let Some(&index) = labels.get(label)
+        {
+            return self.remove_dimension(index);
         }
         None
     }
@@ -262,10 +261,10 @@ impl Coordinates {
             return None;
         }
         
-        let mut direction = vec![0.0; self.dimensions()];
-        for i in 0..self.dimensions() {
-            direction[i] = (target.values[i] - self.values[i]) / distance;
-        }
+        let direction: Vec<f32> = self.values.iter()
+            .zip(&target.values)
+            .map(|(from, to)| (to - from) / distance)
+            .collect();
         
         Some(Coordinates {
             values: direction,
@@ -465,9 +464,9 @@ mod tests {
         let mut coords = Coordinates::new(3);
         
         // Set values and check them
-        assert_eq!(coords.set(0, 10.0), true);
-        assert_eq!(coords.set(1, 20.0), true);
-        assert_eq!(coords.set(2, 30.0), true);
+        assert!(coords.set(0, 10.0));
+        assert!(coords.set(1, 20.0));
+        assert!(coords.set(2, 30.0));
         
         assert_eq!(coords.get(0), Some(10.0));
         assert_eq!(coords.get(1), Some(20.0));
@@ -475,7 +474,7 @@ mod tests {
         
         // Out of bounds access should return None or false
         assert_eq!(coords.get(3), None);
-        assert_eq!(coords.set(3, 40.0), false);
+        assert!(!coords.set(3, 40.0));
         
         // Test index access
         assert_eq!(coords[0], 10.0);
@@ -546,10 +545,10 @@ mod tests {
         
         // Test set by label
         let mut coords2 = coords.clone();
-        assert_eq!(coords2.set_by_label("x", 100.0), true);
-        assert_eq!(coords2.set_by_label("y", 200.0), true);
-        assert_eq!(coords2.set_by_label("z", 300.0), true);
-        assert_eq!(coords2.set_by_label("time", 400.0), true);
+        assert!(coords2.set_by_label("x", 100.0));
+        assert!(coords2.set_by_label("y", 200.0));
+        assert!(coords2.set_by_label("z", 300.0));
+        assert!(coords2.set_by_label("time", 400.0));
         
         assert_eq!(coords2.get_by_label("x"), Some(100.0));
         assert_eq!(coords2.get_by_label("y"), Some(200.0));
@@ -557,7 +556,7 @@ mod tests {
         assert_eq!(coords2.get_by_label("time"), Some(400.0));
         
         // Non-existent label should return false
-        assert_eq!(coords2.set_by_label("w", 500.0), false);
+        assert!(!coords2.set_by_label("w", 500.0));
         
         // Test label order
         let labels = coords2.dimension_labels();
@@ -635,7 +634,7 @@ mod tests {
         let mut coords = Coordinates::new_2d(0.0, 0.0);
         let target = Coordinates::new_2d(10.0, 10.0);
         
-        assert_eq!(coords.move_toward(&target, 5.0 * 2.0_f32.sqrt()), true);
+        assert!(coords.move_toward(&target, 5.0 * 2.0_f32.sqrt()));
         assert!((coords.get(0).unwrap() - 5.0).abs() < 0.0001);
         assert!((coords.get(1).unwrap() - 5.0).abs() < 0.0001);
         
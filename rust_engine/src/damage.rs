@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::combat::{CombatError, CombatResult, COMBAT_CONTEXT};
+use crate::condition::EntityRef;
+use crate::calculated_stats::ModifierType;
+use crate::entity::Entity;
+use crate::game_state::{EntityId, GameState};
+use crate::property::{PropertyType, PropertyValue};
+use crate::property_modifiers::{TAG_MODIFIER_SOURCE, TYPE_MODIFIER_SOURCE};
+use crate::stats::StatValue;
+
+/// Damage type every game starts with, and the type of attacks that name none
+pub const PHYSICAL: &str = "physical";
+/// Suffix of the stats and item values that add damage of a type to attacks ("fire_damage")
+pub const DAMAGE_SUFFIX: &str = "_damage";
+/// Suffix of the fraction of a damage type taken off ("fire_resistance" 0.5 halves it)
+pub const RESISTANCE_SUFFIX: &str = "_resistance";
+/// Suffix of the fraction of a damage type added on ("ice_vulnerability" 0.5 is +50%)
+pub const VULNERABILITY_SUFFIX: &str = "_vulnerability";
+/// Suffix of the flag that blocks a damage type completely ("poison_immunity")
+pub const IMMUNITY_SUFFIX: &str = "_immunity";
+
+/// A kind of damage, defined by the game rather than the engine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DamageType {
+    pub id: String,
+    pub name: String,
+    /// Broader type whose defenses also apply ("fire" under "elemental")
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Whether the defender's "defense" stat leaves this damage alone
+    #[serde(default)]
+    pub ignores_defense: bool,
+}
+
+impl DamageType {
+    pub fn new(id: &str, name: &str) -> Self {
+        DamageType { id: id.to_string(), name: name.to_string(), parent: None, ignores_defense: false }
+    }
+
+    pub fn with_parent(mut self, parent: &str) -> Self {
+        self.parent = Some(parent.to_string());
+        self
+    }
+
+    pub fn with_ignores_defense(mut self, ignores_defense: bool) -> Self {
+        self.ignores_defense = ignores_defense;
+        self
+    }
+}
+
+/// The damage types a new game has: just physical
+pub fn default_damage_types() -> HashMap<String, DamageType> {
+    HashMap::from([(PHYSICAL.to_string(), DamageType::new(PHYSICAL, "Physical"))])
+}
+
+/// An amount of one type of damage
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DamageComponent {
+    pub damage_type: String,
+    pub amount: f32,
+}
+
+/// Damage of one or more types dealt at once
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DamagePacket {
+    /// At most one component per type, in the order they were added
+    pub components: Vec<DamageComponent>,
+}
+
+impl DamagePacket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Packet with a single component
+    pub fn of(damage_type: &str, amount: f32) -> Self {
+        Self::new().with(damage_type, amount)
+    }
+
+    pub fn with(mut self, damage_type: &str, amount: f32) -> Self {
+        self.add(damage_type, amount);
+        self
+    }
+
+    /// Add damage, merging it into the component of the same type
+    pub fn add(&mut self, damage_type: &str, amount: f32) {
+        match self.components.iter_mut().find(|c| c.damage_type == damage_type) {
+            Some(component) => component.amount += amount,
+            None => self.components.push(DamageComponent { damage_type: damage_type.to_string(), amount }),
+        }
+    }
+
+    pub fn amount(&self, damage_type: &str) -> f32 {
+        self.components.iter()
+            .filter(|c| c.damage_type == damage_type)
+            .map(|c| c.amount)
+            .sum()
+    }
+
+    pub fn total(&self) -> f32 {
+        self.components.iter().map(|c| c.amount).sum()
+    }
+
+    /// Multiply every component, e.g. for a critical hit
+    pub fn scale(&mut self, factor: f32) {
+        for component in &mut self.components {
+            component.amount *= factor;
+        }
+    }
+}
+
+/// How well an entity stands up to a damage type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Defenses {
+    pub resistance: f32,
+    pub vulnerability: f32,
+    pub immune: bool,
+}
+
+impl Defenses {
+    /// What damage of the type is multiplied by: 0 when immune, otherwise one minus the
+    /// resistance plus the vulnerability, never below 0
+    pub fn multiplier(&self) -> f32 {
+        if self.immune { 0.0 } else { (1.0 - self.resistance + self.vulnerability).max(0.0) }
+    }
+}
+
+/// One component of a packet after defense and the defender's defenses
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResolvedDamage {
+    pub damage_type: String,
+    /// Damage after defense, before resistances
+    pub raw: f32,
+    pub defenses: Defenses,
+    /// Damage dealt
+    pub amount: f32,
+}
+
+/// A packet as a defender takes it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DamageReport {
+    pub components: Vec<ResolvedDamage>,
+    /// Hit points taken: the components' damage added up and rounded
+    pub total: i32,
+}
+
+impl DamageReport {
+    fn new(components: Vec<ResolvedDamage>) -> Self {
+        let total = components.iter().map(|c| c.amount).sum::<f32>().round().max(0.0) as i32;
+        DamageReport { components, total }
+    }
+
+    pub fn raw(&self) -> f32 {
+        self.components.iter().map(|c| c.raw).sum()
+    }
+
+    /// Multiply the damage after defense, e.g. for a critical hit
+    pub fn scale(&mut self, factor: f32) {
+        for component in &mut self.components {
+            component.raw *= factor;
+            component.amount *= factor;
+        }
+        *self = DamageReport::new(std::mem::take(&mut self.components));
+    }
+}
+
+impl fmt::Display for DamageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.components.as_slice() {
+            [single] => write!(f, "{} {} damage", self.total, single.damage_type),
+            components => {
+                let parts: Vec<String> = components.iter()
+                    .map(|c| if c.defenses.immune {
+                        format!("immune to {}", c.damage_type)
+                    } else {
+                        format!("{} {}", c.amount.round(), c.damage_type)
+                    })
+                    .collect();
+                write!(f, "{} damage ({})", self.total, parts.join(", "))
+            },
+        }
+    }
+}
+
+/// A damage type followed by its parents, broadest last
+pub fn lineage<'a>(game_state: &'a GameState, damage_type: &str) -> CombatResult<Vec<&'a DamageType>> {
+    let mut lineage: Vec<&DamageType> = Vec::new();
+    let mut next = Some(damage_type);
+    while let Some(id) = next {
+        // A parent loop would never end
+        if lineage.iter().any(|t| t.id == id) {
+            break;
+        }
+        let damage_type = game_state.damage_types.get(id).ok_or_else(|| CombatError::UnknownDamageType(id.to_string()))?;
+        lineage.push(damage_type);
+        next = damage_type.parent.as_deref();
+    }
+    Ok(lineage)
+}
+
+fn as_number(value: &StatValue) -> Option<f32> {
+    match value {
+        StatValue::Boolean(flag) => Some(if *flag { 1.0 } else { 0.0 }),
+        value => value.as_float(),
+    }
+}
+
+// An entity's defense stat: the base stat and tag and type properties for it added up,
+// then equipment and buff modifiers applied. The properties always add, since a
+// resistance without a base stat has nothing to multiply. They count in the entity's
+// active contexts and in combat.
+fn defense_value(game_state: &GameState, entity: EntityRef, key: &str) -> f32 {
+    let stats = entity.stats();
+    let mut value = stats.base_stats().get(key).and_then(as_number).unwrap_or(0.0);
+
+    let mut contexts = entity.active_contexts().to_vec();
+    contexts.push(COMBAT_CONTEXT.to_string());
+    let tag_properties = game_state.tag_collection.resolved_properties_for_tags(&entity.tag_ids())
+        .into_iter()
+        .map(|(_, property)| property);
+    let own_properties = entity.entity_type().into_iter().flat_map(|t| t.properties.iter());
+    for property in tag_properties.chain(own_properties) {
+        if property.property_type != PropertyType::StatModifier
+            || property.matched_contexts(&contexts).is_empty()
+            || !game_state.is_property_active(entity, property)
+        {
+            continue;
+        }
+        if let PropertyValue::Stat(stat, amount) = &property.value
+            && stat == key
+        {
+            value += as_number(amount).unwrap_or(0.0);
+        }
+    }
+
+    for modifier in stats.get_modifiers(key) {
+        if modifier.source.starts_with(TAG_MODIFIER_SOURCE) || modifier.source.starts_with(TYPE_MODIFIER_SOURCE) {
+            continue;
+        }
+        let Some(amount) = as_number(&modifier.value) else { continue };
+        match modifier.modifier_type {
+            ModifierType::Additive => value += amount,
+            ModifierType::Multiplicative => value *= amount,
+            ModifierType::Override => value = amount,
+        }
+    }
+    value
+}
+
+/// An entity's defenses against a damage type, its parents' included: resistances and
+/// vulnerabilities add up, and immunity to any of them is immunity to the type
+pub fn defenses(game_state: &GameState, entity: &EntityId, damage_type: &str) -> CombatResult<Defenses> {
+    let entity_ref = game_state.get_entity(entity).ok_or_else(|| CombatError::UnknownEntity(entity.clone()))?;
+    let mut defenses = Defenses::default();
+    for damage_type in lineage(game_state, damage_type)? {
+        let value = |suffix: &str| defense_value(game_state, entity_ref, &format!("{}{}", damage_type.id, suffix));
+        defenses.resistance += value(RESISTANCE_SUFFIX);
+        defenses.vulnerability += value(VULNERABILITY_SUFFIX);
+        defenses.immune |= value(IMMUNITY_SUFFIX) > 0.0;
+    }
+    Ok(defenses)
+}
+
+/// Work out what a packet does to a defender without applying it. `defense` is taken off
+/// the components whose type does not ignore it, in order, until it is used up.
+pub fn resolve_damage(game_state: &GameState, defender: &EntityId, packet: &DamagePacket, defense: f32) -> CombatResult<DamageReport> {
+    let mut defense = defense.max(0.0);
+    let mut components = Vec::new();
+    for component in &packet.components {
+        let ignores_defense = lineage(game_state, &component.damage_type)?[0].ignores_defense;
+        let mut raw = component.amount.max(0.0);
+        if !ignores_defense {
+            let absorbed = defense.min(raw);
+            raw -= absorbed;
+            defense -= absorbed;
+        }
+        let defenses = defenses(game_state, defender, &component.damage_type)?;
+        components.push(ResolvedDamage {
+            damage_type: component.damage_type.clone(),
+            raw,
+            defenses,
+            amount: raw * defenses.multiplier(),
+        });
+    }
+    Ok(DamageReport::new(components))
+}
+
+/// Deal a packet to an entity outside an attack (traps, poison, scripts): defense does not
+/// apply, but resistances do. Hit points are taken with `GameState::damage_entity`.
+pub fn apply_damage(game_state: &mut GameState, entity: &EntityId, packet: &DamagePacket, source: Option<EntityId>) -> CombatResult<DamageReport> {
+    let entity_ref = game_state.get_entity(entity).ok_or_else(|| CombatError::UnknownEntity(entity.clone()))?;
+    if !matches!(entity_ref.get_stat("hp"), Some(StatValue::Integer(_))) {
+        return Err(CombatError::NoHitPoints(entity.clone()));
+    }
+    let report = resolve_damage(game_state, entity, packet, 0.0)?;
+    if report.total > 0 {
+        game_state.damage_entity(entity, report.total, source);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+    use crate::property::Property;
+
+    fn game() -> GameState {
+        let mut game_state = GameState::new();
+        game_state.add_damage_type(DamageType::new("elemental", "Elemental"));
+        game_state.add_damage_type(DamageType::new("fire", "Fire").with_parent("elemental"));
+        game_state.add_damage_type(DamageType::new("poison", "Poison").with_ignores_defense(true));
+        game_state
+    }
+
+    #[test]
+    fn test_defenses_from_tags_types_and_stats() {
+        let mut game_state = game();
+        let fire = game_state.tag_collection.add_tag("fire");
+        if let Some(tag) = game_state.tag_collection.get_tag_mut(fire) {
+            *tag = tag.clone().with_property(Property::stat_modifier("fire_resistance", StatValue::Float(0.5)));
+        }
+        let mut golem_type = EntityType::new("golem", "Golem").with_tag_id(fire);
+        golem_type.properties.push(Property::stat_modifier("poison_immunity", StatValue::Boolean(true)));
+        let mut golem = NPC::new("golem".to_string(), golem_type);
+        golem.set_base_stat("hp", StatValue::Integer(50));
+        golem.set_base_stat("elemental_resistance", StatValue::Float(0.25));
+        golem.set_base_stat("physical_vulnerability", StatValue::Float(0.5));
+        game_state.add_npc(golem).unwrap();
+        game_state.update(0.1);
+        let golem = EntityId::Npc("golem".to_string());
+
+        // Fire: the tag's 0.5 plus elemental 0.25 from the stats
+        let fire_defenses = defenses(&game_state, &golem, "fire").unwrap();
+        assert_eq!((fire_defenses.resistance, fire_defenses.multiplier()), (0.75, 0.25));
+        assert!(defenses(&game_state, &golem, "poison").unwrap().immune);
+
+        let packet = DamagePacket::of(PHYSICAL, 10.0).with("fire", 8.0).with("poison", 5.0);
+        let report = resolve_damage(&game_state, &golem, &packet, 4.0).unwrap();
+        // Physical: (10 - 4) * 1.5; fire: 8 * 0.25; poison: immune
+        assert_eq!(report.components.iter().map(|c| c.amount).collect::<Vec<_>>(), [9.0, 2.0, 0.0]);
+        assert_eq!(report.to_string(), "11 damage (9 physical, 2 fire, immune to poison)");
+
+        assert_eq!(apply_damage(&mut game_state, &golem, &packet, None).unwrap().total, 17);
+        assert_eq!(game_state.get_npc("golem").unwrap().get_int_stat("hp"), Some(33));
+        assert_eq!(resolve_damage(&game_state, &golem, &DamagePacket::of("acid", 1.0), 0.0),
+            Err(CombatError::UnknownDamageType("acid".to_string())));
+    }
+
+    #[test]
+    fn test_attacks_carry_typed_packets() {
+        let mut game_state = game();
+        game_state.player.set_base_stat("hp", StatValue::Integer(30));
+        game_state.player.set_base_stat("fire_vulnerability", StatValue::Float(1.0));
+        let mut imp = NPC::new("imp".to_string(), EntityType::new("imp", "Imp"));
+        imp.set_base_stat("attack", StatValue::Integer(3));
+        imp.set_base_stat("fire_damage", StatValue::Integer(2));
+        game_state.player.set_base_stat("defense", StatValue::Integer(1));
+        game_state.add_npc(imp).unwrap();
+        let imp = EntityId::Npc("imp".to_string());
+
+        let attack = crate::combat::build_attack(&game_state, &imp).unwrap();
+        assert_eq!(attack.damage, DamagePacket::of(PHYSICAL, 3.0).with("fire", 2.0));
+        // Physical 3 - 1, fire 2 doubled
+        let entry = game_state.attack(&imp, &EntityId::Player).unwrap();
+        assert_eq!(entry.damage.total, 6);
+        assert_eq!(entry.to_string(), "imp hits player for 6 damage (2 physical, 4 fire)");
+
+        let output = crate::script::run_script(&mut game_state, "return damage(player(), 3, \"fire\")", EntityId::Player, None).unwrap();
+        assert_eq!(output.value, crate::script::ScriptValue::Int(6));
+        assert_eq!(game_state.player.get_int_stat("hp"), Some(18));
+    }
+}
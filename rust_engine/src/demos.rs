@@ -2,7 +2,10 @@ use crate::character::Character;
 use crate::npc::NPC;
 use crate::entity_type::EntityType;
 use crate::stats::StatValue;
-use crate::calculated_stats::{StatModifier, ModifierType};
+use crate::calculated_stats::ModifierType;
+use crate::damage::PHYSICAL;
+use crate::game_state::{EntityId, GameState};
+use crate::status_effect::StatusEffectDef;
 use crate::property::{Property, PropertyValue};
 use crate::tag::TagCollection;
 use crate::coordinates::Coordinates;
@@ -88,7 +91,7 @@ pub fn demo_tag_system() {
     
     // Demo tag searches
     println!("\nEntity types with 'fire' tag:");
-    let entity_types = vec![&fire_sword, &ice_staff, &poison_dagger];
+    let entity_types = [&fire_sword, &ice_staff, &poison_dagger];
     for entity in entity_types.iter() {
         if entity.has_tag_id(fire_id) {
             println!("- {}", entity.name);
@@ -166,16 +169,25 @@ pub fn showcase_different_game_mechanics() {
     
     println!("{} now has {} health", combat_npc.npc_type.name, combat_npc.get_int_stat("health").unwrap_or(0));
     
-    // Add a status effect
-    let _poison_effect = StatModifier {
-        source: "poison".to_string(), 
-        modifier_type: ModifierType::Additive,
-        value: StatValue::Integer(-2),
-        priority: 10
-    };
-    combat_npc.add_status_effect("poisoned");
-    combat_npc.add_stat_modifier("health", "poison", ModifierType::Additive, StatValue::Integer(-2), 10);
-    println!("Applied poison to {}, dealing 2 damage per turn", combat_npc.npc_type.name);
+    // Poison the goblin: a status effect that ticks in the game loop until it wears off
+    let mut arena = GameState::new();
+    arena.add_status_effect(StatusEffectDef::new("poisoned", "Poisoned")
+        .with_duration(3.0)
+        .with_tick_interval(1.0)
+        .with_tick_damage(PHYSICAL, 2.0)
+        .with_modifier("damage", ModifierType::Additive, StatValue::Integer(-1)));
+    let goblin_id = EntityId::Npc(combat_npc.id.clone());
+    let mut poisoned_npc = NPC::new(combat_npc.id.clone(), combat_type.clone());
+    poisoned_npc.set_base_stat("hp", StatValue::Integer(combat_npc.get_int_stat("health").unwrap_or(0)));
+    arena.add_npc(poisoned_npc).expect("the arena starts empty");
+    arena.apply_status_effect(&goblin_id, "poisoned", Some(EntityId::Player)).expect("poison is defined and the goblin is in the arena");
+    println!("Applied poison to {}, dealing 2 damage per second for 3 seconds", combat_npc.npc_type.name);
+    for _ in 0..3 {
+        arena.update(1.0);
+        let goblin = arena.get_npc(&combat_npc.id).expect("the goblin is in the arena");
+        println!("  {} has {} hp{}", goblin.npc_type.name, goblin.get_int_stat("hp").unwrap_or(0),
+            if goblin.has_status_effect("poisoned") { " and is still poisoned" } else { " and the poison wore off" });
+    }
     
     // Fan adoration game mechanics using the same NPC system
     let mut fan_npc = NPC::new("Sammy the Superfan".to_string(), fan_type.clone());
@@ -260,7 +272,7 @@ pub fn demo_game_state() {
         .with_property("combat_style", "melee")
         .with_property("monster", "true");
     
-    game_state.entity_types.insert("goblin".to_string(), goblin_type.clone());
+    game_state.add_entity_type(goblin_type.clone());
     
     // Add some NPCs
     let mut goblin = NPC::new("Goblin Guard".to_string(), goblin_type);
@@ -268,7 +280,7 @@ pub fn demo_game_state() {
     goblin.base_stats_mut().set("damage", crate::stats::StatValue::Integer(5));
     goblin.position.set(0, 10.0);
     goblin.position.set(1, 15.0);
-    game_state.npcs.push(goblin);
+    game_state.add_npc(goblin).expect("the game state starts without NPCs");
     
     println!("Added 1 NPC to the game state");
     
@@ -361,19 +373,19 @@ pub fn demo_asset_management() {
     match asset_manager.duplicate_asset("test_image", "transformed_image") {
         Ok(_) => {
             // Apply a transformation to the duplicated asset
-            if let Ok(_) = asset_manager.transform_asset("transformed_image", |asset| {
+            if asset_manager.transform_asset("transformed_image", |asset| {
                 // Simulated transformation - just change metadata
                 asset.metadata.insert("width".to_string(), "256".to_string());
                 asset.metadata.insert("height".to_string(), "256".to_string());
                 asset.metadata.insert("transformed".to_string(), "true".to_string());
                 // In a real implementation, we would modify the actual asset data
-            }) {
-                if let Some(asset) = asset_manager.get_asset("transformed_image") {
-                    println!("- Name: {}", asset.name);
-                    println!("- Metadata after transformation:");
-                    for (key, value) in &asset.metadata {
-                        println!("  * {}: {}", key, value);
-                    }
+            }).is_ok()
+                && let Some(asset) = asset_manager.get_asset("transformed_image")
+            {
+                println!("- Name: {}", asset.name);
+                println!("- Metadata after transformation:");
+                for (key, value) in &asset.metadata {
+                    println!("  * {}: {}", key, value);
                 }
             }
         },
@@ -0,0 +1,351 @@
+//! Component view of the player and NPCs.
+//!
+//! Entities are identified by `EntityId`. Each one has some of these components:
+//!
+//! | Component       | Data                                   | Player | NPC |
+//! |-----------------|----------------------------------------|--------|-----|
+//! | `Position`      | `Coordinates`                          | yes    | yes |
+//! | `Stats`         | `CalculatedStats`                      | yes    | yes |
+//! | `Inventory`     | `inventory::Inventory`                 | yes    | yes |
+//! | `Tags`          | type tags and instance tags            | yes    | yes |
+//! | `StatusEffects` | effect names                           | yes    | yes |
+//! | `Behavior`      | behavior state                         | no     | yes |
+//! | `Perception`    | `perception::Perception`               | no     | yes |
+//!
+//! `GameState::query` and `GameState::query_mut` visit every entity that has all the
+//! components asked for, so systems can be written once for both kinds of entity:
+//!
+//! ```ignore
+//! for (id, (position, effects)) in game_state.query::<(ecs::Position, ecs::StatusEffects)>() { ... }
+//! for (_, (position, behavior)) in game_state.query_mut::<(ecs::Position, Option<ecs::Behavior>)>() { ... }
+//! ```
+//!
+//! `Character` and `NPC` own the data and stay the convenient way to work with a single
+//! entity; `components()` and `components_mut()` expose it to this layer.
+//!
+//! This is a view over that storage, not component storage of its own: the set of
+//! components each kind of entity has is the fixed table above, and components cannot be
+//! added to or removed from an entity at runtime.
+//!
+//! `Tags` is read-only even in mutable queries. Tags change through the `GameState` tag
+//! methods (`add_instance_tag`, `add_tag_to_npc`, ...), which keep the tag index current.
+
+use std::collections::HashSet;
+use crate::calculated_stats::CalculatedStats;
+use crate::coordinates::Coordinates;
+use crate::inventory::Inventory as ItemInventory;
+use crate::perception::Perception as PerceptionState;
+use crate::tag::InstanceTags;
+
+/// Shared borrows of every component an entity has
+#[derive(Clone, Copy)]
+pub struct Components<'a> {
+    pub(crate) position: &'a Coordinates,
+    pub(crate) stats: &'a CalculatedStats,
+    pub(crate) inventory: &'a ItemInventory,
+    pub(crate) type_tags: Option<&'a HashSet<i32>>,
+    pub(crate) instance_tags: &'a InstanceTags,
+    pub(crate) status_effects: &'a Vec<String>,
+    pub(crate) behavior: Option<&'a String>,
+    pub(crate) perception: Option<&'a PerceptionState>,
+}
+
+impl<'a> Components<'a> {
+    /// One component, if the entity has it
+    pub fn get<C: Component>(&self) -> Option<C::Ref<'a>> {
+        C::fetch(self)
+    }
+
+    /// A combination of components, if the entity has all of them
+    pub fn query<Q: Query>(&self) -> Option<Q::Item<'a>> {
+        Q::fetch(self)
+    }
+}
+
+/// Exclusive borrows of an entity's components. Each component can be taken out once, which
+/// is what lets a query hand out several of them at the same time.
+pub struct ComponentsMut<'a> {
+    pub(crate) position: Option<&'a mut Coordinates>,
+    pub(crate) stats: Option<&'a mut CalculatedStats>,
+    pub(crate) inventory: Option<&'a mut ItemInventory>,
+    pub(crate) tags: Option<(Option<&'a mut HashSet<i32>>, &'a mut InstanceTags)>,
+    pub(crate) status_effects: Option<&'a mut Vec<String>>,
+    pub(crate) behavior: Option<&'a mut String>,
+    pub(crate) perception: Option<&'a mut PerceptionState>,
+}
+
+impl<'a> ComponentsMut<'a> {
+    /// Take one component, if the entity has it and it has not been taken yet
+    pub fn take<C: Component>(&mut self) -> Option<C::Mut<'a>> {
+        C::fetch_mut(self)
+    }
+
+    /// Take a combination of components
+    pub fn query<Q: Query>(mut self) -> Option<Q::ItemMut<'a>> {
+        Q::fetch_mut(&mut self)
+    }
+}
+
+/// A kind of entity data that queries can ask for
+pub trait Component {
+    type Ref<'a>;
+    type Mut<'a>;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Ref<'a>>;
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::Mut<'a>>;
+}
+
+/// Where the entity is
+pub struct Position;
+/// Base stats and modifiers
+pub struct Stats;
+/// Items carried
+pub struct Inventory;
+/// Type tags and instance tags
+pub struct Tags;
+/// Named status effects such as "poisoned"
+pub struct StatusEffects;
+/// The NPC behavior state
+pub struct Behavior;
+/// What an NPC sees and remembers
+pub struct Perception;
+
+/// Type tags (if the entity has a type) and instance tags
+#[derive(Clone, Copy)]
+pub struct TagsRef<'a> {
+    pub type_tags: Option<&'a HashSet<i32>>,
+    pub instance: &'a InstanceTags,
+}
+
+impl TagsRef<'_> {
+    pub fn contains(&self, tag_id: i32) -> bool {
+        self.type_tags.is_some_and(|tags| tags.contains(&tag_id)) || self.instance.contains(tag_id)
+    }
+
+    /// Type tags and instance tags combined
+    pub fn ids(&self) -> HashSet<i32> {
+        self.type_tags.into_iter().flatten().copied().chain(self.instance.ids()).collect()
+    }
+}
+
+impl Component for Position {
+    type Ref<'a> = &'a Coordinates;
+    type Mut<'a> = &'a mut Coordinates;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Ref<'a>> {
+        Some(components.position)
+    }
+
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::Mut<'a>> {
+        components.position.take()
+    }
+}
+
+impl Component for Stats {
+    type Ref<'a> = &'a CalculatedStats;
+    type Mut<'a> = &'a mut CalculatedStats;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Ref<'a>> {
+        Some(components.stats)
+    }
+
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::Mut<'a>> {
+        components.stats.take()
+    }
+}
+
+impl Component for Inventory {
+    type Ref<'a> = &'a ItemInventory;
+    type Mut<'a> = &'a mut ItemInventory;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Ref<'a>> {
+        Some(components.inventory)
+    }
+
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::Mut<'a>> {
+        components.inventory.take()
+    }
+}
+
+impl Component for Tags {
+    type Ref<'a> = TagsRef<'a>;
+    // Shared even when fetched mutably, so tag edits cannot skip the tag index
+    type Mut<'a> = TagsRef<'a>;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Ref<'a>> {
+        Some(TagsRef { type_tags: components.type_tags, instance: components.instance_tags })
+    }
+
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::Mut<'a>> {
+        components.tags.take().map(|(type_tags, instance)| TagsRef {
+            type_tags: type_tags.map(|tags| &*tags),
+            instance: &*instance,
+        })
+    }
+}
+
+impl Component for StatusEffects {
+    type Ref<'a> = &'a [String];
+    type Mut<'a> = &'a mut Vec<String>;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Ref<'a>> {
+        Some(components.status_effects.as_slice())
+    }
+
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::Mut<'a>> {
+        components.status_effects.take()
+    }
+}
+
+impl Component for Behavior {
+    type Ref<'a> = &'a str;
+    type Mut<'a> = &'a mut String;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Ref<'a>> {
+        components.behavior.map(|state| state.as_str())
+    }
+
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::Mut<'a>> {
+        components.behavior.take()
+    }
+}
+
+impl Component for Perception {
+    type Ref<'a> = &'a PerceptionState;
+    type Mut<'a> = &'a mut PerceptionState;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Ref<'a>> {
+        components.perception
+    }
+
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::Mut<'a>> {
+        components.perception.take()
+    }
+}
+
+/// A component, an `Option` of one (present or not, never filters), or a tuple of up to
+/// four of those
+pub trait Query {
+    type Item<'a>;
+    type ItemMut<'a>;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Item<'a>>;
+    /// Fails if a component is missing or asked for twice
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::ItemMut<'a>>;
+}
+
+impl<C: Component> Query for C {
+    type Item<'a> = C::Ref<'a>;
+    type ItemMut<'a> = C::Mut<'a>;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Item<'a>> {
+        C::fetch(components)
+    }
+
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::ItemMut<'a>> {
+        C::fetch_mut(components)
+    }
+}
+
+impl<C: Component> Query for Option<C> {
+    type Item<'a> = Option<C::Ref<'a>>;
+    type ItemMut<'a> = Option<C::Mut<'a>>;
+
+    fn fetch<'a>(components: &Components<'a>) -> Option<Self::Item<'a>> {
+        Some(C::fetch(components))
+    }
+
+    fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::ItemMut<'a>> {
+        Some(C::fetch_mut(components))
+    }
+}
+
+macro_rules! tuple_query {
+    ($($name:ident),+) => {
+        impl<$($name: Query),+> Query for ($($name,)+) {
+            type Item<'a> = ($($name::Item<'a>,)+);
+            type ItemMut<'a> = ($($name::ItemMut<'a>,)+);
+
+            fn fetch<'a>(components: &Components<'a>) -> Option<Self::Item<'a>> {
+                Some(($($name::fetch(components)?,)+))
+            }
+
+            fn fetch_mut<'a>(components: &mut ComponentsMut<'a>) -> Option<Self::ItemMut<'a>> {
+                Some(($($name::fetch_mut(components)?,)+))
+            }
+        }
+    };
+}
+
+tuple_query!(A, B);
+tuple_query!(A, B, C);
+tuple_query!(A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::game_state::{EntityId, GameState};
+    use crate::inventory::Item;
+    use crate::npc::NPC;
+
+    fn game() -> GameState {
+        let mut game_state = GameState::new();
+        let mut wolf = NPC::new("wolf1".to_string(), EntityType::new("wolf", "Wolf").with_tag_id(3));
+        wolf.add_status_effect("poisoned");
+        game_state.add_npc(wolf).unwrap();
+        game_state.add_npc(NPC::new("wolf2".to_string(), EntityType::new("wolf", "Wolf"))).unwrap();
+        game_state.player.add_status_effect("poisoned");
+        game_state
+    }
+
+    #[test]
+    fn test_queries_cover_player_and_npcs() {
+        let game_state = game();
+        let poisoned: Vec<EntityId> = game_state.query::<(Position, StatusEffects)>().into_iter()
+            .filter(|(_, (_, effects))| effects.iter().any(|e| e == "poisoned"))
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(poisoned, vec![EntityId::Player, EntityId::Npc("wolf1".to_string())]);
+
+        // The player has no behavior, so only NPCs match
+        let behaving = game_state.query::<(Behavior, Tags)>();
+        assert_eq!(behaving.len(), 2);
+        assert!(behaving[0].1.1.contains(3));
+        let optional = game_state.query::<(Position, Option<Behavior>)>();
+        assert_eq!(optional[0].1.1, None);
+        assert_eq!(optional[1].1.1, Some("idle"));
+    }
+
+    #[test]
+    fn test_mutable_queries() {
+        let mut game_state = game();
+        for (_, (position, inventory)) in game_state.query_mut::<(Position, Inventory)>() {
+            position.set(0, 5.0);
+            inventory.add_item(Item::new("bone", "Bone"));
+        }
+        assert_eq!(game_state.player.x(), 5.0);
+        assert!(game_state.get_npc("wolf2").unwrap().inventory.has_item("bone"));
+
+        if let Some(state) = game_state.component_mut::<Behavior>(&EntityId::Npc("wolf2".to_string())) {
+            *state = "hunting".to_string();
+        }
+        assert_eq!(game_state.component::<Behavior>(&EntityId::Npc("wolf2".to_string())), Some("hunting"));
+        assert!(game_state.component::<Behavior>(&EntityId::Player).is_none());
+
+        // A component can only be borrowed once per entity
+        assert!(game_state.query_mut::<(Position, Position)>().is_empty());
+
+        // Tags are read-only in mutable queries; they change through GameState so the
+        // tag index sees every edit
+        let slowed = game_state.tag_collection.add_tag("slowed");
+        let npcs: Vec<EntityId> = game_state.query_mut::<(Tags, Behavior)>().into_iter()
+            .filter(|(_, (tags, _))| !tags.contains(3))
+            .map(|(id, _)| id)
+            .collect();
+        for id in &npcs {
+            assert!(game_state.add_instance_tag(id, slowed, None));
+        }
+        assert_eq!(game_state.tag_index.npcs_with_tag(slowed), vec!["wolf2"]);
+    }
+}
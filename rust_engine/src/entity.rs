@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+use crate::calculated_stats::{CalculatedStats, ModifierType, StatModifier};
+use crate::character::Character;
+use crate::condition::EntityRef;
+use crate::coordinates::Coordinates;
+use crate::ecs::{Components, ComponentsMut};
+use crate::entity_type::EntityType;
+use crate::game_state::EntityId;
+use crate::inventory::Inventory;
+use crate::npc::NPC;
+use crate::stats::StatValue;
+use crate::tag::{InstanceTags, TagCollection};
+
+/// What the player and NPCs have in common: position, stats and modifiers, tags, status
+/// effects and inventory. Helpers written against this trait work for both.
+///
+/// Implementors only provide identity, type, contexts and their components; everything
+/// else is built on those.
+pub trait Entity {
+    fn entity_id(&self) -> EntityId;
+
+    fn components(&self) -> Components<'_>;
+
+    /// The entity's type (for the player, its optional character type)
+    fn entity_type(&self) -> Option<&EntityType>;
+
+    /// Contexts the entity is in, oldest first
+    fn active_contexts(&self) -> &[String];
+
+    fn position(&self) -> &Coordinates {
+        self.components().position
+    }
+
+    /// Distance to another entity, NaN if their positions have different dimensions
+    fn distance_to(&self, other: &dyn Entity) -> f32 {
+        self.position().distance(other.position())
+    }
+
+    fn stats(&self) -> &CalculatedStats {
+        self.components().stats
+    }
+
+    /// Calculated stat value (base stats with modifiers applied)
+    fn get_stat(&self, key: &str) -> Option<StatValue> {
+        self.stats().get(key)
+    }
+
+    fn get_int_stat(&self, key: &str) -> Option<i32> {
+        self.stats().get_int(key)
+    }
+
+    fn get_float_stat(&self, key: &str) -> Option<f32> {
+        self.stats().get_float(key)
+    }
+
+    /// Modifiers currently applied to a stat
+    fn get_stat_modifiers(&self, stat: &str) -> &[StatModifier] {
+        self.stats().get_modifiers(stat)
+    }
+
+    fn instance_tags(&self) -> &InstanceTags {
+        self.components().instance_tags
+    }
+
+    /// Tags of the entity's type together with its instance tags
+    fn tag_ids(&self) -> HashSet<i32> {
+        let components = self.components();
+        components.type_tags.into_iter().flatten().copied().chain(components.instance_tags.ids()).collect()
+    }
+
+    fn has_tag_id(&self, tag_id: i32) -> bool {
+        let components = self.components();
+        components.type_tags.is_some_and(|tags| tags.contains(&tag_id)) || components.instance_tags.contains(tag_id)
+    }
+
+    /// Check for a tag or one of its descendants
+    fn has_tag_id_in_hierarchy(&self, tag_id: i32, tag_collection: &TagCollection) -> bool {
+        self.tag_ids().into_iter().any(|id| tag_collection.is_a(id, tag_id))
+    }
+
+    fn status_effects(&self) -> &[String] {
+        self.components().status_effects
+    }
+
+    fn has_status_effect(&self, effect: &str) -> bool {
+        self.status_effects().iter().any(|e| e == effect)
+    }
+
+    /// Current behavior state, if the entity has one
+    fn behavior_state(&self) -> Option<&str> {
+        self.components().behavior.map(|state| state.as_str())
+    }
+
+    fn inventory(&self) -> &Inventory {
+        self.components().inventory
+    }
+
+    fn has_item(&self, item_id: &str) -> bool {
+        self.inventory().has_item(item_id)
+    }
+
+    fn has_item_equipped(&self, item_id: &str) -> bool {
+        self.inventory().get_item(item_id)
+            .and_then(|item| item.get_bool("equipped"))
+            .unwrap_or(false)
+    }
+}
+
+/// Changing an entity through the shared components
+pub trait EntityMut: Entity {
+    fn components_mut(&mut self) -> ComponentsMut<'_>;
+
+    fn position_mut(&mut self) -> &mut Coordinates {
+        self.components_mut().position.expect("every entity has a position")
+    }
+
+    /// Move toward a position by a distance, returns false if the positions do not match up
+    fn move_toward(&mut self, target: &Coordinates, distance: f32) -> bool {
+        self.position_mut().move_toward(target, distance)
+    }
+
+    fn stats_mut(&mut self) -> &mut CalculatedStats {
+        self.components_mut().stats.expect("every entity has stats")
+    }
+
+    fn set_base_stat(&mut self, key: &str, value: StatValue) {
+        self.stats_mut().base_stats_mut().set(key, value);
+    }
+
+    fn add_stat_modifier(&mut self, stat: &str, source: &str, modifier_type: ModifierType, value: StatValue, priority: i32) {
+        self.stats_mut().add_modifier(stat, StatModifier { source: source.to_string(), modifier_type, value, priority });
+    }
+
+    fn remove_stat_modifiers_by_source_prefix(&mut self, prefix: &str) {
+        self.stats_mut().remove_modifiers_by_source_prefix(prefix);
+    }
+
+    fn instance_tags_mut(&mut self) -> &mut InstanceTags {
+        self.components_mut().tags.expect("every entity has tags").1
+    }
+
+    fn status_effects_mut(&mut self) -> &mut Vec<String> {
+        self.components_mut().status_effects.expect("every entity has status effects")
+    }
+
+    fn add_status_effect(&mut self, effect: &str) {
+        if !self.has_status_effect(effect) {
+            self.status_effects_mut().push(effect.to_string());
+        }
+    }
+
+    fn remove_status_effect(&mut self, effect: &str) {
+        self.status_effects_mut().retain(|e| e != effect);
+    }
+
+    /// Direct inventory access. Unlike `Character::add_item`, this does not refresh item stats.
+    fn inventory_mut(&mut self) -> &mut Inventory {
+        self.components_mut().inventory.expect("every entity has an inventory")
+    }
+}
+
+impl Entity for Character {
+    fn entity_id(&self) -> EntityId {
+        EntityId::Player
+    }
+
+    fn components(&self) -> Components<'_> {
+        Character::components(self)
+    }
+
+    fn entity_type(&self) -> Option<&EntityType> {
+        self.character_type.as_ref()
+    }
+
+    fn active_contexts(&self) -> &[String] {
+        self.active_contexts.as_slice()
+    }
+}
+
+impl EntityMut for Character {
+    fn components_mut(&mut self) -> ComponentsMut<'_> {
+        Character::components_mut(self)
+    }
+}
+
+impl Entity for NPC {
+    fn entity_id(&self) -> EntityId {
+        EntityId::Npc(self.id.clone())
+    }
+
+    fn components(&self) -> Components<'_> {
+        NPC::components(self)
+    }
+
+    fn entity_type(&self) -> Option<&EntityType> {
+        Some(&self.npc_type)
+    }
+
+    fn active_contexts(&self) -> &[String] {
+        self.active_contexts.as_slice()
+    }
+}
+
+impl EntityMut for NPC {
+    fn components_mut(&mut self) -> ComponentsMut<'_> {
+        NPC::components_mut(self)
+    }
+}
+
+impl Entity for EntityRef<'_> {
+    fn entity_id(&self) -> EntityId {
+        match self {
+            EntityRef::Character(character) => character.entity_id(),
+            EntityRef::Npc(npc) => Entity::entity_id(*npc),
+        }
+    }
+
+    fn components(&self) -> Components<'_> {
+        EntityRef::components(self)
+    }
+
+    fn entity_type(&self) -> Option<&EntityType> {
+        EntityRef::entity_type(self)
+    }
+
+    fn active_contexts(&self) -> &[String] {
+        EntityRef::active_contexts(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Written once, works for the player and NPCs alike
+    fn poison<E: EntityMut>(entity: &mut E) {
+        entity.add_status_effect("poisoned");
+        entity.add_stat_modifier("speed", "poison", ModifierType::Multiplicative, StatValue::Float(0.5), 0);
+    }
+
+    #[test]
+    fn test_shared_behaviour_for_player_and_npcs() {
+        let mut player = Character::new();
+        player.set_base_stat("speed", StatValue::Float(4.0));
+        let mut wolf = NPC::new("wolf1".to_string(), EntityType::new("wolf", "Wolf").with_tag_id(2));
+        EntityMut::set_base_stat(&mut wolf, "speed", StatValue::Float(6.0));
+        wolf.instance_tags_mut().add(5);
+
+        poison(&mut player);
+        poison(&mut wolf);
+        assert!(player.has_status_effect("poisoned"));
+        assert_eq!(Entity::get_float_stat(&wolf, "speed"), Some(3.0));
+        assert_eq!(Entity::tag_ids(&wolf), HashSet::from([2, 5]));
+
+        let entities: [&dyn Entity; 2] = [&player, &wolf];
+        assert_eq!(entities[1].entity_id(), EntityId::Npc("wolf1".to_string()));
+        assert_eq!(entities[0].get_stat_modifiers("speed")[0].source, "poison");
+        assert_eq!(entities[0].behavior_state(), None);
+        assert_eq!(EntityRef::Npc(&wolf).entity_id(), wolf.entity_id());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_generic_helpers() {
+        use crate::utils::{calculate_damage, find_entities_in_radius, has_line_of_sight};
+
+        let player = Character::new();
+        let mut wolf = NPC::new("wolf1".to_string(), EntityType::new("wolf", "Wolf"));
+        wolf.position = Coordinates::new_2d(4.0, 0.0);
+
+        // Multipliers only, truncated; combat::resolve_attack applies attack and defense
+        assert_eq!(calculate_damage(&player, &wolf, 10, &[2.0]), 20);
+        assert_eq!(calculate_damage(&wolf, &player, 7, &[1.5, 0.5]), 5);
+
+        let entities: [&dyn Entity; 2] = [&player, &wolf];
+        assert_eq!(find_entities_in_radius(&entities, &Coordinates::new_2d(3.0, 0.0), 1.5).len(), 1);
+
+        let rock = (Coordinates::new_2d(2.0, 0.5), 0.75);
+        assert!(!has_line_of_sight(&player, &wolf, std::slice::from_ref(&rock)));
+        assert!(has_line_of_sight(&player, &wolf, &[(Coordinates::new_2d(2.0, 1.0), 0.75)]));
+        assert!(!has_line_of_sight(&player, &wolf, &[(Coordinates::new_2d(2.0, 1.0), 0.75), rock]));
+    }
+}
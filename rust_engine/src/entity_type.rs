@@ -1,7 +1,9 @@
 use std::collections::{HashMap, HashSet};
 use crate::tag::{Tag, TagCollection};
 use crate::property::{Property, PropertyValue, PropertyType};
-use crate::stats::StatValue;
+use crate::context::{match_properties, ContextMatch};
+use crate::prefab::Provenance;
+use crate::spawner::{SpawnProfile, StatTemplate};
 use serde::{Serialize, Deserialize};
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -16,6 +18,34 @@ pub struct EntityType {
     
     // Additional properties that don't belong to any tag
     pub properties: Vec<Property>,
+    
+    // ID of the base type this one extends, see prefab::resolve_entity_types
+    #[serde(default)]
+    pub extends: Option<String>,
+    
+    // Where inherited parts came from, set once the type has been resolved
+    #[serde(default)]
+    pub provenance: Option<Provenance>,
+    
+    // Base stats, starting items and behavior for spawned NPCs
+    #[serde(default)]
+    pub spawn: SpawnProfile,
+    
+    // ID of the state machine driving this type's NPCs, see state_machine::StateMachine
+    #[serde(default)]
+    pub state_machine: Option<String>,
+    
+    // ID of the behavior tree driving this type's NPCs, see behavior_tree::BehaviorTree
+    #[serde(default)]
+    pub behavior_tree: Option<String>,
+    
+    // ID of the utility AI choosing this type's NPCs' actions, see utility::UtilityAi
+    #[serde(default)]
+    pub utility_ai: Option<String>,
+    
+    // ID of the faction this type's entities belong to, see faction::Faction
+    #[serde(default)]
+    pub faction: Option<String>,
 }
 
 impl EntityType {
@@ -27,6 +57,13 @@ impl EntityType {
             category: None,
             tag_ids: HashSet::new(),
             properties: Vec::new(),
+            extends: None,
+            provenance: None,
+            spawn: SpawnProfile::default(),
+            state_machine: None,
+            behavior_tree: None,
+            utility_ai: None,
+            faction: None,
         }
     }
     
@@ -40,6 +77,134 @@ impl EntityType {
         self
     }
     
+    // Extend a base type, inheriting its tags, category, description and properties
+    pub fn with_base(mut self, base_id: &str) -> Self {
+        self.extends = Some(base_id.to_string());
+        self
+    }
+    
+    // Check if this type is, or was resolved from, the given type
+    pub fn inherits_from(&self, type_id: &str) -> bool {
+        match &self.provenance {
+            Some(provenance) => provenance.chain.iter().any(|id| id == type_id),
+            None => self.id == type_id || self.extends.as_deref() == Some(type_id),
+        }
+    }
+    
+    // ID of the type that declared a property, by index into `properties`
+    pub fn property_origin(&self, index: usize) -> Option<&str> {
+        if index >= self.properties.len() {
+            return None;
+        }
+        let origin = self.provenance.as_ref().and_then(|p| p.properties.get(index));
+        Some(origin.map(|id| id.as_str()).unwrap_or(&self.id))
+    }
+    
+    // Properties this type declares itself rather than inherits
+    pub fn own_properties(&self) -> Vec<&Property> {
+        self.properties.iter()
+            .enumerate()
+            .filter(|(index, _)| self.property_origin(*index) == Some(self.id.as_str()))
+            .map(|(_, property)| property)
+            .collect()
+    }
+    
+    // The type as declared, without anything inherited. Parts added after resolution
+    // count as declared.
+    pub fn declaration(&self) -> EntityType {
+        let Some(provenance) = &self.provenance else {
+            return self.clone();
+        };
+        let is_own = |origin: Option<&String>| origin.is_none_or(|id| *id == self.id);
+        
+        let mut declaration = self.clone();
+        declaration.properties = self.own_properties().into_iter().cloned().collect();
+        declaration.tag_ids = self.tag_ids.iter()
+            .filter(|tag_id| is_own(provenance.tags.get(tag_id)))
+            .copied()
+            .collect();
+        if !is_own(provenance.description.as_ref()) {
+            declaration.description = None;
+        }
+        if !is_own(provenance.category.as_ref()) {
+            declaration.category = None;
+        }
+        declaration.spawn.stats.retain(|key, _| is_own(provenance.spawn_stats.get(key)));
+        declaration.spawn.items = self.spawn.items.iter()
+            .enumerate()
+            .filter(|(index, _)| is_own(provenance.spawn_items.get(*index)))
+            .map(|(_, item_id)| item_id.clone())
+            .collect();
+        if !is_own(provenance.spawn_dimensions.as_ref()) {
+            declaration.spawn.dimensions = None;
+        }
+        if !is_own(provenance.spawn_behavior.as_ref()) {
+            declaration.spawn.behavior = None;
+        }
+        if !is_own(provenance.state_machine.as_ref()) {
+            declaration.state_machine = None;
+        }
+        if !is_own(provenance.behavior_tree.as_ref()) {
+            declaration.behavior_tree = None;
+        }
+        if !is_own(provenance.utility_ai.as_ref()) {
+            declaration.utility_ai = None;
+        }
+        if !is_own(provenance.faction.as_ref()) {
+            declaration.faction = None;
+        }
+        declaration.provenance = None;
+        declaration
+    }
+    
+    // Set how a base stat is rolled for spawned NPCs
+    pub fn with_stat_template(mut self, key: &str, template: StatTemplate) -> Self {
+        self.spawn.stats.insert(key.to_string(), template);
+        self
+    }
+    
+    // Require spawned NPCs to have positions with this many dimensions
+    pub fn with_dimensions(mut self, dimensions: usize) -> Self {
+        self.spawn.dimensions = Some(dimensions);
+        self
+    }
+    
+    // Give spawned NPCs an item, by item template ID
+    pub fn with_starting_item(mut self, item_id: &str) -> Self {
+        self.spawn.items.push(item_id.to_string());
+        self
+    }
+    
+    // Set the behavior state spawned NPCs start in
+    pub fn with_starting_behavior(mut self, behavior: &str) -> Self {
+        self.spawn.behavior = Some(behavior.to_string());
+        self
+    }
+    
+    // Drive this type's NPCs with a state machine registered in the game state
+    pub fn with_state_machine(mut self, machine_id: &str) -> Self {
+        self.state_machine = Some(machine_id.to_string());
+        self
+    }
+    
+    // Drive this type's NPCs with a behavior tree registered in the game state
+    pub fn with_behavior_tree(mut self, tree_id: &str) -> Self {
+        self.behavior_tree = Some(tree_id.to_string());
+        self
+    }
+    
+    // Put this type's entities in a faction registered in the game state
+    pub fn with_faction(mut self, faction_id: &str) -> Self {
+        self.faction = Some(faction_id.to_string());
+        self
+    }
+    
+    // Let a utility AI registered in the game state choose this type's NPCs' actions
+    pub fn with_utility_ai(mut self, ai_id: &str) -> Self {
+        self.utility_ai = Some(ai_id.to_string());
+        self
+    }
+    
     // Add a tag by ID
     pub fn with_tag_id(mut self, tag_id: i32) -> Self {
         self.tag_ids.insert(tag_id);
@@ -62,6 +227,7 @@ impl EntityType {
             context: vec!["entity".to_string()],
             conditions: Vec::new(),
             metadata: HashMap::new(),
+            exclusive: false,
         };
         self.properties.push(property);
         self
@@ -85,10 +251,10 @@ impl EntityType {
     
     // Get property value as text
     pub fn get_property_value(&self, key: &str) -> Option<&str> {
-        if let Some(property) = self.get_property(key) {
-            if let PropertyValue::Text(text) = &property.value {
-                return Some(text);
-            }
+        if let Some(property) = self.get_property(key)
+            && let PropertyValue::Text(text) = &property.value
+        {
+            return Some(text);
         }
         None
     }
@@ -98,6 +264,11 @@ impl EntityType {
         self.tag_ids.contains(&tag_id)
     }
     
+    // Check if entity has a tag, directly or through a tag that inherits from it
+    pub fn has_tag_id_in_hierarchy(&self, tag_id: i32, tag_collection: &TagCollection) -> bool {
+        self.tag_ids.iter().any(|&id| tag_collection.is_a(id, tag_id))
+    }
+    
     // Get all tags this entity has (needs TagCollection to resolve IDs to Tags)
     pub fn get_tags<'a>(&self, tag_collection: &'a TagCollection) -> Vec<&'a Tag> {
         self.tag_ids.iter()
@@ -113,6 +284,21 @@ impl EntityType {
             .collect()
     }
     
+    // Get tag properties including inherited ones, each with the tag that declares it.
+    // Tags that are ancestors of another tag of this entity are covered by that tag's lineage.
+    pub fn get_inherited_tag_properties<'a>(&self, tag_collection: &'a TagCollection) -> Vec<(&'a Tag, &'a Property)> {
+        tag_collection.resolved_properties_for_tags(&self.tag_ids)
+    }
+    
+    // Get properties of all tags of this entity in a specific context, including inherited ones
+    pub fn get_tag_properties_in_context_in_hierarchy<'a>(&self, tag_collection: &'a TagCollection, context: &str) -> Vec<&'a Property> {
+        self.get_inherited_tag_properties(tag_collection)
+            .into_iter()
+            .map(|(_, property)| property)
+            .filter(|p| p.applies_in_context(context))
+            .collect()
+    }
+    
     // Get entity properties in a specific context
     pub fn get_properties_in_context(&self, context: &str) -> Vec<&Property> {
         self.properties.iter()
@@ -131,6 +317,15 @@ impl EntityType {
         properties
     }
     
+    // Get properties (inherited tag properties, then the entity's own) applying in any of the
+    // active contexts, each with the contexts that matched
+    pub fn match_properties_in_contexts<'a>(&'a self, tag_collection: &'a TagCollection, active_contexts: &[String]) -> Vec<ContextMatch<'a>> {
+        let tag_properties = self.get_inherited_tag_properties(tag_collection)
+            .into_iter()
+            .map(|(_, property)| property);
+        match_properties(tag_properties.chain(self.properties.iter()), active_contexts)
+    }
+    
     // Helper method to add tag by name (needs mutable TagCollection to register new tags if needed)
     pub fn with_tag_by_name(self, tag_name: &str, tag_collection: &mut TagCollection) -> Self {
         // Get existing tag ID or create new tag
@@ -0,0 +1,514 @@
+//! Engine event bus and the Trigger/Reaction properties that subscribe to it.
+//!
+//! Engine code emits `GameEvent`s into `GameState::events`; `GameState::dispatch_events`
+//! (run by `update` and after every command) delivers them to listeners registered from code
+//! and to Trigger and Reaction properties on the tags, entity types and equipped items of the
+//! player and NPCs.
+//!
+//! A property subscribes through its metadata:
+//! - `event`: the event kind, e.g. "damaged" (required)
+//! - `scope`: "self" (events about the owner, or about no entity), "other" (events about
+//!   another entity) or "any". Reactions default to "self", triggers to "any".
+//! - `filter.<field>`: the event field must match, e.g. `filter.amount = ">=10"`. Values
+//!   may start with `=`, `!=`, `<`, `<=`, `>` or `>=`; numeric fields compare as numbers.
+//!
+//! The property's contexts and conditions are checked against the owner. A `Function` value
+//! is invoked with the owner as caster and the other entity involved as target; a `Script`
+//! runs with `event` bound to the event kind and every field bound as `event_<field>`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use crate::condition::EntityRef;
+use crate::coordinates::Coordinates;
+use crate::functions::FunctionCall;
+use crate::game_state::{EntityId, GameState};
+use crate::property::{Property, PropertyType, PropertyValue};
+use crate::property_modifiers::applies_in_any_context;
+use crate::script::ScriptValue;
+
+/// Metadata key naming the event kind a Trigger or Reaction subscribes to
+pub const EVENT_METADATA: &str = "event";
+/// Metadata key choosing whose events a Trigger or Reaction hears
+pub const SCOPE_METADATA: &str = "scope";
+/// Prefix of metadata keys filtering on event fields
+pub const FILTER_PREFIX: &str = "filter.";
+/// Events handled by one dispatch before the rest of a cascade is dropped
+pub const DEFAULT_MAX_EVENTS_PER_DISPATCH: usize = 1000;
+
+/// Something that happened in the game
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    /// An entity lost hit points
+    Damaged { entity: EntityId, amount: i32, source: Option<EntityId> },
+    /// An entity moved into a region
+    EnteredRegion { entity: EntityId, region: String },
+    /// An entity moved out of a region
+    LeftRegion { entity: EntityId, region: String },
+    /// An entity equipped an item
+    ItemEquipped { entity: EntityId, item_id: String },
+    /// An entity unequipped an item
+    ItemUnequipped { entity: EntityId, item_id: String },
+    /// An NPC was spawned from an entity type
+    Spawned { entity: EntityId, type_id: String },
+    /// A despawned NPC was removed from the game
+    Despawned { entity: EntityId, type_id: String },
+    /// An NPC's state machine moved it from one behavior state to another
+    StateChanged { entity: EntityId, from: String, to: String },
+    /// Something made a noise NPCs can hear; `loudness` scales how far it carries
+    Noise { source: Option<EntityId>, position: Coordinates, loudness: f32 },
+    /// The game state advanced by one update
+    Tick { delta_time: f32 },
+    /// A command was processed; `command` is its lowercased first word
+    CommandExecuted { command: String, line: String },
+    /// A game-specific event
+    Custom { name: String, entity: Option<EntityId>, data: HashMap<String, String> },
+}
+
+impl GameEvent {
+    /// Kind name properties subscribe to
+    pub fn kind(&self) -> &str {
+        match self {
+            GameEvent::Damaged { .. } => "damaged",
+            GameEvent::EnteredRegion { .. } => "entered_region",
+            GameEvent::LeftRegion { .. } => "left_region",
+            GameEvent::ItemEquipped { .. } => "item_equipped",
+            GameEvent::ItemUnequipped { .. } => "item_unequipped",
+            GameEvent::Spawned { .. } => "spawned",
+            GameEvent::Despawned { .. } => "despawned",
+            GameEvent::StateChanged { .. } => "state_changed",
+            GameEvent::Noise { .. } => "noise",
+            GameEvent::Tick { .. } => "tick",
+            GameEvent::CommandExecuted { .. } => "command_executed",
+            GameEvent::Custom { name, .. } => name,
+        }
+    }
+
+    /// The entity the event is about, if any
+    pub fn subject(&self) -> Option<&EntityId> {
+        match self {
+            GameEvent::Damaged { entity, .. }
+            | GameEvent::EnteredRegion { entity, .. }
+            | GameEvent::LeftRegion { entity, .. }
+            | GameEvent::ItemEquipped { entity, .. }
+            | GameEvent::ItemUnequipped { entity, .. }
+            | GameEvent::Spawned { entity, .. }
+            | GameEvent::Despawned { entity, .. }
+            | GameEvent::StateChanged { entity, .. } => Some(entity),
+            GameEvent::Tick { .. } | GameEvent::CommandExecuted { .. } => None,
+            GameEvent::Noise { source, .. } | GameEvent::Custom { entity: source, .. } => source.as_ref(),
+        }
+    }
+
+    /// Target for a subscriber's action: whoever caused the event, or else its subject
+    /// when that is someone other than the subscriber
+    pub fn target_for(&self, owner: &EntityId) -> Option<EntityId> {
+        if let GameEvent::Damaged { source: Some(source), .. } = self {
+            return Some(source.clone());
+        }
+        self.subject().filter(|subject| *subject != owner).cloned()
+    }
+
+    /// Named fields, as filters and scripts see them
+    pub fn fields(&self) -> Vec<(String, ScriptValue)> {
+        let entity = |id: &EntityId| ScriptValue::Entity(id.clone());
+        let text = |value: &str| ScriptValue::Str(value.to_string());
+        let mut fields = match self {
+            GameEvent::Damaged { entity: id, amount, source } => vec![
+                ("entity", entity(id)),
+                ("amount", ScriptValue::Int(*amount)),
+                ("source", source.as_ref().map(entity).unwrap_or(ScriptValue::Nil)),
+            ],
+            GameEvent::EnteredRegion { entity: id, region } | GameEvent::LeftRegion { entity: id, region } => vec![
+                ("entity", entity(id)),
+                ("region", text(region)),
+            ],
+            GameEvent::ItemEquipped { entity: id, item_id } | GameEvent::ItemUnequipped { entity: id, item_id } => vec![
+                ("entity", entity(id)),
+                ("item", text(item_id)),
+            ],
+            GameEvent::Spawned { entity: id, type_id } | GameEvent::Despawned { entity: id, type_id } => vec![
+                ("entity", entity(id)),
+                ("type", text(type_id)),
+            ],
+            GameEvent::StateChanged { entity: id, from, to } => vec![
+                ("entity", entity(id)),
+                ("from", text(from)),
+                ("to", text(to)),
+            ],
+            GameEvent::Noise { source, loudness, .. } => vec![
+                ("source", source.as_ref().map(entity).unwrap_or(ScriptValue::Nil)),
+                ("loudness", ScriptValue::Float(*loudness)),
+            ],
+            GameEvent::Tick { delta_time } => vec![("delta", ScriptValue::Float(*delta_time))],
+            GameEvent::CommandExecuted { command, line } => vec![
+                ("command", text(command)),
+                ("line", text(line)),
+            ],
+            GameEvent::Custom { entity: id, .. } => vec![
+                ("entity", id.as_ref().map(entity).unwrap_or(ScriptValue::Nil)),
+            ],
+        }
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect::<Vec<_>>();
+
+        if let GameEvent::Custom { data, .. } = self {
+            let mut data: Vec<_> = data.iter().collect();
+            data.sort();
+            fields.extend(data.into_iter().map(|(key, value)| (key.clone(), ScriptValue::Str(value.clone()))));
+        }
+        fields
+    }
+
+    /// Value of one field
+    pub fn field(&self, name: &str) -> Option<ScriptValue> {
+        self.fields().into_iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Identifies a listener registered from code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(u64);
+
+/// Code-side event handler
+pub type EventListener = Arc<dyn Fn(&mut GameState, &GameEvent) + Send + Sync>;
+
+/// Record of a Trigger or Reaction property firing
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerFiring {
+    /// Kind of the event that fired it
+    pub event: String,
+    /// Entity the property belongs to
+    pub owner: EntityId,
+    /// Where the property came from: "tag:<name>", "type:<id>" or "item:<id>"
+    pub source: String,
+    /// Message of the function or script output, or the error
+    pub result: Result<String, String>,
+}
+
+/// Queue of pending events plus the listeners registered from code
+#[derive(Clone)]
+pub struct EventBus {
+    queue: VecDeque<GameEvent>,
+    listeners: Vec<(ListenerId, Option<String>, EventListener)>,
+    next_listener: u64,
+    /// Cap on events handled per dispatch, so triggers emitting events cannot loop forever
+    pub max_events_per_dispatch: usize,
+    last_firings: Vec<TriggerFiring>,
+    dropped: usize,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus {
+            queue: VecDeque::new(),
+            listeners: Vec::new(),
+            next_listener: 0,
+            max_events_per_dispatch: DEFAULT_MAX_EVENTS_PER_DISPATCH,
+            last_firings: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Queue an event for the next dispatch
+    pub fn emit(&mut self, event: GameEvent) {
+        self.queue.push_back(event);
+    }
+
+    /// Number of events waiting to be dispatched
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Listen to every event of one kind, or to all events when `kind` is `None`
+    pub fn subscribe<F>(&mut self, kind: Option<&str>, listener: F) -> ListenerId
+    where
+        F: Fn(&mut GameState, &GameEvent) + Send + Sync + 'static,
+    {
+        self.next_listener += 1;
+        let id = ListenerId(self.next_listener);
+        self.listeners.push((id, kind.map(|k| k.to_string()), Arc::new(listener)));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|(listener, _, _)| *listener != id);
+        self.listeners.len() != before
+    }
+
+    /// Properties fired by the last dispatch, in order
+    pub fn last_firings(&self) -> &[TriggerFiring] {
+        &self.last_firings
+    }
+
+    /// Events dropped because a dispatch hit `max_events_per_dispatch`
+    pub fn dropped_events(&self) -> usize {
+        self.dropped
+    }
+
+    fn listeners_for(&self, kind: &str) -> Vec<EventListener> {
+        self.listeners.iter()
+            .filter(|(_, filter, _)| filter.as_deref().is_none_or(|k| k == kind))
+            .map(|(_, _, listener)| listener.clone())
+            .collect()
+    }
+}
+
+/// Deliver queued events, including the ones emitted while handling them. Returns the
+/// Trigger and Reaction properties that fired.
+pub fn dispatch_events(game_state: &mut GameState) -> Vec<TriggerFiring> {
+    let mut firings = Vec::new();
+    let mut handled = 0;
+
+    while let Some(event) = game_state.events.queue.pop_front() {
+        if handled >= game_state.events.max_events_per_dispatch {
+            game_state.events.dropped += game_state.events.queue.len() + 1;
+            game_state.events.queue.clear();
+            break;
+        }
+        handled += 1;
+
+        crate::perception::hear(game_state, &event);
+        crate::faction::apply_reputation_rules(game_state, &event);
+        for listener in game_state.events.listeners_for(event.kind()) {
+            listener(game_state, &event);
+        }
+
+        // Clone the subscriptions out so the actions can borrow the whole game state
+        for (owner, source, property) in subscriptions(game_state, &event) {
+            let result = fire(game_state, &event, &owner, &property);
+            firings.push(TriggerFiring { event: event.kind().to_string(), owner, source, result });
+        }
+    }
+
+    game_state.events.last_firings = firings.clone();
+    firings
+}
+
+/// Whose events a property hears, from its "scope" metadata or its type's default
+fn scope(property: &Property) -> &str {
+    match property.metadata.get(SCOPE_METADATA) {
+        Some(scope) => scope,
+        None if property.property_type == PropertyType::Reaction => "self",
+        None => "any",
+    }
+}
+
+/// Check whether a Trigger or Reaction property subscribes to an event seen by `owner`
+pub fn subscribes_to(property: &Property, event: &GameEvent, owner: &EntityId) -> bool {
+    if !matches!(property.property_type, PropertyType::Trigger | PropertyType::Reaction) {
+        return false;
+    }
+    if property.metadata.get(EVENT_METADATA).map(|kind| kind.as_str()) != Some(event.kind()) {
+        return false;
+    }
+
+    let in_scope = match scope(property) {
+        "self" => event.subject().is_none_or(|subject| subject == owner),
+        "other" => event.subject().is_some_and(|subject| subject != owner),
+        _ => true,
+    };
+    in_scope && property.metadata.iter()
+        .filter_map(|(key, expected)| key.strip_prefix(FILTER_PREFIX).map(|field| (field, expected)))
+        .all(|(field, expected)| event.field(field).is_some_and(|value| filter_matches(&value, expected)))
+}
+
+/// Compare an event field against a filter such as "goblin1", "!=camp" or ">=10"
+pub(crate) fn filter_matches(value: &ScriptValue, filter: &str) -> bool {
+    let (op, expected) = ["<=", ">=", "!=", "<", ">", "="].iter()
+        .find_map(|op| filter.strip_prefix(op).map(|rest| (*op, rest.trim())))
+        .unwrap_or(("=", filter.trim()));
+
+    let number = match value {
+        ScriptValue::Int(v) => Some(*v as f32),
+        ScriptValue::Float(v) => Some(*v),
+        _ => None,
+    };
+    if let Some(actual) = number
+        && let Ok(expected) = expected.parse::<f32>()
+    {
+        return match op {
+            "<=" => actual <= expected,
+            ">=" => actual >= expected,
+            "<" => actual < expected,
+            ">" => actual > expected,
+            "!=" => actual != expected,
+            _ => actual == expected,
+        };
+    }
+
+    let actual = value.to_string();
+    match op {
+        "!=" => actual != expected,
+        "=" => actual == expected,
+        _ => false,
+    }
+}
+
+/// Trigger and Reaction properties that should fire for an event, with their owners and
+/// sources, in a stable order: the player first, then NPCs in order
+fn subscriptions(game_state: &GameState, event: &GameEvent) -> Vec<(EntityId, String, Property)> {
+    let owners = std::iter::once(EntityId::Player)
+        .chain(game_state.npcs.iter().map(|npc| EntityId::Npc(npc.id.clone())));
+
+    let mut found = Vec::new();
+    for owner in owners {
+        let Some(entity) = game_state.get_entity(&owner) else { continue };
+        for (source, property) in event_properties(game_state, entity) {
+            if subscribes_to(property, event, &owner)
+                && applies_in_any_context(property, entity.active_contexts())
+                && game_state.is_property_active(entity, property)
+            {
+                found.push((owner.clone(), source, property.clone()));
+            }
+        }
+    }
+    found
+}
+
+/// Properties an entity can subscribe with: tag properties (inherited ones included), its
+/// type's own properties and those of its equipped items
+fn event_properties<'a>(game_state: &'a GameState, entity: EntityRef<'a>) -> Vec<(String, &'a Property)> {
+    let mut properties: Vec<(String, &Property)> = game_state.tag_collection
+        .resolved_properties_for_tags(&entity.tag_ids())
+        .into_iter()
+        .map(|(tag, property)| (format!("tag:{}", tag.name), property))
+        .collect();
+
+    if let Some(entity_type) = entity.entity_type() {
+        properties.extend(entity_type.properties.iter().map(|p| (format!("type:{}", entity_type.id), p)));
+    }
+
+    let mut items = entity.inventory().get_all_items();
+    items.retain(|item| item.get_bool("equipped").unwrap_or(false));
+    items.sort_by(|a, b| a.id().cmp(b.id()));
+    for item in items {
+        properties.extend(item.attached_properties().iter().map(|p| (format!("item:{}", item.id()), p)));
+    }
+    properties
+}
+
+fn fire(game_state: &mut GameState, event: &GameEvent, owner: &EntityId, property: &Property) -> Result<String, String> {
+    let target = event.target_for(owner);
+    match &property.value {
+        PropertyValue::Function(function_id) => {
+            let call = FunctionCall::new(function_id, owner.clone(), target.into_iter().collect());
+            game_state.invoke_function(call).map_err(|e| e.to_string())
+        },
+        PropertyValue::Script(source) => {
+            let mut bindings = vec![("event".to_string(), ScriptValue::Str(event.kind().to_string()))];
+            bindings.extend(event.fields().into_iter().map(|(name, value)| (format!("event_{}", name), value)));
+            crate::script::run_script_with_bindings(game_state, source, owner.clone(), target, bindings)
+                .map(|output| output.value.to_string())
+                .map_err(|e| e.to_string())
+        },
+        _ => Err("trigger has no function or script to run".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::inventory::Item;
+    use crate::npc::NPC;
+    use crate::stats::StatValue;
+
+    #[test]
+    fn test_filters_and_scope() {
+        let hit = GameEvent::Damaged { entity: EntityId::Npc("orc1".to_string()), amount: 12, source: Some(EntityId::Player) };
+        let orc = EntityId::Npc("orc1".to_string());
+        let goblin = EntityId::Npc("goblin1".to_string());
+
+        let enraged = Property::reaction("damaged", PropertyValue::Function("rage".to_string()))
+            .with_filter("amount", ">=10");
+        assert!(subscribes_to(&enraged, &hit, &orc));
+        // Reactions only hear about their owner
+        assert!(!subscribes_to(&enraged, &hit, &goblin));
+        assert!(!subscribes_to(&enraged.clone().with_filter("amount", "<10"), &hit, &orc));
+
+        let avenger = Property::trigger("damaged", PropertyValue::Function("avenge".to_string()))
+            .with_metadata(SCOPE_METADATA, "other")
+            .with_filter("source", "player");
+        assert!(subscribes_to(&avenger, &hit, &goblin));
+        assert!(!subscribes_to(&avenger, &hit, &orc));
+        assert_eq!(hit.target_for(&goblin), Some(EntityId::Player));
+    }
+
+    #[test]
+    fn test_reactions_fire_functions_and_scripts() {
+        let mut game_state = GameState::new();
+        let thorns = game_state.tag_collection.add_tag("thorns");
+        game_state.tag_collection.get_tag_mut(thorns).unwrap().properties.push(
+            Property::reaction("damaged", PropertyValue::Function("reflect".to_string())));
+        game_state.functions.register_fn("reflect", |state, call| {
+            let attacker = call.targets[0].clone();
+            state.damage_entity(&attacker, 2, Some(call.caster.clone()));
+            Ok(format!("reflected onto {}", attacker))
+        });
+
+        let mut orc = NPC::new("orc1".to_string(), EntityType::new("orc", "Orc").with_tag_id(thorns));
+        orc.set_base_stat("hp", StatValue::Integer(20));
+        // NPC items fire too: an equipped war horn raises the orc's rage when it is hit
+        let mut horn = Item::new("horn", "War Horn").with_property(Property::reaction("damaged",
+            PropertyValue::Script("add_stat(self, \"rage\", 1);".to_string())));
+        horn.set_bool("equipped", true);
+        orc.inventory.add_item(horn);
+        game_state.add_npc(orc).unwrap();
+        game_state.player.set_base_stat("hp", StatValue::Integer(10));
+
+        // An equipped ring heals the player whenever they are hurt, using the event fields
+        let ring = Item::new("ring", "Ring of Mending").with_property(Property::reaction("damaged",
+            PropertyValue::Script("add_stat(self, \"hp\", 1); return event_amount;".to_string())));
+        game_state.player.add_item(ring);
+        assert!(game_state.equip_item("ring"));
+
+        let orc1 = EntityId::Npc("orc1".to_string());
+        game_state.damage_entity(&orc1, 5, Some(EntityId::Player));
+        let firings = game_state.dispatch_events();
+
+        assert_eq!(firings.len(), 3);
+        assert_eq!(firings[0].source, "tag:thorns");
+        assert_eq!(firings[0].result, Ok("reflected onto player".to_string()));
+        assert_eq!(firings[1].source, "item:horn");
+        assert_eq!(firings[2].source, "item:ring");
+        assert_eq!(firings[2].result, Ok("2".to_string()));
+        assert_eq!(game_state.get_npc("orc1").unwrap().get_int_stat("rage"), Some(1));
+        assert_eq!(game_state.get_npc("orc1").unwrap().get_int_stat("hp"), Some(15));
+        assert_eq!(game_state.player.get_int_stat("hp"), Some(9));
+    }
+
+    #[test]
+    fn test_listeners_regions_and_cascade_limit() {
+        let mut game_state = GameState::new();
+        game_state.regions.push(crate::region::Region::sphere("camp",
+            crate::coordinates::Coordinates::from_values(vec![10.0, 10.0]), 2.0));
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = seen.clone();
+        game_state.events.subscribe(Some("entered_region"), move |_, event| {
+            log.lock().unwrap().push(event.field("region").unwrap().to_string());
+        });
+
+        game_state.process_command("move 10 11");
+        game_state.update(0.1);
+        game_state.update(0.1);
+        assert_eq!(*seen.lock().unwrap(), vec!["camp".to_string()]);
+
+        // A listener that keeps re-emitting its own event is cut off
+        game_state.events.max_events_per_dispatch = 5;
+        game_state.events.subscribe(Some("echo"), |state, event| state.events.emit(event.clone()));
+        game_state.emit_event(GameEvent::Custom { name: "echo".to_string(), entity: None, data: HashMap::new() });
+        game_state.dispatch_events();
+        assert_eq!(game_state.events.dropped_events(), 1);
+        assert_eq!(game_state.events.pending(), 0);
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::events::GameEvent;
+use crate::game_state::{EntityId, GameState};
+
+/// Standings run from -100 (sworn enemies) to 100 (staunch allies)
+pub const MAX_STANDING: f32 = 100.0;
+/// Standing at or above which an entity counts as an ally
+pub const ALLY_THRESHOLD: f32 = 50.0;
+/// Standing at or below which an entity counts as hostile
+pub const HOSTILE_THRESHOLD: f32 = -50.0;
+
+/// How one side regards another, from its standing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Attitude {
+    Ally,
+    Neutral,
+    Hostile,
+}
+
+impl Attitude {
+    pub fn from_standing(standing: f32) -> Attitude {
+        if standing >= ALLY_THRESHOLD {
+            Attitude::Ally
+        } else if standing <= HOSTILE_THRESHOLD {
+            Attitude::Hostile
+        } else {
+            Attitude::Neutral
+        }
+    }
+
+    /// The standing a relationship set to this attitude gets
+    pub fn standing(self) -> f32 {
+        match self {
+            Attitude::Ally => MAX_STANDING,
+            Attitude::Neutral => 0.0,
+            Attitude::Hostile => -MAX_STANDING,
+        }
+    }
+
+    /// "ally", "neutral" or "hostile"
+    pub fn parse(value: &str) -> Option<Attitude> {
+        match value {
+            "ally" => Some(Attitude::Ally),
+            "neutral" => Some(Attitude::Neutral),
+            "hostile" => Some(Attitude::Hostile),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Attitude {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Attitude::Ally => write!(f, "ally"),
+            Attitude::Neutral => write!(f, "neutral"),
+            Attitude::Hostile => write!(f, "hostile"),
+        }
+    }
+}
+
+/// A change to someone's reputation with a faction whenever an event happens. For
+/// `damaged` events it applies to whoever dealt the damage, and only when a member of the
+/// faction was hurt; for other events it applies to the entity the event is about.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReputationRule {
+    pub event: String,
+    pub amount: f32,
+    /// Event fields that must match, as in Trigger property filters (`"amount": ">=10"`)
+    #[serde(default)]
+    pub filters: BTreeMap<String, String>,
+}
+
+impl ReputationRule {
+    pub fn new(event: &str, amount: f32) -> Self {
+        ReputationRule { event: event.to_string(), amount, filters: BTreeMap::new() }
+    }
+
+    pub fn with_filter(mut self, field: &str, filter: &str) -> Self {
+        self.filters.insert(field.to_string(), filter.to_string());
+        self
+    }
+}
+
+/// A side entities belong to; entity types join one with `faction`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Faction {
+    pub id: String,
+    pub name: String,
+    /// How this faction regards others, by faction ID; others are neutral and the faction
+    /// itself is an ally unless listed
+    #[serde(default)]
+    pub relations: BTreeMap<String, f32>,
+    #[serde(default)]
+    pub reputation_rules: Vec<ReputationRule>,
+}
+
+impl Faction {
+    pub fn new(id: &str, name: &str) -> Self {
+        Faction { id: id.to_string(), name: name.to_string(), relations: BTreeMap::new(), reputation_rules: Vec::new() }
+    }
+
+    pub fn with_relation(mut self, faction_id: &str, standing: f32) -> Self {
+        self.relations.insert(faction_id.to_string(), standing.clamp(-MAX_STANDING, MAX_STANDING));
+        self
+    }
+
+    pub fn with_attitude(self, faction_id: &str, attitude: Attitude) -> Self {
+        self.with_relation(faction_id, attitude.standing())
+    }
+
+    pub fn with_reputation_rule(mut self, rule: ReputationRule) -> Self {
+        self.reputation_rules.push(rule);
+        self
+    }
+
+    /// How this faction regards another faction
+    pub fn relation(&self, faction_id: &str) -> f32 {
+        match self.relations.get(faction_id) {
+            Some(standing) => *standing,
+            None if faction_id == self.id => MAX_STANDING,
+            None => 0.0,
+        }
+    }
+}
+
+/// Factions, who belongs to which beyond their entity type, and reputations
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Factions {
+    pub factions: BTreeMap<String, Faction>,
+    /// Faction of entities that have left their type's faction (or the player, who has no
+    /// type), by entity ID
+    #[serde(default)]
+    members: BTreeMap<String, String>,
+    /// Reputation of each entity with each faction, by entity ID and faction ID
+    #[serde(default)]
+    reputation: BTreeMap<String, BTreeMap<String, f32>>,
+}
+
+impl Factions {
+    pub fn add(&mut self, faction: Faction) {
+        self.factions.insert(faction.id.clone(), faction);
+    }
+
+    pub fn get(&self, faction_id: &str) -> Option<&Faction> {
+        self.factions.get(faction_id)
+    }
+
+    /// Put an entity in a faction other than its type's, or back in its type's with `None`
+    pub fn set_member(&mut self, entity: &EntityId, faction_id: Option<&str>) {
+        match faction_id {
+            Some(faction_id) => self.members.insert(entity.to_string(), faction_id.to_string()),
+            None => self.members.remove(&entity.to_string()),
+        };
+    }
+
+    pub fn reputation(&self, entity: &EntityId, faction_id: &str) -> f32 {
+        self.reputation.get(&entity.to_string()).and_then(|r| r.get(faction_id)).copied().unwrap_or(0.0)
+    }
+
+    /// Change an entity's reputation with a faction, within the standing range; returns
+    /// the new reputation
+    pub fn add_reputation(&mut self, entity: &EntityId, faction_id: &str, amount: f32) -> f32 {
+        let reputation = self.reputation.entry(entity.to_string()).or_default().entry(faction_id.to_string()).or_insert(0.0);
+        *reputation = (*reputation + amount).clamp(-MAX_STANDING, MAX_STANDING);
+        *reputation
+    }
+
+    /// Drop what is kept about an entity that left the game
+    pub fn forget(&mut self, entity: &EntityId) {
+        self.members.remove(&entity.to_string());
+        self.reputation.remove(&entity.to_string());
+    }
+}
+
+/// Faction of an entity: the one it was moved to, or else its type's
+pub fn faction_of(game_state: &GameState, entity: &EntityId) -> Option<String> {
+    if let Some(faction_id) = game_state.factions.members.get(&entity.to_string()) {
+        return Some(faction_id.clone());
+    }
+    game_state.get_entity(entity)?.entity_type()?.faction.clone()
+}
+
+/// How `observer` regards `target`: its faction's relation to the target's faction plus the
+/// target's reputation with it. Entities without a faction are neutral to everyone.
+pub fn standing(game_state: &GameState, observer: &EntityId, target: &EntityId) -> f32 {
+    if observer == target {
+        return MAX_STANDING;
+    }
+    let Some(observer_faction) = faction_of(game_state, observer) else { return 0.0 };
+    let relation = match (game_state.factions.get(&observer_faction), faction_of(game_state, target)) {
+        (Some(faction), Some(target_faction)) => faction.relation(&target_faction),
+        (None, Some(target_faction)) if target_faction == observer_faction => MAX_STANDING,
+        _ => 0.0,
+    };
+    (relation + game_state.factions.reputation(target, &observer_faction)).clamp(-MAX_STANDING, MAX_STANDING)
+}
+
+/// Entities `observer` regards with `attitude` within `radius` of it, nearest first
+pub fn entities_with_attitude_within(game_state: &GameState, observer: &EntityId, attitude: Attitude, radius: f32) -> Vec<EntityId> {
+    let Some(center) = game_state.get_entity(observer).map(|entity| entity.position().clone()) else {
+        return Vec::new();
+    };
+    let mut found: Vec<(f32, EntityId)> = std::iter::once(EntityId::Player)
+        .chain(game_state.npcs.handles().into_iter()
+            .filter(|handle| !game_state.npcs.is_despawning(*handle))
+            .filter_map(|handle| game_state.npcs.get(handle))
+            .map(|npc| EntityId::Npc(npc.id.clone())))
+        .filter(|id| id != observer)
+        .filter_map(|id| {
+            let distance = game_state.get_entity(&id)?.position().distance(&center);
+            (distance <= radius).then_some((distance, id))
+        })
+        .filter(|(_, id)| Attitude::from_standing(standing(game_state, observer, id)) == attitude)
+        .collect();
+    found.sort_by(|a, b| a.0.total_cmp(&b.0));
+    found.into_iter().map(|(_, id)| id).collect()
+}
+
+/// Apply every faction's reputation rules to an event; called as the event is dispatched
+pub fn apply_reputation_rules(game_state: &mut GameState, event: &GameEvent) {
+    let mut changes = Vec::new();
+    for faction in game_state.factions.factions.values() {
+        for rule in faction.reputation_rules.iter().filter(|rule| rule.event == event.kind()) {
+            let actor = match event {
+                GameEvent::Damaged { entity, source: Some(source), .. } => {
+                    if faction_of(game_state, entity).as_ref() != Some(&faction.id) {
+                        continue;
+                    }
+                    source
+                },
+                GameEvent::Damaged { source: None, .. } => continue,
+                _ => match event.subject() {
+                    Some(subject) => subject,
+                    None => continue,
+                },
+            };
+            let matches = rule.filters.iter()
+                .all(|(field, filter)| event.field(field).is_some_and(|value| crate::events::filter_matches(&value, filter)));
+            if matches {
+                changes.push((actor.clone(), faction.id.clone(), rule.amount));
+            }
+        }
+    }
+    for (actor, faction_id, amount) in changes {
+        game_state.factions.add_reputation(&actor, &faction_id, amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordinates::Coordinates;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+    use crate::stats::StatValue;
+
+    fn game() -> GameState {
+        let mut game_state = GameState::new();
+        game_state.add_faction(Faction::new("guards", "City Watch")
+            .with_attitude("bandits", Attitude::Hostile)
+            .with_relation("merchants", 60.0)
+            .with_reputation_rule(ReputationRule::new("damaged", -30.0))
+            .with_reputation_rule(ReputationRule::new("bounty_paid", 40.0).with_filter("tier", "gold")));
+        game_state.add_faction(Faction::new("bandits", "Bandits").with_attitude("guards", Attitude::Hostile));
+        game_state.add_faction(Faction::new("merchants", "Merchants"));
+        for (id, faction, x) in [("guard1", "guards", 1.0), ("guard2", "guards", 2.0), ("bandit1", "bandits", 3.0), ("trader1", "merchants", 8.0)] {
+            let entity_type = EntityType::new(faction, faction).with_faction(faction);
+            let mut npc = NPC::new(id.to_string(), entity_type);
+            npc.position = Coordinates::new_2d(x, 0.0);
+            npc.set_base_stat("hp", StatValue::Integer(20));
+            game_state.add_npc(npc).unwrap();
+        }
+        game_state
+    }
+
+    #[test]
+    fn test_relationships_and_queries() {
+        let mut game_state = game();
+        let npc = |id: &str| EntityId::Npc(id.to_string());
+
+        assert_eq!(game_state.attitude(&npc("guard1"), &npc("guard2")), Attitude::Ally);
+        assert_eq!(game_state.attitude(&npc("guard1"), &npc("bandit1")), Attitude::Hostile);
+        assert_eq!(game_state.attitude(&npc("guard1"), &npc("trader1")), Attitude::Ally);
+        // Relations go one way: merchants have no opinion of the guards
+        assert_eq!(game_state.attitude(&npc("trader1"), &npc("guard1")), Attitude::Neutral);
+        assert_eq!(game_state.attitude(&npc("guard1"), &EntityId::Player), Attitude::Neutral);
+        assert_eq!(game_state.hostile_entities_within(&npc("guard1"), 5.0), vec![npc("bandit1")]);
+        assert!(game_state.hostile_entities_within(&npc("guard1"), 1.5).is_empty());
+
+        // A bandit who changes sides
+        game_state.set_faction(&npc("bandit1"), Some("guards"));
+        assert_eq!(game_state.attitude(&npc("guard1"), &npc("bandit1")), Attitude::Ally);
+        game_state.player.position = Coordinates::new_2d(1.0, 1.0);
+        game_state.set_faction(&EntityId::Player, Some("bandits"));
+        assert_eq!(game_state.hostile_entities_within(&npc("guard2"), 5.0), vec![EntityId::Player]);
+    }
+
+    #[test]
+    fn test_reputation_changes_through_events() {
+        let mut game_state = game();
+        let guard = EntityId::Npc("guard1".to_string());
+
+        game_state.damage_entity(&guard, 5, Some(EntityId::Player));
+        game_state.dispatch_events();
+        assert_eq!(game_state.factions.reputation(&EntityId::Player, "guards"), -30.0);
+        game_state.damage_entity(&guard, 5, Some(EntityId::Player));
+        game_state.dispatch_events();
+        assert_eq!(game_state.attitude(&guard, &EntityId::Player), Attitude::Hostile);
+
+        // Hurting a bandit is none of the guards' business
+        game_state.damage_entity(&EntityId::Npc("bandit1".to_string()), 5, Some(EntityId::Player));
+        let bounty = |tier: &str| GameEvent::Custom {
+            name: "bounty_paid".to_string(),
+            entity: Some(EntityId::Player),
+            data: [("tier".to_string(), tier.to_string())].into(),
+        };
+        game_state.emit_event(bounty("silver"));
+        game_state.emit_event(bounty("gold"));
+        game_state.dispatch_events();
+        assert_eq!(game_state.factions.reputation(&EntityId::Player, "guards"), -20.0);
+        assert_eq!(game_state.attitude(&guard, &EntityId::Player), Attitude::Neutral);
+    }
+}
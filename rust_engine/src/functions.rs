@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use crate::game_state::{EntityId, GameState};
+use crate::property::{Property, PropertyType, PropertyValue};
+use crate::stats::StatValue;
+use crate::entity::Entity;
+
+// Result type for function and ability calls
+pub type FunctionResult<T> = Result<T, FunctionError>;
+
+// Reasons a function or ability call can be rejected or fail
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionError {
+    UnknownFunction(String),
+    UnknownEntity(EntityId),
+    AbilityNotFound(String),
+    AbilityInactive(String),
+    OnCooldown { function_id: String, remaining: f32 },
+    InsufficientResource { stat: String, required: f32, available: f32 },
+    OutOfRange { target: EntityId, distance: f32, range: f32 },
+    InvalidTargets(String),
+    ValidationFailed(String),
+    Failed(String),
+}
+
+impl fmt::Display for FunctionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionError::UnknownFunction(id) => write!(f, "Unknown function '{}'", id),
+            FunctionError::UnknownEntity(id) => write!(f, "Unknown entity '{}'", id),
+            FunctionError::AbilityNotFound(name) => write!(f, "No ability named '{}'", name),
+            FunctionError::AbilityInactive(name) => write!(f, "Ability '{}' is not available right now", name),
+            FunctionError::OnCooldown { function_id, remaining } =>
+                write!(f, "'{}' is on cooldown for {:.1}s", function_id, remaining),
+            FunctionError::InsufficientResource { stat, required, available } =>
+                write!(f, "Not enough {} ({} required, {} available)", stat, required, available),
+            FunctionError::OutOfRange { target, distance, range } =>
+                write!(f, "{} is out of range ({:.1} > {:.1})", target, distance, range),
+            FunctionError::InvalidTargets(reason) => write!(f, "Invalid targets: {}", reason),
+            FunctionError::ValidationFailed(reason) => write!(f, "Cannot use: {}", reason),
+            FunctionError::Failed(reason) => write!(f, "Failed: {}", reason),
+        }
+    }
+}
+
+/// A single call of a registered function
+#[derive(Debug, Clone)]
+pub struct FunctionCall {
+    pub function_id: String,
+    pub caster: EntityId,
+    pub targets: Vec<EntityId>,
+}
+
+impl FunctionCall {
+    pub fn new(function_id: &str, caster: EntityId, targets: Vec<EntityId>) -> Self {
+        FunctionCall {
+            function_id: function_id.to_string(),
+            caster,
+            targets,
+        }
+    }
+}
+
+/// Game code behind a function ID. Returns a message describing what happened.
+pub type FunctionHandler = Arc<dyn Fn(&mut GameState, &FunctionCall) -> FunctionResult<String> + Send + Sync>;
+
+/// Extra check run before a function is called. Returns the reason on rejection.
+pub type FunctionValidator = Arc<dyn Fn(&GameState, &FunctionCall) -> Result<(), String> + Send + Sync>;
+
+/// A registered function with its usage rules
+#[derive(Clone)]
+pub struct FunctionDefinition {
+    pub id: String,
+    /// Game-time seconds before the same caster can use it again
+    pub cooldown: f32,
+    /// Stats spent by the caster on a successful call. They are checked against and taken
+    /// from base stats, so modifiers (a max mana bonus, say) cannot be spent. Costs on
+    /// integer stats must be whole numbers.
+    pub costs: Vec<(String, f32)>,
+    /// Maximum distance between the caster and each target
+    pub range: Option<f32>,
+    pub min_targets: usize,
+    pub max_targets: Option<usize>,
+    handler: FunctionHandler,
+    validators: Vec<FunctionValidator>,
+}
+
+impl FunctionDefinition {
+    pub fn new<F>(id: &str, handler: F) -> Self
+    where F: Fn(&mut GameState, &FunctionCall) -> FunctionResult<String> + Send + Sync + 'static {
+        FunctionDefinition {
+            id: id.to_string(),
+            cooldown: 0.0,
+            costs: Vec::new(),
+            range: None,
+            min_targets: 0,
+            max_targets: None,
+            handler: Arc::new(handler),
+            validators: Vec::new(),
+        }
+    }
+
+    pub fn with_cooldown(mut self, seconds: f32) -> Self {
+        self.cooldown = seconds;
+        self
+    }
+
+    pub fn with_cost(mut self, stat: &str, amount: f32) -> Self {
+        self.costs.push((stat.to_string(), amount));
+        self
+    }
+
+    pub fn with_range(mut self, range: f32) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    pub fn with_targets(mut self, min: usize, max: Option<usize>) -> Self {
+        self.min_targets = min;
+        self.max_targets = max;
+        self
+    }
+
+    pub fn with_validator<F>(mut self, validator: F) -> Self
+    where F: Fn(&GameState, &FunctionCall) -> Result<(), String> + Send + Sync + 'static {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+}
+
+/// Maps function IDs (as stored in `PropertyValue::Function`) to Rust code and tracks cooldowns
+#[derive(Default, Clone)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, FunctionDefinition>,
+    // Game time at which an entity may use a function again
+    ready_at: HashMap<(EntityId, String), f32>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        FunctionRegistry {
+            functions: HashMap::new(),
+            ready_at: HashMap::new(),
+        }
+    }
+
+    /// Register a function, replacing any previous definition with the same ID
+    pub fn register(&mut self, definition: FunctionDefinition) {
+        self.functions.insert(definition.id.clone(), definition);
+    }
+
+    /// Register a function without cooldown, costs or validation
+    pub fn register_fn<F>(&mut self, id: &str, handler: F)
+    where F: Fn(&mut GameState, &FunctionCall) -> FunctionResult<String> + Send + Sync + 'static {
+        self.register(FunctionDefinition::new(id, handler));
+    }
+
+    pub fn unregister(&mut self, id: &str) -> bool {
+        self.functions.remove(id).is_some()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.functions.contains_key(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&FunctionDefinition> {
+        self.functions.get(id)
+    }
+
+    /// Seconds until an entity can use a function again (0 when ready)
+    pub fn remaining_cooldown(&self, entity: &EntityId, function_id: &str, now: f32) -> f32 {
+        self.ready_at.get(&(entity.clone(), function_id.to_string()))
+            .map(|ready_at| (ready_at - now).max(0.0))
+            .unwrap_or(0.0)
+    }
+
+    /// Forget all cooldowns of an entity
+    pub fn reset_cooldowns(&mut self, entity: &EntityId) {
+        self.ready_at.retain(|(owner, _), _| owner != entity);
+    }
+
+    /// Run every check a call must pass before its handler runs
+    pub fn validate(&self, game_state: &GameState, call: &FunctionCall) -> FunctionResult<()> {
+        let definition = self.functions.get(&call.function_id)
+            .ok_or_else(|| FunctionError::UnknownFunction(call.function_id.clone()))?;
+        let caster = game_state.get_entity(&call.caster)
+            .ok_or_else(|| FunctionError::UnknownEntity(call.caster.clone()))?;
+
+        if call.targets.len() < definition.min_targets {
+            return Err(FunctionError::InvalidTargets(
+                format!("needs at least {} target(s)", definition.min_targets)));
+        }
+        if let Some(max) = definition.max_targets
+            && call.targets.len() > max
+        {
+            return Err(FunctionError::InvalidTargets(format!("accepts at most {} target(s)", max)));
+        }
+
+        for target_id in &call.targets {
+            let target = game_state.get_entity(target_id)
+                .ok_or_else(|| FunctionError::UnknownEntity(target_id.clone()))?;
+            if let Some(range) = definition.range {
+                let distance = caster.position().distance(target.position());
+                if distance.is_nan() || distance > range {
+                    return Err(FunctionError::OutOfRange { target: target_id.clone(), distance, range });
+                }
+            }
+        }
+
+        let remaining = self.remaining_cooldown(&call.caster, &call.function_id, game_state.game_time);
+        if remaining > 0.0 {
+            return Err(FunctionError::OnCooldown { function_id: call.function_id.clone(), remaining });
+        }
+
+        for (stat, required) in &definition.costs {
+            let base = caster.stats().base_stats().get(stat);
+            if matches!(base, Some(StatValue::Integer(_))) && required.fract() != 0.0 {
+                return Err(FunctionError::ValidationFailed(
+                    format!("{} is a whole number but costs {}", stat, required)));
+            }
+            let available = base.and_then(|v| v.as_float()).unwrap_or(0.0);
+            if available < *required {
+                return Err(FunctionError::InsufficientResource {
+                    stat: stat.clone(),
+                    required: *required,
+                    available,
+                });
+            }
+        }
+
+        for validator in &definition.validators {
+            validator(game_state, call).map_err(FunctionError::ValidationFailed)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Validate and run a registered function, then charge its costs and start its cooldown
+pub fn invoke_function(game_state: &mut GameState, call: FunctionCall) -> FunctionResult<String> {
+    game_state.functions.validate(game_state, &call)?;
+
+    // Clone the definition so the handler can borrow the whole game state
+    let definition = game_state.functions.functions[&call.function_id].clone();
+    let message = (definition.handler)(game_state, &call)?;
+
+    // The caster may have been removed by its own function; then there is nothing to charge
+    if let Some(stats) = game_state.entity_base_stats_mut(&call.caster) {
+        for (stat, amount) in &definition.costs {
+            match stats.get(stat) {
+                Some(StatValue::Integer(value)) => {
+                    let new_value = value - *amount as i32;
+                    stats.set_int(stat, new_value);
+                },
+                Some(StatValue::Float(value)) => {
+                    let new_value = value - amount;
+                    stats.set_float(stat, new_value);
+                },
+                _ => {}
+            }
+        }
+    }
+
+    if definition.cooldown > 0.0 {
+        let ready_at = game_state.game_time + definition.cooldown;
+        game_state.functions.ready_at.insert((call.caster.clone(), call.function_id.clone()), ready_at);
+    }
+
+    Ok(message)
+}
+
+/// Name an ability property is invoked by: its "name" metadata, or else its function ID
+pub fn ability_name(property: &Property) -> Option<&str> {
+    if property.property_type != PropertyType::Ability {
+        return None;
+    }
+    match &property.value {
+        PropertyValue::Function(function_id) => Some(
+            property.metadata.get("name").map(|name| name.as_str()).unwrap_or(function_id)),
+        _ => None,
+    }
+}
+
+/// Ability properties of an entity, from its entity type and its tags (type and instance
+/// tags, with their ancestors)
+pub fn entity_abilities<'a>(game_state: &'a GameState, entity: &EntityId) -> Vec<&'a Property> {
+    let Some(entity_ref) = game_state.get_entity(entity) else {
+        return Vec::new();
+    };
+
+    let tag_properties = game_state.tag_collection.resolved_properties_for_tags(&entity_ref.tag_ids())
+        .into_iter()
+        .map(|(_, property)| property);
+    let type_properties = game_state.get_entity_type(entity)
+        .into_iter()
+        .flat_map(|entity_type| entity_type.properties.iter());
+
+    tag_properties.chain(type_properties)
+        .filter(|property| ability_name(property).is_some())
+        .collect()
+}
+
+/// Invoke one of an entity's abilities by name. The ability must apply in one of the
+/// entity's active contexts and its conditions must hold.
+pub fn use_ability(game_state: &mut GameState, caster: EntityId, name: &str, targets: Vec<EntityId>) -> FunctionResult<String> {
+    let entity = game_state.get_entity(&caster)
+        .ok_or_else(|| FunctionError::UnknownEntity(caster.clone()))?;
+    let property = entity_abilities(game_state, &caster)
+        .into_iter()
+        .find(|property| ability_name(property) == Some(name))
+        .ok_or_else(|| FunctionError::AbilityNotFound(name.to_string()))?;
+
+    if !crate::property_modifiers::applies_in_any_context(property, entity.active_contexts())
+        || !game_state.is_property_active(entity, property)
+    {
+        return Err(FunctionError::AbilityInactive(name.to_string()));
+    }
+
+    let PropertyValue::Function(function_id) = &property.value else {
+        return Err(FunctionError::AbilityNotFound(name.to_string()));
+    };
+    let call = FunctionCall::new(function_id, caster, targets);
+    invoke_function(game_state, call)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+
+    fn register_fireball(state: &mut GameState) {
+        state.functions.register(FunctionDefinition::new("fireball", |state, call| {
+            for target in &call.targets {
+                if let Some(stats) = state.entity_base_stats_mut(target) {
+                    let hp = stats.get_int("hp").unwrap_or(0);
+                    stats.set_int("hp", hp - 10);
+                }
+            }
+            Ok(format!("{} casts fireball", call.caster))
+        })
+            .with_cooldown(2.0)
+            .with_cost("mana", 5.0)
+            .with_range(10.0)
+            .with_targets(1, Some(1)));
+    }
+
+    fn state_with_mage() -> GameState {
+        let mut state = GameState::new();
+        register_fireball(&mut state);
+
+        let mage_type = EntityType::new("mage", "Mage")
+            .with_property_object(Property::ability("fireball").with_metadata("name", "Fireball"));
+        let mut mage = NPC::new("mage1".to_string(), mage_type);
+        mage.set_base_stat("mana", StatValue::Integer(8));
+        state.add_npc(mage).unwrap();
+
+        let mut target = NPC::new("dummy".to_string(), EntityType::new("dummy", "Dummy"));
+        target.set_base_stat("hp", StatValue::Integer(30));
+        target.set_position(3.0, 4.0);
+        state.add_npc(target).unwrap();
+        state
+    }
+
+    #[test]
+    fn test_use_ability_charges_cost_and_cooldown() {
+        let mut state = state_with_mage();
+        let mage = EntityId::Npc("mage1".to_string());
+        let dummy = EntityId::Npc("dummy".to_string());
+
+        let message = state.use_ability(mage.clone(), "Fireball", vec![dummy.clone()]).unwrap();
+        assert_eq!(message, "mage1 casts fireball");
+        assert_eq!(state.get_npc("dummy").unwrap().get_int_stat("hp"), Some(20));
+        assert_eq!(state.get_npc("mage1").unwrap().get_int_stat("mana"), Some(3));
+
+        let result = state.use_ability(mage.clone(), "Fireball", vec![dummy.clone()]);
+        assert!(matches!(result, Err(FunctionError::OnCooldown { .. })));
+
+        // Once the cooldown is over, the caster is out of mana
+        state.game_time += 2.0;
+        let result = state.use_ability(mage, "Fireball", vec![dummy]);
+        assert!(matches!(result, Err(FunctionError::InsufficientResource { .. })));
+        assert_eq!(state.get_npc("dummy").unwrap().get_int_stat("hp"), Some(20));
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let mut state = state_with_mage();
+        let mage = EntityId::Npc("mage1".to_string());
+
+        assert_eq!(state.use_ability(mage.clone(), "Fireball", Vec::new()),
+            Err(FunctionError::InvalidTargets("needs at least 1 target(s)".to_string())));
+        assert_eq!(state.use_ability(mage.clone(), "Teleport", Vec::new()),
+            Err(FunctionError::AbilityNotFound("Teleport".to_string())));
+
+        state.get_npc_mut("dummy").unwrap().set_position(30.0, 40.0);
+        let result = state.use_ability(mage.clone(), "Fireball", vec![EntityId::Npc("dummy".to_string())]);
+        assert!(matches!(result, Err(FunctionError::OutOfRange { .. })));
+
+        let ghost = EntityId::Npc("ghost".to_string());
+        assert_eq!(state.use_ability(mage, "Fireball", vec![ghost.clone()]),
+            Err(FunctionError::UnknownEntity(ghost)));
+    }
+
+    #[test]
+    fn test_custom_validator_and_ability_conditions() {
+        let mut state = GameState::new();
+        state.functions.register(FunctionDefinition::new("shout", |_, _| Ok("AAAH".to_string()))
+            .with_validator(|state, _| if state.game_time > 5.0 { Err("too late".to_string()) } else { Ok(()) }));
+
+        let shout = Property::ability("shout")
+            .with_condition(Property::create_stat_threshold_condition("courage", StatValue::Integer(0), true));
+        state.player.character_type = Some(EntityType::new("barbarian", "Barbarian").with_property_object(shout));
+
+        assert_eq!(state.use_ability(EntityId::Player, "shout", Vec::new()),
+            Err(FunctionError::AbilityInactive("shout".to_string())));
+
+        state.player.set_base_stat("courage", StatValue::Integer(1));
+        assert_eq!(state.use_ability(EntityId::Player, "shout", Vec::new()), Ok("AAAH".to_string()));
+
+        state.game_time = 6.0;
+        assert_eq!(state.use_ability(EntityId::Player, "shout", Vec::new()),
+            Err(FunctionError::ValidationFailed("too late".to_string())));
+    }
+
+    #[test]
+    fn test_costs_use_base_stats_and_abilities_respect_contexts() {
+        let mut state = state_with_mage();
+        let mage = EntityId::Npc("mage1".to_string());
+        let dummy = EntityId::Npc("dummy".to_string());
+
+        // A mana bonus is not mana that can be spent
+        let npc = state.get_npc_mut("mage1").unwrap();
+        npc.set_base_stat("mana", StatValue::Integer(2));
+        npc.add_stat_modifier("mana", "buff:focus", crate::calculated_stats::ModifierType::Additive, StatValue::Integer(10), 20);
+        let result = state.use_ability(mage.clone(), "Fireball", vec![dummy.clone()]);
+        assert_eq!(result, Err(FunctionError::InsufficientResource { stat: "mana".to_string(), required: 5.0, available: 2.0 }));
+        assert_eq!(state.get_npc("mage1").unwrap().base_stats().get_int("mana"), Some(2));
+
+        // Half a point of an integer stat cannot be charged
+        state.functions.register(FunctionDefinition::new("spark", |_, _| Ok("spark".to_string())).with_cost("mana", 0.5));
+        assert_eq!(state.invoke_function(FunctionCall::new("spark", mage.clone(), Vec::new())),
+            Err(FunctionError::ValidationFailed("mana is a whole number but costs 0.5".to_string())));
+
+        // A combat-only ability is not available outside combat
+        state.player.character_type = Some(EntityType::new("duelist", "Duelist")
+            .with_property_object(Property::ability("riposte").exclusive_to("combat")));
+        state.functions.register_fn("riposte", |_, _| Ok("riposte".to_string()));
+        assert_eq!(state.use_ability(EntityId::Player, "riposte", Vec::new()),
+            Err(FunctionError::AbilityInactive("riposte".to_string())));
+        state.player.enter_context("combat");
+        assert_eq!(state.use_ability(EntityId::Player, "riposte", Vec::new()), Ok("riposte".to_string()));
+    }
+
+    #[test]
+    fn test_ability_command() {
+        let mut state = GameState::new();
+        state.functions.register_fn("wave", |_, call| Ok(format!("{} waves at {}", call.caster, call.targets[0])));
+        state.player.character_type = Some(EntityType::new("bard", "Bard")
+            .with_property_object(Property::ability("wave")));
+        state.add_npc(NPC::new("fan".to_string(), EntityType::new("fan", "Fan"))).unwrap();
+
+        assert!(state.process_command("abilities").contains("wave"));
+        assert_eq!(state.process_command("ability wave fan"), "player waves at fan");
+        assert!(state.process_command("ability dance").contains("No ability named 'dance'"));
+    }
+}
@@ -1,9 +1,66 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use serde::{Serialize, Deserialize};
 use crate::character::Character;
 use crate::npc::NPC;
+use crate::registry::{EntityHandle, EntityRegistry, RegistryResult};
+use crate::ecs::{Component, Query};
 use crate::entity_type::EntityType;
 use crate::tag::TagCollection;
+use crate::tag_index::TagIndex;
+use crate::tag::TagHierarchyError;
+use crate::tag_merge::{TagMergePolicy, TagMergeReport};
+use crate::stats::Stats;
+use crate::property::{Property, PropertyValue};
+use crate::coordinates::Coordinates;
+use crate::condition::{ConditionEvaluator, EntityRef};
+use crate::context::{match_properties, ContextMatch};
+use crate::functions::{FunctionCall, FunctionRegistry, FunctionResult};
+use crate::script::{ScriptEngine, ScriptError, ScriptOutput, ScriptResult};
+use crate::events::{EventBus, GameEvent, TriggerFiring};
+use crate::region::Region;
+use crate::template::NpcTemplate;
+use crate::prefab::EntityTypeError;
+use crate::inventory::Item;
+use crate::content::{ContentLoader, ContentResult, ContentSummary};
+use crate::stats::StatValue;
+use crate::rng::SeededRng;
+use crate::spawner::{self, SpawnResult, Wave};
+use crate::state_machine::{StateMachine, StateMachineError, StateMachineResult};
+use crate::behavior_tree::BehaviorTree;
+use crate::utility::UtilityAi;
+use crate::faction::{Attitude, Faction, Factions};
+use crate::combat::{CombatLogEntry, CombatResult, CombatState, Encounter};
+use crate::damage::{DamagePacket, DamageReport, DamageType};
+use crate::status_effect::{StatusEffectDef, StatusEffectResult, StatusEffectState};
+
+/// Identifies an entity that lives in the game state
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EntityId {
+    /// The player character
+    Player,
+    /// An NPC, by its ID
+    Npc(String),
+}
+
+impl EntityId {
+    /// Parse a command argument: "player" names the player, anything else an NPC
+    pub fn parse(value: &str) -> EntityId {
+        if value.eq_ignore_ascii_case("player") {
+            EntityId::Player
+        } else {
+            EntityId::Npc(value.to_string())
+        }
+    }
+}
+
+impl std::fmt::Display for EntityId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntityId::Player => write!(f, "player"),
+            EntityId::Npc(id) => write!(f, "{}", id),
+        }
+    }
+}
 
 /// Represents the current state of the game world
 #[derive(Serialize, Deserialize)]
@@ -18,8 +75,8 @@ pub struct GameState {
     pub last_updated: u64,
     /// The main player character
     pub player: Character,
-    /// All NPCs in the game world
-    pub npcs: Vec<NPC>,
+    /// All NPCs in the game world, by handle and ID
+    pub npcs: EntityRegistry,
     /// Collection of all tags in the game
     pub tag_collection: TagCollection,
     /// All entity types defined in the game
@@ -31,6 +88,75 @@ pub struct GameState {
     pub running: bool,
     /// Custom game properties that can be set by the game logic
     pub properties: HashMap<String, String>,
+    /// Evaluates property conditions, including registered custom conditions
+    #[serde(skip)]
+    pub conditions: ConditionEvaluator,
+    /// Functions that Ability and Function properties can call
+    #[serde(skip)]
+    pub functions: FunctionRegistry,
+    /// Compiles and caches Script properties
+    #[serde(skip)]
+    pub scripts: ScriptEngine,
+    /// Reverse index from tags to entity types and NPCs
+    #[serde(skip)]
+    pub tag_index: TagIndex,
+    /// Whether `tag_index` has been built; false for a loaded game until it is
+    #[serde(skip)]
+    tag_index_built: bool,
+    /// Pending events and the listeners registered from code
+    #[serde(skip)]
+    pub events: EventBus,
+    /// Named areas that raise entered/left region events
+    #[serde(default)]
+    pub regions: Vec<Region>,
+    /// Blueprints for NPCs, by template ID
+    #[serde(default)]
+    pub npc_templates: HashMap<String, NpcTemplate>,
+    /// Blueprints for items, by item ID
+    #[serde(default)]
+    pub item_templates: HashMap<String, Item>,
+    /// Random numbers for spawning and other game rolls; saved so a loaded game rolls the same
+    #[serde(default)]
+    pub rng: SeededRng,
+    /// Counter behind spawned NPC IDs
+    #[serde(default)]
+    pub next_spawn_id: u64,
+    /// NPC state machines, by ID; entity types refer to them with `state_machine`
+    #[serde(default)]
+    pub state_machines: HashMap<String, StateMachine>,
+    /// State machine actions that failed during the last update
+    #[serde(skip)]
+    pub last_state_machine_errors: Vec<StateMachineError>,
+    /// NPC behavior trees, by ID; entity types refer to them with `behavior_tree`
+    #[serde(default)]
+    pub behavior_trees: HashMap<String, BehaviorTree>,
+    /// NPC utility AIs, by ID; entity types refer to them with `utility_ai`
+    #[serde(default)]
+    pub utility_ais: HashMap<String, UtilityAi>,
+    /// Factions, their relationships and everyone's reputation with them
+    #[serde(default)]
+    pub factions: Factions,
+    /// Spheres (center and radius) that block line of sight
+    #[serde(default)]
+    pub obstacles: Vec<(Coordinates, f32)>,
+    /// Damage types, by ID; attacks and damage packets may only use these
+    #[serde(default = "crate::damage::default_damage_types")]
+    pub damage_types: HashMap<String, DamageType>,
+    /// Combat log, the running turn-based encounter and real-time attack cooldowns
+    #[serde(default)]
+    pub combat: CombatState,
+    /// Status effect definitions and the effects running on each entity
+    #[serde(default)]
+    pub status_effects: StatusEffectState,
+    /// Which entities were inside which regions at the last check
+    #[serde(default)]
+    region_occupancy: HashSet<(String, EntityId)>,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GameState {
@@ -49,12 +175,33 @@ impl GameState {
                 .unwrap()
                 .as_secs(),
             player: Character::new(),
-            npcs: Vec::new(),
+            npcs: EntityRegistry::new(),
             tag_collection: TagCollection::new(),
             entity_types: HashMap::new(),
             game_time: 0.0,
             running: true,
             properties: HashMap::new(),
+            conditions: ConditionEvaluator::new(),
+            functions: FunctionRegistry::new(),
+            scripts: ScriptEngine::new(),
+            tag_index: TagIndex::new(),
+            tag_index_built: true,
+            events: EventBus::new(),
+            regions: Vec::new(),
+            npc_templates: HashMap::new(),
+            item_templates: HashMap::new(),
+            rng: SeededRng::default(),
+            next_spawn_id: 0,
+            state_machines: HashMap::new(),
+            last_state_machine_errors: Vec::new(),
+            behavior_trees: HashMap::new(),
+            utility_ais: HashMap::new(),
+            factions: Factions::default(),
+            obstacles: Vec::new(),
+            damage_types: crate::damage::default_damage_types(),
+            combat: CombatState::default(),
+            status_effects: StatusEffectState::default(),
+            region_occupancy: HashSet::new(),
         };
         
         println!("Game state initialized");
@@ -73,16 +220,62 @@ impl GameState {
             .unwrap()
             .as_secs();
         
+        // Expire timed instance tags
+        self.expire_instance_tags(delta_time);
+        
+        // Tick status effects and start or end those added or removed by name
+        self.update_status_effects(delta_time);
+        
+        // Drop references to tags removed through the collection directly, and index a
+        // loaded game that has not been indexed yet
+        if self.tag_index_built {
+            self.purge_removed_tags();
+        } else {
+            self.sync_tag_index();
+        }
+        
+        // Re-apply type and tag modifiers so tag, context and condition changes take effect
+        self.refresh_property_modifiers();
+        
+        // Let NPCs look around before they decide what to do
+        self.update_perception();
+        
+        // Move NPCs through their state machines
+        self.last_state_machine_errors = self.update_state_machines(delta_time);
+        self.update_behavior_trees(delta_time);
+        self.update_utility_ai();
+        
+        // Raise region and tick events, then let triggers and reactions respond
+        self.update_regions();
+        self.emit_event(GameEvent::Tick { delta_time });
+        self.dispatch_events();
+        
+        // Remove NPCs despawned during this update
+        self.flush_despawned();
+        
         // Print game state occasionally
-        if self.tick % 10 == 0 {
+        if self.tick.is_multiple_of(10) {
             println!("Tick {}: Player at {}, {} NPCs", 
                 self.tick, self.player.position, self.npcs.len());
         }
     }
     
-    /// Process a command from the user or external tool
+    /// Process a command from the user or external tool, then dispatch the resulting
+    /// command executed event
     pub fn process_command(&mut self, command: &str) -> String {
-        let parts: Vec<&str> = command.trim().split_whitespace().collect();
+        let response = self.execute_command(command);
+        if let Some(name) = command.split_whitespace().next() {
+            self.emit_event(GameEvent::CommandExecuted {
+                command: name.to_lowercase(),
+                line: command.trim().to_string(),
+            });
+            self.dispatch_events();
+        }
+        response
+    }
+    
+    fn execute_command(&mut self, command: &str) -> String {
+        let parts: Vec<&str> = command.split_whitespace().collect();
         if parts.is_empty() {
             return "No command provided".to_string();
         }
@@ -114,7 +307,55 @@ impl GameState {
                 status
             },
             "help" => {
-                "Available commands:\n  move <x> <y> - Move player to coordinates\n  status - Show game status\n  json - Get game state as JSON\n  demo - Run game state demo\n  demo_tags - Run tag system demo\n  demo_mechanics - Run game mechanics demo\n  demo_assets - Run asset management demo\n  quit/exit - Exit the game\n  help - Show this help".to_string()
+                "Available commands:\n  move <x> <y> - Move player to coordinates\n  status - Show game status\n  ability <name> [targets...] - Use one of the player's abilities\n  abilities - List the player's abilities\n  script <code> - Run a script as the player\n  load <directory> - Load JSON content files\n  json - Get game state as JSON\n  demo - Run game state demo\n  demo_tags - Run tag system demo\n  demo_mechanics - Run game mechanics demo\n  demo_assets - Run asset management demo\n  quit/exit - Exit the game\n  help - Show this help".to_string()
+            },
+            "ability" | "use" => {
+                if parts.len() >= 2 {
+                    let targets = parts[2..].iter().map(|t| EntityId::parse(t)).collect();
+                    match self.use_ability(EntityId::Player, parts[1], targets) {
+                        Ok(message) => message,
+                        Err(e) => e.to_string(),
+                    }
+                } else {
+                    "Not enough arguments. Usage: ability <name> [targets...]".to_string()
+                }
+            },
+            "abilities" => {
+                let names = self.get_ability_names(&EntityId::Player);
+                if names.is_empty() {
+                    "Player has no abilities".to_string()
+                } else {
+                    format!("Abilities: {}", names.join(", "))
+                }
+            },
+            "script" => {
+                let source = command.trim_start()[parts[0].len()..].trim();
+                if source.is_empty() {
+                    return "Not enough arguments. Usage: script <code>".to_string();
+                }
+                match self.run_script(source, EntityId::Player, None) {
+                    Ok(output) => {
+                        let mut lines = output.log;
+                        lines.push(format!("=> {}", output.value));
+                        lines.join("\n")
+                    },
+                    Err(e) => e.to_string(),
+                }
+            },
+            "load" => {
+                if parts.len() < 2 {
+                    return "Not enough arguments. Usage: load <directory>".to_string();
+                }
+                match self.load_content(parts[1]) {
+                    Ok(summary) => format!(
+                        "Loaded {} files: {} tags, {} entity types, {} NPC templates, {} item templates, {} NPCs",
+                        summary.files, summary.tags, summary.entity_types,
+                        summary.npc_templates, summary.item_templates, summary.npcs),
+                    Err(errors) => {
+                        let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                        format!("Failed to load content:\n{}", lines.join("\n"))
+                    },
+                }
             },
             "json" => {
                 match serde_json::to_string_pretty(self) {
@@ -169,11 +410,681 @@ impl GameState {
         }
     }
     
+    /// Find an NPC by its ID
+    pub fn get_npc(&self, id: &str) -> Option<&NPC> {
+        self.npcs.get_by_id(id)
+    }
+    
+    /// Find an NPC by its ID for modification
+    pub fn get_npc_mut(&mut self, id: &str) -> Option<&mut NPC> {
+        self.npcs.get_by_id_mut(id)
+    }
+    
+    /// Add an entity type and index its tags
+    pub fn add_entity_type(&mut self, entity_type: EntityType) {
+        self.tag_index.index_entity_type(&entity_type.id, &entity_type.tag_ids);
+        self.entity_types.insert(entity_type.id.clone(), entity_type);
+    }
+    
+    /// Resolve the `extends` chains of all entity types and re-index their tags. NPCs keep
+    /// the copy of their type they were created with.
+    pub fn resolve_entity_types(&mut self) -> Result<(), EntityTypeError> {
+        crate::prefab::resolve_entity_types(&mut self.entity_types)?;
+        for entity_type in self.entity_types.values() {
+            self.tag_index.index_entity_type(&entity_type.id, &entity_type.tag_ids);
+        }
+        Ok(())
+    }
+    
+    /// Add an NPC and index its tags. Fails if another NPC has the same ID.
+    pub fn add_npc(&mut self, npc: NPC) -> RegistryResult<EntityHandle> {
+        let tag_ids = npc.tag_ids();
+        let handle = self.npcs.insert(npc)?;
+        let npc = self.npcs.get(handle).expect("NPC was just added");
+        self.tag_index.index_npc(&npc.id, &tag_ids);
+        Ok(handle)
+    }
+    
+    /// Remove an NPC by its ID right away
+    pub fn remove_npc(&mut self, id: &str) -> Option<NPC> {
+        let npc = self.npcs.remove_by_id(id)?;
+        self.forget_npc(id);
+        Some(npc)
+    }
+    
+    /// Mark an NPC for removal at the end of the update. Returns false if there is no such
+    /// NPC or it is already despawning.
+    pub fn despawn_npc(&mut self, id: &str) -> bool {
+        self.npcs.handle_of(id).is_some_and(|handle| self.npcs.despawn(handle))
+    }
+    
+    /// Remove despawned NPCs, queueing a despawned event for each
+    pub fn flush_despawned(&mut self) -> Vec<NPC> {
+        let removed = self.npcs.flush_despawned();
+        for npc in &removed {
+            self.forget_npc(&npc.id);
+            self.emit_event(GameEvent::Despawned { entity: EntityId::Npc(npc.id.clone()), type_id: npc.npc_type.id.clone() });
+        }
+        removed
+    }
+    
+    // Drop everything kept about a removed NPC by ID, so a new NPC with the same ID starts
+    // fresh
+    fn forget_npc(&mut self, id: &str) {
+        let entity = EntityId::Npc(id.to_string());
+        self.tag_index.remove_npc(id);
+        self.factions.forget(&entity);
+        self.combat.forget(&entity);
+        self.status_effects.forget(&entity);
+    }
+    
+    /// The NPC an NPC is targeting, if it still exists
+    pub fn target_of(&self, npc_id: &str) -> Option<&NPC> {
+        self.npcs.get(self.get_npc(npc_id)?.target?)
+    }
+    
+    /// Point an NPC's target at another NPC, or clear it with `None`. Returns false if
+    /// either NPC does not exist.
+    pub fn set_npc_target(&mut self, npc_id: &str, target_id: Option<&str>) -> bool {
+        self.link_npc(npc_id, target_id, |npc, handle| npc.target = handle)
+    }
+    
+    /// Make an NPC follow another NPC, or stop following with `None`. Returns false if
+    /// either NPC does not exist.
+    pub fn set_npc_leader(&mut self, npc_id: &str, leader_id: Option<&str>) -> bool {
+        self.link_npc(npc_id, leader_id, |npc, handle| npc.leader = handle)
+    }
+    
+    /// NPCs following an NPC
+    pub fn followers_of(&self, npc_id: &str) -> Vec<&NPC> {
+        let Some(handle) = self.npcs.handle_of(npc_id) else {
+            return Vec::new();
+        };
+        self.npcs.iter().filter(|npc| npc.leader == Some(handle)).collect()
+    }
+    
+    fn link_npc(&mut self, npc_id: &str, other_id: Option<&str>, link: impl FnOnce(&mut NPC, Option<EntityHandle>)) -> bool {
+        let handle = match other_id {
+            Some(other_id) => match self.npcs.handle_of(other_id) {
+                Some(handle) => Some(handle),
+                None => return false,
+            },
+            None => None,
+        };
+        match self.get_npc_mut(npc_id) {
+            Some(npc) => {
+                link(npc, handle);
+                true
+            },
+            None => false,
+        }
+    }
+    
+    /// Give an entity type a tag, returns false if the type or tag does not exist
+    pub fn add_tag_to_entity_type(&mut self, type_id: &str, tag_id: i32) -> bool {
+        if self.tag_collection.get_tag(tag_id).is_none() {
+            return false;
+        }
+        let Some(entity_type) = self.entity_types.get_mut(type_id) else {
+            return false;
+        };
+        entity_type.tag_ids.insert(tag_id);
+        self.tag_index.index_entity_type(type_id, &entity_type.tag_ids);
+        true
+    }
+    
+    /// Take a tag from an entity type, returns whether it had it
+    pub fn remove_tag_from_entity_type(&mut self, type_id: &str, tag_id: i32) -> bool {
+        let Some(entity_type) = self.entity_types.get_mut(type_id) else {
+            return false;
+        };
+        let removed = entity_type.tag_ids.remove(&tag_id);
+        self.tag_index.index_entity_type(type_id, &entity_type.tag_ids);
+        removed
+    }
+    
+    /// Give an entity an instance tag, optionally expiring after `duration` game-time seconds.
+    /// Returns false if the entity or tag does not exist.
+    pub fn add_instance_tag(&mut self, entity: &EntityId, tag_id: i32, duration: Option<f32>) -> bool {
+        if self.tag_collection.get_tag(tag_id).is_none() {
+            return false;
+        }
+        let instance_tags = match entity {
+            EntityId::Player => &mut self.player.instance_tags,
+            EntityId::Npc(npc_id) => match self.npcs.get_by_id_mut(npc_id) {
+                Some(npc) => &mut npc.instance_tags,
+                None => return false,
+            },
+        };
+        match duration {
+            Some(duration) => instance_tags.add_timed(tag_id, duration),
+            None => instance_tags.add(tag_id),
+        };
+        self.reindex_entity(entity);
+        true
+    }
+    
+    /// Take an instance tag from an entity, returns whether it had it
+    pub fn remove_instance_tag(&mut self, entity: &EntityId, tag_id: i32) -> bool {
+        let removed = match entity {
+            EntityId::Player => self.player.remove_instance_tag(tag_id),
+            EntityId::Npc(npc_id) => self.get_npc_mut(npc_id).is_some_and(|npc| npc.remove_instance_tag(tag_id)),
+        };
+        self.reindex_entity(entity);
+        removed
+    }
+    
+    /// Count down timed instance tags and drop the expired ones
+    pub fn expire_instance_tags(&mut self, delta_time: f32) {
+        self.player.instance_tags.tick(delta_time);
+        for npc in &mut self.npcs {
+            if !npc.instance_tags.tick(delta_time).is_empty() {
+                self.tag_index.index_npc(&npc.id, &npc.tag_ids());
+            }
+        }
+    }
+    
+    fn reindex_entity(&mut self, entity: &EntityId) {
+        if let EntityId::Npc(npc_id) = entity
+            && let Some(npc) = self.npcs.get_by_id(npc_id)
+        {
+            self.tag_index.index_npc(npc_id, &npc.tag_ids());
+        }
+    }
+    
+    /// Give an NPC a tag, returns false if the NPC or tag does not exist
+    pub fn add_tag_to_npc(&mut self, npc_id: &str, tag_id: i32) -> bool {
+        if self.tag_collection.get_tag(tag_id).is_none() {
+            return false;
+        }
+        let Some(npc) = self.npcs.get_by_id_mut(npc_id) else {
+            return false;
+        };
+        npc.npc_type.tag_ids.insert(tag_id);
+        self.tag_index.index_npc(npc_id, &npc.tag_ids());
+        true
+    }
+    
+    /// Take a tag from an NPC, returns whether it had it
+    pub fn remove_tag_from_npc(&mut self, npc_id: &str, tag_id: i32) -> bool {
+        let Some(npc) = self.npcs.get_by_id_mut(npc_id) else {
+            return false;
+        };
+        let removed = npc.npc_type.tag_ids.remove(&tag_id);
+        self.tag_index.index_npc(npc_id, &npc.tag_ids());
+        removed
+    }
+    
+    /// Remove a tag from the collection and from every entity that carries it
+    pub fn remove_tag(&mut self, tag_id: i32) -> bool {
+        let removed = self.tag_collection.remove_tag(tag_id);
+        self.purge_removed_tags();
+        removed
+    }
+    
+    /// Clean up references to tags removed from the collection, including removals made
+    /// directly through `TagCollection::remove_tag`
+    pub fn purge_removed_tags(&mut self) {
+        for tag_id in self.tag_collection.take_removed_tags() {
+            for entity_type in self.entity_types.values_mut() {
+                entity_type.tag_ids.remove(&tag_id);
+            }
+            for npc in &mut self.npcs {
+                npc.npc_type.tag_ids.remove(&tag_id);
+                npc.remove_instance_tag(tag_id);
+            }
+            if let Some(character_type) = &mut self.player.character_type {
+                character_type.tag_ids.remove(&tag_id);
+            }
+            self.player.remove_instance_tag(tag_id);
+            self.tag_index.remove_tag(tag_id);
+        }
+    }
+    
+    /// Merge tags from another source, e.g. a content pack, together with the entity types
+    /// that reference them. The types' tag IDs are remapped before they are added.
+    pub fn merge_tags(
+        &mut self,
+        tags: &TagCollection,
+        entity_types: Vec<EntityType>,
+        policy: TagMergePolicy,
+    ) -> Result<TagMergeReport, TagHierarchyError> {
+        let report = self.tag_collection.merge(tags, policy)?;
+        for mut entity_type in entity_types {
+            report.apply_to_entity_type(&mut entity_type);
+            self.add_entity_type(entity_type);
+        }
+        Ok(report)
+    }
+    
+    /// Purge removed tags and re-index entities whose tags were edited directly. The
+    /// `GameState` tag methods keep the index exact, so this is only needed after editing
+    /// `tag_ids` or instance tags by hand; `update` does not run it.
+    pub fn sync_tag_index(&mut self) {
+        self.purge_removed_tags();
+        self.tag_index.sync(self.entity_types.values(), self.npcs.iter());
+        self.tag_index_built = true;
+    }
+    
+    /// Entity types carrying a tag, optionally counting tags that inherit from it
+    pub fn entity_types_with_tag(&self, tag_id: i32, include_descendants: bool) -> Vec<&EntityType> {
+        self.tag_index.entity_types_with_any_tag(&self.tag_with_descendants(tag_id, include_descendants))
+            .into_iter()
+            .filter_map(|id| self.entity_types.get(id))
+            .collect()
+    }
+    
+    /// NPCs carrying a tag, optionally counting tags that inherit from it
+    pub fn npcs_with_tag(&self, tag_id: i32, include_descendants: bool) -> Vec<&NPC> {
+        self.tag_index.npcs_with_any_tag(&self.tag_with_descendants(tag_id, include_descendants))
+            .into_iter()
+            .filter_map(|id| self.get_npc(id))
+            .collect()
+    }
+    
+    fn tag_with_descendants(&self, tag_id: i32, include_descendants: bool) -> Vec<i32> {
+        let mut tag_ids = vec![tag_id];
+        if include_descendants {
+            tag_ids.extend(self.tag_collection.descendants(tag_id));
+        }
+        tag_ids
+    }
+    
+    /// Resolve an entity ID to a borrowed view of the entity
+    pub fn get_entity(&self, id: &EntityId) -> Option<EntityRef<'_>> {
+        match id {
+            EntityId::Player => Some(EntityRef::Character(&self.player)),
+            EntityId::Npc(npc_id) => self.get_npc(npc_id).map(EntityRef::Npc),
+        }
+    }
+    
+    /// The player and every NPC that have all the components in `Q`, player first
+    pub fn query<Q: Query>(&self) -> Vec<(EntityId, Q::Item<'_>)> {
+        std::iter::once((EntityId::Player, self.player.components()))
+            .chain(self.npcs.iter().map(|npc| (EntityId::Npc(npc.id.clone()), npc.components())))
+            .filter_map(|(id, components)| Some((id, Q::fetch(&components)?)))
+            .collect()
+    }
+    
+    /// Like `query`, with mutable access to the components (`Tags` stays read-only)
+    pub fn query_mut<Q: Query>(&mut self) -> Vec<(EntityId, Q::ItemMut<'_>)> {
+        let player = (EntityId::Player, self.player.components_mut());
+        let npcs = self.npcs.iter_mut().map(|npc| (EntityId::Npc(npc.id.clone()), npc.components_mut()));
+        std::iter::once(player)
+            .chain(npcs)
+            .filter_map(|(id, mut components)| Some((id, Q::fetch_mut(&mut components)?)))
+            .collect()
+    }
+    
+    /// One component of an entity
+    pub fn component<C: Component>(&self, id: &EntityId) -> Option<C::Ref<'_>> {
+        match id {
+            EntityId::Player => self.player.components().get::<C>(),
+            EntityId::Npc(npc_id) => self.get_npc(npc_id)?.components().get::<C>(),
+        }
+    }
+    
+    /// One component of an entity, mutably
+    pub fn component_mut<C: Component>(&mut self, id: &EntityId) -> Option<C::Mut<'_>> {
+        match id {
+            EntityId::Player => self.player.components_mut().take::<C>(),
+            EntityId::Npc(npc_id) => self.get_npc_mut(npc_id)?.components_mut().take::<C>(),
+        }
+    }
+    
+    /// Entity type of an entity (the player's optional character type)
+    pub fn get_entity_type(&self, id: &EntityId) -> Option<&EntityType> {
+        match id {
+            EntityId::Player => self.player.character_type.as_ref(),
+            EntityId::Npc(npc_id) => self.get_npc(npc_id).map(|npc| &npc.npc_type),
+        }
+    }
+    
+    /// Properties applying to an entity in its active contexts: tag properties (type and
+    /// instance tags, inherited ones included) followed by its type's own properties, each
+    /// with the contexts that made it apply. Conditions are not evaluated.
+    pub fn entity_properties_in_context(&self, id: &EntityId) -> Vec<ContextMatch<'_>> {
+        let Some(entity) = self.get_entity(id) else { return Vec::new() };
+        let tag_properties = self.tag_collection.resolved_properties_for_tags(&entity.tag_ids())
+            .into_iter()
+            .map(|(_, property)| property);
+        let own_properties = entity.entity_type().into_iter().flat_map(|t| t.properties.iter());
+        match_properties(tag_properties.chain(own_properties), entity.active_contexts())
+    }
+    
+    /// Mutable base stats of an entity
+    pub fn entity_base_stats_mut(&mut self, id: &EntityId) -> Option<&mut Stats> {
+        match id {
+            EntityId::Player => Some(self.player.base_stats_mut()),
+            EntityId::Npc(npc_id) => self.get_npc_mut(npc_id).map(|npc| npc.base_stats_mut()),
+        }
+    }
+    
+    /// Mutable position of an entity
+    pub fn entity_position_mut(&mut self, id: &EntityId) -> Option<&mut Coordinates> {
+        match id {
+            EntityId::Player => Some(&mut self.player.position),
+            EntityId::Npc(npc_id) => self.get_npc_mut(npc_id).map(|npc| &mut npc.position),
+        }
+    }
+    
+    /// Check whether a property's conditions currently hold for an entity
+    pub fn is_property_active(&self, entity: EntityRef, property: &Property) -> bool {
+        self.conditions.is_property_active(entity, self, property)
+    }
+    
+    /// Re-apply StatModifier properties from entity types and tags to the player and NPCs
+    pub fn refresh_property_modifiers(&mut self) {
+        crate::property_modifiers::refresh_property_modifiers(self);
+    }
+    
+    /// Call a registered function, checking cooldowns, costs and targets first
+    pub fn invoke_function(&mut self, call: FunctionCall) -> FunctionResult<String> {
+        crate::functions::invoke_function(self, call)
+    }
+    
+    /// Use one of an entity's abilities by name
+    pub fn use_ability(&mut self, caster: EntityId, name: &str, targets: Vec<EntityId>) -> FunctionResult<String> {
+        crate::functions::use_ability(self, caster, name, targets)
+    }
+    
+    /// Names of the abilities an entity currently has
+    pub fn get_ability_names(&self, entity: &EntityId) -> Vec<String> {
+        crate::functions::entity_abilities(self, entity)
+            .into_iter()
+            .filter_map(crate::functions::ability_name)
+            .map(|name| name.to_string())
+            .collect()
+    }
+    
+    /// Run a script on behalf of an entity, with an optional target
+    pub fn run_script(&mut self, source: &str, entity: EntityId, target: Option<EntityId>) -> ScriptResult<ScriptOutput> {
+        crate::script::run_script(self, source, entity, target)
+    }
+    
+    /// Run a Script property; properties holding other values are rejected
+    pub fn run_property_script(&mut self, property: &Property, entity: EntityId, target: Option<EntityId>) -> ScriptResult<ScriptOutput> {
+        match &property.value {
+            PropertyValue::Script(source) => self.run_script(source, entity, target),
+            _ => Err(ScriptError::Runtime { line: 0, message: "property is not a script".to_string() }),
+        }
+    }
+    
+    /// Load tags, entity types, templates and NPCs from a directory of JSON files
+    pub fn load_content<P: AsRef<std::path::Path>>(&mut self, dir: P) -> ContentResult<ContentSummary> {
+        ContentLoader::new().load_dir(dir, self)
+    }
+    
+    /// Create (but do not add) an NPC from a template, if the template and its type exist.
+    /// The NPC is built like a spawned NPC of the type, with rolled stats, starting items
+    /// and behavior, and the template is applied on top.
+    pub fn npc_from_template(&mut self, template_id: &str, npc_id: &str, position: Coordinates) -> Option<NPC> {
+        let template = self.npc_templates.get(template_id)?.clone();
+        let entity_type = self.entity_types.get(&template.entity_type)?.clone();
+        let mut npc = spawner::build_npc(self, &entity_type, npc_id, position);
+        template.apply_to(&mut npc);
+        Some(npc)
+    }
+    
+    /// A fresh copy of an item template
+    pub fn item_from_template(&self, item_id: &str) -> Option<Item> {
+        self.item_templates.get(item_id).cloned()
+    }
+    
+    /// Spawn an NPC of an entity type, rolling its stats and giving it its starting items.
+    /// Returns the new NPC's ID.
+    pub fn spawn_npc(&mut self, type_id: &str, position: Coordinates) -> SpawnResult<String> {
+        spawner::spawn_npc(self, type_id, position)
+    }
+    
+    /// Spawn every NPC in a wave, or none of them if any group cannot spawn
+    pub fn spawn_wave(&mut self, wave: &Wave) -> SpawnResult<Vec<String>> {
+        spawner::spawn_wave(self, wave)
+    }
+    
+    /// Register a state machine, replacing one with the same ID. Fails if it refers to
+    /// states it does not define.
+    pub fn add_state_machine(&mut self, machine: StateMachine) -> StateMachineResult<()> {
+        machine.validate()?;
+        self.state_machines.insert(machine.id.clone(), machine);
+        Ok(())
+    }
+    
+    /// Tick every NPC's state machine; `update` does this. Returns the actions that failed.
+    pub fn update_state_machines(&mut self, delta_time: f32) -> Vec<StateMachineError> {
+        crate::state_machine::update_state_machines(self, delta_time)
+    }
+    
+    /// Register a behavior tree, replacing one with the same ID
+    pub fn add_behavior_tree(&mut self, tree: BehaviorTree) {
+        self.behavior_trees.insert(tree.id.clone(), tree);
+    }
+    
+    /// Tick every NPC's behavior tree; `update` does this
+    pub fn update_behavior_trees(&mut self, delta_time: f32) {
+        crate::behavior_tree::update_behavior_trees(self, delta_time);
+    }
+    
+    /// An NPC's behavior tree with the status of each node in the last tick
+    pub fn dump_behavior_tree(&self, npc_id: &str) -> Option<String> {
+        let npc = self.get_npc(npc_id)?;
+        let tree = self.behavior_trees.get(npc.npc_type.behavior_tree.as_ref()?)?;
+        Some(crate::behavior_tree::dump(tree, &npc.blackboard))
+    }
+    
+    /// Update what every NPC sees and remembers; `update` does this
+    pub fn update_perception(&mut self) {
+        crate::perception::update_perception(self);
+    }
+    
+    /// Whether an NPC had an entity in sight at the last update
+    pub fn can_see(&self, npc_id: &str, entity: &EntityId) -> bool {
+        self.get_npc(npc_id).is_some_and(|npc| npc.perception.can_see(entity))
+    }
+    
+    /// Where an NPC last saw or heard an entity, if it still remembers
+    pub fn last_known_position(&self, npc_id: &str, entity: &EntityId) -> Option<&Coordinates> {
+        self.get_npc(npc_id)?.perception.last_known_position(entity)
+    }
+    
+    /// Register a faction, replacing one with the same ID
+    pub fn add_faction(&mut self, faction: Faction) {
+        self.factions.add(faction);
+    }
+    
+    /// Faction an entity belongs to: the one `set_faction` put it in, or else its type's
+    pub fn faction_of(&self, entity: &EntityId) -> Option<String> {
+        crate::faction::faction_of(self, entity)
+    }
+    
+    /// Move an entity to another faction, or back to its type's with `None`
+    pub fn set_faction(&mut self, entity: &EntityId, faction_id: Option<&str>) {
+        self.factions.set_member(entity, faction_id);
+    }
+    
+    /// How `observer` regards `target`, from -100 (hostile) to 100 (ally)
+    pub fn standing(&self, observer: &EntityId, target: &EntityId) -> f32 {
+        crate::faction::standing(self, observer, target)
+    }
+    
+    pub fn attitude(&self, observer: &EntityId, target: &EntityId) -> Attitude {
+        Attitude::from_standing(self.standing(observer, target))
+    }
+    
+    /// Entities `observer` is hostile to within `radius` of it, nearest first
+    pub fn hostile_entities_within(&self, observer: &EntityId, radius: f32) -> Vec<EntityId> {
+        crate::faction::entities_with_attitude_within(self, observer, Attitude::Hostile, radius)
+    }
+    
+    /// Define a damage type, replacing one with the same ID
+    pub fn add_damage_type(&mut self, damage_type: DamageType) {
+        self.damage_types.insert(damage_type.id.clone(), damage_type);
+    }
+    
+    /// Deal typed damage outside an attack, after the entity's resistances
+    pub fn apply_damage(&mut self, entity: &EntityId, packet: &DamagePacket, source: Option<EntityId>) -> CombatResult<DamageReport> {
+        crate::damage::apply_damage(self, entity, packet, source)
+    }
+    
+    /// Define a status effect, replacing one with the same ID
+    pub fn add_status_effect(&mut self, definition: StatusEffectDef) {
+        self.status_effects.add(definition);
+    }
+    
+    /// Put a defined status effect on an entity, returning its stacks
+    pub fn apply_status_effect(&mut self, entity: &EntityId, id: &str, source: Option<EntityId>) -> StatusEffectResult<u32> {
+        crate::status_effect::apply_status_effect(self, entity, id, source)
+    }
+    
+    /// End a status effect early, running its expiry hooks
+    pub fn remove_status_effect(&mut self, entity: &EntityId, id: &str) -> bool {
+        crate::status_effect::remove_status_effect(self, entity, id)
+    }
+    
+    /// Advance every entity's status effects; `update` does this
+    pub fn update_status_effects(&mut self, delta_time: f32) {
+        crate::status_effect::update_status_effects(self, delta_time);
+    }
+    
+    /// Real-time attack, gated by the attacker's "attack_cooldown"
+    pub fn attack(&mut self, attacker: &EntityId, defender: &EntityId) -> CombatResult<CombatLogEntry> {
+        crate::combat::attack(self, attacker, defender)
+    }
+    
+    /// Start a turn-based encounter between the participants
+    pub fn start_encounter(&mut self, participants: Vec<EntityId>) -> CombatResult<()> {
+        crate::combat::start_encounter(self, participants)
+    }
+    
+    /// Attack on the attacker's turn in the running encounter
+    pub fn take_turn(&mut self, attacker: &EntityId, defender: &EntityId) -> CombatResult<CombatLogEntry> {
+        crate::combat::take_turn(self, attacker, defender)
+    }
+    
+    /// Pass an actor's turn in the running encounter without attacking
+    pub fn end_turn(&mut self, actor: &EntityId) -> CombatResult<()> {
+        crate::combat::end_turn(self, actor)
+    }
+    
+    /// Stop the running encounter
+    pub fn end_encounter(&mut self) -> Option<Encounter> {
+        crate::combat::end_encounter(self)
+    }
+    
+    /// Register a utility AI, replacing one with the same ID
+    pub fn add_utility_ai(&mut self, ai: UtilityAi) {
+        self.utility_ais.insert(ai.id.clone(), ai);
+    }
+    
+    /// Let every NPC's utility AI choose and run an action; `update` does this
+    pub fn update_utility_ai(&mut self) {
+        crate::utility::update_utility_ai(self);
+    }
+    
+    /// How each of an NPC's utility AI actions scored in the last tick
+    pub fn dump_utility_scores(&self, npc_id: &str) -> Option<String> {
+        let npc = self.get_npc(npc_id)?;
+        npc.npc_type.utility_ai.as_ref()?;
+        Some(crate::utility::dump(&npc.utility))
+    }
+    
+    /// Queue an event for the next dispatch
+    pub fn emit_event(&mut self, event: GameEvent) {
+        self.events.emit(event);
+    }
+    
+    /// Deliver queued events to listeners and Trigger/Reaction properties
+    pub fn dispatch_events(&mut self) -> Vec<TriggerFiring> {
+        crate::events::dispatch_events(self)
+    }
+    
+    /// Take hit points from an entity and raise a damaged event. Returns true if its
+    /// hp dropped to zero.
+    ///
+    /// The damage comes off base hp, so tag, type, equipment and status modifiers keep
+    /// applying on top of what is left. It is capped at the hp the entity has, and the
+    /// event carries the damage actually dealt. Amounts of zero or less do nothing.
+    pub fn damage_entity(&mut self, entity: &EntityId, amount: i32, source: Option<EntityId>) -> bool {
+        let Some(StatValue::Integer(hp)) = self.get_entity(entity).and_then(|e| e.get_stat("hp")) else {
+            return false;
+        };
+        let dealt = amount.min(hp).max(0);
+        if dealt > 0 {
+            if let Some(stats) = self.entity_base_stats_mut(entity)
+                && let Some(base) = stats.get_int("hp")
+            {
+                stats.set_int("hp", base - dealt);
+            }
+            self.emit_event(GameEvent::Damaged { entity: entity.clone(), amount: dealt, source });
+        }
+        matches!(self.get_entity(entity).and_then(|e| e.get_stat("hp")), Some(StatValue::Integer(hp)) if hp <= 0)
+    }
+    
+    /// Equip one of the player's items and raise an item equipped event
+    pub fn equip_item(&mut self, item_id: &str) -> bool {
+        let equipped = self.player.equip_item(item_id);
+        if equipped {
+            self.emit_event(GameEvent::ItemEquipped { entity: EntityId::Player, item_id: item_id.to_string() });
+        }
+        equipped
+    }
+    
+    /// Unequip one of the player's items and raise an item unequipped event
+    pub fn unequip_item(&mut self, item_id: &str) -> bool {
+        let unequipped = self.player.unequip_item(item_id);
+        if unequipped {
+            self.emit_event(GameEvent::ItemUnequipped { entity: EntityId::Player, item_id: item_id.to_string() });
+        }
+        unequipped
+    }
+    
+    /// Compare entity positions against the regions and raise left/entered region events
+    /// for every boundary crossed since the last check
+    pub fn update_regions(&mut self) {
+        let positions: Vec<(EntityId, &Coordinates)> = std::iter::once((EntityId::Player, &self.player.position))
+            .chain(self.npcs.iter().map(|npc| (EntityId::Npc(npc.id.clone()), &npc.position)))
+            .collect();
+        
+        let mut current = Vec::new();
+        for region in &self.regions {
+            for (entity, position) in &positions {
+                if region.contains(position) {
+                    current.push((region.id.clone(), entity.clone()));
+                }
+            }
+        }
+        
+        // Entities that no longer exist leave silently
+        let mut left: Vec<(String, EntityId)> = self.region_occupancy.iter()
+            .filter(|entry| !current.contains(entry))
+            .filter(|(_, entity)| self.get_entity(entity).is_some())
+            .cloned()
+            .collect();
+        left.sort_by(|a, b| (&a.0, a.1.to_string()).cmp(&(&b.0, b.1.to_string())));
+        for (region, entity) in left {
+            self.emit_event(GameEvent::LeftRegion { entity, region });
+        }
+        for (region, entity) in &current {
+            if !self.region_occupancy.contains(&(region.clone(), entity.clone())) {
+                self.emit_event(GameEvent::EnteredRegion { entity: entity.clone(), region: region.clone() });
+            }
+        }
+        self.region_occupancy = current.into_iter().collect();
+    }
+    
     /// Export the game state as JSON
     pub fn to_json(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string_pretty(self)
     }
     
+    /// Load a game state exported with `to_json`, with its tag index rebuilt. A game state
+    /// deserialized some other way is indexed on its first `update`.
+    pub fn from_json(json: &str) -> Result<GameState, serde_json::Error> {
+        let mut game_state: GameState = serde_json::from_str(json)?;
+        game_state.sync_tag_index();
+        Ok(game_state)
+    }
+    
     /// Export the game state as a compact JSON string
     pub fn to_json_compact(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string(self)
@@ -315,15 +1226,247 @@ mod tests {
         game_state.entity_types.insert("goblin".to_string(), entity_type);
         
         // Add NPC to game state
-        game_state.npcs.push(npc);
+        game_state.add_npc(npc).unwrap();
         
         // Check NPC was added
         assert_eq!(game_state.npcs.len(), 1);
-        assert_eq!(game_state.npcs[0].id, "goblin1");
+        assert_eq!(game_state.get_npc("goblin1").unwrap().id, "goblin1");
         assert_eq!(game_state.entity_types.len(), 1);
         assert!(game_state.entity_types.contains_key("goblin"));
     }
 
+    #[test]
+    fn test_despawn_and_links_between_npcs() {
+        let mut game_state = GameState::new();
+        let wolf = EntityType::new("wolf", "Wolf");
+        for id in ["alpha", "wolf1", "wolf2"] {
+            game_state.add_npc(NPC::new(id.to_string(), wolf.clone())).unwrap();
+        }
+        assert!(game_state.add_npc(NPC::new("alpha".to_string(), wolf.clone())).is_err());
+        assert!(game_state.set_npc_leader("wolf1", Some("alpha")));
+        assert!(game_state.set_npc_leader("wolf2", Some("alpha")));
+        assert!(game_state.set_npc_target("alpha", Some("wolf2")));
+        assert!(!game_state.set_npc_target("alpha", Some("sheep")));
+        assert_eq!(game_state.followers_of("alpha").len(), 2);
+        assert_eq!(game_state.target_of("alpha").unwrap().id, "wolf2");
+
+        // Despawned NPCs stay until the end of the update
+        assert!(game_state.despawn_npc("wolf2"));
+        assert!(!game_state.despawn_npc("wolf2"));
+        assert!(game_state.get_npc("wolf2").is_some());
+        game_state.update(0.1);
+        assert!(game_state.get_npc("wolf2").is_none());
+
+        // A new NPC in the freed slot is not mistaken for the old target
+        game_state.add_npc(NPC::new("wolf3".to_string(), wolf)).unwrap();
+        assert!(game_state.target_of("alpha").is_none());
+        assert_eq!(game_state.followers_of("alpha").len(), 1);
+        // The despawned event waits for the next dispatch
+        assert_eq!(game_state.events.pending(), 1);
+    }
+
+    #[test]
+    fn test_removed_npcs_leave_nothing_behind() {
+        let mut game_state = GameState::new();
+        game_state.player.set_base_stat("hp", StatValue::Integer(50));
+        let mut wolf = NPC::new("wolf".to_string(), EntityType::new("wolf", "Wolf"));
+        wolf.set_base_stat("hp", StatValue::Integer(10));
+        wolf.set_base_stat(crate::combat::ATTACK_COOLDOWN_STAT, StatValue::Float(100.0));
+        game_state.add_npc(wolf).unwrap();
+        let wolf_id = EntityId::Npc("wolf".to_string());
+        game_state.add_status_effect(crate::status_effect::StatusEffectDef::new("dazed", "Dazed"));
+        game_state.apply_status_effect(&wolf_id, "dazed", None).unwrap();
+        game_state.attack(&wolf_id, &EntityId::Player).unwrap();
+
+        // A new wolf with the same ID has no cooldown or effects from the old one
+        let old = game_state.remove_npc("wolf").unwrap();
+        let mut wolf = NPC::new(old.id.clone(), old.npc_type.clone());
+        wolf.set_base_stat("hp", StatValue::Integer(10));
+        game_state.add_npc(wolf).unwrap();
+        assert!(game_state.status_effects.active(&wolf_id, "dazed").is_none());
+        assert!(game_state.attack(&wolf_id, &EntityId::Player).is_ok());
+    }
+
+    #[test]
+    fn test_tag_index_tracks_changes() {
+        let mut game_state = GameState::new();
+        let fire = game_state.tag_collection.add_tag("fire");
+        let inferno = game_state.tag_collection.add_tag("inferno");
+        game_state.tag_collection.add_parent(inferno, fire).unwrap();
+        
+        game_state.add_entity_type(EntityType::new("imp", "Imp").with_tag_id(fire));
+        game_state.add_npc(NPC::new("imp1".to_string(), EntityType::new("imp", "Imp").with_tag_id(fire))).unwrap();
+        game_state.add_npc(NPC::new("imp2".to_string(), EntityType::new("imp", "Imp"))).unwrap();
+        assert_eq!(game_state.tag_index.npcs_with_tag(fire), vec!["imp1"]);
+        
+        // Changes through GameState are indexed immediately
+        assert!(game_state.add_tag_to_npc("imp2", inferno));
+        assert_eq!(game_state.tag_index.npcs_with_tag(inferno), vec!["imp2"]);
+        assert_eq!(game_state.npcs_with_tag(fire, true).len(), 2);
+        assert!(game_state.remove_tag_from_npc("imp1", fire));
+        assert!(game_state.npcs_with_tag(fire, false).is_empty());
+        
+        // Direct edits are not indexed until they are synced
+        game_state.get_npc_mut("imp1").unwrap().npc_type.tag_ids.insert(fire);
+        game_state.entity_types.get_mut("imp").unwrap().tag_ids.insert(inferno);
+        game_state.update(0.1);
+        assert!(game_state.tag_index.npcs_with_tag(fire).is_empty());
+        game_state.sync_tag_index();
+        assert_eq!(game_state.tag_index.npcs_with_tag(fire), vec!["imp1"]);
+        assert_eq!(game_state.entity_types_with_tag(inferno, false).len(), 1);
+        
+        // Loading rebuilds the index, which is not saved
+        let json = game_state.to_json().unwrap();
+        assert_eq!(GameState::from_json(&json).unwrap().npcs_with_tag(fire, false).len(), 1);
+        let mut loaded: GameState = serde_json::from_str(&json).unwrap();
+        assert!(loaded.tag_index.npcs_with_tag(fire).is_empty());
+        loaded.update(0.1);
+        assert_eq!(loaded.tag_index.npcs_with_tag(fire), vec!["imp1"]);
+        
+        game_state.remove_npc("imp1");
+        assert!(game_state.tag_index.npcs_with_tag(fire).is_empty());
+    }
+
+    #[test]
+    fn test_removing_a_tag_cleans_up_entities() {
+        let mut game_state = GameState::new();
+        let cursed = game_state.tag_collection.add_tag("cursed");
+        game_state.add_entity_type(EntityType::new("ghost", "Ghost").with_tag_id(cursed));
+        game_state.add_npc(NPC::new("ghost1".to_string(), EntityType::new("ghost", "Ghost").with_tag_id(cursed))).unwrap();
+        
+        assert!(game_state.remove_tag(cursed));
+        assert!(game_state.entity_types["ghost"].tag_ids.is_empty());
+        assert!(game_state.get_npc("ghost1").unwrap().npc_type.tag_ids.is_empty());
+        assert!(game_state.tag_index.entity_types_with_tag(cursed).is_empty());
+        
+        // Removing through the collection directly is cleaned up on the next update
+        let blessed = game_state.tag_collection.add_tag("blessed");
+        game_state.add_tag_to_entity_type("ghost", blessed);
+        game_state.tag_collection.remove_tag(blessed);
+        game_state.update(0.1);
+        assert!(game_state.entity_types["ghost"].tag_ids.is_empty());
+        assert!(game_state.tag_index.entity_types_with_tag(blessed).is_empty());
+    }
+
+    #[test]
+    fn test_instance_tags() {
+        let mut game_state = GameState::new();
+        let enraged = game_state.tag_collection.add_tag("enraged");
+        game_state.tag_collection.get_tag_mut(enraged).unwrap().properties.push(
+            Property::stat_modifier("damage", crate::stats::StatValue::Integer(4)));
+        let mut goblin = NPC::new("goblin1".to_string(), EntityType::new("goblin", "Goblin"));
+        goblin.set_base_stat("damage", crate::stats::StatValue::Integer(6));
+        game_state.add_npc(goblin).unwrap();
+        game_state.add_npc(NPC::new("goblin2".to_string(), EntityType::new("goblin", "Goblin"))).unwrap();
+        let goblin1 = EntityId::Npc("goblin1".to_string());
+        
+        // Only this goblin is enraged, its type is untouched
+        assert!(game_state.add_instance_tag(&goblin1, enraged, Some(1.0)));
+        assert!(game_state.get_npc("goblin1").unwrap().npc_type.tag_ids.is_empty());
+        assert_eq!(game_state.tag_index.npcs_with_tag(enraged), vec!["goblin1"]);
+        game_state.update(0.5);
+        assert_eq!(game_state.get_npc("goblin1").unwrap().get_int_stat("damage"), Some(10));
+        let query = crate::tag_query::TagQuery::compile_str("enraged", &game_state.tag_collection).unwrap();
+        assert!(query.matches_npc(game_state.get_npc("goblin1").unwrap(), &game_state.tag_collection));
+        assert!(!query.matches_npc(game_state.get_npc("goblin2").unwrap(), &game_state.tag_collection));
+        
+        // The tag runs out and takes its modifier with it
+        game_state.update(0.6);
+        assert!(!game_state.get_npc("goblin1").unwrap().has_tag_id(enraged));
+        assert!(game_state.tag_index.npcs_with_tag(enraged).is_empty());
+        assert_eq!(game_state.get_npc("goblin1").unwrap().get_int_stat("damage"), Some(6));
+        
+        assert!(game_state.add_instance_tag(&EntityId::Player, enraged, None));
+        assert!(game_state.player.has_tag_id(enraged));
+        assert!(game_state.remove_instance_tag(&EntityId::Player, enraged));
+        assert!(!game_state.add_instance_tag(&goblin1, 99, None));
+    }
+
+    #[test]
+    fn test_damage_comes_off_base_hp() {
+        let mut game_state = GameState::new();
+        let sturdy = game_state.tag_collection.add_tag("sturdy");
+        let tag = game_state.tag_collection.get_tag_mut(sturdy).unwrap();
+        tag.properties.push(Property::stat_modifier("hp", crate::stats::StatValue::Integer(10)));
+        let mut orc = NPC::new("orc1".to_string(), EntityType::new("orc", "Orc").with_tag_id(sturdy));
+        orc.set_base_stat("hp", crate::stats::StatValue::Integer(20));
+        game_state.add_npc(orc).unwrap();
+        let orc1 = EntityId::Npc("orc1".to_string());
+        game_state.update(0.1);
+        assert_eq!(game_state.get_npc("orc1").unwrap().get_int_stat("hp"), Some(30));
+        
+        // The tag's bonus stays a bonus instead of being folded into base hp
+        assert!(!game_state.damage_entity(&orc1, 5, None));
+        assert_eq!(game_state.get_npc("orc1").unwrap().base_stats().get_int("hp"), Some(15));
+        assert_eq!(game_state.get_npc("orc1").unwrap().get_int_stat("hp"), Some(25));
+        
+        // Negative damage does not heal
+        assert!(!game_state.damage_entity(&orc1, -7, None));
+        assert_eq!(game_state.get_npc("orc1").unwrap().get_int_stat("hp"), Some(25));
+        
+        // Overkill stops at zero hp, and only the damage dealt is reported
+        let amounts = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = amounts.clone();
+        game_state.events.subscribe(Some("damaged"), move |_, event| {
+            log.lock().unwrap().push(event.field("amount").unwrap().to_string());
+        });
+        assert!(game_state.damage_entity(&orc1, 100, None));
+        assert_eq!(game_state.get_npc("orc1").unwrap().base_stats().get_int("hp"), Some(-10));
+        assert_eq!(game_state.get_npc("orc1").unwrap().get_int_stat("hp"), Some(0));
+        assert!(game_state.damage_entity(&orc1, 5, None));
+        game_state.dispatch_events();
+        assert_eq!(*amounts.lock().unwrap(), vec!["5".to_string(), "25".to_string()]);
+    }
+    
+    #[test]
+    fn test_context_exclusive_properties() {
+        let mut game_state = GameState::new();
+        let berserker = game_state.tag_collection.add_tag("berserker");
+        let tag = game_state.tag_collection.get_tag_mut(berserker).unwrap();
+        tag.properties.push(Property::stat_modifier("damage", crate::stats::StatValue::Integer(5)).exclusive_to("combat"));
+        tag.properties.push(Property::stat_modifier("armor", crate::stats::StatValue::Integer(1)));
+        let mut orc = NPC::new("orc1".to_string(), EntityType::new("orc", "Orc").with_tag_id(berserker));
+        orc.set_base_stat("damage", crate::stats::StatValue::Integer(3));
+        game_state.add_npc(orc).unwrap();
+        let orc1 = EntityId::Npc("orc1".to_string());
+        
+        game_state.update(0.1);
+        assert_eq!(game_state.get_npc("orc1").unwrap().get_int_stat("damage"), Some(3));
+        assert_eq!(game_state.entity_properties_in_context(&orc1).len(), 1);
+        
+        game_state.get_npc_mut("orc1").unwrap().enter_context("combat");
+        game_state.get_npc_mut("orc1").unwrap().enter_context("dialogue");
+        game_state.update(0.1);
+        assert_eq!(game_state.get_npc("orc1").unwrap().get_int_stat("damage"), Some(8));
+        let matches = game_state.entity_properties_in_context(&orc1);
+        assert_eq!(matches[0].contexts, vec!["combat".to_string()]);
+        assert!(matches[1].is_default_only());
+        
+        game_state.get_npc_mut("orc1").unwrap().leave_context("combat");
+        game_state.update(0.1);
+        assert_eq!(game_state.get_npc("orc1").unwrap().get_int_stat("damage"), Some(3));
+        assert_eq!(game_state.get_npc_mut("orc1").unwrap().pop_context(), Some("dialogue".to_string()));
+    }
+    
+    #[test]
+    fn test_merge_tags_from_content_pack() {
+        let mut game_state = GameState::new();
+        game_state.tag_collection.add_tag("undead");
+        
+        let mut pack = TagCollection::new();
+        let pack_fire = pack.add_tag("fire");
+        let pack_undead = pack.add_tag("undead");
+        let lich = EntityType::new("lich", "Lich").with_tag_ids(&[pack_fire, pack_undead]);
+        
+        let report = game_state.merge_tags(&pack, vec![lich], TagMergePolicy::KeepExisting).unwrap();
+        let fire = game_state.tag_collection.get_tag_by_name("fire").unwrap().id;
+        let undead = game_state.tag_collection.get_tag_by_name("undead").unwrap().id;
+        assert_eq!(report.remap(pack_fire), Some(fire));
+        assert!(game_state.entity_types["lich"].has_tag_id(fire));
+        assert!(game_state.entity_types["lich"].has_tag_id(undead));
+        assert_eq!(game_state.tag_index.entity_types_with_tag(undead), vec!["lich"]);
+    }
+
     #[test]
     fn test_exit_command() {
         let mut game_state = GameState::new();
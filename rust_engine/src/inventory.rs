@@ -1,5 +1,6 @@
 use std::collections::HashMap;
-use crate::stats::{Stats, StatValue};
+use crate::stats::Stats;
+use crate::property::Property;
 use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize)]
@@ -28,6 +29,22 @@ pub struct Item {
     id: String,
     name: String,
     properties: HashMap<String, ItemValue>,
+    // Engine properties (triggers, reactions, ...) carried by the item
+    #[serde(default)]
+    attached_properties: Vec<Property>,
+}
+
+impl Clone for Item {
+    fn clone(&self) -> Self {
+        let mut new_item = Item::new(&self.id, &self.name);
+        
+        for (key, value) in &self.properties {
+            new_item.properties.insert(key.clone(), value.clone());
+        }
+        new_item.attached_properties = self.attached_properties.clone();
+        
+        new_item
+    }
 }
 
 impl Item {
@@ -36,6 +53,7 @@ impl Item {
             id: id.to_string(),
             name: name.to_string(),
             properties: HashMap::new(),
+            attached_properties: Vec::new(),
         }
     }
     
@@ -51,6 +69,20 @@ impl Item {
         self.name = name.to_string();
     }
     
+    // Attach an engine property, e.g. a Trigger that fires while the item is equipped
+    pub fn attach_property(&mut self, property: Property) {
+        self.attached_properties.push(property);
+    }
+    
+    pub fn with_property(mut self, property: Property) -> Self {
+        self.attach_property(property);
+        self
+    }
+    
+    pub fn attached_properties(&self) -> &[Property] {
+        &self.attached_properties
+    }
+    
     // Property getters
     pub fn get(&self, key: &str) -> Option<&ItemValue> {
         self.properties.get(key)
@@ -125,17 +157,6 @@ impl Item {
     pub fn remove_property(&mut self, key: &str) -> Option<ItemValue> {
         self.properties.remove(key)
     }
-    
-    // Clone this item
-    pub fn clone(&self) -> Item {
-        let mut new_item = Item::new(&self.id, &self.name);
-        
-        for (key, value) in &self.properties {
-            new_item.properties.insert(key.clone(), value.clone());
-        }
-        
-        new_item
-    }
 }
 
 // Move specific factory functions to a separate module or make them examples
@@ -170,6 +191,27 @@ pub struct Inventory {
     capacity: Option<usize>,
 }
 
+impl Clone for Inventory {
+    fn clone(&self) -> Self {
+        let mut new_inventory = match self.capacity {
+            Some(cap) => Inventory::with_capacity(cap),
+            None => Inventory::new(),
+        };
+        
+        for item in self.items.values() {
+            new_inventory.add_item(item.clone());
+        }
+        
+        new_inventory
+    }
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Inventory {
     pub fn new() -> Inventory {
         Inventory {
@@ -263,18 +305,4 @@ impl Inventory {
     pub fn get_items_by_type(&self, item_type: &str) -> Vec<&Item> {
         self.filter_by_property("type", &ItemValue::String(item_type.to_string()))
     }
-    
-    // Clone this inventory
-    pub fn clone(&self) -> Inventory {
-        let mut new_inventory = match self.capacity {
-            Some(cap) => Inventory::with_capacity(cap),
-            None => Inventory::new(),
-        };
-        
-        for item in self.items.values() {
-            new_inventory.add_item(item.clone());
-        }
-        
-        new_inventory
-    }
 } 
\ No newline at end of file
@@ -2,36 +2,90 @@ pub mod stats;
 pub mod character;
 pub mod inventory;
 pub mod npc;
+pub mod ecs;
+pub mod entity;
+pub mod registry;
 pub mod entity_type;
+pub mod prefab;
 pub mod calculated_stats;
 pub mod property;
+pub mod context;
 pub mod tag;
+pub mod tag_query;
+pub mod tag_index;
+pub mod tag_merge;
 pub mod utils;
 pub mod coordinates;
 pub mod demos;
 pub mod game_state;
 pub mod files;
+pub mod condition;
+pub mod property_modifiers;
+pub mod functions;
+pub mod script;
+pub mod events;
+pub mod region;
+pub mod template;
+pub mod rng;
+pub mod spawner;
+pub mod state_machine;
+pub mod behavior_tree;
+pub mod utility;
+pub mod perception;
+pub mod faction;
+pub mod combat;
+pub mod damage;
+pub mod status_effect;
+pub mod content;
 
 // Re-export commonly used structures
 pub use stats::{Stats, StatValue};
 pub use character::Character;
 pub use inventory::{Inventory, Item};
 pub use npc::NPC;
+pub use ecs::{Component, Components, ComponentsMut, Query};
+pub use entity::{Entity, EntityMut};
+pub use registry::{EntityHandle, EntityRegistry, RegistryError, RegistryResult};
 pub use entity_type::EntityType;
+pub use prefab::{EntityTypeError, PropertyOverride, Provenance};
 pub use calculated_stats::{CalculatedStats, StatModifier, ModifierType};
 pub use property::{Property, PropertyType, PropertyValue, Condition, ConditionType};
-pub use tag::{Tag, TagCollection};
+pub use context::{ContextStack, ContextMatch};
+pub use tag::{Tag, TagCollection, TagHierarchyError};
+pub use tag_query::{TagQuery, CompiledTagQuery, TagQueryError};
+pub use tag_index::TagIndex;
+pub use tag_merge::{TagMergePolicy, TagMergeReport};
 pub use coordinates::Coordinates;
 pub use demos::{demo_tag_system, showcase_different_game_mechanics, demo_game_state, demo_asset_management};
-pub use game_state::GameState;
+pub use game_state::{GameState, EntityId};
+pub use condition::{ConditionEvaluator, CustomConditionFn, EntityRef};
+pub use functions::{FunctionRegistry, FunctionDefinition, FunctionCall, FunctionError, FunctionResult};
+pub use script::{Script, ScriptEngine, ScriptError, ScriptOutput, ScriptValue};
+pub use events::{EventBus, GameEvent, ListenerId, TriggerFiring};
+pub use region::{Region, RegionShape};
+pub use template::NpcTemplate;
+pub use rng::SeededRng;
+pub use spawner::{SpawnError, SpawnProfile, SpawnResult, StatTemplate, Wave};
+pub use state_machine::{State, StateAction, StateMachine, StateMachineError, StateMachineResult, Transition};
+pub use behavior_tree::{BehaviorTree, Blackboard, BtNode, BtTarget, NodeStatus};
+pub use utility::{Consideration, ConsiderationInput, ResponseCurve, UtilityAction, UtilityAi, UtilityState};
+pub use perception::{Memory, Perception, Sense};
+pub use faction::{Attitude, Faction, Factions, ReputationRule};
+pub use combat::{Attack, AttackOutcome, CombatError, CombatLogEntry, CombatResult, CombatState, Encounter};
+pub use damage::{DamageComponent, DamagePacket, DamageReport, DamageType, Defenses, ResolvedDamage};
+pub use status_effect::{ActiveStatusEffect, EffectModifier, Stacking, StatusEffectDef, StatusEffectError, StatusEffectResult, StatusEffectState};
+pub use content::{ContentLoader, ContentError, ContentResult, ContentSummary};
 pub use utils::{
     format_entity_with_tags, 
-    calculate_damage, 
     entity_has_any_tag, 
+    find_entities_matching_query,
     find_entities_with_tag, 
     find_entities_with_property,
     calculate_distance, 
     find_entities_in_radius, 
     has_line_of_sight
 }; 
+// Kept for existing callers; deprecated in favour of combat::resolve_attack
+#[allow(deprecated)]
+pub use utils::calculate_damage;
 pub use files::{Asset, AssetManager, AssetType, AssetResult, AssetError, transform_copy}; 
\ No newline at end of file
@@ -1,21 +1,7 @@
-mod character;
-mod npc;
-mod entity_type;
-mod stats;
-mod inventory;
-mod calculated_stats;
-mod property;
-mod tag;
-mod utils;
-mod coordinates;
-mod demos;
-mod game_state;
-mod files;
-
 use std::time::{Instant, Duration};
 use std::thread;
 use std::io::{self, BufRead};
-use game_state::GameState;
+use kean::{demos, GameState};
 
 fn main() {
     println!("Starting game backend service...");
@@ -39,7 +25,7 @@ fn main() {
         let mut handle = stdin.lock();
         let mut buffer = String::new();
         
-        while let Ok(_) = handle.read_line(&mut buffer) {
+        while handle.read_line(&mut buffer).is_ok() {
             // Send the command to the main thread
             let command = buffer.trim().to_string();
             if command.is_empty() {
@@ -1,8 +1,16 @@
 use crate::entity_type::EntityType;
+use crate::tag::InstanceTags;
+use crate::context::ContextStack;
+use crate::ecs::{Components, ComponentsMut};
 use crate::calculated_stats::{CalculatedStats, StatModifier, ModifierType};
 use crate::stats::{Stats, StatValue};
 use crate::coordinates::Coordinates;
-use std::collections::HashMap;
+use crate::inventory::Inventory;
+use crate::registry::EntityHandle;
+use crate::behavior_tree::Blackboard;
+use crate::utility::UtilityState;
+use crate::perception::Perception;
+use std::collections::{HashMap, HashSet};
 use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize)]
@@ -14,13 +22,52 @@ pub struct NPC {
     // Generic properties map for any game-specific data
     properties: HashMap<String, StatValue>,
     
-    // Using the same CalculatedStats system as Character for maximum flexibility
-    #[serde(skip)]
+    // Using the same CalculatedStats system as Character for maximum flexibility.
+    // Saved with its modifiers so status effects and equipment still apply after a load
+    #[serde(default)]
     calculated_stats: CalculatedStats,
     
     // Behavior flags and state
     pub behavior_state: String,
     pub status_effects: Vec<String>,
+    
+    // Seconds spent in behavior_state, None until the type's state machine has entered it
+    #[serde(default)]
+    pub state_time: Option<f32>,
+    
+    // Memory of the type's behavior tree
+    #[serde(default)]
+    pub blackboard: Blackboard,
+    
+    // Actions the type's utility AI chose and how they scored
+    #[serde(default)]
+    pub utility: UtilityState,
+    
+    // What the NPC sees, which way it faces and where it last sensed others
+    #[serde(default)]
+    pub perception: Perception,
+    
+    // Contexts the NPC is currently in (e.g. "combat"), used to select properties
+    #[serde(default)]
+    pub active_contexts: ContextStack,
+    
+    // Tags given to this entity at runtime, on top of its type's tags
+    #[serde(default)]
+    pub instance_tags: InstanceTags,
+    
+    // Items the NPC carries, e.g. starting items from its type's spawn profile
+    #[serde(default)]
+    pub inventory: Inventory,
+    
+    // NPC this one is targeting and NPC it follows; stale once that NPC is removed
+    #[serde(default)]
+    pub target: Option<EntityHandle>,
+    #[serde(default)]
+    pub leader: Option<EntityHandle>,
+    
+    // Handle the registry last gave this NPC, saved so links can be remapped on load
+    #[serde(default)]
+    pub(crate) handle: Option<EntityHandle>,
 }
 
 impl NPC {
@@ -33,6 +80,16 @@ impl NPC {
             calculated_stats: CalculatedStats::new(),
             behavior_state: "idle".to_string(),
             status_effects: Vec::new(),
+            state_time: None,
+            blackboard: Blackboard::default(),
+            utility: UtilityState::default(),
+            perception: Perception::default(),
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            inventory: Inventory::new(),
+            target: None,
+            leader: None,
+            handle: None,
         }
     }
     
@@ -46,6 +103,16 @@ impl NPC {
             calculated_stats: CalculatedStats::new(),
             behavior_state: "idle".to_string(),
             status_effects: Vec::new(),
+            state_time: None,
+            blackboard: Blackboard::default(),
+            utility: UtilityState::default(),
+            perception: Perception::default(),
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            inventory: Inventory::new(),
+            target: None,
+            leader: None,
+            handle: None,
         }
     }
     
@@ -59,6 +126,16 @@ impl NPC {
             calculated_stats: CalculatedStats::new(),
             behavior_state: "idle".to_string(),
             status_effects: Vec::new(),
+            state_time: None,
+            blackboard: Blackboard::default(),
+            utility: UtilityState::default(),
+            perception: Perception::default(),
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            inventory: Inventory::new(),
+            target: None,
+            leader: None,
+            handle: None,
         }
     }
     
@@ -72,6 +149,16 @@ impl NPC {
             calculated_stats: CalculatedStats::new(),
             behavior_state: "idle".to_string(),
             status_effects: Vec::new(),
+            state_time: None,
+            blackboard: Blackboard::default(),
+            utility: UtilityState::default(),
+            perception: Perception::default(),
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            inventory: Inventory::new(),
+            target: None,
+            leader: None,
+            handle: None,
         }
     }
     
@@ -85,6 +172,16 @@ impl NPC {
             calculated_stats: CalculatedStats::new(),
             behavior_state: "idle".to_string(),
             status_effects: Vec::new(),
+            state_time: None,
+            blackboard: Blackboard::default(),
+            utility: UtilityState::default(),
+            perception: Perception::default(),
+            active_contexts: ContextStack::new(),
+            instance_tags: InstanceTags::new(),
+            inventory: Inventory::new(),
+            target: None,
+            leader: None,
+            handle: None,
         }
     }
     
@@ -214,6 +311,58 @@ impl NPC {
         self.calculated_stats.add_modifier(stat, modifier);
     }
     
+    // Remove every modifier whose source starts with a prefix
+    pub fn remove_stat_modifiers_by_source_prefix(&mut self, prefix: &str) {
+        self.calculated_stats.remove_modifiers_by_source_prefix(prefix);
+    }
+    
+    // Modifiers currently applied to a stat
+    pub fn get_stat_modifiers(&self, stat: &str) -> &[StatModifier] {
+        self.calculated_stats.get_modifiers(stat)
+    }
+    
+    // Context management
+    pub fn enter_context(&mut self, context: &str) {
+        self.active_contexts.push(context);
+    }
+    
+    // Undo the most recent enter_context for this context
+    pub fn leave_context(&mut self, context: &str) {
+        self.active_contexts.remove(context);
+    }
+    
+    // Leave the most recently entered context
+    pub fn pop_context(&mut self) -> Option<String> {
+        self.active_contexts.pop()
+    }
+    
+    pub fn is_in_context(&self, context: &str) -> bool {
+        self.active_contexts.contains(context)
+    }
+    
+    // Instance tag management
+    pub fn add_instance_tag(&mut self, tag_id: i32) -> bool {
+        self.instance_tags.add(tag_id)
+    }
+    
+    pub fn add_timed_instance_tag(&mut self, tag_id: i32, duration: f32) -> bool {
+        self.instance_tags.add_timed(tag_id, duration)
+    }
+    
+    pub fn remove_instance_tag(&mut self, tag_id: i32) -> bool {
+        self.instance_tags.remove(tag_id)
+    }
+    
+    // Check for a tag from either the NPC's type or its instance tags
+    pub fn has_tag_id(&self, tag_id: i32) -> bool {
+        self.npc_type.has_tag_id(tag_id) || self.instance_tags.contains(tag_id)
+    }
+    
+    // Type tags and instance tags combined
+    pub fn tag_ids(&self) -> HashSet<i32> {
+        self.npc_type.tag_ids.iter().copied().chain(self.instance_tags.ids()).collect()
+    }
+    
     // Status effect management
     pub fn add_status_effect(&mut self, effect: &str) {
         if !self.status_effects.contains(&effect.to_string()) {
@@ -229,9 +378,44 @@ impl NPC {
         self.status_effects.contains(&effect.to_string())
     }
     
-    // Change behavior state
+    // The NPC's data as ECS components
+    pub fn components(&self) -> Components<'_> {
+        Components {
+            position: &self.position,
+            stats: &self.calculated_stats,
+            inventory: &self.inventory,
+            type_tags: Some(&self.npc_type.tag_ids),
+            instance_tags: &self.instance_tags,
+            status_effects: &self.status_effects,
+            behavior: Some(&self.behavior_state),
+            perception: Some(&self.perception),
+        }
+    }
+    
+    pub fn components_mut(&mut self) -> ComponentsMut<'_> {
+        ComponentsMut {
+            position: Some(&mut self.position),
+            stats: Some(&mut self.calculated_stats),
+            inventory: Some(&mut self.inventory),
+            tags: Some((Some(&mut self.npc_type.tag_ids), &mut self.instance_tags)),
+            status_effects: Some(&mut self.status_effects),
+            behavior: Some(&mut self.behavior_state),
+            perception: Some(&mut self.perception),
+        }
+    }
+    
+    // Turn to look at a position; the NPC also turns the way it moves
+    pub fn face_toward(&mut self, target: &Coordinates) {
+        if let Some(direction) = self.position.direction_to(target) {
+            self.perception.facing = Some(direction);
+        }
+    }
+    
+    // Change behavior state. A state machine runs the new state's enter actions on its next
+    // tick, but not the old state's exit actions.
     pub fn set_behavior_state(&mut self, state: &str) {
         self.behavior_state = state.to_string();
+        self.state_time = None;
     }
     
     // Movement helpers for backward compatibility
@@ -261,7 +445,9 @@ impl NPC {
     
     // Game-specific interaction methods
     
-    // For classic health-based games
+    // For classic health-based games. Edits hp directly, with no damaged event, rolls or
+    // resistances; combat goes through combat::attack or GameState::damage_entity instead
+    #[deprecated(note = "use combat::attack or GameState::damage_entity")]
     pub fn take_damage(&mut self, amount: i32) -> bool {
         if let Some(current_hp) = self.get_int_stat("hp") {
             let new_hp = (current_hp - amount).max(0);
@@ -282,7 +468,10 @@ impl NPC {
         false
     }
     
-    // Attack logic based on whatever stats the game designer chose
+    // Attack logic based on whatever stats the game designer chose. Keeps its own
+    // last_attack_time property, which combat does not read; combat::attack tracks the
+    // "attack_cooldown" of every attacker itself
+    #[deprecated(note = "use combat::attack, which applies attack_cooldown")]
     pub fn can_attack(&mut self, current_time: f32) -> bool {
         if let (Some(last_attack_time), Some(cooldown)) = (
             self.get_float_property("last_attack_time"),
@@ -0,0 +1,265 @@
+use serde::{Serialize, Deserialize};
+use crate::coordinates::Coordinates;
+use crate::events::GameEvent;
+use crate::game_state::{EntityId, GameState};
+use crate::utils::is_path_clear;
+
+/// Stat with how far an NPC sees; NPCs without it are blind
+pub const SIGHT_RANGE_STAT: &str = "sight_range";
+/// Stat with the width of an NPC's view cone in degrees
+pub const FIELD_OF_VIEW_STAT: &str = "field_of_view";
+/// Stat with how far away an NPC hears a noise of loudness 1; NPCs without it are deaf
+pub const HEARING_RADIUS_STAT: &str = "hearing_radius";
+/// Stat with how many seconds a memory lasts
+pub const MEMORY_DURATION_STAT: &str = "memory_duration";
+
+pub const DEFAULT_FIELD_OF_VIEW: f32 = 120.0;
+pub const DEFAULT_MEMORY_DURATION: f32 = 10.0;
+
+/// How an NPC learned where something is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sense {
+    Sight,
+    Hearing,
+}
+
+/// Where an NPC last saw or heard something
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Memory {
+    /// Who it was, or `None` for a noise nobody made
+    pub entity: Option<EntityId>,
+    pub position: Coordinates,
+    /// Game time it was sensed
+    pub time: f32,
+    pub sense: Sense,
+    /// 1 when fresh, falling to 0 over the NPC's memory duration, when it is forgotten
+    pub strength: f32,
+}
+
+/// What an NPC currently sees and remembers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Perception {
+    /// Unit vector the NPC looks along, set by moving or by `NPC::face_toward`; an NPC
+    /// that has never faced anywhere sees all around
+    pub facing: Option<Coordinates>,
+    /// Entities in sight at the last update
+    pub visible: Vec<EntityId>,
+    pub memories: Vec<Memory>,
+    /// Position at the last update, to turn the NPC the way it moved
+    #[serde(default)]
+    last_position: Option<Coordinates>,
+}
+
+impl Perception {
+    pub fn can_see(&self, entity: &EntityId) -> bool {
+        self.visible.contains(entity)
+    }
+
+    pub fn memory_of(&self, entity: &EntityId) -> Option<&Memory> {
+        self.memories.iter().find(|memory| memory.entity.as_ref() == Some(entity))
+    }
+
+    /// Where an entity was last seen or heard, while the NPC still remembers
+    pub fn last_known_position(&self, entity: &EntityId) -> Option<&Coordinates> {
+        self.memory_of(entity).map(|memory| &memory.position)
+    }
+
+    /// Where the last noise without a known source came from
+    pub fn last_noise(&self) -> Option<&Memory> {
+        self.memories.iter().find(|memory| memory.entity.is_none())
+    }
+
+    /// Remember something, replacing an older memory of the same entity
+    pub fn remember(&mut self, memory: Memory) {
+        self.memories.retain(|existing| existing.entity != memory.entity);
+        self.memories.push(memory);
+    }
+}
+
+/// Whether `from`, looking along `facing`, has `to` inside a cone `field_of_view` degrees wide
+pub fn in_view_cone(from: &Coordinates, facing: Option<&Coordinates>, field_of_view: f32, to: &Coordinates) -> bool {
+    let Some(facing) = facing else { return true };
+    if field_of_view >= 360.0 {
+        return true;
+    }
+    let Some(direction) = from.direction_to(to) else {
+        // Standing on the same spot
+        return from.dimensions() == to.dimensions();
+    };
+    if facing.dimensions() != direction.dimensions() {
+        return false;
+    }
+    let cos_angle: f32 = facing.values.iter().zip(&direction.values).map(|(a, b)| a * b).sum();
+    cos_angle >= (field_of_view.to_radians() / 2.0).cos()
+}
+
+/// Turn NPCs the way they moved, decay their memories and work out what they can see
+pub fn update_perception(game_state: &mut GameState) {
+    let game_time = game_state.game_time;
+    let handles: Vec<_> = game_state.npcs.handles().into_iter()
+        .filter(|handle| !game_state.npcs.is_despawning(*handle))
+        .collect();
+    let mut entities = vec![(EntityId::Player, game_state.player.position.clone())];
+    entities.extend(handles.iter()
+        .filter_map(|handle| game_state.npcs.get(*handle))
+        .map(|npc| (EntityId::Npc(npc.id.clone()), npc.position.clone())));
+
+    for handle in handles {
+        let Some(npc) = game_state.npcs.get(handle) else { continue };
+        let self_id = EntityId::Npc(npc.id.clone());
+        let stat = |key: &str| npc.get_stat(key).and_then(|value| value.as_float());
+        let sight_range = stat(SIGHT_RANGE_STAT).unwrap_or(0.0);
+        let field_of_view = stat(FIELD_OF_VIEW_STAT).unwrap_or(DEFAULT_FIELD_OF_VIEW);
+        let memory_duration = stat(MEMORY_DURATION_STAT).unwrap_or(DEFAULT_MEMORY_DURATION);
+
+        let mut perception = npc.perception.clone();
+        if let Some(last) = &perception.last_position
+            && let Some(direction) = last.direction_to(&npc.position)
+        {
+            perception.facing = Some(direction);
+        }
+        perception.last_position = Some(npc.position.clone());
+
+        perception.visible = if sight_range > 0.0 {
+            entities.iter()
+                .filter(|(id, position)| {
+                    *id != self_id
+                        && npc.position.distance(position) <= sight_range
+                        && in_view_cone(&npc.position, perception.facing.as_ref(), field_of_view, position)
+                        && is_path_clear(&npc.position, position, &game_state.obstacles)
+                })
+                .map(|(id, _)| id.clone())
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        for memory in &mut perception.memories {
+            memory.strength = if memory_duration > 0.0 { 1.0 - (game_time - memory.time) / memory_duration } else { 0.0 };
+        }
+        perception.memories.retain(|memory| memory.strength > 0.0);
+        for (id, position) in &entities {
+            if perception.visible.contains(id) {
+                perception.remember(Memory {
+                    entity: Some(id.clone()),
+                    position: position.clone(),
+                    time: game_time,
+                    sense: Sense::Sight,
+                    strength: 1.0,
+                });
+            }
+        }
+
+        if let Some(npc) = game_state.npcs.get_mut(handle) {
+            npc.perception = perception;
+        }
+    }
+}
+
+/// Let NPCs within earshot of a noise event remember where it came from; called as the
+/// event is dispatched
+pub fn hear(game_state: &mut GameState, event: &GameEvent) {
+    let GameEvent::Noise { source, position, loudness } = event else { return };
+    let game_time = game_state.game_time;
+    for handle in game_state.npcs.handles() {
+        if game_state.npcs.is_despawning(handle) {
+            continue;
+        }
+        let Some(npc) = game_state.npcs.get_mut(handle) else { continue };
+        if source.as_ref().is_some_and(|source| *source == EntityId::Npc(npc.id.clone())) {
+            continue;
+        }
+        let Some(hearing_radius) = npc.get_stat(HEARING_RADIUS_STAT).and_then(|value| value.as_float()) else { continue };
+        if npc.position.distance(position) <= hearing_radius * loudness {
+            npc.perception.remember(Memory {
+                entity: source.clone(),
+                position: position.clone(),
+                time: game_time,
+                sense: Sense::Hearing,
+                strength: 1.0,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+    use crate::property::Property;
+    use crate::stats::StatValue;
+
+    fn game() -> GameState {
+        let mut game_state = GameState::new();
+        let mut guard = NPC::new("guard".to_string(), EntityType::new("guard", "Guard"));
+        guard.set_base_stat(SIGHT_RANGE_STAT, StatValue::Float(10.0));
+        guard.set_base_stat(FIELD_OF_VIEW_STAT, StatValue::Float(90.0));
+        guard.set_base_stat(HEARING_RADIUS_STAT, StatValue::Float(4.0));
+        guard.set_base_stat(MEMORY_DURATION_STAT, StatValue::Float(5.0));
+        guard.face_toward(&Coordinates::new_2d(1.0, 0.0));
+        game_state.add_npc(guard).unwrap();
+        game_state
+    }
+
+    #[test]
+    fn test_sight_cone_line_of_sight_and_memory() {
+        let mut game_state = game();
+        let sees_player = Property::create_can_see_condition("player");
+        let remembers_player = Property::create_remembers_condition("player", Some(2.0));
+        let check = |game_state: &GameState, condition| {
+            let guard = game_state.get_npc("guard").unwrap();
+            game_state.conditions.evaluate(crate::condition::EntityRef::Npc(guard), game_state, condition)
+        };
+
+        // In front and in range
+        game_state.player.position = Coordinates::new_2d(6.0, 2.0);
+        game_state.update(1.0);
+        assert!(game_state.can_see("guard", &EntityId::Player));
+        assert!(check(&game_state, &sees_player));
+
+        // Behind a pillar
+        game_state.obstacles.push((Coordinates::new_2d(3.0, 1.0), 0.5));
+        game_state.update(1.0);
+        assert!(!game_state.can_see("guard", &EntityId::Player));
+        assert_eq!(game_state.last_known_position("guard", &EntityId::Player), Some(&Coordinates::new_2d(6.0, 2.0)));
+        assert!(check(&game_state, &remembers_player));
+
+        // Out of the 90 degree cone, then forgotten once the memory has faded
+        game_state.obstacles.clear();
+        game_state.player.position = Coordinates::new_2d(1.0, 5.0);
+        game_state.update(2.0);
+        assert!(!check(&game_state, &sees_player));
+        assert!(!check(&game_state, &remembers_player));
+        game_state.update(3.0);
+        assert_eq!(game_state.last_known_position("guard", &EntityId::Player), None);
+
+        // Walking toward the player turns the guard around
+        game_state.get_npc_mut("guard").unwrap().position = Coordinates::new_2d(0.0, 1.0);
+        game_state.update(1.0);
+        assert!(game_state.can_see("guard", &EntityId::Player));
+    }
+
+    #[test]
+    fn test_hearing_noise_events() {
+        let mut game_state = game();
+        game_state.player.position = Coordinates::new_2d(-6.0, 0.0);
+
+        // Too far to hear, then loud enough
+        game_state.emit_event(GameEvent::Noise { source: Some(EntityId::Player), position: Coordinates::new_2d(-6.0, 0.0), loudness: 0.5 });
+        game_state.update(1.0);
+        assert_eq!(game_state.last_known_position("guard", &EntityId::Player), None);
+        crate::script::run_script(&mut game_state, "noise(player(), 2);", EntityId::Player, None).unwrap();
+        game_state.update(1.0);
+        let guard = game_state.get_npc("guard").unwrap();
+        let memory = guard.perception.memory_of(&EntityId::Player).unwrap();
+        assert_eq!((memory.sense, &memory.position), (Sense::Hearing, &Coordinates::new_2d(-6.0, 0.0)));
+        assert!(!guard.perception.can_see(&EntityId::Player));
+
+        game_state.emit_event(GameEvent::Noise { source: None, position: Coordinates::new_2d(0.0, 3.0), loudness: 1.0 });
+        game_state.dispatch_events();
+        let output = crate::script::run_script(&mut game_state, "return remembers(npc(\"guard\"), player())", EntityId::Player, None).unwrap();
+        assert_eq!(output.value, crate::script::ScriptValue::Bool(true));
+        assert_eq!(game_state.get_npc("guard").unwrap().perception.last_noise().unwrap().position, Coordinates::new_2d(0.0, 3.0));
+    }
+}
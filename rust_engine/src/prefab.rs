@@ -0,0 +1,350 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::entity_type::EntityType;
+use crate::tag::override_key;
+
+/// Problems resolving entity type inheritance
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntityTypeError {
+    /// A type extends a type that does not exist
+    UnknownBase { type_id: String, base: String },
+    /// Types extend each other in a loop; the IDs along the loop, starting and ending with the same type
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for EntityTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntityTypeError::UnknownBase { type_id, base } => write!(f, "entity type '{}' extends unknown type '{}'", type_id, base),
+            EntityTypeError::Cycle(ids) => write!(f, "entity types extend each other in a cycle: {}", ids.join(" -> ")),
+        }
+    }
+}
+
+/// Where the parts of a resolved entity type came from
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The type itself followed by its bases, nearest first
+    pub chain: Vec<String>,
+    /// ID of the type that declared each property, in the same order as `properties`
+    pub properties: Vec<String>,
+    /// ID of the type that declared each tag
+    pub tags: HashMap<i32, String>,
+    /// ID of the type the description came from
+    pub description: Option<String>,
+    /// ID of the type the category came from
+    pub category: Option<String>,
+    /// Base properties replaced along the chain
+    pub overrides: Vec<PropertyOverride>,
+    /// ID of the type that declared each spawn stat template
+    #[serde(default)]
+    pub spawn_stats: HashMap<String, String>,
+    /// ID of the type that declared each starting item, in the same order as `spawn.items`
+    #[serde(default)]
+    pub spawn_items: Vec<String>,
+    /// ID of the type the spawn dimensions came from
+    #[serde(default)]
+    pub spawn_dimensions: Option<String>,
+    /// ID of the type the starting behavior came from
+    #[serde(default)]
+    pub spawn_behavior: Option<String>,
+    /// ID of the type the state machine came from
+    #[serde(default)]
+    pub state_machine: Option<String>,
+    /// ID of the type the behavior tree came from
+    #[serde(default)]
+    pub behavior_tree: Option<String>,
+    /// ID of the type the utility AI came from
+    #[serde(default)]
+    pub utility_ai: Option<String>,
+    /// ID of the type the faction came from
+    #[serde(default)]
+    pub faction: Option<String>,
+}
+
+/// A base type's property replaced by a derived type's property filling the same slot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PropertyOverride {
+    /// Override slot, e.g. "stat:damage@default"
+    pub key: String,
+    /// Type whose property was replaced
+    pub base: String,
+    /// Type whose property replaced it
+    pub overridden_by: String,
+}
+
+/// Resolve `extends` chains in place: every type gets its bases' tags, category, description,
+/// properties, spawn profile, state machine and behavior tree, with its own declarations taking precedence.
+///
+/// Derived types add to their bases' tags and append properties, except that a property
+/// replaces a base property filling the same slot (same stat, function or "key" metadata, in
+/// the same contexts). Spawn stat templates merge by stat and starting items add up, without duplicates. Resolving again after editing a base is safe, since each type keeps
+/// track of what it declared itself. Nothing changes if a base is missing or the chain loops.
+pub fn resolve_entity_types(types: &mut HashMap<String, EntityType>) -> Result<(), EntityTypeError> {
+    let declarations: HashMap<String, EntityType> = types.iter()
+        .map(|(id, entity_type)| (id.clone(), entity_type.declaration()))
+        .collect();
+
+    let mut ids: Vec<&String> = declarations.keys().collect();
+    ids.sort();
+    let mut resolved = HashMap::new();
+    for id in ids {
+        resolve(id, &declarations, &mut resolved, &mut Vec::new())?;
+    }
+    *types = resolved;
+    Ok(())
+}
+
+fn resolve(
+    id: &str,
+    declarations: &HashMap<String, EntityType>,
+    resolved: &mut HashMap<String, EntityType>,
+    path: &mut Vec<String>,
+) -> Result<(), EntityTypeError> {
+    if resolved.contains_key(id) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|visited| visited == id) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(id.to_string());
+        return Err(EntityTypeError::Cycle(cycle));
+    }
+    let declaration = &declarations[id];
+
+    let Some(base_id) = &declaration.extends else {
+        let mut entity_type = declaration.clone();
+        entity_type.provenance = Some(Provenance {
+            chain: vec![id.to_string()],
+            properties: vec![id.to_string(); entity_type.properties.len()],
+            tags: entity_type.tag_ids.iter().map(|tag_id| (*tag_id, id.to_string())).collect(),
+            description: entity_type.description.as_ref().map(|_| id.to_string()),
+            category: entity_type.category.as_ref().map(|_| id.to_string()),
+            overrides: Vec::new(),
+            spawn_stats: entity_type.spawn.stats.keys().map(|key| (key.clone(), id.to_string())).collect(),
+            spawn_items: vec![id.to_string(); entity_type.spawn.items.len()],
+            spawn_dimensions: entity_type.spawn.dimensions.map(|_| id.to_string()),
+            spawn_behavior: entity_type.spawn.behavior.as_ref().map(|_| id.to_string()),
+            state_machine: entity_type.state_machine.as_ref().map(|_| id.to_string()),
+            behavior_tree: entity_type.behavior_tree.as_ref().map(|_| id.to_string()),
+            utility_ai: entity_type.utility_ai.as_ref().map(|_| id.to_string()),
+            faction: entity_type.faction.as_ref().map(|_| id.to_string()),
+        });
+        resolved.insert(id.to_string(), entity_type);
+        return Ok(());
+    };
+
+    if !declarations.contains_key(base_id) {
+        return Err(EntityTypeError::UnknownBase { type_id: id.to_string(), base: base_id.clone() });
+    }
+    path.push(id.to_string());
+    resolve(base_id, declarations, resolved, path)?;
+    path.pop();
+
+    let base = &resolved[base_id];
+    let base_provenance = base.provenance.clone().unwrap_or_default();
+    let mut entity_type = declaration.clone();
+    let mut provenance = Provenance {
+        chain: std::iter::once(id.to_string()).chain(base_provenance.chain).collect(),
+        properties: base_provenance.properties,
+        tags: base_provenance.tags,
+        description: base_provenance.description,
+        category: base_provenance.category,
+        overrides: base_provenance.overrides,
+        spawn_stats: base_provenance.spawn_stats,
+        spawn_items: base_provenance.spawn_items,
+        spawn_dimensions: base_provenance.spawn_dimensions,
+        spawn_behavior: base_provenance.spawn_behavior,
+        state_machine: base_provenance.state_machine,
+        behavior_tree: base_provenance.behavior_tree,
+        utility_ai: base_provenance.utility_ai,
+        faction: base_provenance.faction,
+    };
+
+    // Start from the base's properties and let our own replace or extend them
+    let mut properties = base.properties.clone();
+    for property in &declaration.properties {
+        let slot = override_key(property);
+        let existing = slot.as_ref().and_then(|slot| {
+            properties.iter().position(|p| override_key(p).as_ref() == Some(slot))
+        });
+        match (existing, slot) {
+            (Some(index), Some(key)) => {
+                provenance.overrides.push(PropertyOverride {
+                    key,
+                    base: provenance.properties[index].clone(),
+                    overridden_by: id.to_string(),
+                });
+                properties[index] = property.clone();
+                provenance.properties[index] = id.to_string();
+            },
+            _ => {
+                properties.push(property.clone());
+                provenance.properties.push(id.to_string());
+            },
+        }
+    }
+    entity_type.properties = properties;
+
+    let own_tags: HashSet<i32> = declaration.tag_ids.clone();
+    entity_type.tag_ids = base.tag_ids.union(&own_tags).copied().collect();
+    for tag_id in own_tags {
+        provenance.tags.insert(tag_id, id.to_string());
+    }
+
+    if entity_type.description.is_some() {
+        provenance.description = Some(id.to_string());
+    } else {
+        entity_type.description = base.description.clone();
+    }
+    if entity_type.category.is_some() {
+        provenance.category = Some(id.to_string());
+    } else {
+        entity_type.category = base.category.clone();
+    }
+
+    let mut spawn = base.spawn.clone();
+    for (key, template) in &declaration.spawn.stats {
+        spawn.stats.insert(key.clone(), template.clone());
+        provenance.spawn_stats.insert(key.clone(), id.to_string());
+    }
+    for item_id in &declaration.spawn.items {
+        if spawn.items.contains(item_id) {
+            continue;
+        }
+        spawn.items.push(item_id.clone());
+        provenance.spawn_items.push(id.to_string());
+    }
+    if declaration.spawn.dimensions.is_some() {
+        spawn.dimensions = declaration.spawn.dimensions;
+        provenance.spawn_dimensions = Some(id.to_string());
+    }
+    if declaration.spawn.behavior.is_some() {
+        spawn.behavior = declaration.spawn.behavior.clone();
+        provenance.spawn_behavior = Some(id.to_string());
+    }
+    entity_type.spawn = spawn;
+    
+    if entity_type.state_machine.is_some() {
+        provenance.state_machine = Some(id.to_string());
+    } else {
+        entity_type.state_machine = base.state_machine.clone();
+    }
+    if entity_type.behavior_tree.is_some() {
+        provenance.behavior_tree = Some(id.to_string());
+    } else {
+        entity_type.behavior_tree = base.behavior_tree.clone();
+    }
+    if entity_type.utility_ai.is_some() {
+        provenance.utility_ai = Some(id.to_string());
+    } else {
+        entity_type.utility_ai = base.utility_ai.clone();
+    }
+    if entity_type.faction.is_some() {
+        provenance.faction = Some(id.to_string());
+    } else {
+        entity_type.faction = base.faction.clone();
+    }
+
+    entity_type.provenance = Some(provenance);
+    resolved.insert(id.to_string(), entity_type);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::property::{Property, PropertyValue};
+    use crate::stats::StatValue;
+
+    fn damage(entity_type: &EntityType) -> Option<i32> {
+        entity_type.properties.iter().find_map(|p| match &p.value {
+            PropertyValue::Stat(stat, StatValue::Integer(value)) if stat == "damage" => Some(*value),
+            _ => None,
+        })
+    }
+
+    fn goblins() -> HashMap<String, EntityType> {
+        let goblin = EntityType::new("goblin", "Goblin")
+            .with_category("hostile")
+            .with_description("A small green menace")
+            .with_tag_ids(&[1])
+            .with_property_object(Property::stat_modifier("damage", StatValue::Integer(3)))
+            .with_property_object(Property::stat_modifier("speed", StatValue::Integer(2)));
+        let archer = EntityType::new("goblin_archer", "Goblin Archer")
+            .with_base("goblin")
+            .with_tag_ids(&[2])
+            .with_property_object(Property::stat_modifier("damage", StatValue::Integer(5)))
+            .with_property_object(Property::stat_modifier("range", StatValue::Integer(8)));
+        let chief = EntityType::new("goblin_archer_chief", "Goblin Archer Chief")
+            .with_base("goblin_archer")
+            .with_description("Leads the archers");
+        [goblin, archer, chief].into_iter().map(|t| (t.id.clone(), t)).collect()
+    }
+
+    #[test]
+    fn test_inheritance_with_overrides_and_provenance() {
+        let mut types = goblins();
+        resolve_entity_types(&mut types).unwrap();
+
+        let chief = &types["goblin_archer_chief"];
+        assert_eq!(chief.tag_ids, HashSet::from([1, 2]));
+        assert_eq!(chief.category.as_deref(), Some("hostile"));
+        assert_eq!(chief.description.as_deref(), Some("Leads the archers"));
+        assert_eq!(damage(chief), Some(5));
+        assert_eq!(chief.properties.len(), 3);
+        assert!(chief.inherits_from("goblin"));
+
+        let provenance = chief.provenance.as_ref().unwrap();
+        assert_eq!(provenance.chain, vec!["goblin_archer_chief", "goblin_archer", "goblin"]);
+        assert_eq!(provenance.properties, vec!["goblin_archer", "goblin", "goblin_archer"]);
+        assert_eq!(provenance.tags[&1], "goblin");
+        assert_eq!(provenance.category.as_deref(), Some("goblin"));
+        assert_eq!(provenance.description.as_deref(), Some("goblin_archer_chief"));
+        assert_eq!(provenance.overrides, vec![PropertyOverride {
+            key: "stat:damage@default".to_string(),
+            base: "goblin".to_string(),
+            overridden_by: "goblin_archer".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_resolving_again_picks_up_base_edits() {
+        let mut types = goblins();
+        resolve_entity_types(&mut types).unwrap();
+
+        let goblin = types.get_mut("goblin").unwrap();
+        goblin.tag_ids.insert(7);
+        goblin.category = Some("vermin".to_string());
+        resolve_entity_types(&mut types).unwrap();
+
+        let chief = &types["goblin_archer_chief"];
+        assert!(chief.has_tag_id(7));
+        assert_eq!(chief.category.as_deref(), Some("vermin"));
+        // Inherited properties are not duplicated
+        assert_eq!(chief.properties.len(), 3);
+        assert_eq!(chief.own_properties().len(), 0);
+    }
+
+    #[test]
+    fn test_unknown_bases_and_cycles() {
+        let mut types = goblins();
+        types.insert("imp".to_string(), EntityType::new("imp", "Imp").with_base("demon"));
+        assert_eq!(resolve_entity_types(&mut types), Err(EntityTypeError::UnknownBase {
+            type_id: "imp".to_string(),
+            base: "demon".to_string(),
+        }));
+
+        let mut types = goblins();
+        types.get_mut("goblin").unwrap().extends = Some("goblin_archer_chief".to_string());
+        let error = resolve_entity_types(&mut types).unwrap_err();
+        assert_eq!(error, EntityTypeError::Cycle(vec![
+            "goblin".to_string(),
+            "goblin_archer_chief".to_string(),
+            "goblin_archer".to_string(),
+            "goblin".to_string(),
+        ]));
+        // Nothing was resolved
+        assert!(types["goblin_archer"].provenance.is_none());
+    }
+}
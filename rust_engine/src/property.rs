@@ -2,6 +2,9 @@ use std::collections::HashMap;
 use crate::stats::StatValue;
 use serde::{Serialize, Deserialize};
 
+/// Context listed by properties that apply everywhere, unless they are exclusive
+pub const DEFAULT_CONTEXT: &str = "default";
+
 // A flexible property that can represent various attributes and behaviors
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Property {
@@ -10,6 +13,8 @@ pub struct Property {
     pub context: Vec<String>,        // In what contexts this property applies (e.g., "combat", "exploration")
     pub conditions: Vec<Condition>,  // Conditions under which this property is active
     pub metadata: HashMap<String, String>, // Additional metadata for special use cases
+    #[serde(default)]
+    pub exclusive: bool,             // Applies only in its listed contexts, never by default
 }
 
 // Different types of properties
@@ -55,7 +60,68 @@ pub enum ConditionType {
     TimeOfDay,         // Based on game time
     Proximity,         // When near/far from something
     InventoryContains, // When inventory has an item
+    TimeInState,       // When an NPC has been in its behavior state for a while
+    CanSee,            // When an NPC can see an entity
+    Remembers,         // When an NPC remembers where an entity was
+    HostileNearby,     // When an entity the entity is hostile to is close
     Custom(String),    // Custom condition
+    All(Vec<Condition>), // Every nested condition holds
+    Any(Vec<Condition>), // At least one nested condition holds
+    Not(Box<Condition>), // The nested condition does not hold
+}
+
+impl Condition {
+    pub fn new(condition_type: ConditionType) -> Self {
+        Condition {
+            condition_type,
+            parameters: HashMap::new(),
+        }
+    }
+    
+    // Add a parameter to a condition
+    pub fn with_parameter(mut self, key: &str, value: StatValue) -> Self {
+        self.parameters.insert(key.to_string(), value);
+        self
+    }
+    
+    // Combine conditions so that all of them must hold
+    pub fn all(conditions: Vec<Condition>) -> Self {
+        Condition::new(ConditionType::All(conditions))
+    }
+    
+    // Combine conditions so that at least one of them must hold
+    pub fn any(conditions: Vec<Condition>) -> Self {
+        Condition::new(ConditionType::Any(conditions))
+    }
+    
+    // Invert a condition
+    pub fn negate(condition: Condition) -> Self {
+        Condition::new(ConditionType::Not(Box::new(condition)))
+    }
+    
+    // A condition resolved by a handler registered under `name`
+    pub fn custom(name: &str) -> Self {
+        Condition::new(ConditionType::Custom(name.to_string()))
+    }
+    
+    // Parameter accessors
+    pub fn get_string(&self, key: &str) -> Option<&str> {
+        match self.parameters.get(key) {
+            Some(StatValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+    
+    pub fn get_float(&self, key: &str) -> Option<f32> {
+        self.parameters.get(key).and_then(|value| value.as_float())
+    }
+    
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.parameters.get(key) {
+            Some(StatValue::Boolean(value)) => Some(*value),
+            _ => None,
+        }
+    }
 }
 
 impl Property {
@@ -67,6 +133,7 @@ impl Property {
             context: vec!["default".to_string()],
             conditions: Vec::new(),
             metadata: HashMap::new(),
+            exclusive: false,
         }
     }
     
@@ -78,12 +145,49 @@ impl Property {
             context: vec!["default".to_string()],
             conditions: Vec::new(),
             metadata: HashMap::new(),
+            exclusive: false,
+        }
+    }
+    
+    // Create a trigger: fires its function or script on matching events about anyone
+    pub fn trigger(event: &str, action: PropertyValue) -> Self {
+        Property::event_property(PropertyType::Trigger, event, action)
+    }
+    
+    // Create a reaction: fires its function or script on matching events about its owner
+    pub fn reaction(event: &str, action: PropertyValue) -> Self {
+        Property::event_property(PropertyType::Reaction, event, action)
+    }
+    
+    fn event_property(property_type: PropertyType, event: &str, action: PropertyValue) -> Self {
+        let mut metadata = HashMap::new();
+        metadata.insert(crate::events::EVENT_METADATA.to_string(), event.to_string());
+        Property {
+            property_type,
+            value: action,
+            context: vec!["default".to_string()],
+            conditions: Vec::new(),
+            metadata,
+            exclusive: false,
         }
     }
     
-    // Add a context to a property
+    // Only fire a trigger or reaction when an event field matches, e.g. ("amount", ">=10")
+    pub fn with_filter(self, field: &str, value: &str) -> Self {
+        let key = format!("{}{}", crate::events::FILTER_PREFIX, field);
+        self.with_metadata(&key, value)
+    }
+    
+    // Scope a property to a context. The first context replaces "default", so the property
+    // only applies in the contexts it lists; list "default" again to keep it applying
+    // everywhere as well.
     pub fn with_context(mut self, context: &str) -> Self {
-        self.context.push(context.to_string());
+        if self.context.len() == 1 && self.context[0] == DEFAULT_CONTEXT {
+            self.context.clear();
+        }
+        if !self.context.iter().any(|c| c == context) {
+            self.context.push(context.to_string());
+        }
         self
     }
     
@@ -99,10 +203,37 @@ impl Property {
         self
     }
     
-    // Check if property applies in a given context
+    // Limit a property to a context: it stops applying by default and only applies while
+    // the entity is in this context (or another one added with with_context)
+    pub fn exclusive_to(mut self, context: &str) -> Self {
+        self.exclusive = true;
+        self.context.retain(|c| c != DEFAULT_CONTEXT);
+        if !self.context.iter().any(|c| c == context) {
+            self.context.push(context.to_string());
+        }
+        self
+    }
+    
+    // Check if property applies in a given context. Non-exclusive properties listing
+    // "default" apply in every context.
     pub fn applies_in_context(&self, context: &str) -> bool {
-        self.context.contains(&context.to_string()) || 
-        self.context.contains(&"default".to_string())
+        self.context.iter().any(|c| c == context) ||
+        (!self.exclusive && self.context.iter().any(|c| c == DEFAULT_CONTEXT))
+    }
+    
+    // Which of the active contexts this property applies in, plus "default" when it applies
+    // everywhere. Empty if the property does not apply.
+    pub fn matched_contexts(&self, active_contexts: &[String]) -> Vec<String> {
+        let mut matched: Vec<String> = Vec::new();
+        for context in active_contexts {
+            if context != DEFAULT_CONTEXT && self.context.contains(context) && !matched.contains(context) {
+                matched.push(context.clone());
+            }
+        }
+        if self.applies_in_context(DEFAULT_CONTEXT) {
+            matched.push(DEFAULT_CONTEXT.to_string());
+        }
+        matched
     }
     
     // Helper for creating a stat threshold condition
@@ -128,4 +259,63 @@ impl Property {
             parameters,
         }
     }
+    
+    // Helper for creating an in state condition (NPC behavior state or status effect)
+    pub fn create_in_state_condition(state: &str) -> Condition {
+        Condition::new(ConditionType::InState)
+            .with_parameter("state", StatValue::String(state.to_string()))
+    }
+    
+    // Helper for creating a time of day condition; hours wrap past midnight when start > end
+    pub fn create_time_of_day_condition(start_hour: f32, end_hour: f32) -> Condition {
+        Condition::new(ConditionType::TimeOfDay)
+            .with_parameter("start_hour", StatValue::Float(start_hour))
+            .with_parameter("end_hour", StatValue::Float(end_hour))
+    }
+    
+    // Helper for creating a proximity condition; target is "player" or an NPC id
+    pub fn create_proximity_condition(target: &str, distance: f32, within: bool) -> Condition {
+        Condition::new(ConditionType::Proximity)
+            .with_parameter("target", StatValue::String(target.to_string()))
+            .with_parameter("distance", StatValue::Float(distance))
+            .with_parameter("within", StatValue::Boolean(within))
+    }
+    
+    // Helper for creating a time in state condition: the NPC has been in its current behavior
+    // state for at least `seconds`
+    pub fn create_time_in_state_condition(seconds: f32) -> Condition {
+        Condition::new(ConditionType::TimeInState)
+            .with_parameter("seconds", StatValue::Float(seconds))
+    }
+    
+    // Helper for creating a can see condition: the NPC has `target` ("player" or an NPC ID)
+    // in sight
+    pub fn create_can_see_condition(target: &str) -> Condition {
+        Condition::new(ConditionType::CanSee)
+            .with_parameter("target", StatValue::String(target.to_string()))
+    }
+    
+    // Helper for creating a remembers condition: the NPC knows where `target` was, and
+    // sensed it no more than `within` seconds ago if given
+    pub fn create_remembers_condition(target: &str, within: Option<f32>) -> Condition {
+        let condition = Condition::new(ConditionType::Remembers)
+            .with_parameter("target", StatValue::String(target.to_string()));
+        match within {
+            Some(seconds) => condition.with_parameter("within", StatValue::Float(seconds)),
+            None => condition,
+        }
+    }
+    
+    // Helper for creating a hostile nearby condition: something the entity's faction is
+    // hostile to is within `distance`
+    pub fn create_hostile_nearby_condition(distance: f32) -> Condition {
+        Condition::new(ConditionType::HostileNearby)
+            .with_parameter("distance", StatValue::Float(distance))
+    }
+    
+    // Helper for creating an inventory contains condition
+    pub fn create_inventory_contains_condition(item_id: &str) -> Condition {
+        Condition::new(ConditionType::InventoryContains)
+            .with_parameter("item", StatValue::String(item_id.to_string()))
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,241 @@
+use crate::calculated_stats::{StatModifier, ModifierType};
+use crate::condition::EntityRef;
+use crate::game_state::GameState;
+use crate::property::{Property, PropertyType, PropertyValue};
+use crate::stats::StatValue;
+
+/// Source prefix of modifiers granted by tags ("tag:fire")
+pub const TAG_MODIFIER_SOURCE: &str = "tag:";
+/// Source prefix of modifiers granted by an entity type's own properties ("type:goblin")
+pub const TYPE_MODIFIER_SOURCE: &str = "type:";
+/// Type and tag modifiers are intrinsic, so they apply before equipment (10) and buffs (20)
+pub const PROPERTY_MODIFIER_PRIORITY: i32 = 5;
+
+/// Build a stat modifier from a `PropertyType::StatModifier` property.
+///
+/// The modifier type comes from the "modifier_type" metadata ("additive", "multiplicative"
+/// or "override"). Without it, float values multiply, integers add and anything else overrides.
+/// The "priority" metadata overrides `PROPERTY_MODIFIER_PRIORITY`.
+pub fn stat_modifier_from_property(property: &Property, source: &str) -> Option<(String, StatModifier)> {
+    if property.property_type != PropertyType::StatModifier {
+        return None;
+    }
+    let PropertyValue::Stat(stat, value) = &property.value else {
+        return None;
+    };
+
+    let modifier_type = match property.metadata.get("modifier_type").map(|s| s.as_str()) {
+        Some("additive") => ModifierType::Additive,
+        Some("multiplicative") => ModifierType::Multiplicative,
+        Some("override") => ModifierType::Override,
+        _ => match value {
+            StatValue::Integer(_) => ModifierType::Additive,
+            StatValue::Float(_) => ModifierType::Multiplicative,
+            _ => ModifierType::Override,
+        },
+    };
+    let priority = property.metadata.get("priority")
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(PROPERTY_MODIFIER_PRIORITY);
+
+    Some((stat.clone(), StatModifier {
+        source: source.to_string(),
+        modifier_type,
+        value: value.clone(),
+        priority,
+    }))
+}
+
+/// Check whether a property applies in any of the active contexts. Non-exclusive properties
+/// listing "default" always apply.
+pub fn applies_in_any_context(property: &Property, contexts: &[String]) -> bool {
+    !property.matched_contexts(contexts).is_empty()
+}
+
+/// Collect the modifiers an entity's type and tags (type and instance tags, with their
+/// ancestors) grant it in its active contexts. Properties whose conditions do not hold for the
+/// entity are skipped.
+pub fn collect_property_modifiers(
+    entity: EntityRef,
+    game_state: &GameState,
+    contexts: &[String],
+) -> Vec<(String, StatModifier)> {
+    let is_active = |property: &Property| {
+        applies_in_any_context(property, contexts) && game_state.is_property_active(entity, property)
+    };
+
+    // Inherited tag properties come in a stable order, so modifiers with equal priority
+    // are always applied the same way. Each is sourced from the tag that declares it.
+    let tag_ids = entity.tag_ids();
+    let tag_modifiers = game_state.tag_collection.resolved_properties_for_tags(&tag_ids)
+        .into_iter()
+        .filter(|(_, p)| is_active(p))
+        .filter_map(|(tag, p)| stat_modifier_from_property(p, &format!("{}{}", TAG_MODIFIER_SOURCE, tag.name)));
+
+    let type_modifiers = entity.entity_type().into_iter().flat_map(|entity_type| {
+        let type_source = format!("{}{}", TYPE_MODIFIER_SOURCE, entity_type.id);
+        entity_type.properties.iter()
+            .filter(|p| is_active(p))
+            .filter_map(move |p| stat_modifier_from_property(p, &type_source))
+    });
+
+    tag_modifiers.chain(type_modifiers).collect()
+}
+
+/// Re-apply type and tag modifiers to the player and every NPC.
+///
+/// Old modifiers are removed before conditions are evaluated, so a condition never sees
+/// the bonus it is guarding.
+pub fn refresh_property_modifiers(game_state: &mut GameState) {
+    for handle in game_state.npcs.handles() {
+        let Some(npc) = game_state.npcs.get_mut(handle) else { continue };
+        npc.remove_stat_modifiers_by_source_prefix(TAG_MODIFIER_SOURCE);
+        npc.remove_stat_modifiers_by_source_prefix(TYPE_MODIFIER_SOURCE);
+
+        let npc = &game_state.npcs.get(handle).expect("handle was just checked");
+        let modifiers = collect_property_modifiers(EntityRef::Npc(npc), game_state, npc.active_contexts.as_slice());
+
+        let npc = game_state.npcs.get_mut(handle).expect("handle was just checked");
+        for (stat, modifier) in modifiers {
+            npc.add_stat_modifier(&stat, &modifier.source, modifier.modifier_type, modifier.value, modifier.priority);
+        }
+    }
+
+    game_state.player.remove_stat_modifiers_by_source_prefix(TAG_MODIFIER_SOURCE);
+    game_state.player.remove_stat_modifiers_by_source_prefix(TYPE_MODIFIER_SOURCE);
+
+    let player = &game_state.player;
+    let modifiers = collect_property_modifiers(EntityRef::Character(player), game_state, player.active_contexts.as_slice());
+
+    for (stat, modifier) in modifiers {
+        game_state.player.add_stat_modifier(&stat, &modifier.source, modifier.modifier_type, modifier.value, modifier.priority);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+
+    fn fire_goblin_state() -> GameState {
+        let mut state = GameState::new();
+        let fire_id = state.tag_collection.add_tag("fire");
+        if let Some(fire_tag) = state.tag_collection.get_tag_mut(fire_id) {
+            *fire_tag = fire_tag.clone()
+                .with_property(Property::stat_modifier("damage", StatValue::Integer(5))
+                    .exclusive_to("combat"))
+                .with_property(Property::stat_modifier("speed", StatValue::Float(0.5)));
+        }
+
+        let goblin_type = EntityType::new("goblin", "Goblin")
+            .with_tag_id(fire_id)
+            .with_property_object(Property::stat_modifier("damage", StatValue::Integer(1))
+                .with_condition(Property::create_stat_threshold_condition("hp", StatValue::Integer(10), false)));
+        let mut goblin = NPC::new("goblin1".to_string(), goblin_type);
+        goblin.set_base_stat("damage", StatValue::Integer(10));
+        goblin.set_base_stat("speed", StatValue::Float(2.0));
+        goblin.set_base_stat("hp", StatValue::Integer(30));
+        goblin.enter_context("combat");
+        state.add_npc(goblin).unwrap();
+        state
+    }
+
+    #[test]
+    fn test_modifier_type_from_property() {
+        let (stat, modifier) = stat_modifier_from_property(
+            &Property::stat_modifier("speed", StatValue::Float(0.7)), "tag:ice").unwrap();
+        assert_eq!(stat, "speed");
+        assert!(matches!(modifier.modifier_type, ModifierType::Multiplicative));
+        assert_eq!(modifier.priority, PROPERTY_MODIFIER_PRIORITY);
+
+        let flat_speed = Property::stat_modifier("speed", StatValue::Float(1.5))
+            .with_metadata("modifier_type", "additive")
+            .with_metadata("priority", "30");
+        let (_, modifier) = stat_modifier_from_property(&flat_speed, "tag:haste").unwrap();
+        assert!(matches!(modifier.modifier_type, ModifierType::Additive));
+        assert_eq!(modifier.priority, 30);
+
+        assert!(stat_modifier_from_property(&Property::ability("teleport"), "tag:blink").is_none());
+    }
+
+    #[test]
+    fn test_tag_modifiers_reach_npc_stats() {
+        let mut state = fire_goblin_state();
+        state.update(0.1);
+
+        assert_eq!(state.get_npc("goblin1").unwrap().get_int_stat("damage"), Some(15));
+        assert_eq!(state.get_npc("goblin1").unwrap().get_float_stat("speed"), Some(1.0));
+        assert_eq!(state.get_npc("goblin1").unwrap().get_stat_modifiers("damage")[0].source, "tag:fire");
+
+        // Refreshing again must not stack the same modifiers
+        state.update(0.1);
+        assert_eq!(state.get_npc("goblin1").unwrap().get_int_stat("damage"), Some(15));
+        
+        // Fire's damage is combat-only; its speed penalty applies everywhere
+        state.get_npc_mut("goblin1").unwrap().leave_context("combat");
+        state.update(0.1);
+        assert_eq!(state.get_npc("goblin1").unwrap().get_int_stat("damage"), Some(10));
+        assert_eq!(state.get_npc("goblin1").unwrap().get_float_stat("speed"), Some(1.0));
+    }
+
+    #[test]
+    fn test_conditions_and_tag_changes_are_picked_up() {
+        let mut state = fire_goblin_state();
+
+        // The type's own bonus only applies while hp is below 10
+        state.get_npc_mut("goblin1").unwrap().set_base_stat("hp", StatValue::Integer(5));
+        state.refresh_property_modifiers();
+        assert_eq!(state.get_npc("goblin1").unwrap().get_int_stat("damage"), Some(16));
+
+        // Losing the tag drops its modifiers on the next refresh
+        state.get_npc_mut("goblin1").unwrap().npc_type.tag_ids.clear();
+        state.refresh_property_modifiers();
+        assert_eq!(state.get_npc("goblin1").unwrap().get_int_stat("damage"), Some(11));
+        assert_eq!(state.get_npc("goblin1").unwrap().get_float_stat("speed"), Some(2.0));
+    }
+
+    #[test]
+    fn test_inherited_tag_modifiers() {
+        let mut state = fire_goblin_state();
+        let inferno_id = state.tag_collection.add_tag("inferno");
+        let fire_id = state.tag_collection.get_tag_by_name("fire").unwrap().id;
+        state.tag_collection.add_parent(inferno_id, fire_id).unwrap();
+        state.tag_collection.get_tag_mut(inferno_id).unwrap().properties.push(
+            Property::stat_modifier("damage", StatValue::Integer(8)).exclusive_to("combat"));
+
+        // Holding both inferno and its parent fire applies fire's properties only once,
+        // with inferno's damage overriding fire's
+        state.get_npc_mut("goblin1").unwrap().npc_type.tag_ids.insert(inferno_id);
+        state.refresh_property_modifiers();
+        assert_eq!(state.get_npc("goblin1").unwrap().get_int_stat("damage"), Some(18));
+        assert_eq!(state.get_npc("goblin1").unwrap().get_float_stat("speed"), Some(1.0));
+        assert_eq!(state.get_npc("goblin1").unwrap().get_stat_modifiers("speed")[0].source, "tag:fire");
+
+        // A HasTag condition on the parent tag is met through the child
+        let condition = Property::create_has_tag_condition("fire");
+        state.get_npc_mut("goblin1").unwrap().npc_type.tag_ids.remove(&fire_id);
+        let npc = EntityRef::Npc(state.get_npc("goblin1").unwrap());
+        assert!(state.conditions.evaluate(npc, &state, &condition));
+        let exact = condition.with_parameter("exact", StatValue::Boolean(true));
+        assert!(!state.conditions.evaluate(npc, &state, &exact));
+    }
+
+    #[test]
+    fn test_player_character_type_modifiers() {
+        let mut state = GameState::new();
+        let warrior_id = state.tag_collection.add_tag("warrior");
+        if let Some(tag) = state.tag_collection.get_tag_mut(warrior_id) {
+            tag.properties.push(Property::stat_modifier("attack", StatValue::Integer(3)));
+        }
+        state.player.set_base_stat("attack", StatValue::Integer(10));
+        state.player.character_type = Some(EntityType::new("warrior", "Warrior").with_tag_id(warrior_id));
+
+        state.refresh_property_modifiers();
+        assert_eq!(state.player.get_int_stat("attack"), Some(13));
+
+        state.player.character_type = None;
+        state.refresh_property_modifiers();
+        assert_eq!(state.player.get_int_stat("attack"), Some(10));
+    }
+}
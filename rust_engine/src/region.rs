@@ -0,0 +1,65 @@
+use serde::{Serialize, Deserialize};
+use crate::coordinates::Coordinates;
+
+/// Shape of a region, in as many dimensions as its coordinates have
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RegionShape {
+    /// Everything within `radius` of `center`
+    Sphere { center: Coordinates, radius: f32 },
+    /// Everything between `min` and `max` in every dimension, inclusive
+    Box { min: Coordinates, max: Coordinates },
+}
+
+/// A named area of the world. `GameState` emits entered/left region events as entities
+/// cross its boundary.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub id: String,
+    pub shape: RegionShape,
+}
+
+impl Region {
+    pub fn sphere(id: &str, center: Coordinates, radius: f32) -> Self {
+        Region { id: id.to_string(), shape: RegionShape::Sphere { center, radius } }
+    }
+
+    pub fn bounding_box(id: &str, min: Coordinates, max: Coordinates) -> Self {
+        Region { id: id.to_string(), shape: RegionShape::Box { min, max } }
+    }
+
+    /// Check whether a position lies inside. Positions with a different number of
+    /// dimensions than the region are never inside.
+    pub fn contains(&self, position: &Coordinates) -> bool {
+        match &self.shape {
+            RegionShape::Sphere { center, radius } => {
+                // NaN for mismatched dimensions compares false
+                center.distance(position) <= *radius
+            },
+            RegionShape::Box { min, max } => {
+                min.dimensions() == position.dimensions()
+                    && max.dimensions() == position.dimensions()
+                    && position.values.iter().enumerate()
+                        .all(|(i, value)| *value >= min.values[i] && *value <= max.values[i])
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_contains() {
+        let camp = Region::sphere("camp", Coordinates::from_values(vec![0.0, 0.0]), 5.0);
+        assert!(camp.contains(&Coordinates::from_values(vec![3.0, 4.0])));
+        assert!(!camp.contains(&Coordinates::from_values(vec![4.0, 4.0])));
+        assert!(!camp.contains(&Coordinates::from_values(vec![0.0, 0.0, 0.0])));
+
+        let vault = Region::bounding_box("vault",
+            Coordinates::from_values(vec![10.0, 10.0]),
+            Coordinates::from_values(vec![20.0, 12.0]));
+        assert!(vault.contains(&Coordinates::from_values(vec![20.0, 10.0])));
+        assert!(!vault.contains(&Coordinates::from_values(vec![15.0, 13.0])));
+    }
+}
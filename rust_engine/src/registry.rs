@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::npc::NPC;
+
+/// Reference to an NPC in an `EntityRegistry`.
+///
+/// Handles are cheap to copy and safe to keep: once the NPC is removed its slot gets a new
+/// generation, so an old handle resolves to nothing instead of whoever reuses the slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EntityHandle {
+    index: u32,
+    generation: u32,
+}
+
+impl EntityHandle {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+impl fmt::Display for EntityHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}v{}", self.index, self.generation)
+    }
+}
+
+/// Problems adding NPCs to a registry
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryError {
+    /// Another NPC already has this ID
+    DuplicateId(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::DuplicateId(id) => write!(f, "an NPC with ID '{}' already exists", id),
+        }
+    }
+}
+
+pub type RegistryResult<T> = Result<T, RegistryError>;
+
+struct Slot {
+    generation: u32,
+    npc: Option<NPC>,
+}
+
+/// Generational storage for NPCs with unique string IDs.
+///
+/// Lookups by handle are O(1) and by ID go through a hash map. Iteration is in slot order,
+/// which is insertion order until slots are reused. NPC IDs should not be changed while
+/// the NPC is registered.
+///
+/// A registry is saved as a plain list of its NPCs. Loading registers them again, with
+/// fresh handles, and points `target` and `leader` links at the new ones; links to NPCs
+/// that are gone are cleared. Pending despawns are not saved.
+#[derive(Default)]
+pub struct EntityRegistry {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    ids: HashMap<String, EntityHandle>,
+    /// Handles despawned but not yet removed, in despawn order
+    despawned: Vec<EntityHandle>,
+}
+
+impl EntityRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an NPC, failing if its ID is taken
+    pub fn insert(&mut self, mut npc: NPC) -> RegistryResult<EntityHandle> {
+        if self.ids.contains_key(&npc.id) {
+            return Err(RegistryError::DuplicateId(npc.id));
+        }
+        let handle = match self.free.last() {
+            Some(&index) => EntityHandle { index, generation: self.slots[index as usize].generation },
+            None => EntityHandle { index: self.slots.len() as u32, generation: 0 },
+        };
+        npc.handle = Some(handle);
+        self.ids.insert(npc.id.clone(), handle);
+        if self.free.pop().is_some() {
+            self.slots[handle.index as usize].npc = Some(npc);
+        } else {
+            self.slots.push(Slot { generation: 0, npc: Some(npc) });
+        }
+        Ok(handle)
+    }
+
+    /// Register saved NPCs again, pointing their links at the handles they get now
+    fn from_saved(npcs: Vec<NPC>) -> RegistryResult<Self> {
+        let mut registry = EntityRegistry::new();
+        let mut remapped = HashMap::new();
+        for npc in npcs {
+            let saved = npc.handle;
+            let handle = registry.insert(npc)?;
+            if let Some(saved) = saved {
+                remapped.insert(saved, handle);
+            }
+        }
+        for npc in registry.iter_mut() {
+            npc.target = npc.target.and_then(|handle| remapped.get(&handle).copied());
+            npc.leader = npc.leader.and_then(|handle| remapped.get(&handle).copied());
+        }
+        Ok(registry)
+    }
+
+    /// Remove an NPC right away. Its handle and any copies of it stop resolving.
+    pub fn remove(&mut self, handle: EntityHandle) -> Option<NPC> {
+        self.get(handle)?;
+        let slot = &mut self.slots[handle.index as usize];
+        let npc = slot.npc.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        self.ids.remove(&npc.id);
+        self.despawned.retain(|despawned| *despawned != handle);
+        Some(npc)
+    }
+
+    /// Remove an NPC by ID right away
+    pub fn remove_by_id(&mut self, id: &str) -> Option<NPC> {
+        let handle = self.handle_of(id)?;
+        self.remove(handle)
+    }
+
+    /// Mark an NPC for removal at the next `flush_despawned`. It stays visible until then,
+    /// so despawning in the middle of an update does not disturb the rest of it.
+    /// Returns false if the handle is stale or the NPC is already despawning.
+    pub fn despawn(&mut self, handle: EntityHandle) -> bool {
+        if !self.contains(handle) || self.is_despawning(handle) {
+            return false;
+        }
+        self.despawned.push(handle);
+        true
+    }
+
+    pub fn is_despawning(&self, handle: EntityHandle) -> bool {
+        self.despawned.contains(&handle)
+    }
+
+    /// Remove every despawned NPC, returning them in despawn order
+    pub fn flush_despawned(&mut self) -> Vec<NPC> {
+        std::mem::take(&mut self.despawned).into_iter()
+            .filter_map(|handle| self.remove(handle))
+            .collect()
+    }
+
+    /// Whether the handle still refers to a registered NPC
+    pub fn contains(&self, handle: EntityHandle) -> bool {
+        self.get(handle).is_some()
+    }
+
+    pub fn get(&self, handle: EntityHandle) -> Option<&NPC> {
+        self.slots.get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.npc.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: EntityHandle) -> Option<&mut NPC> {
+        self.slots.get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.npc.as_mut())
+    }
+
+    /// Current handle of the NPC with this ID
+    pub fn handle_of(&self, id: &str) -> Option<EntityHandle> {
+        self.ids.get(id).copied()
+    }
+
+    pub fn get_by_id(&self, id: &str) -> Option<&NPC> {
+        self.get(self.handle_of(id)?)
+    }
+
+    pub fn get_by_id_mut(&mut self, id: &str) -> Option<&mut NPC> {
+        self.get_mut(self.handle_of(id)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &NPC> {
+        self.slots.iter().filter_map(|slot| slot.npc.as_ref())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut NPC> {
+        self.slots.iter_mut().filter_map(|slot| slot.npc.as_mut())
+    }
+
+    /// NPCs together with their handles
+    pub fn entries(&self) -> impl Iterator<Item = (EntityHandle, &NPC)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let handle = EntityHandle { index: index as u32, generation: slot.generation };
+            slot.npc.as_ref().map(|npc| (handle, npc))
+        })
+    }
+
+    /// Handles of all NPCs, e.g. to visit each one while also borrowing the game state
+    pub fn handles(&self) -> Vec<EntityHandle> {
+        self.entries().map(|(handle, _)| handle).collect()
+    }
+}
+
+impl Serialize for EntityRegistry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for EntityRegistry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let npcs = Vec::<NPC>::deserialize(deserializer)?;
+        EntityRegistry::from_saved(npcs).map_err(serde::de::Error::custom)
+    }
+}
+
+impl<'a> IntoIterator for &'a EntityRegistry {
+    type Item = &'a NPC;
+    type IntoIter = Box<dyn Iterator<Item = &'a NPC> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+impl<'a> IntoIterator for &'a mut EntityRegistry {
+    type Item = &'a mut NPC;
+    type IntoIter = Box<dyn Iterator<Item = &'a mut NPC> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+
+    fn npc(id: &str) -> NPC {
+        NPC::new(id.to_string(), EntityType::new("goblin", "Goblin"))
+    }
+
+    #[test]
+    fn test_handles_go_stale_when_slots_are_reused() {
+        let mut registry = EntityRegistry::new();
+        let first = registry.insert(npc("goblin1")).unwrap();
+        let second = registry.insert(npc("goblin2")).unwrap();
+        assert_eq!(registry.insert(npc("goblin1")), Err(RegistryError::DuplicateId("goblin1".to_string())));
+        assert_eq!(registry.handle_of("goblin2"), Some(second));
+
+        assert_eq!(registry.remove(first).unwrap().id, "goblin1");
+        let third = registry.insert(npc("goblin3")).unwrap();
+        assert_eq!(third.index(), first.index());
+        assert!(registry.get(first).is_none());
+        assert_eq!(registry.get(third).unwrap().id, "goblin3");
+        assert!(registry.get_by_id("goblin1").is_none());
+
+        let ids: Vec<&str> = registry.iter().map(|npc| npc.id.as_str()).collect();
+        assert_eq!(ids, vec!["goblin3", "goblin2"]);
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn test_despawn_is_deferred() {
+        let mut registry = EntityRegistry::new();
+        let handles: Vec<EntityHandle> = ["a", "b", "c"].iter().map(|id| registry.insert(npc(id)).unwrap()).collect();
+
+        for handle in registry.handles() {
+            if registry.get(handle).unwrap().id != "b" {
+                assert!(registry.despawn(handle));
+            }
+        }
+        assert!(!registry.despawn(handles[0]));
+        assert!(registry.is_despawning(handles[2]));
+        assert_eq!(registry.len(), 3);
+
+        let removed: Vec<String> = registry.flush_despawned().into_iter().map(|npc| npc.id).collect();
+        assert_eq!(removed, vec!["a", "c"]);
+        assert_eq!(registry.len(), 1);
+        assert!(!registry.contains(handles[0]));
+        assert!(registry.flush_despawned().is_empty());
+    }
+
+    #[test]
+    fn test_saved_as_a_list_with_links_remapped() {
+        let mut registry = EntityRegistry::new();
+        let first = registry.insert(npc("a")).unwrap();
+        let leader = registry.insert(npc("b")).unwrap();
+        let gone = registry.insert(npc("c")).unwrap();
+        registry.remove(first);
+        let reused = registry.insert(npc("d")).unwrap();
+        registry.get_mut(reused).unwrap().leader = Some(leader);
+        registry.get_mut(reused).unwrap().target = Some(gone);
+        registry.remove(gone);
+
+        let json = serde_json::to_value(&registry).unwrap();
+        let ids: Vec<&str> = json.as_array().unwrap().iter().map(|npc| npc["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec!["d", "b"]);
+
+        // Handles change on load, links follow them, and the link to a removed NPC is dropped
+        let loaded: EntityRegistry = serde_json::from_value(json).unwrap();
+        let follower = loaded.get_by_id("d").unwrap();
+        assert_eq!(follower.leader, loaded.handle_of("b"));
+        assert_ne!(loaded.handle_of("d"), Some(reused));
+        assert_eq!(follower.target, None);
+
+        // Lists saved before NPCs recorded their handles still load
+        let mut old = serde_json::json!([serde_json::to_value(npc("e")).unwrap()]);
+        old[0].as_object_mut().unwrap().remove("handle");
+        let loaded: EntityRegistry = serde_json::from_value(old).unwrap();
+        assert!(loaded.get_by_id("e").is_some());
+        let duplicate = serde_json::json!([serde_json::to_value(npc("e")).unwrap(), serde_json::to_value(npc("e")).unwrap()]);
+        assert!(serde_json::from_value::<EntityRegistry>(duplicate).is_err());
+    }
+}
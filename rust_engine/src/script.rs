@@ -0,0 +1,1454 @@
+//! A small sandboxed scripting language for `PropertyValue::Script`.
+//!
+//! Scripts can only touch the game state through the builtins below; there is no file,
+//! process or network access. Every statement and expression costs one instruction and a
+//! run stops with `ScriptError::InstructionLimit` once its budget is spent, so a buggy
+//! script cannot hang the game loop. Scripts started while another one runs (a `call()`
+//! whose function runs a script, say) spend what is left of the outermost run's budget,
+//! and a chain more than `MAX_SCRIPT_DEPTH` scripts deep fails with `ScriptError::DepthLimit`.
+//!
+//! ```text
+//! # Heal when badly hurt, then tell everyone
+//! let hp = stat(self, "hp");
+//! if hp < 10 and not has_tag(self, "undead") {
+//!     set_stat(self, "hp", hp + 5);
+//!     log("healed to " + stat(self, "hp"));
+//! } else if target != nil {
+//!     move_toward(self, target, 1.5);
+//! }
+//! ```
+//!
+//! Values are integers, floats, booleans, strings, entities and `nil`. `self` and `target`
+//! are bound to the running entity and its target (or `nil`).
+//!
+//! Builtins:
+//! - entities: `player()`, `npc(id)`, `id(e)`, `distance(a, b)`, `time()`
+//! - stats: `stat(e, name)`, `base_stat(e, name)`, `set_stat(e, name, value)`, `add_stat(e, name, amount)`
+//! - tags: `has_tag(e, name)`, `add_tag(e, name[, seconds])` (an instance tag, optionally
+//!   timed), `remove_tag(e, name)`
+//! - status effects: `has_status(e, name)`, `add_status(e, name)`, `remove_status(e, name)`
+//! - positions: `pos(e, dim)`, `set_pos(e, dim, value)`, `move_toward(e, other, distance)`
+//!   where `dim` is an index or a label such as "x"
+//! - perception: `can_see(npc, e)`, `remembers(npc, e)`, `noise(e, loudness)` (a noise
+//!   event at e's position)
+//! - damage: `damage(e, amount[, type])` deals typed damage (physical by default) from
+//!   `self` after e's resistances and returns the hit points taken
+//! - factions: `faction(e)`, `attitude(e, other)` ("ally", "neutral" or "hostile"),
+//!   `reputation(e, faction)`, `add_reputation(e, faction, amount)`
+//! - inventory: `has_item(e, id)`, `add_item(e, id, name)`, `remove_item(e, id)`, `item_count(e)`
+//! - functions: `call(function_id, caster, targets...)` runs a registered function
+//! - misc: `log(values...)`, `min`, `max`, `abs`, `floor`, `int`, `float`, `str`
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use crate::coordinates::Coordinates;
+use crate::damage::DamagePacket;
+use crate::ecs::{Perception, StatusEffects};
+use crate::events::GameEvent;
+use crate::functions::FunctionCall;
+use crate::game_state::{EntityId, GameState};
+use crate::inventory::Item;
+use crate::stats::StatValue;
+
+/// Instructions a script may execute per run unless the engine says otherwise
+pub const DEFAULT_INSTRUCTION_LIMIT: usize = 10_000;
+/// Most scripts that may run inside one another
+pub const MAX_SCRIPT_DEPTH: usize = 8;
+/// Compiled scripts a `ScriptEngine` keeps unless told otherwise
+pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+/// Deepest nesting of blocks and expressions the parser accepts
+const MAX_NESTING: usize = 64;
+/// Longest string a script may build
+const MAX_STRING_LENGTH: usize = 4096;
+
+// Result type for compiling and running scripts
+pub type ScriptResult<T> = Result<T, ScriptError>;
+
+// Errors raised while compiling or running a script
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    Syntax { line: usize, message: String },
+    Runtime { line: usize, message: String },
+    InstructionLimit(usize),
+    /// A script tried to start inside this many running scripts
+    DepthLimit(usize),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Syntax { line, message } => write!(f, "Syntax error on line {}: {}", line, message),
+            ScriptError::Runtime { line, message } => write!(f, "Script error on line {}: {}", line, message),
+            ScriptError::InstructionLimit(limit) => write!(f, "Script exceeded its limit of {} instructions", limit),
+            ScriptError::DepthLimit(depth) => write!(f, "Scripts nested more than {} deep", depth),
+        }
+    }
+}
+
+/// A value handled by scripts
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptValue {
+    Nil,
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+    Str(String),
+    Entity(EntityId),
+}
+
+impl ScriptValue {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            ScriptValue::Nil => false,
+            ScriptValue::Bool(value) => *value,
+            ScriptValue::Int(value) => *value != 0,
+            ScriptValue::Float(value) => *value != 0.0,
+            ScriptValue::Str(value) => !value.is_empty(),
+            ScriptValue::Entity(_) => true,
+        }
+    }
+
+    fn as_float(&self) -> Option<f32> {
+        match self {
+            ScriptValue::Int(value) => Some(*value as f32),
+            ScriptValue::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            ScriptValue::Nil => "nil",
+            ScriptValue::Int(_) => "int",
+            ScriptValue::Float(_) => "float",
+            ScriptValue::Bool(_) => "bool",
+            ScriptValue::Str(_) => "string",
+            ScriptValue::Entity(_) => "entity",
+        }
+    }
+}
+
+impl fmt::Display for ScriptValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptValue::Nil => write!(f, "nil"),
+            ScriptValue::Int(value) => write!(f, "{}", value),
+            ScriptValue::Float(value) => write!(f, "{}", value),
+            ScriptValue::Bool(value) => write!(f, "{}", value),
+            ScriptValue::Str(value) => write!(f, "{}", value),
+            ScriptValue::Entity(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+impl From<StatValue> for ScriptValue {
+    fn from(value: StatValue) -> Self {
+        match value {
+            StatValue::Integer(v) => ScriptValue::Int(v),
+            StatValue::Float(v) => ScriptValue::Float(v),
+            StatValue::Boolean(v) => ScriptValue::Bool(v),
+            StatValue::String(v) => ScriptValue::Str(v),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Lexer
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Ident(String),
+    Let, If, Else, While, Return, True, False, Nil, And, Or, Not,
+    LParen, RParen, LBrace, RBrace, Comma, Semicolon,
+    Assign, Eq, NotEq, Lt, LtEq, Gt, GtEq,
+    Plus, Minus, Star, Slash, Percent,
+    Eof,
+}
+
+fn tokenize(source: &str) -> ScriptResult<Vec<(Token, usize)>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    let syntax = |line: usize, message: String| ScriptError::Syntax { line, message };
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\n' => { line += 1; i += 1; },
+            c if c.is_whitespace() => i += 1,
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            },
+            '"' => {
+                let start_line = line;
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(syntax(start_line, "unterminated string".to_string())),
+                        Some('"') => { i += 1; break; },
+                        Some('\\') => {
+                            match chars.get(i + 1) {
+                                Some('n') => value.push('\n'),
+                                Some('t') => value.push('\t'),
+                                Some('"') => value.push('"'),
+                                Some('\\') => value.push('\\'),
+                                other => return Err(syntax(line, format!("invalid escape {:?}", other))),
+                            }
+                            i += 2;
+                        },
+                        Some(ch) => {
+                            if *ch == '\n' {
+                                line += 1;
+                            }
+                            value.push(*ch);
+                            i += 1;
+                        },
+                    }
+                }
+                tokens.push((Token::Str(value), start_line));
+            },
+            c if c.is_ascii_digit() => {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let is_float = i + 1 < chars.len() && chars[i] == '.' && chars[i + 1].is_ascii_digit();
+                if is_float {
+                    i += 1;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                let text: String = chars[start..i].iter().collect();
+                let token = if is_float {
+                    Token::Float(text.parse().map_err(|_| syntax(line, format!("invalid number '{}'", text)))?)
+                } else {
+                    Token::Int(text.parse().map_err(|_| syntax(line, format!("integer '{}' is too large", text)))?)
+                };
+                tokens.push((token, line));
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let token = match word.as_str() {
+                    "let" => Token::Let,
+                    "if" => Token::If,
+                    "else" => Token::Else,
+                    "while" => Token::While,
+                    "return" => Token::Return,
+                    "true" => Token::True,
+                    "false" => Token::False,
+                    "nil" => Token::Nil,
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Ident(word),
+                };
+                tokens.push((token, line));
+            },
+            _ => {
+                let next = chars.get(i + 1).copied();
+                let (token, width) = match (c, next) {
+                    ('=', Some('=')) => (Token::Eq, 2),
+                    ('!', Some('=')) => (Token::NotEq, 2),
+                    ('<', Some('=')) => (Token::LtEq, 2),
+                    ('>', Some('=')) => (Token::GtEq, 2),
+                    ('=', _) => (Token::Assign, 1),
+                    ('<', _) => (Token::Lt, 1),
+                    ('>', _) => (Token::Gt, 1),
+                    ('(', _) => (Token::LParen, 1),
+                    (')', _) => (Token::RParen, 1),
+                    ('{', _) => (Token::LBrace, 1),
+                    ('}', _) => (Token::RBrace, 1),
+                    (',', _) => (Token::Comma, 1),
+                    (';', _) => (Token::Semicolon, 1),
+                    ('+', _) => (Token::Plus, 1),
+                    ('-', _) => (Token::Minus, 1),
+                    ('*', _) => (Token::Star, 1),
+                    ('/', _) => (Token::Slash, 1),
+                    ('%', _) => (Token::Percent, 1),
+                    _ => return Err(syntax(line, format!("unexpected character '{}'", c))),
+                };
+                tokens.push((token, line));
+                i += width;
+            },
+        }
+    }
+
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
+
+// ---------------------------------------------------------------------------
+// Parser
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add, Sub, Mul, Div, Rem,
+    Eq, NotEq, Lt, LtEq, Gt, GtEq,
+    And, Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone)]
+enum ExprKind {
+    Literal(ScriptValue),
+    Var(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone)]
+struct Expr {
+    kind: ExprKind,
+    line: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Let(String, Expr),
+    Assign(String, Expr, usize),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.position].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].0.clone();
+        if token != Token::Eof {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn check(&self, token: &Token) -> bool {
+        self.peek() == token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.check(token) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> ScriptResult<T> {
+        Err(ScriptError::Syntax { line: self.line(), message: message.to_string() })
+    }
+
+    fn expect(&mut self, token: &Token, what: &str) -> ScriptResult<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            self.error(&format!("expected {}, found {:?}", what, self.peek()))
+        }
+    }
+
+    fn enter(&mut self) -> ScriptResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return self.error("script is nested too deeply");
+        }
+        Ok(())
+    }
+
+    fn program(&mut self) -> ScriptResult<Vec<Stmt>> {
+        let mut statements = Vec::new();
+        while !self.check(&Token::Eof) {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn block(&mut self) -> ScriptResult<Vec<Stmt>> {
+        self.enter()?;
+        self.expect(&Token::LBrace, "'{'")?;
+        let mut statements = Vec::new();
+        while !self.check(&Token::RBrace) {
+            if self.check(&Token::Eof) {
+                return self.error("missing '}'");
+            }
+            statements.push(self.statement()?);
+        }
+        self.advance();
+        self.depth -= 1;
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> ScriptResult<Stmt> {
+        let line = self.line();
+        let statement = match self.peek().clone() {
+            Token::Let => {
+                self.advance();
+                let Token::Ident(name) = self.advance() else {
+                    return self.error("expected a variable name after 'let'");
+                };
+                self.expect(&Token::Assign, "'='")?;
+                Stmt::Let(name, self.expression()?)
+            },
+            Token::If => return self.if_statement(),
+            Token::While => {
+                self.advance();
+                let condition = self.expression()?;
+                return Ok(Stmt::While(condition, self.block()?));
+            },
+            Token::Return => {
+                self.advance();
+                if self.check(&Token::Semicolon) || self.check(&Token::RBrace) || self.check(&Token::Eof) {
+                    Stmt::Return(None)
+                } else {
+                    Stmt::Return(Some(self.expression()?))
+                }
+            },
+            Token::Ident(name) if self.tokens.get(self.position + 1).map(|t| &t.0) == Some(&Token::Assign) => {
+                self.advance();
+                self.advance();
+                Stmt::Assign(name, self.expression()?, line)
+            },
+            _ => Stmt::Expr(self.expression()?),
+        };
+        // Semicolons are optional at the end of a block or script
+        if !self.eat(&Token::Semicolon) && !self.check(&Token::RBrace) && !self.check(&Token::Eof) {
+            return self.error(&format!("expected ';', found {:?}", self.peek()));
+        }
+        Ok(statement)
+    }
+
+    fn if_statement(&mut self) -> ScriptResult<Stmt> {
+        self.expect(&Token::If, "'if'")?;
+        let condition = self.expression()?;
+        let then_branch = self.block()?;
+        let else_branch = if self.eat(&Token::Else) {
+            if self.check(&Token::If) {
+                vec![self.if_statement()?]
+            } else {
+                self.block()?
+            }
+        } else {
+            Vec::new()
+        };
+        Ok(Stmt::If(condition, then_branch, else_branch))
+    }
+
+    fn expression(&mut self) -> ScriptResult<Expr> {
+        self.enter()?;
+        let expr = self.or_expr();
+        self.depth -= 1;
+        expr
+    }
+
+    fn binary_level(
+        &mut self,
+        operators: &[(Token, BinaryOp)],
+        next: fn(&mut Parser) -> ScriptResult<Expr>,
+    ) -> ScriptResult<Expr> {
+        let mut left = next(self)?;
+        // Each operator nests the chain so far one level deeper, so it counts as nesting too
+        let mut links = 0;
+        'outer: loop {
+            for (token, op) in operators {
+                if self.check(token) {
+                    self.enter()?;
+                    links += 1;
+                    let line = self.line();
+                    self.advance();
+                    let right = next(self)?;
+                    left = Expr { kind: ExprKind::Binary(*op, Box::new(left), Box::new(right)), line };
+                    continue 'outer;
+                }
+            }
+            self.depth -= links;
+            return Ok(left);
+        }
+    }
+
+    fn or_expr(&mut self) -> ScriptResult<Expr> {
+        self.binary_level(&[(Token::Or, BinaryOp::Or)], Parser::and_expr)
+    }
+
+    fn and_expr(&mut self) -> ScriptResult<Expr> {
+        self.binary_level(&[(Token::And, BinaryOp::And)], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> ScriptResult<Expr> {
+        self.binary_level(&[
+            (Token::Eq, BinaryOp::Eq), (Token::NotEq, BinaryOp::NotEq),
+            (Token::Lt, BinaryOp::Lt), (Token::LtEq, BinaryOp::LtEq),
+            (Token::Gt, BinaryOp::Gt), (Token::GtEq, BinaryOp::GtEq),
+        ], Parser::term)
+    }
+
+    fn term(&mut self) -> ScriptResult<Expr> {
+        self.binary_level(&[(Token::Plus, BinaryOp::Add), (Token::Minus, BinaryOp::Sub)], Parser::factor)
+    }
+
+    fn factor(&mut self) -> ScriptResult<Expr> {
+        self.binary_level(&[
+            (Token::Star, BinaryOp::Mul), (Token::Slash, BinaryOp::Div), (Token::Percent, BinaryOp::Rem),
+        ], Parser::unary)
+    }
+
+    fn unary(&mut self) -> ScriptResult<Expr> {
+        let line = self.line();
+        let op = match self.peek() {
+            Token::Minus => UnaryOp::Neg,
+            Token::Not => UnaryOp::Not,
+            _ => return self.primary(),
+        };
+        self.advance();
+        self.enter()?;
+        let operand = self.unary()?;
+        self.depth -= 1;
+        Ok(Expr { kind: ExprKind::Unary(op, Box::new(operand)), line })
+    }
+
+    fn primary(&mut self) -> ScriptResult<Expr> {
+        let line = self.line();
+        let kind = match self.advance() {
+            Token::Int(value) => ExprKind::Literal(ScriptValue::Int(value)),
+            Token::Float(value) => ExprKind::Literal(ScriptValue::Float(value)),
+            Token::Str(value) => ExprKind::Literal(ScriptValue::Str(value)),
+            Token::True => ExprKind::Literal(ScriptValue::Bool(true)),
+            Token::False => ExprKind::Literal(ScriptValue::Bool(false)),
+            Token::Nil => ExprKind::Literal(ScriptValue::Nil),
+            Token::LParen => {
+                let inner = self.expression()?;
+                self.expect(&Token::RParen, "')'")?;
+                return Ok(inner);
+            },
+            Token::Ident(name) => {
+                if self.eat(&Token::LParen) {
+                    let mut args = Vec::new();
+                    if !self.eat(&Token::RParen) {
+                        loop {
+                            args.push(self.expression()?);
+                            if self.eat(&Token::RParen) {
+                                break;
+                            }
+                            self.expect(&Token::Comma, "',' or ')'")?;
+                        }
+                    }
+                    ExprKind::Call(name, args)
+                } else {
+                    ExprKind::Var(name)
+                }
+            },
+            token => return Err(ScriptError::Syntax { line, message: format!("unexpected {:?}", token) }),
+        };
+        Ok(Expr { kind, line })
+    }
+}
+
+/// A compiled script, ready to run any number of times
+#[derive(Debug, Clone)]
+pub struct Script {
+    source: String,
+    program: Vec<Stmt>,
+}
+
+impl Script {
+    /// Parse a script, reporting the first syntax error
+    pub fn compile(source: &str) -> ScriptResult<Script> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, position: 0, depth: 0 };
+        let program = parser.program()?;
+        Ok(Script { source: source.to_string(), program })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Interpreter
+// ---------------------------------------------------------------------------
+
+/// Outcome of a successful script run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptOutput {
+    /// Value of the first `return`, or nil
+    pub value: ScriptValue,
+    /// Lines written with `log`
+    pub log: Vec<String>,
+    pub instructions_used: usize,
+}
+
+/// Compiles, caches and runs scripts
+#[derive(Clone)]
+pub struct ScriptEngine {
+    pub instruction_limit: usize,
+    /// Most compiled scripts kept; the least recently used one makes room for a new one
+    pub cache_capacity: usize,
+    cache: HashMap<String, (Arc<Script>, u64)>,
+    compilations: u64,
+    // Scripts running inside one another, and the instructions left to all of them
+    depth: usize,
+    budget: usize,
+}
+
+impl Default for ScriptEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptEngine {
+    pub fn new() -> Self {
+        ScriptEngine {
+            instruction_limit: DEFAULT_INSTRUCTION_LIMIT,
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            cache: HashMap::new(),
+            compilations: 0,
+            depth: 0,
+            budget: 0,
+        }
+    }
+
+    /// Compile a script, reusing an earlier compilation of the same source
+    pub fn compile(&mut self, source: &str) -> ScriptResult<Arc<Script>> {
+        self.compilations += 1;
+        if let Some((script, last_used)) = self.cache.get_mut(source) {
+            *last_used = self.compilations;
+            return Ok(script.clone());
+        }
+        let script = Arc::new(Script::compile(source)?);
+        while self.cache.len() >= self.cache_capacity.max(1) {
+            let oldest = self.cache.iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(source, _)| source.clone())
+                .expect("the cache is not empty");
+            self.cache.remove(&oldest);
+        }
+        self.cache.insert(source.to_string(), (script.clone(), self.compilations));
+        Ok(script)
+    }
+
+    /// Check whether a source has a cached compilation
+    pub fn is_cached(&self, source: &str) -> bool {
+        self.cache.contains_key(source)
+    }
+
+    /// Drop all cached compilations
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+}
+
+/// Run a script against the game state on behalf of `self_entity`
+pub fn run_script(
+    game_state: &mut GameState,
+    source: &str,
+    self_entity: EntityId,
+    target: Option<EntityId>,
+) -> ScriptResult<ScriptOutput> {
+    let script = game_state.scripts.compile(source)?;
+    let limit = game_state.scripts.instruction_limit;
+    run_compiled(game_state, &script, self_entity, target, limit)
+}
+
+/// Run a script with extra variables bound before it starts, such as the fields of the
+/// event that fired it. `self` and `target` cannot be rebound.
+pub fn run_script_with_bindings(
+    game_state: &mut GameState,
+    source: &str,
+    self_entity: EntityId,
+    target: Option<EntityId>,
+    bindings: Vec<(String, ScriptValue)>,
+) -> ScriptResult<ScriptOutput> {
+    let script = game_state.scripts.compile(source)?;
+    let limit = game_state.scripts.instruction_limit;
+    run_with_variables(game_state, &script, self_entity, target, bindings, limit)
+}
+
+/// Run an already compiled script with an explicit instruction limit
+pub fn run_compiled(
+    game_state: &mut GameState,
+    script: &Script,
+    self_entity: EntityId,
+    target: Option<EntityId>,
+    instruction_limit: usize,
+) -> ScriptResult<ScriptOutput> {
+    run_with_variables(game_state, script, self_entity, target, Vec::new(), instruction_limit)
+}
+
+fn run_with_variables(
+    game_state: &mut GameState,
+    script: &Script,
+    self_entity: EntityId,
+    target: Option<EntityId>,
+    bindings: Vec<(String, ScriptValue)>,
+    instruction_limit: usize,
+) -> ScriptResult<ScriptOutput> {
+    let engine = &mut game_state.scripts;
+    if engine.depth >= MAX_SCRIPT_DEPTH {
+        return Err(ScriptError::DepthLimit(MAX_SCRIPT_DEPTH));
+    }
+    // A nested run gets no more than the outermost run has left
+    let limit = if engine.depth == 0 { instruction_limit } else { instruction_limit.min(engine.budget) };
+    if engine.depth == 0 {
+        engine.budget = limit;
+    }
+    engine.depth += 1;
+    let result = interpret(game_state, script, self_entity, target, bindings, limit);
+    game_state.scripts.depth -= 1;
+    result
+}
+
+fn interpret(
+    game_state: &mut GameState,
+    script: &Script,
+    self_entity: EntityId,
+    target: Option<EntityId>,
+    bindings: Vec<(String, ScriptValue)>,
+    instruction_limit: usize,
+) -> ScriptResult<ScriptOutput> {
+    let mut variables: HashMap<String, ScriptValue> = bindings.into_iter().collect();
+    variables.insert("self".to_string(), ScriptValue::Entity(self_entity));
+    variables.insert("target".to_string(), target.map(ScriptValue::Entity).unwrap_or(ScriptValue::Nil));
+
+    let mut interpreter = Interpreter {
+        game_state,
+        variables,
+        log: Vec::new(),
+        instructions: 0,
+        limit: instruction_limit,
+    };
+    let value = match interpreter.run_block(&script.program)? {
+        Flow::Return(value) => value,
+        Flow::Next => ScriptValue::Nil,
+    };
+
+    Ok(ScriptOutput {
+        value,
+        log: interpreter.log,
+        instructions_used: interpreter.instructions,
+    })
+}
+
+enum Flow {
+    Next,
+    Return(ScriptValue),
+}
+
+struct Interpreter<'a> {
+    game_state: &'a mut GameState,
+    variables: HashMap<String, ScriptValue>,
+    log: Vec<String>,
+    instructions: usize,
+    limit: usize,
+}
+
+fn runtime<T>(line: usize, message: String) -> ScriptResult<T> {
+    Err(ScriptError::Runtime { line, message })
+}
+
+impl Interpreter<'_> {
+    fn step(&mut self) -> ScriptResult<()> {
+        self.instructions += 1;
+        // The shared budget also pays for scripts this one starts
+        let scripts = &mut self.game_state.scripts;
+        if self.instructions > self.limit || scripts.budget == 0 {
+            return Err(ScriptError::InstructionLimit(self.limit));
+        }
+        scripts.budget -= 1;
+        Ok(())
+    }
+
+    fn run_block(&mut self, statements: &[Stmt]) -> ScriptResult<Flow> {
+        for statement in statements {
+            if let Flow::Return(value) = self.run_statement(statement)? {
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn run_statement(&mut self, statement: &Stmt) -> ScriptResult<Flow> {
+        self.step()?;
+        match statement {
+            Stmt::Let(name, expr) => {
+                let value = self.eval(expr)?;
+                self.variables.insert(name.clone(), value);
+            },
+            Stmt::Assign(name, expr, line) => {
+                if !self.variables.contains_key(name) {
+                    return runtime(*line, format!("assignment to undeclared variable '{}'", name));
+                }
+                let value = self.eval(expr)?;
+                self.variables.insert(name.clone(), value);
+            },
+            Stmt::If(condition, then_branch, else_branch) => {
+                let branch = if self.eval(condition)?.is_truthy() { then_branch } else { else_branch };
+                return self.run_block(branch);
+            },
+            Stmt::While(condition, body) => {
+                while self.eval(condition)?.is_truthy() {
+                    if let Flow::Return(value) = self.run_block(body)? {
+                        return Ok(Flow::Return(value));
+                    }
+                    self.step()?;
+                }
+            },
+            Stmt::Return(expr) => {
+                let value = match expr {
+                    Some(expr) => self.eval(expr)?,
+                    None => ScriptValue::Nil,
+                };
+                return Ok(Flow::Return(value));
+            },
+            Stmt::Expr(expr) => {
+                self.eval(expr)?;
+            },
+        }
+        Ok(Flow::Next)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> ScriptResult<ScriptValue> {
+        self.step()?;
+        let line = expr.line;
+        match &expr.kind {
+            ExprKind::Literal(value) => Ok(value.clone()),
+            ExprKind::Var(name) => match self.variables.get(name) {
+                Some(value) => Ok(value.clone()),
+                None => runtime(line, format!("unknown variable '{}'", name)),
+            },
+            ExprKind::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                match (op, value) {
+                    (UnaryOp::Not, value) => Ok(ScriptValue::Bool(!value.is_truthy())),
+                    (UnaryOp::Neg, ScriptValue::Int(v)) => v.checked_neg().map(ScriptValue::Int)
+                        .map_or_else(|| runtime(line, "integer overflow".to_string()), Ok),
+                    (UnaryOp::Neg, ScriptValue::Float(v)) => Ok(ScriptValue::Float(-v)),
+                    (UnaryOp::Neg, value) => runtime(line, format!("cannot negate {}", value.type_name())),
+                }
+            },
+            ExprKind::Binary(BinaryOp::And, left, right) => {
+                if !self.eval(left)?.is_truthy() {
+                    return Ok(ScriptValue::Bool(false));
+                }
+                Ok(ScriptValue::Bool(self.eval(right)?.is_truthy()))
+            },
+            ExprKind::Binary(BinaryOp::Or, left, right) => {
+                if self.eval(left)?.is_truthy() {
+                    return Ok(ScriptValue::Bool(true));
+                }
+                Ok(ScriptValue::Bool(self.eval(right)?.is_truthy()))
+            },
+            ExprKind::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                binary(*op, left, right, line)
+            },
+            ExprKind::Call(name, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.eval(arg)?);
+                }
+                self.call_builtin(name, values, line)
+            },
+        }
+    }
+
+    fn call_builtin(&mut self, name: &str, args: Vec<ScriptValue>, line: usize) -> ScriptResult<ScriptValue> {
+        let arity = |count: usize| -> ScriptResult<()> {
+            if args.len() == count {
+                Ok(())
+            } else {
+                runtime(line, format!("{}() takes {} argument(s), got {}", name, count, args.len()))
+            }
+        };
+        let entity = |index: usize| -> ScriptResult<EntityId> {
+            match args.get(index) {
+                Some(ScriptValue::Entity(id)) => Ok(id.clone()),
+                Some(other) => runtime(line, format!("{}() expects an entity, got {}", name, other.type_name())),
+                None => runtime(line, format!("{}() is missing an entity argument", name)),
+            }
+        };
+        let text = |index: usize| -> ScriptResult<String> {
+            match args.get(index) {
+                Some(ScriptValue::Str(value)) => Ok(value.clone()),
+                Some(other) => runtime(line, format!("{}() expects a string, got {}", name, other.type_name())),
+                None => runtime(line, format!("{}() is missing a string argument", name)),
+            }
+        };
+        let number = |index: usize| -> ScriptResult<f32> {
+            match args.get(index).and_then(|v| v.as_float()) {
+                Some(value) => Ok(value),
+                None => runtime(line, format!("{}() expects a number", name)),
+            }
+        };
+
+        match name {
+            "player" => {
+                arity(0)?;
+                Ok(ScriptValue::Entity(EntityId::Player))
+            },
+            "npc" => {
+                arity(1)?;
+                let id = text(0)?;
+                if self.game_state.get_npc(&id).is_some() {
+                    Ok(ScriptValue::Entity(EntityId::Npc(id)))
+                } else {
+                    Ok(ScriptValue::Nil)
+                }
+            },
+            "id" => {
+                arity(1)?;
+                Ok(ScriptValue::Str(entity(0)?.to_string()))
+            },
+            "time" => {
+                arity(0)?;
+                Ok(ScriptValue::Float(self.game_state.game_time))
+            },
+            "distance" => {
+                arity(2)?;
+                let (a, b) = (self.position(&entity(0)?, line)?, self.position(&entity(1)?, line)?);
+                Ok(ScriptValue::Float(a.distance(&b)))
+            },
+            "stat" => {
+                arity(2)?;
+                let (id, stat) = (entity(0)?, text(1)?);
+                let value = self.require_entity(&id, line)?.get_stat(&stat);
+                Ok(value.map(ScriptValue::from).unwrap_or(ScriptValue::Nil))
+            },
+            "base_stat" => {
+                arity(2)?;
+                let (id, stat) = (entity(0)?, text(1)?);
+                let stats = self.base_stats_mut(&id, line)?;
+                Ok(stats.get(&stat).cloned().map(ScriptValue::from).unwrap_or(ScriptValue::Nil))
+            },
+            "set_stat" => {
+                arity(3)?;
+                let (id, stat) = (entity(0)?, text(1)?);
+                let value = match &args[2] {
+                    ScriptValue::Int(v) => StatValue::Integer(*v),
+                    ScriptValue::Float(v) => StatValue::Float(*v),
+                    ScriptValue::Bool(v) => StatValue::Boolean(*v),
+                    ScriptValue::Str(v) => StatValue::String(v.clone()),
+                    other => return runtime(line, format!("cannot store {} in a stat", other.type_name())),
+                };
+                self.base_stats_mut(&id, line)?.set(&stat, value);
+                Ok(ScriptValue::Nil)
+            },
+            "add_stat" => {
+                arity(3)?;
+                let (id, stat, amount) = (entity(0)?, text(1)?, &args[2]);
+                let stats = self.base_stats_mut(&id, line)?;
+                let new_value = match (stats.get(&stat), amount) {
+                    (Some(StatValue::Integer(v)), ScriptValue::Int(a)) => StatValue::Integer(v.saturating_add(*a)),
+                    (Some(StatValue::Integer(v)), ScriptValue::Float(a)) => StatValue::Integer((*v as f32 + a).round() as i32),
+                    (Some(StatValue::Float(v)), a) if a.as_float().is_some() => StatValue::Float(v + a.as_float().unwrap_or(0.0)),
+                    (None, ScriptValue::Int(a)) => StatValue::Integer(*a),
+                    (None, ScriptValue::Float(a)) => StatValue::Float(*a),
+                    _ => return runtime(line, format!("cannot add {} to stat '{}'", amount.type_name(), stat)),
+                };
+                stats.set(&stat, new_value.clone());
+                Ok(ScriptValue::from(new_value))
+            },
+            "has_tag" => {
+                arity(2)?;
+                let (id, tag) = (entity(0)?, text(1)?);
+                let Some(tag_id) = self.game_state.tag_collection.get_tag_by_name(&tag).map(|t| t.id) else {
+                    return Ok(ScriptValue::Bool(false));
+                };
+                let entity = self.require_entity(&id, line)?;
+                Ok(ScriptValue::Bool(entity.has_tag_id_in_hierarchy(tag_id, &self.game_state.tag_collection)))
+            },
+            "add_tag" => {
+                if args.len() != 2 && args.len() != 3 {
+                    return runtime(line, format!("add_tag() takes 2 or 3 arguments, got {}", args.len()));
+                }
+                let (id, tag) = (entity(0)?, text(1)?);
+                let duration = if args.len() == 3 { Some(number(2)?) } else { None };
+                let Some(tag_id) = self.game_state.tag_collection.get_tag_by_name(&tag).map(|t| t.id) else {
+                    return runtime(line, format!("unknown tag '{}'", tag));
+                };
+                let had_tag = self.require_entity(&id, line)?.has_tag_id(tag_id);
+                self.game_state.add_instance_tag(&id, tag_id, duration);
+                Ok(ScriptValue::Bool(!had_tag))
+            },
+            "remove_tag" => {
+                arity(2)?;
+                let (id, tag) = (entity(0)?, text(1)?);
+                let Some(tag_id) = self.game_state.tag_collection.get_tag_by_name(&tag).map(|t| t.id) else {
+                    return runtime(line, format!("unknown tag '{}'", tag));
+                };
+                self.require_entity(&id, line)?;
+                let mut removed = self.game_state.remove_instance_tag(&id, tag_id);
+                // An NPC owns its copy of its type, so type tags can be taken away too
+                if let EntityId::Npc(npc_id) = &id {
+                    removed |= self.game_state.remove_tag_from_npc(npc_id, tag_id);
+                }
+                Ok(ScriptValue::Bool(removed))
+            },
+            "has_status" => {
+                arity(2)?;
+                let (id, effect) = (entity(0)?, text(1)?);
+                Ok(ScriptValue::Bool(self.require_entity(&id, line)?.has_status_effect(&effect)))
+            },
+            "add_status" | "remove_status" => {
+                arity(2)?;
+                let (id, effect) = (entity(0)?, text(1)?);
+                let Some(effects) = self.game_state.component_mut::<StatusEffects>(&id) else {
+                    return runtime(line, format!("unknown entity '{}'", id));
+                };
+                if name == "remove_status" {
+                    effects.retain(|e| *e != effect);
+                } else if !effects.contains(&effect) {
+                    effects.push(effect);
+                }
+                Ok(ScriptValue::Nil)
+            },
+            "pos" => {
+                arity(2)?;
+                let position = self.position(&entity(0)?, line)?;
+                let value = match &args[1] {
+                    ScriptValue::Int(index) if *index >= 0 => position.get(*index as usize),
+                    ScriptValue::Str(label) => position.get_by_label(label),
+                    other => return runtime(line, format!("invalid dimension {}", other)),
+                };
+                Ok(value.map(ScriptValue::Float).unwrap_or(ScriptValue::Nil))
+            },
+            "set_pos" => {
+                arity(3)?;
+                let (id, value) = (entity(0)?, number(2)?);
+                let Some(position) = self.game_state.entity_position_mut(&id) else {
+                    return runtime(line, format!("unknown entity '{}'", id));
+                };
+                let changed = match &args[1] {
+                    ScriptValue::Int(index) if *index >= 0 => position.set(*index as usize, value),
+                    ScriptValue::Str(label) => position.set_by_label(label, value),
+                    other => return runtime(line, format!("invalid dimension {}", other)),
+                };
+                Ok(ScriptValue::Bool(changed))
+            },
+            "move_toward" => {
+                arity(3)?;
+                let (id, target, distance) = (entity(0)?, entity(1)?, number(2)?);
+                let target_position = self.position(&target, line)?;
+                let Some(position) = self.game_state.entity_position_mut(&id) else {
+                    return runtime(line, format!("unknown entity '{}'", id));
+                };
+                Ok(ScriptValue::Bool(position.move_toward(&target_position, distance)))
+            },
+            "can_see" | "remembers" => {
+                arity(2)?;
+                let (id, other) = (entity(0)?, entity(1)?);
+                let perception = self.require_entity(&id, line)?.components().get::<Perception>();
+                let Some(perception) = perception else {
+                    return Ok(ScriptValue::Bool(false));
+                };
+                let sensed = if name == "can_see" { perception.can_see(&other) } else { perception.memory_of(&other).is_some() };
+                Ok(ScriptValue::Bool(sensed))
+            },
+            "noise" => {
+                arity(2)?;
+                let (id, loudness) = (entity(0)?, number(1)?);
+                let position = self.position(&id, line)?;
+                self.game_state.emit_event(GameEvent::Noise { source: Some(id), position, loudness });
+                Ok(ScriptValue::Nil)
+            },
+            "damage" => {
+                if args.len() != 2 && args.len() != 3 {
+                    return runtime(line, format!("damage() takes 2 or 3 arguments, got {}", args.len()));
+                }
+                let (id, amount) = (entity(0)?, number(1)?);
+                let damage_type = if args.len() == 3 { text(2)? } else { crate::damage::PHYSICAL.to_string() };
+                let source = match self.variables.get("self") {
+                    Some(ScriptValue::Entity(source)) => Some(source.clone()),
+                    _ => None,
+                };
+                let packet = DamagePacket::of(&damage_type, amount);
+                match self.game_state.apply_damage(&id, &packet, source) {
+                    Ok(report) => Ok(ScriptValue::Int(report.total)),
+                    Err(error) => runtime(line, error.to_string()),
+                }
+            },
+            "faction" => {
+                arity(1)?;
+                let id = entity(0)?;
+                self.require_entity(&id, line)?;
+                Ok(self.game_state.faction_of(&id).map(ScriptValue::Str).unwrap_or(ScriptValue::Nil))
+            },
+            "attitude" => {
+                arity(2)?;
+                let (id, other) = (entity(0)?, entity(1)?);
+                self.require_entity(&id, line)?;
+                self.require_entity(&other, line)?;
+                Ok(ScriptValue::Str(self.game_state.attitude(&id, &other).to_string()))
+            },
+            "reputation" => {
+                arity(2)?;
+                let (id, faction) = (entity(0)?, text(1)?);
+                Ok(ScriptValue::Float(self.game_state.factions.reputation(&id, &faction)))
+            },
+            "add_reputation" => {
+                arity(3)?;
+                let (id, faction, amount) = (entity(0)?, text(1)?, number(2)?);
+                self.require_entity(&id, line)?;
+                Ok(ScriptValue::Float(self.game_state.factions.add_reputation(&id, &faction, amount)))
+            },
+            "has_item" => {
+                arity(2)?;
+                let (id, item) = (entity(0)?, text(1)?);
+                Ok(ScriptValue::Bool(self.require_entity(&id, line)?.has_item(&item)))
+            },
+            "add_item" => {
+                arity(3)?;
+                let (id, item_id, item_name) = (entity(0)?, text(1)?, text(2)?);
+                let item = Item::new(&item_id, &item_name);
+                match &id {
+                    EntityId::Player => Ok(ScriptValue::Bool(self.game_state.player.add_item(item))),
+                    EntityId::Npc(npc_id) => match self.game_state.get_npc_mut(npc_id) {
+                        Some(npc) => Ok(ScriptValue::Bool(npc.inventory.add_item(item))),
+                        None => runtime(line, format!("unknown entity '{}'", id)),
+                    },
+                }
+            },
+            "remove_item" => {
+                arity(2)?;
+                let (id, item_id) = (entity(0)?, text(1)?);
+                match &id {
+                    EntityId::Player => Ok(ScriptValue::Bool(self.game_state.player.remove_item(&item_id).is_some())),
+                    EntityId::Npc(npc_id) => match self.game_state.get_npc_mut(npc_id) {
+                        Some(npc) => Ok(ScriptValue::Bool(npc.inventory.remove_item(&item_id).is_some())),
+                        None => runtime(line, format!("unknown entity '{}'", id)),
+                    },
+                }
+            },
+            "item_count" => {
+                arity(1)?;
+                let id = entity(0)?;
+                let count = self.require_entity(&id, line)?.inventory().count();
+                Ok(ScriptValue::Int(count as i32))
+            },
+            "call" => {
+                if args.len() < 2 {
+                    return runtime(line, "call() needs a function id and a caster".to_string());
+                }
+                let function_id = text(0)?;
+                let caster = entity(1)?;
+                let targets = (2..args.len()).map(&entity).collect::<ScriptResult<Vec<_>>>()?;
+                match self.game_state.invoke_function(FunctionCall::new(&function_id, caster, targets)) {
+                    Ok(message) => Ok(ScriptValue::Str(message)),
+                    Err(e) => runtime(line, e.to_string()),
+                }
+            },
+            "log" => {
+                let message: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+                self.log.push(message.join(" "));
+                Ok(ScriptValue::Nil)
+            },
+            "min" | "max" => {
+                arity(2)?;
+                let (a, b) = (number(0)?, number(1)?);
+                let pick_first = if name == "min" { a <= b } else { a >= b };
+                Ok(if pick_first { args[0].clone() } else { args[1].clone() })
+            },
+            "abs" => {
+                arity(1)?;
+                match &args[0] {
+                    ScriptValue::Int(v) => Ok(ScriptValue::Int(v.saturating_abs())),
+                    _ => Ok(ScriptValue::Float(number(0)?.abs())),
+                }
+            },
+            "floor" | "int" => {
+                arity(1)?;
+                Ok(ScriptValue::Int(number(0)?.floor() as i32))
+            },
+            "float" => {
+                arity(1)?;
+                Ok(ScriptValue::Float(number(0)?))
+            },
+            "str" => {
+                arity(1)?;
+                Ok(ScriptValue::Str(args[0].to_string()))
+            },
+            _ => runtime(line, format!("unknown function '{}'", name)),
+        }
+    }
+
+    fn require_entity(&self, id: &EntityId, line: usize) -> ScriptResult<crate::condition::EntityRef<'_>> {
+        match self.game_state.get_entity(id) {
+            Some(entity) => Ok(entity),
+            None => runtime(line, format!("unknown entity '{}'", id)),
+        }
+    }
+
+    fn position(&self, id: &EntityId, line: usize) -> ScriptResult<Coordinates> {
+        Ok(self.require_entity(id, line)?.position().clone())
+    }
+
+    fn base_stats_mut(&mut self, id: &EntityId, line: usize) -> ScriptResult<&mut crate::stats::Stats> {
+        match self.game_state.entity_base_stats_mut(id) {
+            Some(stats) => Ok(stats),
+            None => runtime(line, format!("unknown entity '{}'", id)),
+        }
+    }
+}
+
+fn binary(op: BinaryOp, left: ScriptValue, right: ScriptValue, line: usize) -> ScriptResult<ScriptValue> {
+    use ScriptValue::*;
+
+    match op {
+        BinaryOp::Eq | BinaryOp::NotEq => {
+            let equal = match (left.as_float(), right.as_float()) {
+                (Some(a), Some(b)) => a == b,
+                _ => left == right,
+            };
+            return Ok(Bool(equal == (op == BinaryOp::Eq)));
+        },
+        BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => {
+            let ordering = match (&left, &right) {
+                (Str(a), Str(b)) => a.partial_cmp(b),
+                _ => match (left.as_float(), right.as_float()) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    _ => None,
+                },
+            };
+            let Some(ordering) = ordering else {
+                return runtime(line, format!("cannot compare {} with {}", left.type_name(), right.type_name()));
+            };
+            return Ok(Bool(match op {
+                BinaryOp::Lt => ordering.is_lt(),
+                BinaryOp::LtEq => ordering.is_le(),
+                BinaryOp::Gt => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }));
+        },
+        _ => {}
+    }
+
+    // String concatenation with anything printable
+    if op == BinaryOp::Add && (matches!(left, Str(_)) || matches!(right, Str(_))) {
+        let joined = format!("{}{}", left, right);
+        if joined.len() > MAX_STRING_LENGTH {
+            return runtime(line, "string is too long".to_string());
+        }
+        return Ok(Str(joined));
+    }
+
+    match (&left, &right) {
+        (Int(a), Int(b)) => {
+            let result = match op {
+                BinaryOp::Add => a.checked_add(*b),
+                BinaryOp::Sub => a.checked_sub(*b),
+                BinaryOp::Mul => a.checked_mul(*b),
+                BinaryOp::Div if *b == 0 => return runtime(line, "division by zero".to_string()),
+                BinaryOp::Div => a.checked_div(*b),
+                BinaryOp::Rem if *b == 0 => return runtime(line, "division by zero".to_string()),
+                BinaryOp::Rem => a.checked_rem(*b),
+                _ => None,
+            };
+            result.map(Int).map_or_else(|| runtime(line, "integer overflow".to_string()), Ok)
+        },
+        _ => match (left.as_float(), right.as_float()) {
+            (Some(a), Some(b)) => Ok(Float(match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                _ => a % b,
+            })),
+            _ => runtime(line, format!("cannot apply {:?} to {} and {}", op, left.type_name(), right.type_name())),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+
+    fn state_with_goblin() -> GameState {
+        let mut state = GameState::new();
+        let undead_id = state.tag_collection.add_tag("undead");
+        state.tag_collection.add_tag("enraged");
+        let mut goblin = NPC::new("goblin1".to_string(), EntityType::new("goblin", "Goblin").with_tag_id(undead_id));
+        goblin.set_base_stat("hp", StatValue::Integer(8));
+        goblin.set_position(4.0, 0.0);
+        state.add_npc(goblin).unwrap();
+        state
+    }
+
+    fn goblin() -> EntityId {
+        EntityId::Npc("goblin1".to_string())
+    }
+
+    #[test]
+    fn test_arithmetic_and_control_flow() {
+        let mut state = GameState::new();
+        let source = "
+            let total = 0;
+            let i = 1;
+            while i <= 10 { total = total + i; i = i + 1; }
+            if total == 55 and not false { return total * 2 } else { return -1 }
+        ";
+        let output = run_script(&mut state, source, EntityId::Player, None).unwrap();
+        assert_eq!(output.value, ScriptValue::Int(110));
+        assert!(output.instructions_used > 10);
+
+        let output = run_script(&mut state, "return 7 / 2.0 + 1", EntityId::Player, None).unwrap();
+        assert_eq!(output.value, ScriptValue::Float(4.5));
+    }
+
+    #[test]
+    fn test_stat_tag_and_position_api() {
+        let mut state = state_with_goblin();
+        let source = r#"
+            if stat(self, "hp") < 10 and has_tag(self, "undead") {
+                add_stat(self, "hp", 5);
+                add_tag(self, "enraged");
+                remove_tag(self, "undead");
+            }
+            move_toward(self, target, 1.0);
+            log("goblin at", pos(self, "x"));
+            return distance(self, target);
+        "#;
+        let output = run_script(&mut state, source, goblin(), Some(EntityId::Player)).unwrap();
+
+        let npc = state.get_npc("goblin1").unwrap();
+        assert_eq!(npc.get_int_stat("hp"), Some(13));
+        let enraged = state.tag_collection.get_tag_by_name("enraged").unwrap().id;
+        let undead = state.tag_collection.get_tag_by_name("undead").unwrap().id;
+        assert!(npc.instance_tags.contains(enraged));
+        assert!(!npc.has_tag_id(undead));
+        assert_eq!(npc.x(), 3.0);
+        assert_eq!(output.value, ScriptValue::Float(3.0));
+        assert_eq!(output.log, vec!["goblin at 3".to_string()]);
+    }
+
+    #[test]
+    fn test_inventory_and_functions() {
+        let mut state = GameState::new();
+        state.functions.register_fn("heal", |state, call| {
+            let stats = state.entity_base_stats_mut(&call.caster).unwrap();
+            stats.set_int("hp", 100);
+            Ok("healed".to_string())
+        });
+        let source = r#"
+            add_item(player(), "potion", "Potion");
+            if has_item(player(), "potion") {
+                remove_item(player(), "potion");
+                return call("heal", player());
+            }
+        "#;
+        let output = run_script(&mut state, source, EntityId::Player, None).unwrap();
+        assert_eq!(output.value, ScriptValue::Str("healed".to_string()));
+        assert_eq!(state.player.get_int_stat("hp"), Some(100));
+        assert_eq!(state.player.inventory.count(), 0);
+    }
+
+    #[test]
+    fn test_instruction_limit_stops_infinite_loops() {
+        let mut state = GameState::new();
+        state.scripts.instruction_limit = 500;
+        let result = run_script(&mut state, "while true { }", EntityId::Player, None);
+        assert_eq!(result, Err(ScriptError::InstructionLimit(500)));
+    }
+
+    #[test]
+    fn test_nested_scripts_share_budget_and_depth() {
+        let mut state = GameState::new();
+        let count = "let i = 0; while i < 20 { i = i + 1; }";
+        let used = run_script(&mut state, count, EntityId::Player, None).unwrap().instructions_used;
+        state.scripts.instruction_limit = used + used / 2;
+        state.functions.register_fn("count", move |state, call| {
+            run_script(state, count, call.caster.clone(), None)
+                .map(|output| output.value.to_string())
+                .map_err(|e| crate::functions::FunctionError::Failed(e.to_string()))
+        });
+
+        // Each fits the limit on its own, but not together
+        assert!(run_script(&mut state, "call(\"count\", self);", EntityId::Player, None).is_ok());
+        let both = format!("{} call(\"count\", self);", count);
+        let result = run_script(&mut state, &both, EntityId::Player, None);
+        assert!(matches!(result, Err(ScriptError::Runtime { message, .. }) if message.contains("exceeded its limit")));
+
+        // Scripts calling themselves through a function stop at the depth limit
+        state.scripts.instruction_limit = DEFAULT_INSTRUCTION_LIMIT;
+        state.functions.register_fn("echo", |state, call| {
+            run_script(state, "return call(\"echo\", self);", call.caster.clone(), None)
+                .map(|output| output.value.to_string())
+                .map_err(|e| crate::functions::FunctionError::Failed(e.to_string()))
+        });
+        let result = run_script(&mut state, "return call(\"echo\", self);", EntityId::Player, None);
+        assert!(matches!(result, Err(ScriptError::Runtime { message, .. }) if message.contains("nested more than 8 deep")));
+        assert_eq!(run_script(&mut state, "return 1;", EntityId::Player, None).unwrap().value, ScriptValue::Int(1));
+    }
+
+    #[test]
+    fn test_cache_drops_least_recently_used() {
+        let mut engine = ScriptEngine::new();
+        engine.cache_capacity = 2;
+        for source in ["return 1;", "return 2;", "return 1;", "return 3;"] {
+            engine.compile(source).unwrap();
+        }
+        assert!(engine.is_cached("return 1;"));
+        assert!(!engine.is_cached("return 2;"));
+        assert!(engine.is_cached("return 3;"));
+    }
+
+    #[test]
+    fn test_errors_report_lines() {
+        let mut state = GameState::new();
+        assert_eq!(Script::compile("let x = 1;\nlet = 2;").unwrap_err(),
+            ScriptError::Syntax { line: 2, message: "expected a variable name after 'let'".to_string() });
+        assert!(matches!(Script::compile("let s = \"open"), Err(ScriptError::Syntax { line: 1, .. })));
+
+        let result = run_script(&mut state, "let x = 1;\n\nx = x / 0;", EntityId::Player, None);
+        assert_eq!(result, Err(ScriptError::Runtime { line: 3, message: "division by zero".to_string() }));
+
+        let result = run_script(&mut state, "open_file(\"/etc/passwd\")", EntityId::Player, None);
+        assert!(matches!(result, Err(ScriptError::Runtime { message, .. }) if message.contains("unknown function")));
+
+        let deep = format!("return {}1{}", "(".repeat(200), ")".repeat(200));
+        assert!(matches!(Script::compile(&deep), Err(ScriptError::Syntax { .. })));
+
+        // Long operator chains nest just as deeply, and must fail rather than overflow the stack
+        let chain = format!("return {}1;", "1 + ".repeat(5000));
+        assert!(matches!(run_script(&mut state, &chain, EntityId::Player, None), Err(ScriptError::Syntax { .. })));
+        let chain = format!("return {}1;", "1 + ".repeat(20));
+        assert_eq!(run_script(&mut state, &chain, EntityId::Player, None).unwrap().value, ScriptValue::Int(21));
+    }
+}
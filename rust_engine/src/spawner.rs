@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::coordinates::Coordinates;
+use crate::entity_type::EntityType;
+use crate::events::GameEvent;
+use crate::game_state::{EntityId, GameState};
+use crate::npc::NPC;
+use crate::rng::SeededRng;
+use crate::stats::StatValue;
+
+/// How a base stat is set when an NPC spawns
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StatTemplate {
+    /// Always this value
+    Fixed(StatValue),
+    /// A random integer between the bounds, inclusive
+    IntRange(i32, i32),
+    /// A random float between the bounds
+    FloatRange(f32, f32),
+}
+
+impl StatTemplate {
+    pub fn roll(&self, rng: &mut SeededRng) -> StatValue {
+        match self {
+            StatTemplate::Fixed(value) => value.clone(),
+            StatTemplate::IntRange(min, max) => StatValue::Integer(rng.range_i32(*min, *max)),
+            StatTemplate::FloatRange(min, max) => StatValue::Float(rng.range_f32(*min, *max)),
+        }
+    }
+}
+
+/// What an entity type's NPCs start with. Inherited through `extends`: stats merge by key,
+/// items add up without duplicates, and dimensions and behavior fall back to the base's.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpawnProfile {
+    /// Base stats, rolled per NPC
+    #[serde(default)]
+    pub stats: BTreeMap<String, StatTemplate>,
+    /// Number of position dimensions spawned NPCs must have
+    #[serde(default)]
+    pub dimensions: Option<usize>,
+    /// Item template IDs put in each NPC's inventory
+    #[serde(default)]
+    pub items: Vec<String>,
+    /// Starting behavior state, "idle" if unset
+    #[serde(default)]
+    pub behavior: Option<String>,
+}
+
+impl SpawnProfile {
+    pub fn is_empty(&self) -> bool {
+        self.stats.is_empty() && self.dimensions.is_none() && self.items.is_empty() && self.behavior.is_none()
+    }
+}
+
+/// Why a spawn failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpawnError {
+    UnknownType(String),
+    /// A starting item has no item template
+    UnknownItem { type_id: String, item_id: String },
+    /// The position has the wrong number of dimensions for the type
+    DimensionMismatch { type_id: String, expected: usize, found: usize },
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::UnknownType(type_id) => write!(f, "Unknown entity type: {}", type_id),
+            SpawnError::UnknownItem { type_id, item_id } => write!(f, "Entity type {} starts with unknown item {}", type_id, item_id),
+            SpawnError::DimensionMismatch { type_id, expected, found } => write!(
+                f, "Entity type {} spawns in {} dimensions, position has {}", type_id, expected, found),
+        }
+    }
+}
+
+pub type SpawnResult<T> = Result<T, SpawnError>;
+
+/// A group of NPCs spawned together, scattered around a center point
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wave {
+    /// Entity type IDs and how many of each
+    pub groups: Vec<(String, usize)>,
+    pub center: Coordinates,
+    /// NPCs are placed at random within this distance of the center
+    pub radius: f32,
+}
+
+impl Wave {
+    pub fn new(center: Coordinates, radius: f32) -> Self {
+        Wave { groups: Vec::new(), center, radius }
+    }
+
+    pub fn with_group(mut self, type_id: &str, count: usize) -> Self {
+        self.groups.push((type_id.to_string(), count));
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.groups.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// Spawn one NPC of an entity type at a position and return its new ID
+pub fn spawn_npc(game_state: &mut GameState, type_id: &str, position: Coordinates) -> SpawnResult<String> {
+    let entity_type = spawnable_type(game_state, type_id, position.dimensions())?;
+    Ok(place(game_state, &entity_type, position))
+}
+
+/// Spawn a whole wave. Every group is checked first, so either all NPCs spawn or none do.
+pub fn spawn_wave(game_state: &mut GameState, wave: &Wave) -> SpawnResult<Vec<String>> {
+    let mut types = Vec::new();
+    for (type_id, count) in &wave.groups {
+        types.push((spawnable_type(game_state, type_id, wave.center.dimensions())?, *count));
+    }
+
+    let mut ids = Vec::new();
+    for (entity_type, count) in types {
+        for _ in 0..count {
+            let position = scatter(&mut game_state.rng, &wave.center, wave.radius);
+            ids.push(place(game_state, &entity_type, position));
+        }
+    }
+    Ok(ids)
+}
+
+/// Create a fully initialised NPC of a type without adding it to the game
+pub fn build_npc(game_state: &mut GameState, entity_type: &EntityType, npc_id: &str, position: Coordinates) -> NPC {
+    let mut npc = NPC::new(npc_id.to_string(), entity_type.clone());
+    npc.position = position;
+    for (key, template) in &entity_type.spawn.stats {
+        let value = template.roll(&mut game_state.rng);
+        npc.set_base_stat(key, value);
+    }
+    for item_id in &entity_type.spawn.items {
+        if let Some(item) = game_state.item_from_template(item_id) {
+            npc.inventory.add_item(item);
+        }
+    }
+    if let Some(behavior) = &entity_type.spawn.behavior {
+        npc.behavior_state = behavior.clone();
+    }
+    npc
+}
+
+// Look up a type and check that it can spawn at a position with this many dimensions
+fn spawnable_type(game_state: &GameState, type_id: &str, dimensions: usize) -> SpawnResult<EntityType> {
+    let entity_type = game_state.entity_types.get(type_id)
+        .ok_or_else(|| SpawnError::UnknownType(type_id.to_string()))?;
+    if let Some(expected) = entity_type.spawn.dimensions
+        && expected != dimensions
+    {
+        return Err(SpawnError::DimensionMismatch { type_id: type_id.to_string(), expected, found: dimensions });
+    }
+    if let Some(item_id) = entity_type.spawn.items.iter().find(|id| !game_state.item_templates.contains_key(*id)) {
+        return Err(SpawnError::UnknownItem { type_id: type_id.to_string(), item_id: item_id.clone() });
+    }
+    Ok(entity_type.clone())
+}
+
+fn place(game_state: &mut GameState, entity_type: &EntityType, position: Coordinates) -> String {
+    let id = unique_id(game_state, &entity_type.id);
+    let npc = build_npc(game_state, entity_type, &id, position);
+    game_state.add_npc(npc).expect("spawn IDs are unique");
+    game_state.emit_event(GameEvent::Spawned { entity: EntityId::Npc(id.clone()), type_id: entity_type.id.clone() });
+    id
+}
+
+// "<type>_<n>" with a counter that never goes back, so IDs of despawned NPCs are not reused
+fn unique_id(game_state: &mut GameState, type_id: &str) -> String {
+    loop {
+        game_state.next_spawn_id += 1;
+        let id = format!("{}_{}", type_id, game_state.next_spawn_id);
+        if game_state.get_npc(&id).is_none() {
+            return id;
+        }
+    }
+}
+
+// Random point within `radius` of `center`, in as many dimensions as the center has
+fn scatter(rng: &mut SeededRng, center: &Coordinates, radius: f32) -> Coordinates {
+    if radius <= 0.0 {
+        return center.clone();
+    }
+    // Rejection sampling from the bounding box; fall back to the center if unlucky
+    for _ in 0..16 {
+        let offset: Vec<f32> = center.values.iter().map(|_| rng.range_f32(-radius, radius)).collect();
+        if offset.iter().map(|v| v * v).sum::<f32>() <= radius * radius {
+            let mut position = center.clone();
+            for (value, delta) in position.values.iter_mut().zip(offset) {
+                *value += delta;
+            }
+            return position;
+        }
+    }
+    center.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::Item;
+
+    fn game() -> GameState {
+        let mut game_state = GameState::new();
+        game_state.rng = SeededRng::new(7);
+        game_state.item_templates.insert("club".to_string(), Item::new("club", "Club"));
+        game_state.add_entity_type(EntityType::new("goblin", "Goblin")
+            .with_stat_template("hp", StatTemplate::IntRange(8, 12))
+            .with_stat_template("speed", StatTemplate::Fixed(StatValue::Float(1.5)))
+            .with_starting_item("club")
+            .with_starting_behavior("patrol")
+            .with_dimensions(2));
+        game_state.add_entity_type(EntityType::new("goblin_brute", "Goblin Brute")
+            .with_base("goblin")
+            .with_stat_template("hp", StatTemplate::IntRange(20, 25)));
+        game_state.resolve_entity_types().unwrap();
+        game_state
+    }
+
+    #[test]
+    fn test_spawn_initialises_npc() {
+        let mut game_state = game();
+        let id = game_state.spawn_npc("goblin_brute", Coordinates::new_2d(1.0, 2.0)).unwrap();
+        let brute = game_state.get_npc(&id).unwrap();
+        assert_eq!(id, "goblin_brute_1");
+        assert!((20..=25).contains(&brute.get_int_stat("hp").unwrap()));
+        assert_eq!(brute.get_float_stat("speed"), Some(1.5));
+        assert!(brute.inventory.has_item("club"));
+        assert_eq!(brute.behavior_state, "patrol");
+
+        // A "spawned" event is queued for the next dispatch
+        assert_eq!(game_state.events.pending(), 1);
+        assert_eq!(
+            game_state.spawn_npc("goblin", Coordinates::new_3d(0.0, 0.0, 0.0)),
+            Err(SpawnError::DimensionMismatch { type_id: "goblin".to_string(), expected: 2, found: 3 }));
+        assert_eq!(game_state.spawn_npc("troll", Coordinates::new_2d(0.0, 0.0)), Err(SpawnError::UnknownType("troll".to_string())));
+    }
+
+    #[test]
+    fn test_waves_are_all_or_nothing_and_reproducible() {
+        let wave = Wave::new(Coordinates::new_2d(10.0, 10.0), 3.0)
+            .with_group("goblin", 4)
+            .with_group("goblin_brute", 1);
+
+        let mut first = game();
+        let ids = first.spawn_wave(&wave).unwrap();
+        assert_eq!(ids.len(), wave.size());
+        assert_eq!(ids[4], "goblin_brute_5");
+        for id in &ids {
+            let npc = first.get_npc(id).unwrap();
+            assert!(npc.position.distance(&wave.center) <= 3.0);
+        }
+
+        // Same seed, same wave
+        let mut second = game();
+        second.spawn_wave(&wave).unwrap();
+        for id in &ids {
+            assert_eq!(first.get_npc(id).unwrap().position, second.get_npc(id).unwrap().position);
+            assert_eq!(first.get_npc(id).unwrap().get_int_stat("hp"), second.get_npc(id).unwrap().get_int_stat("hp"));
+        }
+
+        let mut third = game();
+        let bad = wave.clone().with_group("troll", 1);
+        assert!(third.spawn_wave(&bad).is_err());
+        assert!(third.npcs.is_empty());
+    }
+}
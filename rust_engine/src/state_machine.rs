@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::condition::EntityRef;
+use crate::events::GameEvent;
+use crate::functions::FunctionCall;
+use crate::game_state::{EntityId, GameState};
+use crate::property::Condition;
+use crate::registry::EntityHandle;
+use crate::script::ScriptValue;
+
+/// Something a state does when it is entered, on every tick while it is current, or when
+/// it is left
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StateAction {
+    /// Call a registered function with the NPC as caster and its target, if any, as target
+    Function(String),
+    /// Run a script with `self` bound to the NPC, `target` to its target and `state` to the
+    /// name of the state
+    Script(String),
+}
+
+/// A move to another state, taken once all of its conditions hold
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Transition {
+    pub to: String,
+    /// Evaluated against the NPC; an empty list always holds
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+impl Transition {
+    pub fn new(to: &str) -> Self {
+        Transition { to: to.to_string(), conditions: Vec::new() }
+    }
+
+    pub fn with_condition(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+}
+
+/// One state of a machine: its actions and the ways out of it
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub on_enter: Vec<StateAction>,
+    #[serde(default)]
+    pub on_update: Vec<StateAction>,
+    #[serde(default)]
+    pub on_exit: Vec<StateAction>,
+    /// Checked in order each tick; the first one that holds is taken
+    #[serde(default)]
+    pub transitions: Vec<Transition>,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_enter_action(mut self, action: StateAction) -> Self {
+        self.on_enter.push(action);
+        self
+    }
+
+    pub fn with_update_action(mut self, action: StateAction) -> Self {
+        self.on_update.push(action);
+        self
+    }
+
+    pub fn with_exit_action(mut self, action: StateAction) -> Self {
+        self.on_exit.push(action);
+        self
+    }
+
+    pub fn with_transition(mut self, transition: Transition) -> Self {
+        self.transitions.push(transition);
+        self
+    }
+}
+
+/// A finite state machine over an NPC's `behavior_state`.
+///
+/// Entity types name their machine with `state_machine`. Each tick the NPC's current state
+/// either takes its first transition that holds (running the state's exit actions, then the
+/// new state's enter actions) or runs its update actions. An NPC whose behavior state is not
+/// one of the machine's states starts over in the initial state. Timers are
+/// `ConditionType::TimeInState` conditions.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StateMachine {
+    pub id: String,
+    /// State NPCs start in
+    pub initial: String,
+    pub states: BTreeMap<String, State>,
+}
+
+impl StateMachine {
+    pub fn new(id: &str, initial: &str) -> Self {
+        StateMachine { id: id.to_string(), initial: initial.to_string(), states: BTreeMap::new() }
+    }
+
+    pub fn with_state(mut self, name: &str, state: State) -> Self {
+        self.states.insert(name.to_string(), state);
+        self
+    }
+
+    pub fn state(&self, name: &str) -> Option<&State> {
+        self.states.get(name)
+    }
+
+    /// Check that the initial state and every transition target exist
+    pub fn validate(&self) -> StateMachineResult<()> {
+        let unknown = |state: &str| StateMachineError::UnknownState { machine: self.id.clone(), state: state.to_string() };
+        if !self.states.contains_key(&self.initial) {
+            return Err(unknown(&self.initial));
+        }
+        for state in self.states.values() {
+            if let Some(transition) = state.transitions.iter().find(|t| !self.states.contains_key(&t.to)) {
+                return Err(unknown(&transition.to));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Problems with state machines and their actions
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateMachineError {
+    /// A state the machine does not define
+    UnknownState { machine: String, state: String },
+    /// An enter, update or exit action failed; the NPC's other actions still ran
+    ActionFailed { entity: EntityId, state: String, message: String },
+}
+
+impl fmt::Display for StateMachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateMachineError::UnknownState { machine, state } => write!(f, "State machine {} has no state {}", machine, state),
+            StateMachineError::ActionFailed { entity, state, message } => write!(f, "{} in state {}: {}", entity, state, message),
+        }
+    }
+}
+
+pub type StateMachineResult<T> = Result<T, StateMachineError>;
+
+// What an NPC's machine does this tick
+enum Step {
+    /// Run the current state's exit actions (none if it was never entered), then move to
+    /// another state and run its enter actions
+    Enter { exit: Vec<StateAction>, to: String, enter: Vec<StateAction> },
+    Update(Vec<StateAction>),
+}
+
+/// Tick the state machine of every NPC whose type has one. Returns the actions that failed.
+pub fn update_state_machines(game_state: &mut GameState, delta_time: f32) -> Vec<StateMachineError> {
+    let mut errors = Vec::new();
+    for handle in game_state.npcs.handles() {
+        tick(game_state, handle, delta_time, &mut errors);
+    }
+    errors
+}
+
+fn tick(game_state: &mut GameState, handle: EntityHandle, delta_time: f32, errors: &mut Vec<StateMachineError>) {
+    // Earlier NPCs' actions may have removed or despawned this one
+    if game_state.npcs.is_despawning(handle) {
+        return;
+    }
+    let Some(npc) = game_state.npcs.get_mut(handle) else { return };
+    if let Some(time) = &mut npc.state_time {
+        *time += delta_time;
+    }
+
+    let Some(npc) = game_state.npcs.get(handle) else { return };
+    let Some(machine) = npc.npc_type.state_machine.as_ref().and_then(|id| game_state.state_machines.get(id)) else {
+        return;
+    };
+    let enter = |to: &str, exit: Vec<StateAction>| Step::Enter {
+        exit,
+        to: to.to_string(),
+        enter: machine.state(to).map(|state| state.on_enter.clone()).unwrap_or_default(),
+    };
+    let step = match (machine.state(&npc.behavior_state), npc.state_time) {
+        (None, _) => enter(&machine.initial, Vec::new()),
+        // Put in this state from outside, e.g. by its spawn profile
+        (Some(_), None) => enter(&npc.behavior_state, Vec::new()),
+        (Some(state), Some(_)) => {
+            let entity = EntityRef::Npc(npc);
+            let taken = state.transitions.iter()
+                .find(|transition| game_state.conditions.evaluate_all(entity, game_state, &transition.conditions));
+            match taken {
+                Some(transition) => enter(&transition.to, state.on_exit.clone()),
+                None => Step::Update(state.on_update.clone()),
+            }
+        },
+    };
+
+    let id = EntityId::Npc(npc.id.clone());
+    let from = npc.behavior_state.clone();
+    match step {
+        Step::Update(actions) => run_actions(game_state, handle, &id, &from, &actions, errors),
+        Step::Enter { exit, to, enter } => {
+            run_actions(game_state, handle, &id, &from, &exit, errors);
+            let Some(npc) = game_state.npcs.get_mut(handle) else { return };
+            npc.behavior_state = to.clone();
+            npc.state_time = Some(0.0);
+            if from != to {
+                game_state.emit_event(GameEvent::StateChanged { entity: id.clone(), from, to: to.clone() });
+            }
+            run_actions(game_state, handle, &id, &to, &enter, errors);
+        },
+    }
+}
+
+fn run_actions(
+    game_state: &mut GameState,
+    handle: EntityHandle,
+    id: &EntityId,
+    state: &str,
+    actions: &[StateAction],
+    errors: &mut Vec<StateMachineError>,
+) {
+    for action in actions {
+        let Some(npc) = game_state.npcs.get(handle) else { return };
+        let target = npc.target
+            .and_then(|target| game_state.npcs.get(target))
+            .map(|target| EntityId::Npc(target.id.clone()));
+        let result = match action {
+            StateAction::Function(function_id) => {
+                let call = FunctionCall::new(function_id, id.clone(), target.into_iter().collect());
+                game_state.invoke_function(call).map(|_| ()).map_err(|e| e.to_string())
+            },
+            StateAction::Script(source) => {
+                let bindings = vec![("state".to_string(), ScriptValue::Str(state.to_string()))];
+                crate::script::run_script_with_bindings(game_state, source, id.clone(), target, bindings)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            },
+        };
+        if let Err(message) = result {
+            errors.push(StateMachineError::ActionFailed { entity: id.clone(), state: state.to_string(), message });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordinates::Coordinates;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+    use crate::property::Property;
+    use crate::stats::StatValue;
+
+    fn guard_machine() -> StateMachine {
+        StateMachine::new("guard", "patrol")
+            .with_state("patrol", State::new()
+                .with_update_action(StateAction::Script("add_stat(self, \"steps\", 1);".to_string()))
+                .with_transition(Transition::new("chase")
+                    .with_condition(Property::create_proximity_condition("player", 5.0, true))))
+            .with_state("chase", State::new()
+                .with_enter_action(StateAction::Script("set_stat(self, \"alert\", 1);".to_string()))
+                .with_exit_action(StateAction::Script("set_stat(self, \"alert\", 0);".to_string()))
+                .with_transition(Transition::new("patrol")
+                    .with_condition(Property::create_proximity_condition("player", 5.0, false))
+                    .with_condition(Property::create_time_in_state_condition(2.0))))
+    }
+
+    fn game() -> GameState {
+        let mut game_state = GameState::new();
+        game_state.add_state_machine(guard_machine()).unwrap();
+        game_state.add_entity_type(EntityType::new("guard", "Guard").with_state_machine("guard"));
+        let mut guard = NPC::new("guard1".to_string(), game_state.entity_types["guard"].clone());
+        guard.set_base_stat("steps", StatValue::Integer(0));
+        game_state.add_npc(guard).unwrap();
+        game_state.player.position = Coordinates::new_2d(20.0, 0.0);
+        game_state
+    }
+
+    #[test]
+    fn test_transitions_actions_and_timers() {
+        let mut game_state = game();
+        let guard = |game_state: &GameState| game_state.get_npc("guard1").unwrap().behavior_state.clone();
+
+        // "idle" is not a state, so the guard starts patrolling
+        game_state.update(1.0);
+        assert_eq!(guard(&game_state), "patrol");
+        game_state.update(1.0);
+        assert_eq!(game_state.get_npc("guard1").unwrap().get_int_stat("steps"), Some(1));
+
+        game_state.player.position = Coordinates::new_2d(3.0, 0.0);
+        game_state.update(1.0);
+        assert_eq!(guard(&game_state), "chase");
+        assert_eq!(game_state.get_npc("guard1").unwrap().get_int_stat("alert"), Some(1));
+
+        // Out of range, but the chase lasts at least two seconds
+        game_state.player.position = Coordinates::new_2d(20.0, 0.0);
+        game_state.update(1.0);
+        assert_eq!(guard(&game_state), "chase");
+        game_state.update(1.0);
+        assert_eq!(guard(&game_state), "patrol");
+        assert_eq!(game_state.get_npc("guard1").unwrap().get_int_stat("alert"), Some(0));
+        assert!(game_state.last_state_machine_errors.is_empty());
+    }
+
+    #[test]
+    fn test_validation_and_failed_actions() {
+        let broken = StateMachine::new("broken", "idle")
+            .with_state("idle", State::new().with_transition(Transition::new("flee")));
+        let mut game_state = game();
+        assert_eq!(
+            game_state.add_state_machine(broken).err(),
+            Some(StateMachineError::UnknownState { machine: "broken".to_string(), state: "flee".to_string() }));
+
+        let failing = StateMachine::new("failing", "idle")
+            .with_state("idle", State::new().with_update_action(StateAction::Function("missing".to_string())));
+        game_state.add_state_machine(failing).unwrap();
+        game_state.add_entity_type(EntityType::new("dummy", "Dummy").with_state_machine("failing"));
+        game_state.add_npc(NPC::new("dummy1".to_string(), game_state.entity_types["dummy"].clone())).unwrap();
+        game_state.update(1.0);
+        game_state.update(1.0);
+        assert_eq!(game_state.last_state_machine_errors.len(), 1);
+        assert!(matches!(&game_state.last_state_machine_errors[0],
+            StateMachineError::ActionFailed { entity: EntityId::Npc(id), state, .. } if id == "dummy1" && state == "idle"));
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum StatValue {
     Integer(i32),
     Float(f32),
@@ -9,6 +9,17 @@ pub enum StatValue {
     String(String),
 }
 
+impl StatValue {
+    // Numeric view of the value (integers are widened, other variants have none)
+    pub fn as_float(&self) -> Option<f32> {
+        match self {
+            StatValue::Integer(val) => Some(*val as f32),
+            StatValue::Float(val) => Some(*val),
+            _ => None,
+        }
+    }
+}
+
 impl Clone for StatValue {
     fn clone(&self) -> Self {
         match self {
@@ -26,6 +37,29 @@ pub struct Stats {
     modification_count: u64,
 }
 
+impl Clone for Stats {
+    fn clone(&self) -> Self {
+        let mut new_stats = Stats::new();
+        
+        for (key, value) in &self.values {
+            match value {
+                StatValue::Integer(val) => new_stats.set_int(key, *val),
+                StatValue::Float(val) => new_stats.set_float(key, *val),
+                StatValue::Boolean(val) => new_stats.set_bool(key, *val),
+                StatValue::String(val) => new_stats.set_string(key, val.clone()),
+            }
+        }
+        
+        new_stats
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Stats {
     // Create a completely empty stats object
     pub fn new() -> Stats {
@@ -123,22 +157,6 @@ impl Stats {
         result
     }
     
-    // Clone this stats object
-    pub fn clone(&self) -> Stats {
-        let mut new_stats = Stats::new();
-        
-        for (key, value) in &self.values {
-            match value {
-                StatValue::Integer(val) => new_stats.set_int(key, *val),
-                StatValue::Float(val) => new_stats.set_float(key, *val),
-                StatValue::Boolean(val) => new_stats.set_bool(key, *val),
-                StatValue::String(val) => new_stats.set_string(key, val.clone()),
-            }
-        }
-        
-        new_stats
-    }
-    
     // Apply numerical modifiers to stats 
     pub fn apply_modifier(&mut self, key: &str, modifier: f32) {
         if self.has_stat(key) {
@@ -0,0 +1,517 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::calculated_stats::{ModifierType, StatModifier};
+use crate::combat::CombatError;
+use crate::damage::DamagePacket;
+use crate::ecs;
+use crate::entity::Entity;
+use crate::functions::FunctionCall;
+use crate::game_state::{EntityId, GameState};
+use crate::script::ScriptValue;
+use crate::state_machine::StateAction;
+use crate::stats::StatValue;
+
+/// Source prefix of the stat modifiers status effects grant ("status:slowed")
+pub const STATUS_MODIFIER_SOURCE: &str = "status:";
+/// Status effect modifiers apply along with buffs, after equipment
+pub const STATUS_MODIFIER_PRIORITY: i32 = 20;
+
+// Leeway for timers that add up to a tick or the end of an effect
+const TIME_EPSILON: f32 = 1e-4;
+
+/// What happens when an effect is applied to an entity that already has it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stacking {
+    /// The duration starts over
+    #[default]
+    Refresh,
+    /// The duration is added to what is left
+    Extend,
+    /// A stack is added, up to the effect's `max_stacks`, and the duration starts over
+    Stack,
+    /// Nothing; the effect runs its course
+    Ignore,
+}
+
+impl Stacking {
+    /// Parse "refresh", "extend", "stack" or "ignore"
+    pub fn parse(value: &str) -> Option<Stacking> {
+        match value.to_lowercase().as_str() {
+            "refresh" => Some(Stacking::Refresh),
+            "extend" => Some(Stacking::Extend),
+            "stack" => Some(Stacking::Stack),
+            "ignore" => Some(Stacking::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/// A stat modifier an effect grants for as long as it lasts, once per stack
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectModifier {
+    pub stat: String,
+    pub modifier_type: ModifierType,
+    pub value: StatValue,
+}
+
+/// Definition of a status effect such as "poisoned"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusEffectDef {
+    pub id: String,
+    pub name: String,
+    /// Seconds the effect lasts, or `None` until it is removed
+    #[serde(default)]
+    pub duration: Option<f32>,
+    #[serde(default)]
+    pub stacking: Stacking,
+    /// Most stacks with `Stacking::Stack`
+    #[serde(default = "default_max_stacks")]
+    pub max_stacks: u32,
+    /// Seconds between ticks, or 0 for an effect that never ticks
+    #[serde(default)]
+    pub tick_interval: f32,
+    /// Damage dealt each tick, once per stack, after the entity's resistances
+    #[serde(default)]
+    pub tick_damage: DamagePacket,
+    #[serde(default)]
+    pub modifiers: Vec<EffectModifier>,
+    /// Run when the effect starts, each tick and when it ends. Scripts get `self` bound to
+    /// the affected entity, `target` to whoever applied the effect, and `effect` and
+    /// `stacks`; functions are called with the same caster and target.
+    #[serde(default)]
+    pub on_apply: Vec<StateAction>,
+    #[serde(default)]
+    pub on_tick: Vec<StateAction>,
+    #[serde(default)]
+    pub on_expire: Vec<StateAction>,
+}
+
+fn default_max_stacks() -> u32 {
+    1
+}
+
+impl StatusEffectDef {
+    pub fn new(id: &str, name: &str) -> Self {
+        StatusEffectDef {
+            id: id.to_string(),
+            name: name.to_string(),
+            duration: None,
+            stacking: Stacking::Refresh,
+            max_stacks: 1,
+            tick_interval: 0.0,
+            tick_damage: DamagePacket::new(),
+            modifiers: Vec::new(),
+            on_apply: Vec::new(),
+            on_tick: Vec::new(),
+            on_expire: Vec::new(),
+        }
+    }
+
+    pub fn with_duration(mut self, seconds: f32) -> Self {
+        self.duration = Some(seconds);
+        self
+    }
+
+    pub fn with_stacking(mut self, stacking: Stacking, max_stacks: u32) -> Self {
+        self.stacking = stacking;
+        self.max_stacks = max_stacks.max(1);
+        self
+    }
+
+    pub fn with_tick_interval(mut self, seconds: f32) -> Self {
+        self.tick_interval = seconds;
+        self
+    }
+
+    pub fn with_tick_damage(mut self, damage_type: &str, amount: f32) -> Self {
+        self.tick_damage.add(damage_type, amount);
+        self
+    }
+
+    pub fn with_modifier(mut self, stat: &str, modifier_type: ModifierType, value: StatValue) -> Self {
+        self.modifiers.push(EffectModifier { stat: stat.to_string(), modifier_type, value });
+        self
+    }
+
+    pub fn with_on_apply(mut self, action: StateAction) -> Self {
+        self.on_apply.push(action);
+        self
+    }
+
+    pub fn with_on_tick(mut self, action: StateAction) -> Self {
+        self.on_tick.push(action);
+        self
+    }
+
+    pub fn with_on_expire(mut self, action: StateAction) -> Self {
+        self.on_expire.push(action);
+        self
+    }
+}
+
+/// A status effect running on an entity
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActiveStatusEffect {
+    pub id: String,
+    pub stacks: u32,
+    /// Seconds left, or `None` for an effect without a duration
+    pub remaining: Option<f32>,
+    /// Seconds since the last tick
+    pub since_tick: f32,
+    /// Who applied the effect
+    pub source: Option<EntityId>,
+}
+
+/// Problems applying and running status effects
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatusEffectError {
+    UnknownEntity(EntityId),
+    /// An effect without a definition
+    UnknownEffect(String),
+    /// A hook or tick failed; the effect carries on
+    ActionFailed { entity: EntityId, effect: String, message: String },
+}
+
+impl fmt::Display for StatusEffectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusEffectError::UnknownEntity(entity) => write!(f, "Unknown entity: {}", entity),
+            StatusEffectError::UnknownEffect(effect) => write!(f, "Unknown status effect: {}", effect),
+            StatusEffectError::ActionFailed { entity, effect, message } => write!(f, "{} on {}: {}", effect, entity, message),
+        }
+    }
+}
+
+pub type StatusEffectResult<T> = Result<T, StatusEffectError>;
+
+/// Status effect definitions and the effects running on each entity
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatusEffectState {
+    /// Definitions, by ID
+    pub definitions: HashMap<String, StatusEffectDef>,
+    // Running effects by entity, oldest first
+    #[serde(default)]
+    active: BTreeMap<String, Vec<ActiveStatusEffect>>,
+    /// Hooks and ticks that failed since the last update began
+    #[serde(skip)]
+    pub last_errors: Vec<StatusEffectError>,
+}
+
+impl StatusEffectState {
+    /// Define an effect, replacing one with the same ID
+    pub fn add(&mut self, definition: StatusEffectDef) {
+        self.definitions.insert(definition.id.clone(), definition);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&StatusEffectDef> {
+        self.definitions.get(id)
+    }
+
+    /// Effects running on an entity, oldest first
+    pub fn active_on(&self, entity: &EntityId) -> &[ActiveStatusEffect] {
+        self.active.get(&entity.to_string()).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn active(&self, entity: &EntityId, id: &str) -> Option<&ActiveStatusEffect> {
+        self.active_on(entity).iter().find(|effect| effect.id == id)
+    }
+
+    fn active_mut(&mut self, entity: &EntityId, id: &str) -> Option<&mut ActiveStatusEffect> {
+        self.active.get_mut(&entity.to_string())?.iter_mut().find(|effect| effect.id == id)
+    }
+
+    fn take(&mut self, entity: &EntityId, id: &str) -> Option<ActiveStatusEffect> {
+        let effects = self.active.get_mut(&entity.to_string())?;
+        let index = effects.iter().position(|effect| effect.id == id)?;
+        let effect = effects.remove(index);
+        if effects.is_empty() {
+            self.active.remove(&entity.to_string());
+        }
+        Some(effect)
+    }
+
+    /// Drop what is kept about an entity that left the game
+    pub fn forget(&mut self, entity: &EntityId) {
+        self.active.remove(&entity.to_string());
+    }
+}
+
+// The modifier an effect grants with this many stacks
+fn stacked(modifier: &EffectModifier, stacks: u32) -> StatValue {
+    match (modifier.modifier_type, &modifier.value) {
+        (ModifierType::Additive, StatValue::Integer(value)) => StatValue::Integer(value.saturating_mul(stacks as i32)),
+        (ModifierType::Additive, StatValue::Float(value)) => StatValue::Float(value * stacks as f32),
+        (ModifierType::Multiplicative, StatValue::Float(value)) => StatValue::Float(value.powi(stacks as i32)),
+        (_, value) => value.clone(),
+    }
+}
+
+// Replace the modifiers an effect grants an entity; no stacks removes them
+fn set_modifiers(game_state: &mut GameState, entity: &EntityId, definition: &StatusEffectDef, stacks: u32) {
+    let Some(stats) = game_state.component_mut::<ecs::Stats>(entity) else { return };
+    let source = format!("{}{}", STATUS_MODIFIER_SOURCE, definition.id);
+    stats.remove_modifiers_by_source(&source);
+    if stacks == 0 {
+        return;
+    }
+    for modifier in &definition.modifiers {
+        stats.add_modifier(&modifier.stat, StatModifier {
+            source: source.clone(),
+            modifier_type: modifier.modifier_type,
+            value: stacked(modifier, stacks),
+            priority: STATUS_MODIFIER_PRIORITY,
+        });
+    }
+}
+
+fn set_named(game_state: &mut GameState, entity: &EntityId, id: &str, named: bool) {
+    let Some(names) = game_state.component_mut::<ecs::StatusEffects>(entity) else { return };
+    if !named {
+        names.retain(|name| name != id);
+    } else if !names.iter().any(|name| name == id) {
+        names.push(id.to_string());
+    }
+}
+
+fn run_actions(game_state: &mut GameState, entity: &EntityId, effect: &ActiveStatusEffect, actions: &[StateAction]) {
+    for action in actions {
+        let result = match action {
+            StateAction::Function(function_id) => {
+                let call = FunctionCall::new(function_id, entity.clone(), effect.source.clone().into_iter().collect());
+                game_state.invoke_function(call).map(|_| ()).map_err(|e| e.to_string())
+            },
+            StateAction::Script(source) => {
+                let bindings = vec![
+                    ("effect".to_string(), ScriptValue::Str(effect.id.clone())),
+                    ("stacks".to_string(), ScriptValue::Int(effect.stacks as i32)),
+                ];
+                crate::script::run_script_with_bindings(game_state, source, entity.clone(), effect.source.clone(), bindings)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            },
+        };
+        if let Err(message) = result {
+            game_state.status_effects.last_errors.push(StatusEffectError::ActionFailed {
+                entity: entity.clone(),
+                effect: effect.id.clone(),
+                message,
+            });
+        }
+    }
+}
+
+/// Put a defined effect on an entity, following its stacking rule if the entity already
+/// has it. A new effect adds its name to the entity's status effects, grants its modifiers
+/// and runs its `on_apply` actions. Returns the effect's stacks.
+pub fn apply_status_effect(game_state: &mut GameState, entity: &EntityId, id: &str, source: Option<EntityId>) -> StatusEffectResult<u32> {
+    let definition = game_state.status_effects.get(id).cloned().ok_or_else(|| StatusEffectError::UnknownEffect(id.to_string()))?;
+    if game_state.get_entity(entity).is_none() {
+        return Err(StatusEffectError::UnknownEntity(entity.clone()));
+    }
+
+    if let Some(effect) = game_state.status_effects.active_mut(entity, id) {
+        match definition.stacking {
+            Stacking::Refresh => effect.remaining = definition.duration,
+            Stacking::Extend => effect.remaining = effect.remaining.zip(definition.duration).map(|(left, more)| left + more),
+            Stacking::Stack => {
+                effect.stacks = (effect.stacks + 1).min(definition.max_stacks);
+                effect.remaining = definition.duration;
+            },
+            Stacking::Ignore => return Ok(effect.stacks),
+        }
+        if source.is_some() {
+            effect.source = source;
+        }
+        let stacks = effect.stacks;
+        set_modifiers(game_state, entity, &definition, stacks);
+        return Ok(stacks);
+    }
+
+    let effect = ActiveStatusEffect {
+        id: id.to_string(),
+        stacks: 1,
+        remaining: definition.duration,
+        since_tick: 0.0,
+        source,
+    };
+    game_state.status_effects.active.entry(entity.to_string()).or_default().push(effect.clone());
+    set_named(game_state, entity, id, true);
+    set_modifiers(game_state, entity, &definition, 1);
+    run_actions(game_state, entity, &effect, &definition.on_apply);
+    Ok(1)
+}
+
+// End a running effect: take its name and modifiers away, then run its on_expire actions
+fn end_effect(game_state: &mut GameState, entity: &EntityId, id: &str) -> bool {
+    let Some(effect) = game_state.status_effects.take(entity, id) else { return false };
+    set_named(game_state, entity, id, false);
+    if let Some(definition) = game_state.status_effects.get(id).cloned() {
+        set_modifiers(game_state, entity, &definition, 0);
+        run_actions(game_state, entity, &effect, &definition.on_expire);
+    }
+    true
+}
+
+/// End an effect early, running its `on_expire` actions. Returns false if the entity did
+/// not have it.
+pub fn remove_status_effect(game_state: &mut GameState, entity: &EntityId, id: &str) -> bool {
+    end_effect(game_state, entity, id)
+}
+
+// One tick: damage for each stack, then the on_tick actions
+fn tick(game_state: &mut GameState, entity: &EntityId, effect: &ActiveStatusEffect, definition: &StatusEffectDef) {
+    if !definition.tick_damage.components.is_empty() {
+        let mut packet = definition.tick_damage.clone();
+        packet.scale(effect.stacks as f32);
+        match crate::damage::apply_damage(game_state, entity, &packet, effect.source.clone()) {
+            Ok(_) | Err(CombatError::NoHitPoints(_)) => {},
+            Err(error) => game_state.status_effects.last_errors.push(StatusEffectError::ActionFailed {
+                entity: entity.clone(),
+                effect: effect.id.clone(),
+                message: error.to_string(),
+            }),
+        }
+    }
+    run_actions(game_state, entity, effect, &definition.on_tick);
+}
+
+/// Run every entity's status effects for `delta_time` seconds; `update` does this.
+///
+/// Defined effects are started for entities that were given them by name only (templates,
+/// scripts, `add_status_effect`), and ended for entities whose name for them was removed.
+/// Each running effect then ticks once for every `tick_interval` that passed and expires
+/// when its duration runs out, ticks that fall due at that moment included.
+pub fn update_status_effects(game_state: &mut GameState, delta_time: f32) {
+    game_state.status_effects.last_errors.clear();
+
+    let mut entities = vec![EntityId::Player];
+    entities.extend(game_state.npcs.handles().into_iter()
+        .filter(|handle| !game_state.npcs.is_despawning(*handle))
+        .filter_map(|handle| game_state.npcs.get(handle))
+        .map(|npc| EntityId::Npc(npc.id.clone())));
+
+    for entity in &entities {
+        let Some(names) = game_state.get_entity(entity).map(|e| e.status_effects().to_vec()) else { continue };
+        let running: Vec<String> = game_state.status_effects.active_on(entity).iter().map(|e| e.id.clone()).collect();
+        for id in running.iter().filter(|id| !names.contains(id)) {
+            end_effect(game_state, entity, id);
+        }
+        for name in names.iter().filter(|name| !running.contains(name)) {
+            if game_state.status_effects.get(name).is_some() {
+                let _ = apply_status_effect(game_state, entity, name, None);
+            }
+        }
+    }
+
+    for entity in &entities {
+        let ids: Vec<String> = game_state.status_effects.active_on(entity).iter().map(|e| e.id.clone()).collect();
+        for id in ids {
+            let Some(definition) = game_state.status_effects.get(&id).cloned() else { continue };
+            // Scripts run by earlier effects may have removed this one
+            let Some(effect) = game_state.status_effects.active_mut(entity, &id) else { continue };
+            let step = effect.remaining.map_or(delta_time, |left| delta_time.min(left.max(0.0)));
+            effect.remaining = effect.remaining.map(|left| left - delta_time);
+            let mut ticks = 0;
+            if definition.tick_interval > 0.0 {
+                effect.since_tick += step;
+                while effect.since_tick + TIME_EPSILON >= definition.tick_interval {
+                    effect.since_tick -= definition.tick_interval;
+                    ticks += 1;
+                }
+            }
+            let expired = effect.remaining.is_some_and(|left| left <= TIME_EPSILON);
+            let effect = effect.clone();
+
+            for _ in 0..ticks {
+                if game_state.status_effects.active(entity, &id).is_none() {
+                    break;
+                }
+                tick(game_state, entity, &effect, &definition);
+            }
+            if expired {
+                end_effect(game_state, entity, &id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_type::EntityType;
+    use crate::npc::NPC;
+
+    fn game() -> GameState {
+        let mut game_state = GameState::new();
+        game_state.add_status_effect(StatusEffectDef::new("poisoned", "Poisoned")
+            .with_duration(3.0)
+            .with_stacking(Stacking::Stack, 3)
+            .with_tick_interval(1.0)
+            .with_tick_damage(crate::damage::PHYSICAL, 2.0)
+            .with_modifier("speed", ModifierType::Multiplicative, StatValue::Float(0.5))
+            .with_on_expire(StateAction::Script("add_stat(self, \"cured\", 1);".to_string())));
+        let mut wolf = NPC::new("wolf".to_string(), EntityType::new("wolf", "Wolf"));
+        wolf.set_base_stat("hp", StatValue::Integer(30));
+        wolf.set_base_stat("speed", StatValue::Float(4.0));
+        game_state.add_npc(wolf).unwrap();
+        game_state
+    }
+
+    #[test]
+    fn test_ticks_stacks_modifiers_and_expiry() {
+        let mut game_state = game();
+        let wolf = EntityId::Npc("wolf".to_string());
+        let stat = |game_state: &GameState, key: &str| game_state.get_entity(&wolf).unwrap().get_stat(key);
+
+        assert_eq!(game_state.apply_status_effect(&wolf, "poisoned", Some(EntityId::Player)), Ok(1));
+        assert_eq!(game_state.apply_status_effect(&wolf, "poisoned", None), Ok(2));
+        assert!(game_state.get_npc("wolf").unwrap().has_status_effect("poisoned"));
+        // Half speed per stack
+        assert_eq!(stat(&game_state, "speed"), Some(StatValue::Float(1.0)));
+
+        // Two stacks deal 4 per tick, blamed on the player
+        game_state.update(1.0);
+        assert_eq!(stat(&game_state, "hp"), Some(StatValue::Integer(26)));
+        game_state.update(1.5);
+        assert_eq!(stat(&game_state, "hp"), Some(StatValue::Integer(22)));
+        assert_eq!(game_state.status_effects.active(&wolf, "poisoned").unwrap().source, Some(EntityId::Player));
+
+        // The last tick lands as the effect runs out, then on_expire runs
+        game_state.update(0.5);
+        assert_eq!(stat(&game_state, "hp"), Some(StatValue::Integer(18)));
+        assert!(game_state.status_effects.active(&wolf, "poisoned").is_none());
+        assert!(!game_state.get_npc("wolf").unwrap().has_status_effect("poisoned"));
+        assert_eq!(stat(&game_state, "speed"), Some(StatValue::Float(4.0)));
+        assert_eq!(stat(&game_state, "cured"), Some(StatValue::Integer(1)));
+        assert!(game_state.status_effects.last_errors.is_empty(), "{:?}", game_state.status_effects.last_errors);
+    }
+
+    #[test]
+    fn test_named_effects_sync_and_save() {
+        let mut game_state = game();
+        let wolf = EntityId::Npc("wolf".to_string());
+
+        // Effects added by name start on the next update; removing the name ends them
+        crate::script::run_script(&mut game_state, "add_status(npc(\"wolf\"), \"poisoned\");", EntityId::Player, None).unwrap();
+        game_state.update(0.5);
+        assert_eq!(game_state.status_effects.active(&wolf, "poisoned").map(|e| e.remaining), Some(Some(2.5)));
+        let saved = serde_json::to_string(&game_state).unwrap();
+        game_state.get_npc_mut("wolf").unwrap().remove_status_effect("poisoned");
+        game_state.update(0.5);
+        assert!(game_state.status_effects.active(&wolf, "poisoned").is_none());
+        assert_eq!(game_state.get_npc("wolf").unwrap().get_int_stat("cured"), Some(1));
+
+        let mut loaded: GameState = serde_json::from_str(&saved).unwrap();
+        let effect = loaded.status_effects.active(&wolf, "poisoned").unwrap();
+        assert_eq!((effect.stacks, effect.since_tick), (1, 0.5));
+        assert!(loaded.status_effects.get("poisoned").is_some());
+
+        // The effect's modifier and the stats it ticks against come back with it
+        let stat = |game_state: &GameState, key: &str| game_state.get_entity(&wolf).unwrap().get_stat(key);
+        assert_eq!(stat(&loaded, "speed"), Some(StatValue::Float(2.0)));
+        loaded.update(0.5);
+        assert_eq!(stat(&loaded, "hp"), Some(StatValue::Integer(28)));
+        assert_eq!(stat(&loaded, "speed"), Some(StatValue::Float(2.0)));
+    }
+}
@@ -1,7 +1,13 @@
-use std::collections::{HashMap, HashSet};
-use crate::property::Property;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use crate::property::{Property, PropertyValue};
 use serde::{Serialize, Deserialize};
-use crate::stats::StatValue as Stats_StatValue;
+
+/// Property metadata that stops a property from being inherited by child tags
+pub const INHERITABLE_METADATA: &str = "inheritable";
+/// Property metadata naming the slot a property fills; a child's property overrides an
+/// inherited one with the same key
+pub const OVERRIDE_KEY_METADATA: &str = "key";
 
 // Tag structure with ID, name, and properties
 #[derive(Clone, Serialize, Deserialize)]
@@ -10,6 +16,28 @@ pub struct Tag {
     pub name: String,
     pub properties: Vec<Property>,
     pub metadata: HashMap<String, String>,
+    // Parent tag IDs, earlier parents take precedence when inherited values clash
+    #[serde(default)]
+    pub parent_ids: Vec<i32>,
+}
+
+// Errors raised when editing the tag hierarchy
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagHierarchyError {
+    UnknownTag(i32),
+    SelfParent(String),
+    // Tag names along the cycle, starting and ending with the same tag
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for TagHierarchyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagHierarchyError::UnknownTag(id) => write!(f, "Unknown tag ID {}", id),
+            TagHierarchyError::SelfParent(name) => write!(f, "Tag '{}' cannot be its own parent", name),
+            TagHierarchyError::Cycle(names) => write!(f, "Tag hierarchy cycle: {}", names.join(" -> ")),
+        }
+    }
 }
 
 impl Tag {
@@ -19,7 +47,16 @@ impl Tag {
             name: name.to_string(),
             properties: Vec::new(),
             metadata: HashMap::new(),
+            parent_ids: Vec::new(),
+        }
+    }
+    
+    // Add a parent tag by ID (use TagCollection::add_parent to have cycles rejected)
+    pub fn with_parent(mut self, parent_id: i32) -> Self {
+        if !self.parent_ids.contains(&parent_id) {
+            self.parent_ids.push(parent_id);
         }
+        self
     }
     
     // Add a property to this tag
@@ -55,12 +92,114 @@ impl Tag {
     }
 }
 
+// Slot a property fills for override purposes: the "key" metadata, or the modified stat or
+// called function together with the property's contexts. Other properties never override.
+pub(crate) fn override_key(property: &Property) -> Option<String> {
+    let slot = match (property.metadata.get(OVERRIDE_KEY_METADATA), &property.value) {
+        (Some(key), _) => key.clone(),
+        (None, PropertyValue::Stat(stat, _)) => format!("stat:{}", stat),
+        (None, PropertyValue::Function(function_id)) => format!("function:{}", function_id),
+        _ => return None,
+    };
+    let mut contexts = property.context.clone();
+    contexts.sort();
+    Some(format!("{}@{}", slot, contexts.join(",")))
+}
+
+// Tags given to a single entity at runtime, on top of its type's tags.
+// Each tag either lasts until removed or expires after a number of game-time seconds.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct InstanceTags {
+    // Tag ID to remaining seconds, None for tags without a time limit
+    tags: HashMap<i32, Option<f32>>,
+}
+
+impl InstanceTags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    // Add a tag without a time limit, returns whether it was new
+    pub fn add(&mut self, tag_id: i32) -> bool {
+        self.tags.insert(tag_id, None).is_none()
+    }
+    
+    // Add a tag that expires after `duration` seconds. Re-adding keeps the longer time left,
+    // and never shortens a tag without a time limit. Returns whether it was new.
+    pub fn add_timed(&mut self, tag_id: i32, duration: f32) -> bool {
+        match self.tags.get_mut(&tag_id) {
+            Some(None) => false,
+            Some(Some(remaining)) => {
+                *remaining = remaining.max(duration);
+                false
+            },
+            None => {
+                self.tags.insert(tag_id, Some(duration));
+                true
+            },
+        }
+    }
+    
+    // Remove a tag, returns whether it was present
+    pub fn remove(&mut self, tag_id: i32) -> bool {
+        self.tags.remove(&tag_id).is_some()
+    }
+    
+    pub fn contains(&self, tag_id: i32) -> bool {
+        self.tags.contains_key(&tag_id)
+    }
+    
+    // Seconds left on a timed tag (None for missing or permanent tags)
+    pub fn remaining(&self, tag_id: i32) -> Option<f32> {
+        self.tags.get(&tag_id).copied().flatten()
+    }
+    
+    pub fn ids(&self) -> impl Iterator<Item = i32> + '_ {
+        self.tags.keys().copied()
+    }
+    
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+    
+    // Count down timed tags and remove the ones that ran out, returning their IDs sorted
+    pub fn tick(&mut self, delta_time: f32) -> Vec<i32> {
+        let mut expired = Vec::new();
+        for (&tag_id, remaining) in self.tags.iter_mut() {
+            if let Some(time_left) = remaining {
+                *time_left -= delta_time;
+                if *time_left <= 0.0 {
+                    expired.push(tag_id);
+                }
+            }
+        }
+        for tag_id in &expired {
+            self.tags.remove(tag_id);
+        }
+        expired.sort();
+        expired
+    }
+}
+
 // A collection of tags with lookup capabilities
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TagCollection {
     tags: HashMap<i32, Tag>,
     name_to_id: HashMap<String, i32>,
     next_id: i32,
+    // IDs removed since the owner last cleaned up references to them
+    #[serde(skip)]
+    removed_ids: Vec<i32>,
+}
+
+impl Default for TagCollection {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TagCollection {
@@ -69,6 +208,7 @@ impl TagCollection {
             tags: HashMap::new(),
             name_to_id: HashMap::new(),
             next_id: 1, // Start IDs at 1
+            removed_ids: Vec::new(),
         }
     }
     
@@ -76,7 +216,7 @@ impl TagCollection {
     pub fn add_tag(&mut self, name: &str) -> i32 {
         let id = self.next_id;
         self.add_tag_with_id(id, name);
-        self.next_id += 1;
+        self.next_id = self.next_id.max(id + 1);
         id
     }
     
@@ -88,6 +228,8 @@ impl TagCollection {
         
         self.tags.insert(id, Tag::new(id, name));
         self.name_to_id.insert(name.to_string(), id);
+        // Keep auto-incremented IDs clear of explicitly chosen ones
+        self.next_id = self.next_id.max(id + 1);
         true
     }
     
@@ -119,6 +261,10 @@ impl TagCollection {
     pub fn remove_tag(&mut self, id: i32) -> bool {
         if let Some(tag) = self.tags.remove(&id) {
             self.name_to_id.remove(&tag.name);
+            for other in self.tags.values_mut() {
+                other.parent_ids.retain(|&parent| parent != id);
+            }
+            self.removed_ids.push(id);
             true
         } else {
             false
@@ -144,6 +290,11 @@ impl TagCollection {
         self.tags.values().collect()
     }
     
+    // Take the IDs of tags removed since the last call, so references to them can be cleaned up
+    pub fn take_removed_tags(&mut self) -> Vec<i32> {
+        std::mem::take(&mut self.removed_ids)
+    }
+    
     // Get tags that match a filter function
     pub fn filter_tags<F>(&self, filter: F) -> Vec<&Tag>
     where F: Fn(&Tag) -> bool {
@@ -154,12 +305,229 @@ impl TagCollection {
     pub fn tag_count(&self) -> usize {
         self.tags.len()
     }
+    
+    // Make one tag a parent of another, rejecting unknown tags and cycles
+    pub fn add_parent(&mut self, child_id: i32, parent_id: i32) -> Result<(), TagHierarchyError> {
+        let child_name = self.tag_name(child_id)?;
+        self.tag_name(parent_id)?;
+        if child_id == parent_id {
+            return Err(TagHierarchyError::SelfParent(child_name));
+        }
+        if let Some(path) = self.path_to_ancestor(parent_id, child_id) {
+            // child -> parent -> ... -> child
+            let mut names = vec![child_name];
+            names.extend(path.iter().filter_map(|&id| self.get_tag(id)).map(|tag| tag.name.clone()));
+            return Err(TagHierarchyError::Cycle(names));
+        }
+        
+        if let Some(child) = self.tags.get_mut(&child_id)
+            && !child.parent_ids.contains(&parent_id)
+        {
+            child.parent_ids.push(parent_id);
+        }
+        Ok(())
+    }
+    
+    // Make one tag a parent of another, by name
+    pub fn add_parent_by_name(&mut self, child: &str, parent: &str) -> Result<(), TagHierarchyError> {
+        let child_id = self.name_to_id.get(child).copied().unwrap_or(-1);
+        let parent_id = self.name_to_id.get(parent).copied().unwrap_or(-1);
+        self.add_parent(child_id, parent_id)
+    }
+    
+    // Remove a parent link, returns whether it existed
+    pub fn remove_parent(&mut self, child_id: i32, parent_id: i32) -> bool {
+        match self.tags.get_mut(&child_id) {
+            Some(child) => {
+                let before = child.parent_ids.len();
+                child.parent_ids.retain(|&id| id != parent_id);
+                child.parent_ids.len() != before
+            },
+            None => false,
+        }
+    }
+    
+    // All ancestors of a tag, closest first; parents are visited in declaration order
+    pub fn ancestors(&self, id: i32) -> Vec<i32> {
+        let mut lineage = self.lineage(id);
+        if !lineage.is_empty() {
+            lineage.remove(0);
+        }
+        lineage
+    }
+    
+    // The tag followed by its ancestors, closest first (empty for unknown tags)
+    pub fn lineage(&self, id: i32) -> Vec<i32> {
+        if !self.tags.contains_key(&id) {
+            return Vec::new();
+        }
+        let mut visited = HashSet::from([id]);
+        let mut order = vec![id];
+        let mut queue = VecDeque::from([id]);
+        while let Some(current) = queue.pop_front() {
+            let Some(tag) = self.tags.get(&current) else { continue };
+            for &parent in &tag.parent_ids {
+                if self.tags.contains_key(&parent) && visited.insert(parent) {
+                    order.push(parent);
+                    queue.push_back(parent);
+                }
+            }
+        }
+        order
+    }
+    
+    // All tags that inherit from a tag, directly or indirectly
+    pub fn descendants(&self, id: i32) -> Vec<i32> {
+        let mut result: Vec<i32> = self.tags.keys()
+            .copied()
+            .filter(|&other| other != id && self.is_a(other, id))
+            .collect();
+        result.sort();
+        result
+    }
+    
+    // Check if a tag is the given ancestor or inherits from it
+    pub fn is_a(&self, id: i32, ancestor_id: i32) -> bool {
+        self.lineage(id).contains(&ancestor_id)
+    }
+    
+    // Properties of a tag including inherited ones. A property overrides an inherited one
+    // filling the same slot, and properties marked "inheritable" = "false" stay with their tag.
+    // Each property is returned with the tag that declares it.
+    pub fn resolved_properties(&self, id: i32) -> Vec<(&Tag, &Property)> {
+        let mut taken_slots = HashSet::new();
+        let mut result = Vec::new();
+        
+        for (depth, tag_id) in self.lineage(id).into_iter().enumerate() {
+            let Some(tag) = self.tags.get(&tag_id) else { continue };
+            let mut tag_slots = Vec::new();
+            for property in &tag.properties {
+                let inheritable = property.metadata.get(INHERITABLE_METADATA).is_none_or(|v| v != "false");
+                if depth > 0 && !inheritable {
+                    continue;
+                }
+                if let Some(slot) = override_key(property) {
+                    if taken_slots.contains(&slot) {
+                        continue;
+                    }
+                    tag_slots.push(slot);
+                }
+                result.push((tag, property));
+            }
+            taken_slots.extend(tag_slots);
+        }
+        result
+    }
+    
+    // Resolved properties of a set of tags, each with the tag that declares it. Tags that are
+    // ancestors of another tag in the set are covered by that tag's lineage, and a property
+    // reachable through several tags is only returned once.
+    pub fn resolved_properties_for_tags(&self, tag_ids: &HashSet<i32>) -> Vec<(&Tag, &Property)> {
+        let mut ids: Vec<i32> = tag_ids.iter().copied().collect();
+        ids.sort();
+        
+        let mut properties: Vec<(&Tag, &Property)> = Vec::new();
+        for &id in &ids {
+            let covered = ids.iter().any(|&other| other != id && self.is_a(other, id));
+            if covered {
+                continue;
+            }
+            for (tag, property) in self.resolved_properties(id) {
+                if !properties.iter().any(|(_, p)| std::ptr::eq(*p, property)) {
+                    properties.push((tag, property));
+                }
+            }
+        }
+        properties
+    }
+    
+    // Resolved properties of a tag that apply in a context
+    pub fn resolved_properties_in_context(&self, id: i32, context: &str) -> Vec<&Property> {
+        self.resolved_properties(id)
+            .into_iter()
+            .map(|(_, property)| property)
+            .filter(|p| p.applies_in_context(context))
+            .collect()
+    }
+    
+    // Metadata of a tag including inherited entries; closer tags win
+    pub fn resolved_metadata(&self, id: i32) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        for tag_id in self.lineage(id).into_iter().rev() {
+            if let Some(tag) = self.tags.get(&tag_id) {
+                metadata.extend(tag.metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
+            }
+        }
+        metadata
+    }
+    
+    // A single metadata value, looked up through the hierarchy
+    pub fn get_inherited_metadata(&self, id: i32, key: &str) -> Option<&String> {
+        self.lineage(id).into_iter()
+            .find_map(|tag_id| self.tags.get(&tag_id).and_then(|tag| tag.metadata.get(key)))
+    }
+    
+    // Check the whole hierarchy, e.g. after loading, for cycles and dangling parents
+    pub fn validate_hierarchy(&self) -> Result<(), TagHierarchyError> {
+        let mut ids: Vec<i32> = self.tags.keys().copied().collect();
+        ids.sort();
+        for &id in &ids {
+            let tag = &self.tags[&id];
+            if let Some(&missing) = tag.parent_ids.iter().find(|p| !self.tags.contains_key(p)) {
+                return Err(TagHierarchyError::UnknownTag(missing));
+            }
+            if tag.parent_ids.contains(&id) {
+                return Err(TagHierarchyError::SelfParent(tag.name.clone()));
+            }
+            for &parent in &tag.parent_ids {
+                if let Some(path) = self.path_to_ancestor(parent, id) {
+                    let mut names = vec![tag.name.clone()];
+                    names.extend(path.iter().filter_map(|&p| self.get_tag(p)).map(|t| t.name.clone()));
+                    return Err(TagHierarchyError::Cycle(names));
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    fn tag_name(&self, id: i32) -> Result<String, TagHierarchyError> {
+        self.tags.get(&id)
+            .map(|tag| tag.name.clone())
+            .ok_or(TagHierarchyError::UnknownTag(id))
+    }
+    
+    // Parent chain from `from` up to `ancestor`, both included, if `ancestor` is reachable
+    fn path_to_ancestor(&self, from: i32, ancestor: i32) -> Option<Vec<i32>> {
+        let mut came_from: HashMap<i32, i32> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        let mut visited = HashSet::from([from]);
+        while let Some(current) = queue.pop_front() {
+            if current == ancestor {
+                let mut path = vec![current];
+                let mut node = current;
+                while let Some(&previous) = came_from.get(&node) {
+                    path.push(previous);
+                    node = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let Some(tag) = self.tags.get(&current) else { continue };
+            for &parent in &tag.parent_ids {
+                if visited.insert(parent) {
+                    came_from.insert(parent, current);
+                    queue.push_back(parent);
+                }
+            }
+        }
+        None
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::property::{Property, PropertyType, PropertyValue};
+    use crate::property::{Property, PropertyValue};
     use crate::stats::StatValue as Stats_StatValue;
 
     #[test]
@@ -209,14 +577,19 @@ mod tests {
         let property3 = Property::stat_modifier("resistance", Stats_StatValue::Integer(10))
             .with_context("combat");
         
+        let property4 = Property::stat_modifier("dodge", Stats_StatValue::Integer(2))
+            .exclusive_to("movement");
+        
         let tag = Tag::new(1, "fire")
             .with_property(property1)
             .with_property(property2)
-            .with_property(property3);
+            .with_property(property3)
+            .with_property(property4);
         
-        // Properties with "combat" context plus those with "default" context
+        // Only the properties scoped to combat apply in combat
         let combat_properties = tag.get_properties_in_context("combat");
-        assert_eq!(combat_properties.len(), 3);  // All 3 properties apply in their respective contexts or default
+        assert_eq!(combat_properties.len(), 2);
+        assert!(tag.get_properties_in_context("default").is_empty());
         
         // Check the property names
         let prop_names: Vec<String> = combat_properties.iter()
@@ -229,13 +602,11 @@ mod tests {
             })
             .collect();
         
-        assert!(prop_names.contains(&"damage".to_string()));
-        assert!(prop_names.contains(&"resistance".to_string()));
-        assert!(prop_names.contains(&"speed".to_string())); // This is visible because of default context
+        assert_eq!(prop_names, vec!["damage".to_string(), "resistance".to_string()]);
         
-        // Properties with "movement" context plus those with "default" context
+        // with_context and exclusive_to both scope to movement
         let movement_properties = tag.get_properties_in_context("movement");
-        assert_eq!(movement_properties.len(), 3);  // All 3 properties apply
+        assert_eq!(movement_properties.len(), 2);
     }
 
     #[test]
@@ -313,18 +684,110 @@ mod tests {
             *tag = tag.clone().with_property(weight_property);
         }
         
-        // Get tags with combat context - will include all tags due to "default" context
+        // Get tags with combat context - only those with a combat property
         let combat_tags = collection.get_tags_in_context("combat");
-        assert_eq!(combat_tags.len(), 3);  // All tags appear due to default context
+        assert_eq!(combat_tags.len(), 2);
         assert!(combat_tags.iter().any(|t| t.name == "fire"));
         assert!(combat_tags.iter().any(|t| t.name == "ice"));
-        assert!(combat_tags.iter().any(|t| t.name == "stone"));
+        assert!(!combat_tags.iter().any(|t| t.name == "stone"));
         
-        // Get tags with physics context - will include all tags due to "default" context
+        // Get tags with physics context
         let physics_tags = collection.get_tags_in_context("physics");
-        assert_eq!(physics_tags.len(), 3);
-        assert!(physics_tags.iter().any(|t| t.name == "fire"));
-        assert!(physics_tags.iter().any(|t| t.name == "ice"));
-        assert!(physics_tags.iter().any(|t| t.name == "stone"));
+        assert_eq!(physics_tags.len(), 1);
+        assert_eq!(physics_tags[0].name, "stone");
+    }
+
+    fn elemental_hierarchy() -> (TagCollection, i32, i32, i32) {
+        let mut collection = TagCollection::new();
+        let elemental = collection.add_tag("elemental");
+        let fire = collection.add_tag("fire");
+        let inferno = collection.add_tag("inferno");
+        collection.add_parent(fire, elemental).unwrap();
+        collection.add_parent(inferno, fire).unwrap();
+        (collection, elemental, fire, inferno)
+    }
+
+    #[test]
+    fn test_instance_tags_expire() {
+        let mut tags = InstanceTags::new();
+        assert!(tags.add(1));
+        assert!(tags.add_timed(2, 1.0));
+        assert!(!tags.add_timed(2, 3.0));
+        assert!(!tags.add_timed(1, 1.0));
+        assert_eq!(tags.remaining(2), Some(3.0));
+        assert_eq!(tags.remaining(1), None);
+
+        assert!(tags.tick(2.0).is_empty());
+        assert_eq!(tags.tick(1.5), vec![2]);
+        assert!(tags.contains(1));
+        assert!(!tags.contains(2));
+        assert_eq!(tags.len(), 1);
+    }
+
+    #[test]
+    fn test_tag_hierarchy_lineage() {
+        let (collection, elemental, fire, inferno) = elemental_hierarchy();
+
+        assert_eq!(collection.lineage(inferno), vec![inferno, fire, elemental]);
+        assert_eq!(collection.ancestors(inferno), vec![fire, elemental]);
+        assert_eq!(collection.descendants(elemental), vec![fire, inferno]);
+        assert!(collection.is_a(inferno, elemental));
+        assert!(!collection.is_a(elemental, inferno));
+    }
+
+    #[test]
+    fn test_tag_hierarchy_rejects_cycles() {
+        let (mut collection, elemental, fire, inferno) = elemental_hierarchy();
+
+        assert_eq!(collection.add_parent(elemental, inferno), Err(TagHierarchyError::Cycle(
+            vec!["elemental".to_string(), "inferno".to_string(), "fire".to_string(), "elemental".to_string()])));
+        assert_eq!(collection.add_parent(fire, fire), Err(TagHierarchyError::SelfParent("fire".to_string())));
+        assert_eq!(collection.add_parent(fire, 99), Err(TagHierarchyError::UnknownTag(99)));
+        assert!(collection.validate_hierarchy().is_ok());
+
+        // Cycles written directly into the data are caught by validation
+        collection.get_tag_mut(elemental).unwrap().parent_ids.push(inferno);
+        assert!(matches!(collection.validate_hierarchy(), Err(TagHierarchyError::Cycle(_))));
+        assert_eq!(collection.lineage(inferno).len(), 3);
+
+        // Removing a tag unlinks it from its children
+        collection.remove_tag(fire);
+        assert!(collection.get_tag(inferno).unwrap().parent_ids.is_empty());
+    }
+
+    #[test]
+    fn test_tag_hierarchy_inherits_properties_and_metadata() {
+        let (mut collection, elemental, fire, inferno) = elemental_hierarchy();
+        let tag = collection.get_tag_mut(elemental).unwrap();
+        tag.properties.push(Property::stat_modifier("magic_resist", Stats_StatValue::Integer(2)));
+        tag.properties.push(Property::stat_modifier("glow", Stats_StatValue::Integer(1))
+            .with_metadata(INHERITABLE_METADATA, "false"));
+        tag.metadata.insert("school".to_string(), "elemental".to_string());
+        tag.metadata.insert("color".to_string(), "grey".to_string());
+
+        let tag = collection.get_tag_mut(fire).unwrap();
+        tag.properties.push(Property::stat_modifier("damage", Stats_StatValue::Integer(5)).with_context("combat"));
+        tag.metadata.insert("color".to_string(), "red".to_string());
+
+        let tag = collection.get_tag_mut(inferno).unwrap();
+        tag.properties.push(Property::stat_modifier("damage", Stats_StatValue::Integer(12)).with_context("combat"));
+
+        let resolved = collection.resolved_properties(inferno);
+        let summary: Vec<(String, String)> = resolved.iter()
+            .filter_map(|(tag, p)| match &p.value {
+                PropertyValue::Stat(stat, Stats_StatValue::Integer(value)) => Some((tag.name.clone(), format!("{}={}", stat, value))),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(summary, vec![
+            ("inferno".to_string(), "damage=12".to_string()),
+            ("elemental".to_string(), "magic_resist=2".to_string()),
+        ]);
+        assert_eq!(collection.resolved_properties(elemental).len(), 2);
+
+        let metadata = collection.resolved_metadata(inferno);
+        assert_eq!(metadata.get("color"), Some(&"red".to_string()));
+        assert_eq!(metadata.get("school"), Some(&"elemental".to_string()));
+        assert_eq!(collection.get_inherited_metadata(inferno, "color"), Some(&"red".to_string()));
     }
 } 
\ No newline at end of file
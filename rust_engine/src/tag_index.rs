@@ -0,0 +1,203 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use crate::entity_type::EntityType;
+use crate::npc::NPC;
+
+/// Reverse index from tag IDs to the entity types and live NPCs carrying them.
+/// NPCs are indexed by their type tags and instance tags combined.
+///
+/// `GameState` keeps it current when tags are changed through its methods. Edits made
+/// directly to `tag_ids` are only picked up by `sync`, which compares every entity's tags
+/// and so is left to callers (`GameState::sync_tag_index`) rather than run every tick.
+#[derive(Clone, Default)]
+pub struct TagIndex {
+    entity_types: HashMap<i32, BTreeSet<String>>,
+    npcs: HashMap<i32, BTreeSet<String>>,
+    // Tag sets as they were last indexed, to find what changed
+    indexed_entity_types: HashMap<String, HashSet<i32>>,
+    indexed_npcs: HashMap<String, HashSet<i32>>,
+}
+
+impl TagIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index everything from scratch
+    pub fn rebuild<'a>(&mut self, entity_types: impl Iterator<Item = &'a EntityType>, npcs: impl Iterator<Item = &'a NPC>) {
+        *self = TagIndex::new();
+        for entity_type in entity_types {
+            self.index_entity_type(&entity_type.id, &entity_type.tag_ids);
+        }
+        for npc in npcs {
+            self.index_npc(&npc.id, &npc.tag_ids());
+        }
+    }
+
+    /// Bring the index in line with the given entities, touching only what changed.
+    /// Returns whether anything had to be updated.
+    pub fn sync<'a>(&mut self, entity_types: impl Iterator<Item = &'a EntityType>, npcs: impl Iterator<Item = &'a NPC>) -> bool {
+        let mut changed = false;
+
+        let mut seen = HashSet::new();
+        for entity_type in entity_types {
+            seen.insert(entity_type.id.as_str());
+            if self.indexed_entity_types.get(&entity_type.id) != Some(&entity_type.tag_ids) {
+                self.index_entity_type(&entity_type.id, &entity_type.tag_ids);
+                changed = true;
+            }
+        }
+        let stale: Vec<String> = self.indexed_entity_types.keys()
+            .filter(|id| !seen.contains(id.as_str()))
+            .cloned()
+            .collect();
+        for id in stale {
+            self.remove_entity_type(&id);
+            changed = true;
+        }
+
+        let mut seen = HashSet::new();
+        for npc in npcs {
+            seen.insert(npc.id.as_str());
+            let tag_ids = npc.tag_ids();
+            if self.indexed_npcs.get(&npc.id) != Some(&tag_ids) {
+                self.index_npc(&npc.id, &tag_ids);
+                changed = true;
+            }
+        }
+        let stale: Vec<String> = self.indexed_npcs.keys()
+            .filter(|id| !seen.contains(id.as_str()))
+            .cloned()
+            .collect();
+        for id in stale {
+            self.remove_npc(&id);
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// Record the current tags of an entity type, replacing what was indexed before
+    pub fn index_entity_type(&mut self, id: &str, tag_ids: &HashSet<i32>) {
+        Self::reindex(&mut self.entity_types, &mut self.indexed_entity_types, id, tag_ids);
+    }
+
+    /// Forget an entity type
+    pub fn remove_entity_type(&mut self, id: &str) {
+        Self::reindex(&mut self.entity_types, &mut self.indexed_entity_types, id, &HashSet::new());
+        self.indexed_entity_types.remove(id);
+    }
+
+    /// Record the current tags of an NPC, replacing what was indexed before
+    pub fn index_npc(&mut self, id: &str, tag_ids: &HashSet<i32>) {
+        Self::reindex(&mut self.npcs, &mut self.indexed_npcs, id, tag_ids);
+    }
+
+    /// Forget an NPC
+    pub fn remove_npc(&mut self, id: &str) {
+        Self::reindex(&mut self.npcs, &mut self.indexed_npcs, id, &HashSet::new());
+        self.indexed_npcs.remove(id);
+    }
+
+    /// Drop a tag from the index entirely
+    pub fn remove_tag(&mut self, tag_id: i32) {
+        self.entity_types.remove(&tag_id);
+        self.npcs.remove(&tag_id);
+        for tags in self.indexed_entity_types.values_mut().chain(self.indexed_npcs.values_mut()) {
+            tags.remove(&tag_id);
+        }
+    }
+
+    /// IDs of entity types carrying a tag, sorted
+    pub fn entity_types_with_tag(&self, tag_id: i32) -> Vec<&str> {
+        self.entity_types.get(&tag_id)
+            .map(|ids| ids.iter().map(|id| id.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// IDs of NPCs carrying a tag, sorted
+    pub fn npcs_with_tag(&self, tag_id: i32) -> Vec<&str> {
+        self.npcs.get(&tag_id)
+            .map(|ids| ids.iter().map(|id| id.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// IDs of entity types carrying any of the tags, sorted and without duplicates
+    pub fn entity_types_with_any_tag(&self, tag_ids: &[i32]) -> Vec<&str> {
+        Self::union(&self.entity_types, tag_ids)
+    }
+
+    /// IDs of NPCs carrying any of the tags, sorted and without duplicates
+    pub fn npcs_with_any_tag(&self, tag_ids: &[i32]) -> Vec<&str> {
+        Self::union(&self.npcs, tag_ids)
+    }
+
+    fn union<'a>(map: &'a HashMap<i32, BTreeSet<String>>, tag_ids: &[i32]) -> Vec<&'a str> {
+        let ids: BTreeSet<&str> = tag_ids.iter()
+            .filter_map(|tag_id| map.get(tag_id))
+            .flat_map(|ids| ids.iter().map(|id| id.as_str()))
+            .collect();
+        ids.into_iter().collect()
+    }
+
+    fn reindex(
+        map: &mut HashMap<i32, BTreeSet<String>>,
+        indexed: &mut HashMap<String, HashSet<i32>>,
+        id: &str,
+        tag_ids: &HashSet<i32>,
+    ) {
+        if let Some(previous) = indexed.get(id) {
+            for tag_id in previous.difference(tag_ids) {
+                if let Some(ids) = map.get_mut(tag_id) {
+                    ids.remove(id);
+                    if ids.is_empty() {
+                        map.remove(tag_id);
+                    }
+                }
+            }
+        }
+        for &tag_id in tag_ids {
+            map.entry(tag_id).or_default().insert(id.to_string());
+        }
+        indexed.insert(id.to_string(), tag_ids.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_and_reindex() {
+        let goblin = EntityType::new("goblin", "Goblin").with_tag_ids(&[1, 2]);
+        let imp = EntityType::new("imp", "Imp").with_tag_ids(&[2]);
+        let mut index = TagIndex::new();
+        index.rebuild([&goblin, &imp].into_iter(), std::iter::empty());
+
+        assert_eq!(index.entity_types_with_tag(2), vec!["goblin", "imp"]);
+        assert_eq!(index.entity_types_with_any_tag(&[1, 2]), vec!["goblin", "imp"]);
+
+        index.index_entity_type("goblin", &HashSet::from([3]));
+        assert_eq!(index.entity_types_with_tag(1), Vec::<&str>::new());
+        assert_eq!(index.entity_types_with_tag(2), vec!["imp"]);
+        assert_eq!(index.entity_types_with_tag(3), vec!["goblin"]);
+    }
+
+    #[test]
+    fn test_sync_picks_up_direct_edits() {
+        let mut goblin = NPC::new("goblin1".to_string(), EntityType::new("goblin", "Goblin").with_tag_id(1));
+        let orc = NPC::new("orc1".to_string(), EntityType::new("orc", "Orc").with_tag_id(1));
+        let mut index = TagIndex::new();
+        assert!(index.sync(std::iter::empty(), [&goblin, &orc].into_iter()));
+        assert!(!index.sync(std::iter::empty(), [&goblin, &orc].into_iter()));
+        assert_eq!(index.npcs_with_tag(1), vec!["goblin1", "orc1"]);
+
+        goblin.add_instance_tag(4);
+        // The orc despawned
+        assert!(index.sync(std::iter::empty(), [&goblin].into_iter()));
+        assert_eq!(index.npcs_with_tag(1), vec!["goblin1"]);
+        assert_eq!(index.npcs_with_tag(4), vec!["goblin1"]);
+
+        index.remove_tag(1);
+        assert!(index.npcs_with_tag(1).is_empty());
+    }
+}
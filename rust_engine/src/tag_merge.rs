@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+use crate::entity_type::EntityType;
+use crate::tag::{override_key, Tag, TagCollection, TagHierarchyError};
+
+/// How to resolve an incoming tag whose name already exists
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagMergePolicy {
+    /// Keep the existing tag untouched; the incoming tag maps onto it
+    KeepExisting,
+    /// Replace the existing tag's properties, metadata and parents with the incoming ones
+    Overwrite,
+    /// Combine both: incoming properties replace existing ones filling the same slot and are
+    /// appended otherwise, incoming metadata wins, parents are joined
+    MergeProperties,
+    /// Add the incoming tag under a new name ("fire_2", "fire_3", ...)
+    Rename,
+}
+
+/// Outcome of merging one tag collection into another
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagMergeReport {
+    /// Incoming tag ID to the ID it has in the merged collection
+    pub id_map: HashMap<i32, i32>,
+    /// Incoming names that were renamed, with their new names
+    pub renamed: Vec<(String, String)>,
+    /// Names that already existed, sorted
+    pub conflicts: Vec<String>,
+}
+
+impl TagMergeReport {
+    /// New ID of an incoming tag
+    pub fn remap(&self, incoming_id: i32) -> Option<i32> {
+        self.id_map.get(&incoming_id).copied()
+    }
+
+    /// Translate a set of incoming tag IDs. IDs the merged collection did not define are
+    /// left as they are, so references to tags that already existed keep working.
+    pub fn remap_tag_ids(&self, tag_ids: &HashSet<i32>) -> HashSet<i32> {
+        tag_ids.iter()
+            .map(|id| self.remap(*id).unwrap_or(*id))
+            .collect()
+    }
+
+    /// Rewrite an entity type that came with the merged tags to use the new IDs
+    pub fn apply_to_entity_type(&self, entity_type: &mut EntityType) {
+        entity_type.tag_ids = self.remap_tag_ids(&entity_type.tag_ids);
+    }
+}
+
+// What to do with an incoming tag's content once every ID is known
+enum Pending {
+    Skip,
+    Replace,
+    Merge,
+}
+
+impl TagCollection {
+    /// Merge another collection into this one, resolving name conflicts by `policy`.
+    ///
+    /// Incoming tags keep their IDs when those are free and get fresh IDs otherwise; parent
+    /// links are remapped along with them. If the merged hierarchy would contain a cycle the
+    /// collection is left unchanged and the error returned.
+    pub fn merge(&mut self, other: &TagCollection, policy: TagMergePolicy) -> Result<TagMergeReport, TagHierarchyError> {
+        let snapshot = self.clone();
+        let mut report = TagMergeReport::default();
+
+        let mut incoming: Vec<&Tag> = other.get_all_tags();
+        incoming.sort_by_key(|tag| tag.id);
+
+        // First assign every incoming tag an ID, so parent links can be remapped afterwards
+        let mut pending = Vec::new();
+        for tag in &incoming {
+            let action = match self.get_tag_by_name(&tag.name).map(|existing| existing.id) {
+                Some(existing_id) => {
+                    report.conflicts.push(tag.name.clone());
+                    match policy {
+                        TagMergePolicy::KeepExisting => {
+                            report.id_map.insert(tag.id, existing_id);
+                            Pending::Skip
+                        },
+                        TagMergePolicy::Overwrite => {
+                            report.id_map.insert(tag.id, existing_id);
+                            Pending::Replace
+                        },
+                        TagMergePolicy::MergeProperties => {
+                            report.id_map.insert(tag.id, existing_id);
+                            Pending::Merge
+                        },
+                        TagMergePolicy::Rename => {
+                            let new_name = self.unused_name(&tag.name);
+                            let new_id = self.insert_preferring_id(tag.id, &new_name);
+                            report.renamed.push((tag.name.clone(), new_name));
+                            report.id_map.insert(tag.id, new_id);
+                            Pending::Replace
+                        },
+                    }
+                },
+                None => {
+                    let new_id = self.insert_preferring_id(tag.id, &tag.name);
+                    report.id_map.insert(tag.id, new_id);
+                    Pending::Replace
+                },
+            };
+            pending.push(action);
+        }
+
+        for (tag, action) in incoming.iter().zip(pending) {
+            let target_id = report.id_map[&tag.id];
+            // Parents outside the incoming collection cannot be resolved and are dropped
+            let parent_ids: Vec<i32> = tag.parent_ids.iter()
+                .filter_map(|parent| report.remap(*parent))
+                .collect();
+            let Some(target) = self.get_tag_mut(target_id) else { continue };
+
+            match action {
+                Pending::Skip => {},
+                Pending::Replace => {
+                    target.properties = tag.properties.clone();
+                    target.metadata = tag.metadata.clone();
+                    target.parent_ids = parent_ids;
+                },
+                Pending::Merge => {
+                    for property in &tag.properties {
+                        let slot = override_key(property);
+                        let existing = slot.as_ref().and_then(|slot| {
+                            target.properties.iter().position(|p| override_key(p).as_ref() == Some(slot))
+                        });
+                        match existing {
+                            Some(index) => target.properties[index] = property.clone(),
+                            None => target.properties.push(property.clone()),
+                        }
+                    }
+                    target.metadata.extend(tag.metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
+                    for parent in parent_ids {
+                        if !target.parent_ids.contains(&parent) {
+                            target.parent_ids.push(parent);
+                        }
+                    }
+                },
+            }
+        }
+
+        if let Err(error) = self.validate_hierarchy() {
+            *self = snapshot;
+            return Err(error);
+        }
+        report.conflicts.sort();
+        Ok(report)
+    }
+
+    // Insert a new tag, keeping the preferred ID if it is free
+    fn insert_preferring_id(&mut self, preferred_id: i32, name: &str) -> i32 {
+        if preferred_id > 0 && self.add_tag_with_id(preferred_id, name) {
+            preferred_id
+        } else {
+            self.add_tag(name)
+        }
+    }
+
+    // First of "name_2", "name_3", ... not used yet
+    fn unused_name(&self, name: &str) -> String {
+        (2..)
+            .map(|n| format!("{}_{}", name, n))
+            .find(|candidate| self.get_tag_by_name(candidate).is_none())
+            .unwrap_or_else(|| name.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::property::{Property, PropertyValue};
+    use crate::stats::StatValue;
+
+    fn base() -> TagCollection {
+        let mut tags = TagCollection::new();
+        let fire = tags.add_tag("fire");
+        tags.add_tag("weapon");
+        let tag = tags.get_tag_mut(fire).unwrap();
+        tag.properties.push(Property::stat_modifier("damage", StatValue::Integer(5)));
+        tag.metadata.insert("color".to_string(), "red".to_string());
+        tags
+    }
+
+    fn pack() -> TagCollection {
+        // The pack numbers its own tags from 1 as well
+        let mut tags = TagCollection::new();
+        let fire = tags.add_tag("fire");
+        let inferno = tags.add_tag("inferno");
+        let tag = tags.get_tag_mut(fire).unwrap();
+        tag.properties.push(Property::stat_modifier("damage", StatValue::Integer(9)));
+        tag.properties.push(Property::stat_modifier("burn", StatValue::Integer(2)));
+        tag.metadata.insert("element".to_string(), "fire".to_string());
+        tags.add_parent(inferno, fire).unwrap();
+        tags
+    }
+
+    fn damage(tags: &TagCollection, name: &str) -> Option<i32> {
+        tags.get_tag_by_name(name)?.properties.iter().find_map(|p| match &p.value {
+            PropertyValue::Stat(stat, StatValue::Integer(value)) if stat == "damage" => Some(*value),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_merge_remaps_ids_and_entity_types() {
+        let mut tags = base();
+        let report = tags.merge(&pack(), TagMergePolicy::KeepExisting).unwrap();
+
+        // Pack fire (1) is the existing fire; pack inferno (2) collides with weapon's ID
+        assert_eq!(report.remap(1), Some(1));
+        assert_eq!(report.remap(2), Some(3));
+        assert_eq!(report.conflicts, vec!["fire".to_string()]);
+        assert_eq!(tags.get_tag(3).unwrap().parent_ids, vec![1]);
+        assert_eq!(damage(&tags, "fire"), Some(5));
+
+        let mut staff = EntityType::new("staff", "Staff").with_tag_ids(&[2]);
+        report.apply_to_entity_type(&mut staff);
+        assert_eq!(staff.tag_ids, HashSet::from([3]));
+        assert_eq!(tags.add_tag("ice"), 4);
+    }
+
+    #[test]
+    fn test_merge_policies() {
+        let mut overwritten = base();
+        overwritten.merge(&pack(), TagMergePolicy::Overwrite).unwrap();
+        let fire = overwritten.get_tag_by_name("fire").unwrap();
+        assert_eq!(damage(&overwritten, "fire"), Some(9));
+        assert_eq!(fire.properties.len(), 2);
+        assert!(!fire.metadata.contains_key("color"));
+
+        let mut merged = base();
+        merged.merge(&pack(), TagMergePolicy::MergeProperties).unwrap();
+        let fire = merged.get_tag_by_name("fire").unwrap();
+        assert_eq!(damage(&merged, "fire"), Some(9));
+        assert_eq!(fire.properties.len(), 2);
+        assert_eq!(fire.metadata.len(), 2);
+
+        let mut renamed = base();
+        let report = renamed.merge(&pack(), TagMergePolicy::Rename).unwrap();
+        assert_eq!(report.renamed, vec![("fire".to_string(), "fire_2".to_string())]);
+        assert_eq!(damage(&renamed, "fire"), Some(5));
+        assert_eq!(damage(&renamed, "fire_2"), Some(9));
+        let fire_2 = report.remap(1).unwrap();
+        let inferno = renamed.get_tag_by_name("inferno").unwrap();
+        assert_eq!(inferno.parent_ids, vec![fire_2]);
+    }
+
+    #[test]
+    fn test_merge_rolls_back_on_cycles() {
+        let mut tags = base();
+        tags.add_parent_by_name("fire", "weapon").unwrap();
+
+        let mut other = TagCollection::new();
+        other.add_tag("weapon");
+        other.add_tag("fire");
+        other.add_parent_by_name("weapon", "fire").unwrap();
+
+        let result = tags.merge(&other, TagMergePolicy::MergeProperties);
+        assert!(matches!(result, Err(TagHierarchyError::Cycle(_))));
+        assert!(tags.get_tag_by_name("weapon").unwrap().parent_ids.is_empty());
+        assert_eq!(tags.tag_count(), 2);
+    }
+}
@@ -0,0 +1,590 @@
+//! Boolean queries over tags and entity types.
+//!
+//! ```text
+//! (fire OR ice) AND weapon AND NOT cursed
+//! elemental AND damage > 5 in combat
+//! ```
+//!
+//! `AND`, `OR` and `NOT` are case-insensitive and may also be written `&&`, `||` and `!`.
+//! `NOT` binds tighter than `AND`, which binds tighter than `OR`.
+//!
+//! A bare name matches the tag or, through the tag hierarchy, any tag inheriting from it.
+//! A predicate `stat <op> value [in context]` compares the StatModifier properties for
+//! `stat` that the subject carries (from its tags and, for entity types, its own properties).
+//! Numeric values are summed before comparing; other values must match one property exactly.
+//! Subjects without any property for the stat never match.
+//!
+//! `TagQuery::parse` checks the syntax once; `TagQuery::compile` resolves tag names against a
+//! `TagCollection` so the resulting `CompiledTagQuery` can be evaluated cheaply in hot loops.
+
+use std::collections::HashSet;
+use std::fmt;
+use crate::character::Character;
+use crate::entity_type::EntityType;
+use crate::npc::NPC;
+use crate::property::{Property, PropertyType, PropertyValue};
+use crate::stats::StatValue;
+use crate::tag::{Tag, TagCollection};
+
+// Result type for parsing and compiling tag queries
+pub type TagQueryResult<T> = Result<T, TagQueryError>;
+
+// A parse or compile error, pointing at the offending column of the query
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagQueryError {
+    pub query: String,
+    // Character offset of the problem in the query
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TagQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid tag query at column {}: {}", self.position + 1, self.message)?;
+        writeln!(f, "  {}", self.query)?;
+        write!(f, "  {}^", " ".repeat(self.position))
+    }
+}
+
+// Comparison used by property predicates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryComparison {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl QueryComparison {
+    fn holds(&self, ordering: std::cmp::Ordering) -> bool {
+        match self {
+            QueryComparison::Equal => ordering.is_eq(),
+            QueryComparison::NotEqual => ordering.is_ne(),
+            QueryComparison::Greater => ordering.is_gt(),
+            QueryComparison::GreaterOrEqual => ordering.is_ge(),
+            QueryComparison::Less => ordering.is_lt(),
+            QueryComparison::LessOrEqual => ordering.is_le(),
+        }
+    }
+}
+
+// Parsed query, with tag names still unresolved
+#[derive(Debug, Clone)]
+enum QueryNode {
+    Tag { name: String, position: usize },
+    Predicate { stat: String, comparison: QueryComparison, value: StatValue, context: Option<String> },
+    And(Vec<QueryNode>),
+    Or(Vec<QueryNode>),
+    Not(Box<QueryNode>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    In,
+    Compare(QueryComparison),
+    Word(String),
+    Number(StatValue),
+    Text(String),
+    End,
+}
+
+fn tokenize(query: &str) -> TagQueryResult<Vec<(Token, usize)>> {
+    let chars: Vec<char> = query.chars().collect();
+    let error = |position: usize, message: &str| TagQueryError {
+        query: query.to_string(),
+        position,
+        message: message.to_string(),
+    };
+    let is_word_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':');
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let token = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            },
+            ('(', _) => { i += 1; Token::LParen },
+            (')', _) => { i += 1; Token::RParen },
+            ('&', Some('&')) => { i += 2; Token::And },
+            ('|', Some('|')) => { i += 2; Token::Or },
+            ('!', Some('=')) => { i += 2; Token::Compare(QueryComparison::NotEqual) },
+            ('!', _) => { i += 1; Token::Not },
+            ('=', Some('=')) => { i += 2; Token::Compare(QueryComparison::Equal) },
+            ('=', _) => { i += 1; Token::Compare(QueryComparison::Equal) },
+            ('>', Some('=')) => { i += 2; Token::Compare(QueryComparison::GreaterOrEqual) },
+            ('>', _) => { i += 1; Token::Compare(QueryComparison::Greater) },
+            ('<', Some('=')) => { i += 2; Token::Compare(QueryComparison::LessOrEqual) },
+            ('<', _) => { i += 1; Token::Compare(QueryComparison::Less) },
+            ('"', _) => {
+                i += 1;
+                let text_start = i;
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(error(start, "unterminated string"));
+                }
+                let text: String = chars[text_start..i].iter().collect();
+                i += 1;
+                Token::Text(text)
+            },
+            (c, next) if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                if let Ok(value) = text.parse::<i32>() {
+                    Token::Number(StatValue::Integer(value))
+                } else if let Ok(value) = text.parse::<f32>() {
+                    Token::Number(StatValue::Float(value))
+                } else {
+                    return Err(error(start, &format!("invalid number '{}'", text)));
+                }
+            },
+            (c, _) if is_word_char(c) => {
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match word.to_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "in" => Token::In,
+                    _ => Token::Word(word),
+                }
+            },
+            (c, _) => return Err(error(start, &format!("unexpected character '{}'", c))),
+        };
+        tokens.push((token, start));
+    }
+    tokens.push((Token::End, chars.len()));
+    Ok(tokens)
+}
+
+struct Parser<'q> {
+    query: &'q str,
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index].0
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.index].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.index].0.clone();
+        if token != Token::End {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: &str) -> TagQueryResult<T> {
+        Err(TagQueryError {
+            query: self.query.to_string(),
+            position: self.position(),
+            message: message.to_string(),
+        })
+    }
+
+    fn describe(token: &Token) -> String {
+        match token {
+            Token::LParen => "'('".to_string(),
+            Token::RParen => "')'".to_string(),
+            Token::And => "AND".to_string(),
+            Token::Or => "OR".to_string(),
+            Token::Not => "NOT".to_string(),
+            Token::In => "IN".to_string(),
+            Token::Compare(_) => "a comparison".to_string(),
+            Token::Word(word) => format!("'{}'", word),
+            Token::Number(_) => "a number".to_string(),
+            Token::Text(text) => format!("\"{}\"", text),
+            Token::End => "the end of the query".to_string(),
+        }
+    }
+
+    fn query(&mut self) -> TagQueryResult<QueryNode> {
+        let node = self.or_expr()?;
+        if *self.peek() != Token::End {
+            let found = Self::describe(self.peek());
+            return self.error(&format!("expected AND, OR or the end of the query, found {}", found));
+        }
+        Ok(node)
+    }
+
+    fn or_expr(&mut self) -> TagQueryResult<QueryNode> {
+        let mut terms = vec![self.and_expr()?];
+        while *self.peek() == Token::Or {
+            self.advance();
+            terms.push(self.and_expr()?);
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { QueryNode::Or(terms) })
+    }
+
+    fn and_expr(&mut self) -> TagQueryResult<QueryNode> {
+        let mut terms = vec![self.not_expr()?];
+        while *self.peek() == Token::And {
+            self.advance();
+            terms.push(self.not_expr()?);
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { QueryNode::And(terms) })
+    }
+
+    fn not_expr(&mut self) -> TagQueryResult<QueryNode> {
+        if *self.peek() == Token::Not {
+            self.advance();
+            return Ok(QueryNode::Not(Box::new(self.not_expr()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> TagQueryResult<QueryNode> {
+        let position = self.position();
+        match self.advance() {
+            Token::LParen => {
+                let node = self.or_expr()?;
+                if *self.peek() != Token::RParen {
+                    let found = Self::describe(self.peek());
+                    return self.error(&format!("expected ')', found {}", found));
+                }
+                self.advance();
+                Ok(node)
+            },
+            Token::Word(name) => match self.peek().clone() {
+                Token::Compare(comparison) => {
+                    self.advance();
+                    let value = match self.peek().clone() {
+                        Token::Number(value) => value,
+                        Token::Text(text) => StatValue::String(text),
+                        Token::Word(word) if word.eq_ignore_ascii_case("true") => StatValue::Boolean(true),
+                        Token::Word(word) if word.eq_ignore_ascii_case("false") => StatValue::Boolean(false),
+                        Token::Word(word) => StatValue::String(word),
+                        _ => return self.error(&format!("expected a value to compare '{}' with", name)),
+                    };
+                    self.advance();
+                    let context = if *self.peek() == Token::In {
+                        self.advance();
+                        let Token::Word(context) = self.peek().clone() else {
+                            return self.error("expected a context name after IN");
+                        };
+                        self.advance();
+                        Some(context)
+                    } else {
+                        None
+                    };
+                    Ok(QueryNode::Predicate { stat: name, comparison, value, context })
+                },
+                _ => Ok(QueryNode::Tag { name, position }),
+            },
+            Token::End => self.error("query ended early, expected a tag name or '('"),
+            token => {
+                // Point at the unexpected token rather than the one after it
+                self.index -= 1;
+                self.error(&format!("expected a tag name or '(', found {}", Self::describe(&token)))
+            },
+        }
+    }
+}
+
+/// A parsed tag query
+#[derive(Debug, Clone)]
+pub struct TagQuery {
+    source: String,
+    root: QueryNode,
+}
+
+impl TagQuery {
+    /// Parse a query, reporting the first syntax error
+    pub fn parse(query: &str) -> TagQueryResult<TagQuery> {
+        let tokens = tokenize(query)?;
+        let mut parser = Parser { query, tokens, index: 0 };
+        let root = parser.query()?;
+        Ok(TagQuery { source: query.to_string(), root })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Resolve tag names against a collection. Unknown tags are an error.
+    pub fn compile(&self, tag_collection: &TagCollection) -> TagQueryResult<CompiledTagQuery> {
+        Ok(CompiledTagQuery {
+            source: self.source.clone(),
+            root: self.compile_node(&self.root, tag_collection)?,
+        })
+    }
+
+    /// Parse and compile in one step
+    pub fn compile_str(query: &str, tag_collection: &TagCollection) -> TagQueryResult<CompiledTagQuery> {
+        TagQuery::parse(query)?.compile(tag_collection)
+    }
+
+    /// Check an entity type against the query (compiles on every call)
+    pub fn matches_entity(&self, entity_type: &EntityType, tag_collection: &TagCollection) -> TagQueryResult<bool> {
+        Ok(self.compile(tag_collection)?.matches_entity(entity_type, tag_collection))
+    }
+
+    fn compile_node(&self, node: &QueryNode, tag_collection: &TagCollection) -> TagQueryResult<CompiledNode> {
+        let compile_all = |nodes: &[QueryNode]| -> TagQueryResult<Vec<CompiledNode>> {
+            nodes.iter().map(|n| self.compile_node(n, tag_collection)).collect()
+        };
+        Ok(match node {
+            QueryNode::Tag { name, position } => {
+                let Some(tag) = tag_collection.get_tag_by_name(name) else {
+                    return Err(TagQueryError {
+                        query: self.source.clone(),
+                        position: *position,
+                        message: format!("unknown tag '{}'", name),
+                    });
+                };
+                // The tag itself and everything inheriting from it satisfies the term
+                let mut ids: HashSet<i32> = tag_collection.descendants(tag.id).into_iter().collect();
+                ids.insert(tag.id);
+                CompiledNode::AnyTag(ids)
+            },
+            QueryNode::Predicate { stat, comparison, value, context } => CompiledNode::Predicate {
+                stat: stat.clone(),
+                comparison: *comparison,
+                value: value.clone(),
+                context: context.clone(),
+            },
+            QueryNode::And(nodes) => CompiledNode::And(compile_all(nodes)?),
+            QueryNode::Or(nodes) => CompiledNode::Or(compile_all(nodes)?),
+            QueryNode::Not(inner) => CompiledNode::Not(Box::new(self.compile_node(inner, tag_collection)?)),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CompiledNode {
+    AnyTag(HashSet<i32>),
+    Predicate { stat: String, comparison: QueryComparison, value: StatValue, context: Option<String> },
+    And(Vec<CompiledNode>),
+    Or(Vec<CompiledNode>),
+    Not(Box<CompiledNode>),
+}
+
+/// A query with tag names resolved to IDs. Recompile after tags or parents change.
+#[derive(Debug, Clone)]
+pub struct CompiledTagQuery {
+    source: String,
+    root: CompiledNode,
+}
+
+impl CompiledTagQuery {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Check an entity type: its tags and the properties of its tags and itself
+    pub fn matches_entity(&self, entity_type: &EntityType, tag_collection: &TagCollection) -> bool {
+        self.matches_tags(&entity_type.tag_ids, &entity_type.properties, tag_collection)
+    }
+
+    /// Check an NPC, counting its instance tags along with its type's tags
+    pub fn matches_npc(&self, npc: &NPC, tag_collection: &TagCollection) -> bool {
+        self.matches_tags(&npc.tag_ids(), &npc.npc_type.properties, tag_collection)
+    }
+
+    /// Check a character, counting its instance tags along with its character type's tags
+    pub fn matches_character(&self, character: &Character, tag_collection: &TagCollection) -> bool {
+        let own_properties = character.character_type.as_ref().map(|t| t.properties.as_slice()).unwrap_or(&[]);
+        self.matches_tags(&character.tag_ids(), own_properties, tag_collection)
+    }
+
+    /// Check a set of tags plus properties that belong to the subject itself
+    pub fn matches_tags(&self, tag_ids: &HashSet<i32>, own_properties: &[Property], tag_collection: &TagCollection) -> bool {
+        let mut properties: Vec<&Property> = Vec::new();
+        let mut properties_loaded = false;
+        Self::evaluate(&self.root, &mut |node| match node {
+            CompiledNode::AnyTag(ids) => tag_ids.iter().any(|id| ids.contains(id)),
+            CompiledNode::Predicate { stat, comparison, value, context } => {
+                if !properties_loaded {
+                    properties = tag_collection.resolved_properties_for_tags(tag_ids)
+                        .into_iter()
+                        .map(|(_, p)| p)
+                        .chain(own_properties.iter())
+                        .collect();
+                    properties_loaded = true;
+                }
+                predicate_holds(&properties, stat, *comparison, value, context.as_deref())
+            },
+            _ => false,
+        })
+    }
+
+    /// Check a single tag: its lineage and its resolved properties
+    pub fn matches_tag(&self, tag: &Tag, tag_collection: &TagCollection) -> bool {
+        let lineage = tag_collection.lineage(tag.id);
+        let properties: Vec<&Property> = tag_collection.resolved_properties(tag.id)
+            .into_iter()
+            .map(|(_, p)| p)
+            .collect();
+        Self::evaluate(&self.root, &mut |node| match node {
+            CompiledNode::AnyTag(ids) => ids.contains(&tag.id) || lineage.iter().any(|id| ids.contains(id)),
+            CompiledNode::Predicate { stat, comparison, value, context } =>
+                predicate_holds(&properties, stat, *comparison, value, context.as_deref()),
+            _ => false,
+        })
+    }
+
+    // Walk the boolean structure, delegating leaves to `leaf`
+    fn evaluate(node: &CompiledNode, leaf: &mut dyn FnMut(&CompiledNode) -> bool) -> bool {
+        match node {
+            CompiledNode::And(nodes) => nodes.iter().all(|n| Self::evaluate(n, leaf)),
+            CompiledNode::Or(nodes) => nodes.iter().any(|n| Self::evaluate(n, leaf)),
+            CompiledNode::Not(inner) => !Self::evaluate(inner, leaf),
+            leaf_node => leaf(leaf_node),
+        }
+    }
+}
+
+fn predicate_holds(
+    properties: &[&Property],
+    stat: &str,
+    comparison: QueryComparison,
+    value: &StatValue,
+    context: Option<&str>,
+) -> bool {
+    let values: Vec<&StatValue> = properties.iter()
+        .filter(|p| p.property_type == PropertyType::StatModifier)
+        .filter(|p| context.is_none_or(|c| p.applies_in_context(c)))
+        .filter_map(|p| match &p.value {
+            PropertyValue::Stat(name, v) if name == stat => Some(v),
+            _ => None,
+        })
+        .collect();
+    if values.is_empty() {
+        return false;
+    }
+
+    if let Some(expected) = value.as_float() {
+        let numbers: Vec<f32> = values.iter().filter_map(|v| v.as_float()).collect();
+        if numbers.is_empty() {
+            return false;
+        }
+        let total: f32 = numbers.iter().sum();
+        return total.partial_cmp(&expected).is_some_and(|ordering| comparison.holds(ordering));
+    }
+
+    let equal = values.iter().any(|v| match (v, value) {
+        (StatValue::Boolean(a), StatValue::Boolean(b)) => a == b,
+        (StatValue::String(a), StatValue::String(b)) => a == b,
+        _ => false,
+    });
+    match comparison {
+        QueryComparison::Equal => equal,
+        QueryComparison::NotEqual => !equal,
+        _ => false,
+    }
+}
+
+impl TagCollection {
+    /// All tags matching a compiled query, sorted by ID
+    pub fn query_tags(&self, query: &CompiledTagQuery) -> Vec<&Tag> {
+        let mut tags = self.filter_tags(|tag| query.matches_tag(tag, self));
+        tags.sort_by_key(|tag| tag.id);
+        tags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weapons() -> (TagCollection, Vec<EntityType>) {
+        let mut tags = TagCollection::new();
+        for name in ["elemental", "fire", "ice", "inferno", "weapon", "cursed"] {
+            tags.add_tag(name);
+        }
+        tags.add_parent_by_name("fire", "elemental").unwrap();
+        tags.add_parent_by_name("ice", "elemental").unwrap();
+        tags.add_parent_by_name("inferno", "fire").unwrap();
+        tags.get_tag_mut_by_name("fire").unwrap().properties.push(
+            Property::stat_modifier("damage", StatValue::Integer(4)).with_context("combat"));
+
+        let id = |name: &str| tags.get_tag_by_name(name).unwrap().id;
+        let entities = vec![
+            EntityType::new("flame_sword", "Flame Sword").with_tag_ids(&[id("fire"), id("weapon")])
+                .with_property_object(Property::stat_modifier("damage", StatValue::Integer(3)).with_context("combat")),
+            EntityType::new("frost_axe", "Frost Axe").with_tag_ids(&[id("ice"), id("weapon"), id("cursed")]),
+            EntityType::new("inferno_staff", "Inferno Staff").with_tag_ids(&[id("inferno"), id("weapon")]),
+            EntityType::new("torch", "Torch").with_tag_ids(&[id("fire")]),
+        ];
+        (tags, entities)
+    }
+
+    fn matching(query: &str, tags: &TagCollection, entities: &[EntityType]) -> Vec<String> {
+        let compiled = TagQuery::compile_str(query, tags).unwrap();
+        entities.iter()
+            .filter(|e| compiled.matches_entity(e, tags))
+            .map(|e| e.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_boolean_tag_queries() {
+        let (tags, entities) = weapons();
+
+        assert_eq!(matching("(fire OR ice) AND weapon AND NOT cursed", &tags, &entities),
+            vec!["flame_sword", "inferno_staff"]);
+        assert_eq!(matching("weapon && !fire", &tags, &entities), vec!["frost_axe"]);
+        assert_eq!(matching("elemental and not weapon", &tags, &entities), vec!["torch"]);
+        // NOT binds tighter than AND, AND tighter than OR
+        assert_eq!(matching("cursed OR fire AND NOT weapon", &tags, &entities), vec!["frost_axe", "torch"]);
+    }
+
+    #[test]
+    fn test_property_predicates() {
+        let (tags, entities) = weapons();
+
+        // Flame sword: 4 from fire plus 3 of its own
+        assert_eq!(matching("damage > 5 in combat", &tags, &entities), vec!["flame_sword"]);
+        assert_eq!(matching("damage >= 4", &tags, &entities), vec!["flame_sword", "inferno_staff", "torch"]);
+        assert_eq!(matching("weapon AND damage == 4 in combat", &tags, &entities), vec!["inferno_staff"]);
+
+        let compiled = TagQuery::compile_str("damage > 3", &tags).unwrap();
+        let names: Vec<&str> = tags.query_tags(&compiled).iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["fire", "inferno"]);
+    }
+
+    #[test]
+    fn test_parse_errors_are_readable() {
+        let error = TagQuery::parse("(fire OR ice AND weapon").unwrap_err();
+        assert_eq!(error.position, 23);
+        assert_eq!(error.message, "expected ')', found the end of the query");
+
+        let error = TagQuery::parse("fire AND AND ice").unwrap_err();
+        assert_eq!(error.position, 9);
+        assert_eq!(error.to_string(),
+            "Invalid tag query at column 10: expected a tag name or '(', found AND\n  fire AND AND ice\n           ^");
+
+        assert!(TagQuery::parse("damage > in combat").is_err());
+        assert_eq!(TagQuery::parse("damage > 5 in").unwrap_err().position, 13);
+        assert!(TagQuery::parse("fire ice").is_err());
+        assert!(TagQuery::parse("fire $ ice").is_err());
+
+        let (tags, _) = weapons();
+        let error = TagQuery::compile_str("fire OR lightning", &tags).unwrap_err();
+        assert_eq!((error.position, error.message.as_str()), (8, "unknown tag 'lightning'"));
+    }
+}
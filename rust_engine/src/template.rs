@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::npc::NPC;
+use crate::stats::StatValue;
+
+/// Blueprint for NPCs of an entity type with a starting set of stats and state
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NpcTemplate {
+    pub id: String,
+    /// ID of the entity type the NPCs are created with
+    pub entity_type: String,
+    /// Base stats every NPC starts with
+    #[serde(default)]
+    pub stats: HashMap<String, StatValue>,
+    /// Starting behavior state, "idle" if unset
+    #[serde(default)]
+    pub behavior_state: Option<String>,
+    /// Permanent instance tags on top of the type's tags
+    #[serde(default)]
+    pub instance_tags: Vec<i32>,
+    #[serde(default)]
+    pub status_effects: Vec<String>,
+}
+
+impl NpcTemplate {
+    pub fn new(id: &str, entity_type: &str) -> Self {
+        NpcTemplate {
+            id: id.to_string(),
+            entity_type: entity_type.to_string(),
+            stats: HashMap::new(),
+            behavior_state: None,
+            instance_tags: Vec::new(),
+            status_effects: Vec::new(),
+        }
+    }
+
+    pub fn with_stat(mut self, key: &str, value: StatValue) -> Self {
+        self.stats.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn with_behavior_state(mut self, state: &str) -> Self {
+        self.behavior_state = Some(state.to_string());
+        self
+    }
+
+    /// Give an NPC of the template's type the template's stats, behavior state, tags and
+    /// status effects, on top of what its type's spawn profile gave it.
+    /// `GameState::npc_from_template` builds the NPC and applies this.
+    pub fn apply_to(&self, npc: &mut NPC) {
+        for (key, value) in &self.stats {
+            npc.set_base_stat(key, value.clone());
+        }
+        if let Some(state) = &self.behavior_state {
+            npc.behavior_state = state.clone();
+        }
+        for &tag_id in &self.instance_tags {
+            npc.add_instance_tag(tag_id);
+        }
+        for effect in &self.status_effects {
+            npc.add_status_effect(effect);
+        }
+    }
+}
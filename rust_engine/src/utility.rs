@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use serde::{Serialize, Deserialize};
+use crate::behavior_tree::{resolve_target, BtTarget};
+use crate::functions::FunctionCall;
+use crate::game_state::{EntityId, GameState};
+use crate::npc::NPC;
+use crate::registry::EntityHandle;
+use crate::script::ScriptValue;
+use crate::state_machine::StateAction;
+
+/// Maps an input normalized to 0..1 onto a score in 0..1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ResponseCurve {
+    /// `slope * x + intercept`; a slope of -1 with an intercept of 1 scores low inputs highest
+    Linear { slope: f32, intercept: f32 },
+    /// `x ^ exponent`; exponents above 1 stay low until the input is high
+    Polynomial { exponent: f32 },
+    /// An S-curve rising around `midpoint`
+    Logistic { steepness: f32, midpoint: f32 },
+    /// 0 below the threshold, 1 from it on
+    Step { threshold: f32 },
+}
+
+impl ResponseCurve {
+    pub fn evaluate(&self, x: f32) -> f32 {
+        let y = match self {
+            ResponseCurve::Linear { slope, intercept } => slope * x + intercept,
+            ResponseCurve::Polynomial { exponent } => x.powf(*exponent),
+            ResponseCurve::Logistic { steepness, midpoint } => 1.0 / (1.0 + (-steepness * (x - midpoint)).exp()),
+            ResponseCurve::Step { threshold } => if x >= *threshold { 1.0 } else { 0.0 },
+        };
+        y.clamp(0.0, 1.0)
+    }
+}
+
+/// What a consideration measures
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ConsiderationInput {
+    /// One of the NPC's calculated stats
+    Stat(String),
+    /// Distance from the NPC to another entity
+    Distance(BtTarget),
+    /// Seconds since the action last ran; actions that never ran count as `max`
+    TimeSinceRun,
+}
+
+/// One reason for or against an action: an input, the range it is normalized from, and
+/// the curve turning it into a score
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Consideration {
+    pub input: ConsiderationInput,
+    pub min: f32,
+    pub max: f32,
+    pub curve: ResponseCurve,
+}
+
+impl Consideration {
+    pub fn new(input: ConsiderationInput, min: f32, max: f32, curve: ResponseCurve) -> Self {
+        Consideration { input, min, max, curve }
+    }
+
+    /// Score a raw input value
+    pub fn score(&self, value: f32) -> f32 {
+        let x = if self.max == self.min {
+            if value >= self.max { 1.0 } else { 0.0 }
+        } else {
+            ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0)
+        };
+        self.curve.evaluate(x)
+    }
+}
+
+/// Something an NPC can choose to do, scored by multiplying its weight with the scores
+/// of all of its considerations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UtilityAction {
+    pub id: String,
+    /// Run every tick while the action is chosen; scripts also get `action` and `score`
+    pub action: StateAction,
+    pub weight: f32,
+    #[serde(default)]
+    pub considerations: Vec<Consideration>,
+}
+
+impl UtilityAction {
+    pub fn new(id: &str, action: StateAction) -> Self {
+        UtilityAction { id: id.to_string(), action, weight: 1.0, considerations: Vec::new() }
+    }
+
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn with_consideration(mut self, consideration: Consideration) -> Self {
+        self.considerations.push(consideration);
+        self
+    }
+}
+
+/// A set of actions an NPC picks from every tick; entity types refer to it with `utility_ai`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UtilityAi {
+    pub id: String,
+    pub actions: Vec<UtilityAction>,
+    /// Bonus for the current action (0.2 scores it 20% higher), so NPCs don't flip between
+    /// actions with close scores
+    #[serde(default)]
+    pub inertia: f32,
+    /// Up to this fraction is taken off each score at random, so NPCs of a type don't all
+    /// act in lockstep
+    #[serde(default)]
+    pub randomness: f32,
+}
+
+impl UtilityAi {
+    pub fn new(id: &str) -> Self {
+        UtilityAi { id: id.to_string(), actions: Vec::new(), inertia: 0.0, randomness: 0.0 }
+    }
+
+    pub fn with_action(mut self, action: UtilityAction) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    pub fn with_inertia(mut self, inertia: f32) -> Self {
+        self.inertia = inertia;
+        self
+    }
+
+    pub fn with_randomness(mut self, randomness: f32) -> Self {
+        self.randomness = randomness;
+        self
+    }
+}
+
+/// How an action scored in the last tick
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionScore {
+    pub action: String,
+    /// Score of each consideration, in order
+    pub considerations: Vec<f32>,
+    /// Weight times the consideration scores
+    pub score: f32,
+    /// Score after inertia and randomness; the highest one is chosen
+    pub final_score: f32,
+}
+
+/// An NPC's utility AI decisions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UtilityState {
+    /// Action chosen in the last tick
+    pub current: Option<String>,
+    /// Game time each action last ran
+    pub last_run: BTreeMap<String, f32>,
+    /// Scores from the last tick, in the order the actions are declared
+    #[serde(skip)]
+    pub scores: Vec<ActionScore>,
+    /// Why the current action failed to run in the last tick, if it did
+    #[serde(skip)]
+    pub last_error: Option<String>,
+}
+
+impl UtilityState {
+    pub fn score(&self, action_id: &str) -> Option<&ActionScore> {
+        self.scores.iter().find(|score| score.action == action_id)
+    }
+}
+
+/// The last tick's scores as a table, the chosen action marked with `*`
+pub fn dump(utility: &UtilityState) -> String {
+    let mut out = String::new();
+    for score in &utility.scores {
+        let marker = if utility.current.as_ref() == Some(&score.action) { "*" } else { " " };
+        let considerations: Vec<String> = score.considerations.iter().map(|s| format!("{:.2}", s)).collect();
+        let _ = writeln!(out, "{} {} {:.2} (score {:.2} from [{}])", marker, score.action, score.final_score, score.score,
+            considerations.join(", "));
+    }
+    if let Some(error) = &utility.last_error {
+        let _ = writeln!(out, "error: {}", error);
+    }
+    out
+}
+
+/// Score the actions of every NPC whose type has a utility AI and run the best one
+pub fn update_utility_ai(game_state: &mut GameState) {
+    for handle in game_state.npcs.handles() {
+        if game_state.npcs.is_despawning(handle) {
+            continue;
+        }
+        let Some(npc) = game_state.npcs.get(handle) else { continue };
+        let Some(ai) = npc.npc_type.utility_ai.as_ref().and_then(|id| game_state.utility_ais.get(id)) else { continue };
+        let ai = ai.clone();
+        let mut scores: Vec<ActionScore> = ai.actions.iter()
+            .map(|action| score_action(game_state, handle, npc, action))
+            .collect();
+
+        let current = npc.utility.current.clone();
+        let target = resolve_target(game_state, handle, &npc.blackboard, &BtTarget::Target);
+        for score in &mut scores {
+            score.final_score = score.score;
+            if current.as_ref() == Some(&score.action) {
+                score.final_score *= 1.0 + ai.inertia;
+            }
+            if ai.randomness > 0.0 {
+                score.final_score *= 1.0 - ai.randomness * game_state.rng.next_f32();
+            }
+        }
+        // The first of equally good actions wins
+        let best = scores.iter()
+            .filter(|score| score.final_score > 0.0)
+            .fold(None::<&ActionScore>, |best, score| match best {
+                Some(best) if best.final_score >= score.final_score => Some(best),
+                _ => Some(score),
+            })
+            .map(|score| (score.action.clone(), score.score));
+
+        let game_time = game_state.game_time;
+        let Some(npc) = game_state.npcs.get_mut(handle) else { continue };
+        let self_id = EntityId::Npc(npc.id.clone());
+        npc.utility.scores = scores;
+        npc.utility.current = best.as_ref().map(|(action_id, _)| action_id.clone());
+        npc.utility.last_error = None;
+        let Some((action_id, score)) = best else { continue };
+        npc.utility.last_run.insert(action_id.clone(), game_time);
+
+        let action = ai.actions.iter().find(|action| action.id == action_id).expect("chosen from these actions");
+        let result = match &action.action {
+            StateAction::Function(function_id) => {
+                let call = FunctionCall::new(function_id, self_id, target.into_iter().collect());
+                game_state.invoke_function(call).map(|_| ()).map_err(|e| e.to_string())
+            },
+            StateAction::Script(source) => {
+                let bindings = vec![
+                    ("action".to_string(), ScriptValue::Str(action_id.clone())),
+                    ("score".to_string(), ScriptValue::Float(score)),
+                ];
+                crate::script::run_script_with_bindings(game_state, source, self_id, target, bindings)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            },
+        };
+        if let Err(message) = result
+            && let Some(npc) = game_state.npcs.get_mut(handle)
+        {
+            npc.utility.last_error = Some(format!("{}: {}", action_id, message));
+        }
+    }
+}
+
+fn score_action(game_state: &GameState, handle: EntityHandle, npc: &NPC, action: &UtilityAction) -> ActionScore {
+    let considerations: Vec<f32> = action.considerations.iter()
+        .map(|consideration| {
+            let value = match &consideration.input {
+                ConsiderationInput::Stat(stat) => npc.get_stat(stat).and_then(|value| value.as_float()),
+                ConsiderationInput::Distance(target) => resolve_target(game_state, handle, &npc.blackboard, target)
+                    .and_then(|id| game_state.get_entity(&id).map(|entity| npc.position.distance(entity.position())))
+                    .filter(|distance| !distance.is_nan()),
+                ConsiderationInput::TimeSinceRun => Some(npc.utility.last_run.get(&action.id)
+                    .map_or(consideration.max, |last| game_state.game_time - last)),
+            };
+            // An input that can't be measured rules the action out
+            value.map_or(0.0, |value| consideration.score(value))
+        })
+        .collect();
+    let score = action.weight * considerations.iter().product::<f32>();
+    ActionScore { action: action.id.clone(), considerations, score, final_score: score }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordinates::Coordinates;
+    use crate::entity_type::EntityType;
+    use crate::stats::StatValue;
+
+    fn fan_ai() -> UtilityAi {
+        let script = |source: &str| StateAction::Script(source.to_string());
+        UtilityAi::new("fan")
+            .with_action(UtilityAction::new("seek_attention", script("add_stat(self, \"attention\", 10);"))
+                .with_consideration(Consideration::new(ConsiderationInput::Stat("attention".to_string()), 0.0, 100.0,
+                    ResponseCurve::Linear { slope: -1.0, intercept: 1.0 }))
+                .with_consideration(Consideration::new(ConsiderationInput::Distance(BtTarget::Player), 0.0, 10.0,
+                    ResponseCurve::Linear { slope: -1.0, intercept: 1.0 })))
+            .with_action(UtilityAction::new("gossip", script("set_stat(self, \"last\", score);"))
+                .with_weight(0.5)
+                .with_consideration(Consideration::new(ConsiderationInput::TimeSinceRun, 0.0, 4.0,
+                    ResponseCurve::Polynomial { exponent: 2.0 })))
+            .with_action(UtilityAction::new("wander", StateAction::Function("missing".to_string()))
+                .with_weight(0.1))
+    }
+
+    fn game(ai: UtilityAi) -> GameState {
+        let mut game_state = GameState::new();
+        game_state.add_utility_ai(ai);
+        game_state.add_entity_type(EntityType::new("fan", "Fan").with_utility_ai("fan"));
+        let mut fan = NPC::new("fan1".to_string(), game_state.entity_types["fan"].clone());
+        fan.set_base_stat("attention", StatValue::Integer(0));
+        fan.position = Coordinates::new_2d(2.0, 0.0);
+        game_state.add_npc(fan).unwrap();
+        game_state
+    }
+
+    #[test]
+    fn test_best_action_is_chosen_and_scores_recorded() {
+        let mut game_state = game(fan_ai());
+        let current = |game_state: &GameState| game_state.get_npc("fan1").unwrap().utility.current.clone();
+
+        // Near the player and unnoticed: 1.0 * 0.8 beats gossip's 0.5 * 1.0
+        game_state.update(1.0);
+        assert_eq!(current(&game_state).as_deref(), Some("seek_attention"));
+        let utility = &game_state.get_npc("fan1").unwrap().utility;
+        assert_eq!(utility.score("seek_attention").unwrap().considerations, vec![1.0, 0.8]);
+        assert_eq!(utility.score("gossip").unwrap().score, 0.5);
+        assert_eq!(game_state.get_npc("fan1").unwrap().get_int_stat("attention"), Some(10));
+
+        // Attention satisfies the need until gossip wins
+        for _ in 0..4 {
+            game_state.update(1.0);
+        }
+        assert_eq!(current(&game_state).as_deref(), Some("gossip"));
+        assert_eq!(game_state.get_npc("fan1").unwrap().get_float_stat("last"), Some(0.5));
+        // Gossip just ran, so it scores low and wander, which fails, wins once attention
+        // is high enough
+        game_state.get_npc_mut("fan1").unwrap().set_base_stat("attention", StatValue::Integer(100));
+        game_state.update(1.0);
+        assert_eq!(current(&game_state).as_deref(), Some("wander"));
+        let dump = game_state.dump_utility_scores("fan1").unwrap();
+        assert!(dump.contains("* wander 0.10 (score 0.10 from [])"), "{}", dump);
+        assert!(dump.contains("error: wander:"), "{}", dump);
+    }
+
+    #[test]
+    fn test_inertia_randomness_and_curves() {
+        assert_eq!(ResponseCurve::Step { threshold: 0.5 }.evaluate(0.4), 0.0);
+        assert_eq!(ResponseCurve::Logistic { steepness: 10.0, midpoint: 0.5 }.evaluate(0.5), 0.5);
+        assert_eq!(ResponseCurve::Linear { slope: 2.0, intercept: 0.0 }.evaluate(0.8), 1.0);
+
+        let noop = || StateAction::Script("1;".to_string());
+        let stat = |name: &str| Consideration::new(ConsiderationInput::Stat(name.to_string()), 0.0, 1.0,
+            ResponseCurve::Linear { slope: 1.0, intercept: 0.0 });
+        let ai = UtilityAi::new("fan")
+            .with_action(UtilityAction::new("a", noop()).with_consideration(stat("a")))
+            .with_action(UtilityAction::new("b", noop()).with_consideration(stat("b")));
+
+        // b overtakes a only by more than the inertia
+        let mut game_state = game(ai.clone().with_inertia(0.25));
+        let set = |game_state: &mut GameState, a: f32, b: f32| {
+            let fan = game_state.get_npc_mut("fan1").unwrap();
+            fan.set_base_stat("a", StatValue::Float(a));
+            fan.set_base_stat("b", StatValue::Float(b));
+        };
+        set(&mut game_state, 0.6, 0.5);
+        game_state.update(1.0);
+        set(&mut game_state, 0.6, 0.7);
+        game_state.update(1.0);
+        assert_eq!(game_state.get_npc("fan1").unwrap().utility.current.as_deref(), Some("a"));
+        set(&mut game_state, 0.6, 0.8);
+        game_state.update(1.0);
+        assert_eq!(game_state.get_npc("fan1").unwrap().utility.current.as_deref(), Some("b"));
+
+        // With randomness, close actions both get picked, the same way for the same seed
+        let picks = |seed: u64| {
+            let mut game_state = game(ai.clone().with_randomness(0.5));
+            game_state.rng = crate::rng::SeededRng::new(seed);
+            set(&mut game_state, 0.5, 0.5);
+            (0..20).map(|_| {
+                game_state.update(1.0);
+                game_state.get_npc("fan1").unwrap().utility.current.clone().unwrap()
+            }).collect::<Vec<_>>()
+        };
+        let first = picks(7);
+        assert!(first.iter().any(|id| id == "a") && first.iter().any(|id| id == "b"));
+        assert_eq!(first, picks(7));
+    }
+}
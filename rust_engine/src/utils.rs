@@ -1,7 +1,9 @@
 use crate::entity_type::EntityType;
 use crate::tag::TagCollection;
+use crate::tag_query::CompiledTagQuery;
 use crate::property::{PropertyType, PropertyValue};
-use crate::stats::StatValue;
+use crate::coordinates::Coordinates;
+use crate::entity::Entity;
 
 /// Get a formatted string representation of an entity with its tags
 pub fn format_entity_with_tags(entity: &EntityType, tag_collection: &TagCollection) -> String {
@@ -24,18 +26,22 @@ pub fn format_entity_with_tags(entity: &EntityType, tag_collection: &TagCollecti
     
     result.push_str("Properties:\n");
     for property in &entity.properties {
-        if let PropertyType::Custom(key) = &property.property_type {
-            if let PropertyValue::Text(value) = &property.value {
-                result.push_str(&format!("- {}: {}\n", key, value));
-            }
+        if let PropertyType::Custom(key) = &property.property_type
+            && let PropertyValue::Text(value) = &property.value
+        {
+            result.push_str(&format!("- {}: {}\n", key, value));
         }
     }
     
     result
 }
 
-/// Calculate damage with modifiers
-pub fn calculate_damage(base_damage: i32, modifiers: &[f32]) -> i32 {
+/// Calculate damage with modifiers, truncated.
+///
+/// Deprecated: the attacker and defender are not used, so their attack, defense and damage
+/// types are ignored. `combat::resolve_attack` resolves a hit between two entities.
+#[deprecated(note = "use combat::resolve_attack, which applies both entities' stats")]
+pub fn calculate_damage<A: Entity + ?Sized, D: Entity + ?Sized>(_attacker: &A, _defender: &D, base_damage: i32, modifiers: &[f32]) -> i32 {
     let mut final_damage = base_damage as f32;
     
     // Apply all modifiers
@@ -64,19 +70,25 @@ pub fn find_entities_with_tag<'a>(entities: &[&'a EntityType], tag_id: i32) -> V
         .collect()
 }
 
+/// Find all entities matching a compiled tag query
+pub fn find_entities_matching_query<'a>(entities: &[&'a EntityType], query: &CompiledTagQuery, tag_collection: &TagCollection) -> Vec<&'a EntityType> {
+    entities.iter()
+        .filter(|entity| query.matches_entity(entity, tag_collection))
+        .copied()
+        .collect()
+}
+
 /// Find all entities with a property matching a specific value
 pub fn find_entities_with_property<'a>(entities: &[&'a EntityType], key: &str, value: &str) -> Vec<&'a EntityType> {
     entities.iter()
         .filter(|entity| {
             for property in &entity.properties {
-                if let PropertyType::Custom(property_key) = &property.property_type {
-                    if property_key == key {
-                        if let PropertyValue::Text(property_value) = &property.value {
-                            if property_value == value {
-                                return true;
-                            }
-                        }
-                    }
+                if let PropertyType::Custom(property_key) = &property.property_type
+                    && property_key == key
+                    && let PropertyValue::Text(property_value) = &property.value
+                    && property_value == value
+                {
+                    return true;
                 }
             }
             false
@@ -90,60 +102,46 @@ pub fn calculate_distance(x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
     ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt()
 }
 
-/// Find entities within a certain radius
-pub fn find_entities_in_radius<'a, T>(
-    entities: &[&'a T],
-    center_x: f32, 
-    center_y: f32, 
-    radius: f32,
-    position_getter: fn(&T) -> (f32, f32)
-) -> Vec<&'a T> {
+/// Find entities within a radius of a point. Entities whose positions have a different
+/// number of dimensions are never in range.
+pub fn find_entities_in_radius<'a, E: Entity + ?Sized>(entities: &[&'a E], center: &Coordinates, radius: f32) -> Vec<&'a E> {
     entities.iter()
-        .filter(|entity| {
-            let (entity_x, entity_y) = position_getter(entity);
-            calculate_distance(center_x, center_y, entity_x, entity_y) <= radius
-        })
+        .filter(|entity| entity.position().distance(center) <= radius)
         .copied()
         .collect()
 }
 
-/// Check if a line of sight exists between two points (no obstacles)
-pub fn has_line_of_sight(
-    start_x: f32, 
-    start_y: f32, 
-    end_x: f32, 
-    end_y: f32, 
-    obstacles: &[(f32, f32, f32)]  // x, y, radius
-) -> bool {
-    // Create a line from start to end
-    let dx = end_x - start_x;
-    let dy = end_y - start_y;
-    let line_length = (dx.powi(2) + dy.powi(2)).sqrt();
-    
-    // Normalize direction
-    let direction_x = dx / line_length;
-    let direction_y = dy / line_length;
-    
-    // Check if any obstacle intersects the line
-    for &(obs_x, obs_y, obs_radius) in obstacles {
-        // Find closest point on line to obstacle
-        let t = direction_x * (obs_x - start_x) + direction_y * (obs_y - start_y);
-        
-        // If closest point is outside line segment, continue
-        if t < 0.0 || t > line_length {
-            continue;
-        }
-        
-        // Calculate closest point on line
-        let closest_x = start_x + t * direction_x;
-        let closest_y = start_y + t * direction_y;
-        
-        // Check if obstacle blocks line of sight
-        let distance = calculate_distance(closest_x, closest_y, obs_x, obs_y);
-        if distance <= obs_radius {
-            return false;
-        }
+/// Check if a line of sight exists between two entities, given spherical obstacles
+/// (center and radius). Works in any number of dimensions; entities in different
+/// dimensionalities cannot see each other.
+pub fn has_line_of_sight<A: Entity + ?Sized, B: Entity + ?Sized>(from: &A, to: &B, obstacles: &[(Coordinates, f32)]) -> bool {
+    is_path_clear(from.position(), to.position(), obstacles)
+}
+
+/// Check that the straight segment between two points misses every obstacle
+pub fn is_path_clear(start: &Coordinates, end: &Coordinates, obstacles: &[(Coordinates, f32)]) -> bool {
+    if start.dimensions() != end.dimensions() {
+        return false;
     }
+    let direction: Vec<f32> = end.values.iter().zip(&start.values).map(|(e, s)| e - s).collect();
+    let length_squared: f32 = direction.iter().map(|d| d * d).sum();
     
-    true
-} 
\ No newline at end of file
+    obstacles.iter().all(|(center, radius)| {
+        if center.dimensions() != start.dimensions() {
+            return true;
+        }
+        // Closest point on the segment to the obstacle's center
+        let t = if length_squared > 0.0 {
+            let along: f32 = direction.iter().zip(center.values.iter().zip(&start.values))
+                .map(|(d, (c, s))| d * (c - s))
+                .sum();
+            (along / length_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let distance_squared: f32 = start.values.iter().zip(&direction).zip(&center.values)
+            .map(|((s, d), c)| (s + t * d - c).powi(2))
+            .sum();
+        distance_squared > radius * radius
+    })
+}